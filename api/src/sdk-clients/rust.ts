@@ -1,6 +1,8 @@
 import axios from 'axios';
 
 const RUST_SDK_URL = process.env.RUST_SDK_URL || 'http://sdk-rust:5010';
+// Shared secret for the Rust SDK service when it runs with AUTH_TOKEN set
+const RUST_SDK_TOKEN = process.env.RUST_SDK_TOKEN;
 
 export interface TransformRequest {
   event: Record<string, any>;
@@ -28,6 +30,7 @@ export async function transformWithRust(
         timeout: 10000,
         headers: {
           'Content-Type': 'application/json',
+          ...(RUST_SDK_TOKEN ? { Authorization: `Bearer ${RUST_SDK_TOKEN}` } : {}),
         },
       }
     );
@@ -0,0 +1,472 @@
+//! Semantic allowlist of the APIs user code may use
+//!
+//! With `ALLOWED_APIS` set, submissions are parsed with syn before compiling
+//! and rejected if they call a function, method, or macro, or name a path,
+//! outside the list. Entries are written the way they appear in code:
+//!
+//! - `Some`, `String::from`, `drop_with_reason` - function calls and paths
+//! - `std::collections::*` - every path under a prefix
+//! - `.insert`, `.as_str` - method calls (`.*` allows every method)
+//! - `json!`, `format!` - macros
+//!
+//! Single-segment paths that aren't called, like local variables, are always
+//! allowed. Macro arguments are checked as expressions when they parse as
+//! such (`format!`), and otherwise scanned for calls (`json!`).
+//!
+//! The same checker enforces `noStd`, which models SDKs on embedded or
+//! WASM-constrained targets by rejecting paths under `NO_STD_DENIED_PATHS`.
+//! Importing a parent of a denied path, or renaming `std`, is rejected too,
+//! since it would let the code reach denied items by a shorter name.
+
+use crate::sandbox::RUST_KEYWORDS;
+use proc_macro2::{Delimiter, Span, TokenStream, TokenTree};
+use std::collections::BTreeMap;
+use std::fmt;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{
+    Block, Expr, ExprCall, ExprMethodCall, ExprPath, ItemExternCrate, Macro, Path, Token, TypePath,
+    UseTree,
+};
+
+/// std modules and types unavailable with `noStd`: everything needing an OS,
+/// plus the collections that aren't in `alloc`
+pub const NO_STD_DENIED_PATHS: &[&str] = &[
+    "std::env",
+    "std::fs",
+    "std::io",
+    "std::net",
+    "std::os",
+    "std::process",
+    "std::thread",
+    "std::time::Instant",
+    "std::time::SystemTime",
+    "std::collections::HashMap",
+    "std::collections::HashSet",
+];
+
+/// A use of an API outside the allowlist, or code that couldn't be checked
+///
+/// `syntax` reports code that doesn't tokenize in the same shape.
+#[derive(Debug)]
+pub struct Violation {
+    /// Helper module the violation is in, `None` for the main code
+    pub module: Option<String>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(module) = &self.module {
+            write!(f, "module `{}`, ", module)?;
+        }
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Check user code and its helper modules against the allowlist
+///
+/// `code` should already have its feature attributes hoisted. Code syn
+/// can't parse is reported as a violation rather than let through.
+pub fn check(code: &str, modules: &BTreeMap<String, String>, allowed: &[String]) -> Vec<Violation> {
+    check_with(code, modules, Rule::Allow(allowed))
+}
+
+/// Check user code and its helper modules for std APIs unavailable with `noStd`
+pub fn check_no_std(code: &str, modules: &BTreeMap<String, String>) -> Vec<Violation> {
+    check_with(code, modules, Rule::Deny(NO_STD_DENIED_PATHS))
+}
+
+fn check_with(code: &str, modules: &BTreeMap<String, String>, rule: Rule) -> Vec<Violation> {
+    let mut violations = match Block::parse_within.parse_str(code) {
+        Ok(stmts) => {
+            let mut checker = Checker::new(rule, None);
+            stmts.iter().for_each(|stmt| checker.visit_stmt(stmt));
+            checker.into_violations()
+        }
+        Err(e) => vec![parse_violation(&e, None)],
+    };
+
+    for (name, source) in modules {
+        match syn::parse_file(source) {
+            Ok(file) => {
+                let mut checker = Checker::new(rule, Some(name));
+                checker.visit_file(&file);
+                violations.extend(checker.into_violations());
+            }
+            Err(e) => violations.push(parse_violation(&e, Some(name))),
+        }
+    }
+
+    violations
+}
+
+fn parse_violation(error: &syn::Error, module: Option<&String>) -> Violation {
+    let start = error.span().start();
+    Violation {
+        module: module.cloned(),
+        line: start.line,
+        column: start.column + 1,
+        message: format!("could not be parsed for the API allowlist check: {}", error),
+    }
+}
+
+/// Which APIs a checker lets through
+#[derive(Clone, Copy)]
+enum Rule<'a> {
+    /// Only the listed APIs
+    Allow(&'a [String]),
+    /// Everything except the listed paths and what's under them
+    Deny(&'a [&'a str]),
+}
+
+/// The denied path `api` reaches, if any
+///
+/// With `ancestors`, a path that only leads to a denied one (`std`,
+/// `std::collections`) counts too, as importing it would.
+fn denied<'a>(denied: &[&'a str], api: &str, ancestors: bool) -> Option<&'a str> {
+    let api = api.strip_suffix("::*").unwrap_or(api);
+    denied.iter().copied().find(|path| {
+        api == *path
+            || api
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with("::"))
+            || (ancestors
+                && path
+                    .strip_prefix(api)
+                    .is_some_and(|rest| rest.starts_with("::")))
+    })
+}
+
+fn is_allowed(allowed: &[String], api: &str) -> bool {
+    allowed.iter().any(|entry| {
+        entry == api
+            || (entry == ".*" && api.starts_with('.'))
+            || entry
+                .strip_suffix("::*")
+                .and_then(|prefix| api.strip_prefix(prefix))
+                .is_some_and(|rest| rest.starts_with("::"))
+    })
+}
+
+fn is_punct(token: Option<&TokenTree>, c: char) -> bool {
+    matches!(token, Some(TokenTree::Punct(punct)) if punct.as_char() == c)
+}
+
+fn path_name(path: &Path) -> String {
+    path.segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::")
+}
+
+struct Checker<'a> {
+    rule: Rule<'a>,
+    module: Option<&'a String>,
+    violations: Vec<Violation>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(rule: Rule<'a>, module: Option<&'a String>) -> Self {
+        Checker {
+            rule,
+            module,
+            violations: Vec::new(),
+        }
+    }
+
+    /// Violations in source order
+    fn into_violations(mut self) -> Vec<Violation> {
+        self.violations
+            .sort_by_key(|violation| (violation.line, violation.column));
+        self.violations
+    }
+
+    fn check(&mut self, api: String, span: Span) {
+        let message = match self.rule {
+            Rule::Allow(allowed) if is_allowed(allowed, &api) => return,
+            Rule::Allow(_) => format!("`{}` is not in the API allowlist", api),
+            Rule::Deny(paths) => match denied(paths, &api, false) {
+                Some(path) => format!("`{}` is not available with noStd", path),
+                None => return,
+            },
+        };
+        self.report(message, span);
+    }
+
+    /// Check a path a `use` imports, which may bring denied items into scope
+    /// under a shorter name
+    fn check_import(&mut self, api: String, span: Span, renamed: bool) {
+        let Rule::Deny(paths) = self.rule else {
+            return self.check(api, span);
+        };
+        let api = api.strip_suffix("::self").unwrap_or(&api);
+        if renamed && api == "std" {
+            self.report("`std` can't be renamed with noStd".to_string(), span);
+        } else if let Some(path) = denied(paths, api, true) {
+            let message = if path == api || api.starts_with(&format!("{}::", path)) {
+                format!("`{}` is not available with noStd", path)
+            } else {
+                format!(
+                    "`{}` contains `{}`, which is not available with noStd; import the items you need",
+                    api, path
+                )
+            };
+            self.report(message, span);
+        }
+    }
+
+    fn report(&mut self, message: String, span: Span) {
+        let start = span.start();
+        self.violations.push(Violation {
+            module: self.module.cloned(),
+            line: start.line,
+            column: start.column + 1,
+            message,
+        });
+    }
+
+    /// Check each path a `use` imports, reported at the start of the path
+    fn check_use(&mut self, prefix: &str, start: Span, tree: &UseTree) {
+        let join = |name: String| {
+            if prefix.is_empty() {
+                name
+            } else {
+                format!("{}::{}", prefix, name)
+            }
+        };
+        let start = |span: Span| if prefix.is_empty() { span } else { start };
+        match tree {
+            UseTree::Path(path) => self.check_use(
+                &join(path.ident.to_string()),
+                start(path.ident.span()),
+                &path.tree,
+            ),
+            UseTree::Name(name) => self.check_import(
+                join(name.ident.to_string()),
+                start(name.ident.span()),
+                false,
+            ),
+            UseTree::Rename(rename) => self.check_import(
+                join(rename.ident.to_string()),
+                start(rename.ident.span()),
+                true,
+            ),
+            UseTree::Glob(glob) => {
+                self.check_import(join("*".to_string()), start(glob.span()), false)
+            }
+            UseTree::Group(group) => {
+                for tree in &group.items {
+                    self.check_use(prefix, start(group.span()), tree);
+                }
+            }
+        }
+    }
+
+    /// Check calls in macro arguments that don't parse as expressions
+    fn scan_tokens(&mut self, tokens: TokenStream) {
+        let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+        let mut i = 0;
+        while i < tokens.len() {
+            let ident = match &tokens[i] {
+                TokenTree::Group(group) => {
+                    self.scan_tokens(group.stream());
+                    i += 1;
+                    continue;
+                }
+                TokenTree::Ident(ident) => ident,
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let method = i > 0 && is_punct(tokens.get(i - 1), '.');
+
+            // Gather the rest of a path like `a::b::c`
+            let mut segments = vec![ident.to_string()];
+            let mut next = i + 1;
+            while is_punct(tokens.get(next), ':') && is_punct(tokens.get(next + 1), ':') {
+                let Some(TokenTree::Ident(segment)) = tokens.get(next + 2) else {
+                    break;
+                };
+                segments.push(segment.to_string());
+                next += 3;
+            }
+            let path = segments.join("::");
+
+            match tokens.get(next) {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == '!' => {
+                    self.check(format!("{}!", path), ident.span())
+                }
+                Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+                    if method {
+                        self.check(format!(".{}", path), ident.span());
+                    } else if !RUST_KEYWORDS.contains(&path.as_str()) {
+                        self.check(path, ident.span());
+                    }
+                }
+                _ if segments.len() > 1 => self.check(path, ident.span()),
+                _ => {}
+            }
+            i = next;
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for Checker<'_> {
+    fn visit_expr_call(&mut self, call: &'ast ExprCall) {
+        let Expr::Path(func) = &*call.func else {
+            return visit::visit_expr_call(self, call);
+        };
+        self.check(path_name(&func.path), func.path.span());
+        call.args.iter().for_each(|arg| self.visit_expr(arg));
+    }
+
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        self.check(format!(".{}", call.method), call.method.span());
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_expr_path(&mut self, expr: &'ast ExprPath) {
+        if expr.qself.is_some() || expr.path.segments.len() > 1 {
+            self.check(path_name(&expr.path), expr.path.span());
+        }
+        visit::visit_expr_path(self, expr);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast Macro) {
+        self.check(format!("{}!", path_name(&mac.path)), mac.path.span());
+        match mac.parse_body_with(Punctuated::<Expr, Token![,]>::parse_terminated) {
+            Ok(args) => args.iter().for_each(|arg| self.visit_expr(arg)),
+            Err(_) => self.scan_tokens(mac.tokens.clone()),
+        }
+    }
+
+    fn visit_use_tree(&mut self, tree: &'ast UseTree) {
+        self.check_use("", tree.span(), tree);
+    }
+
+    // Type paths and crate imports only matter for `noStd`; the allowlist
+    // lists what code may call
+    fn visit_type_path(&mut self, ty: &'ast TypePath) {
+        if matches!(self.rule, Rule::Deny(_)) {
+            self.check(path_name(&ty.path), ty.path.span());
+        }
+        visit::visit_type_path(self, ty);
+    }
+
+    fn visit_item_extern_crate(&mut self, item: &'ast ItemExternCrate) {
+        if matches!(self.rule, Rule::Deny(_)) && item.rename.is_some() {
+            self.check_import(item.ident.to_string(), item.ident.span(), true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(code: &str, allowed: &[&str]) -> Vec<String> {
+        let allowed: Vec<String> = allowed.iter().map(|api| api.to_string()).collect();
+        check(code, &BTreeMap::new(), &allowed)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    const ALLOWED: &[&str] = &["Some", ".as_object_mut", ".insert", ".to_string", "json!"];
+
+    #[test]
+    fn allowed_operations_pass() {
+        let code = r#"event.as_object_mut()?.insert("tags".to_string(), json!({ "a": 1 }));
+let level = event["level"].clone();
+Some(event)"#;
+        assert_eq!(
+            violations(code, &[ALLOWED, &[".clone"]].concat()),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn disallowed_calls_are_reported_with_their_position() {
+        let code = "let code = 1;\n  std::process::exit(code);\nevent.take();\nprintln!(\"hi\");\nSome(event)";
+        assert_eq!(
+            violations(code, ALLOWED),
+            [
+                "line 2, column 3: `std::process::exit` is not in the API allowlist",
+                "line 3, column 7: `.take` is not in the API allowlist",
+                "line 4, column 1: `println!` is not in the API allowlist",
+            ]
+        );
+    }
+
+    #[test]
+    fn prefixes_and_wildcard_methods_allow_everything_under_them() {
+        let code = "let map = std::collections::BTreeMap::<String, String>::new();\nmap.len();\nSome(event)";
+        assert!(violations(code, &["Some", "std::collections::*", ".*"]).is_empty());
+        assert_eq!(
+            violations(code, &["Some", "std::collections::BTreeMap"]),
+            [
+                "line 1, column 11: `std::collections::BTreeMap::new` is not in the API allowlist",
+                "line 2, column 5: `.len` is not in the API allowlist",
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_in_helper_modules_and_unparsable_code_are_rejected() {
+        let modules = BTreeMap::from([(
+            "rules".to_string(),
+            "pub fn run() { std::fs::remove_file(\"x\"); }".to_string(),
+        )]);
+        let allowed = vec!["Some".to_string()];
+        let found: Vec<String> = check("Some(event)", &modules, &allowed)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            ["module `rules`, line 1, column 16: `std::fs::remove_file` is not in the API allowlist"]
+        );
+
+        let found = violations("Some(event", &["Some"]);
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].contains("could not be parsed for the API allowlist check"),
+            "{:?}",
+            found
+        );
+    }
+
+    #[test]
+    fn no_std_rejects_os_apis_and_their_parents() {
+        let found: Vec<String> = check_no_std(
+            "use std::collections;\nlet now = std::time::Instant::now();\nSome(event)",
+            &BTreeMap::new(),
+        )
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(
+            found,
+            [
+                "line 1, column 5: `std::collections` contains `std::collections::HashMap`, which is not available with noStd; import the items you need",
+                "line 2, column 11: `std::time::Instant` is not available with noStd",
+            ]
+        );
+        assert!(check_no_std(
+            "use std::collections::BTreeMap;\nSome(event)",
+            &BTreeMap::new()
+        )
+        .is_empty());
+    }
+}
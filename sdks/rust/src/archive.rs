@@ -0,0 +1,87 @@
+//! Minimal tar archives of generated projects
+//!
+//! Only regular files with short paths are needed, so archives are written
+//! directly in the ustar format rather than pulling in a tar crate.
+
+/// Size of a tar header and of the blocks file contents are padded to
+const BLOCK: usize = 512;
+
+/// Build an uncompressed tar archive of `(path, contents)` entries
+///
+/// Paths must be relative and at most 100 bytes. Entries are owned by root
+/// with mode 0644 and a zero mtime, so identical inputs give identical
+/// archives.
+pub fn tar(entries: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (path, contents) in entries {
+        archive.extend_from_slice(&header(path, contents.len()));
+        archive.extend_from_slice(contents);
+        archive.resize(archive.len().next_multiple_of(BLOCK), 0);
+    }
+    // The archive ends with two empty blocks
+    archive.resize(archive.len() + 2 * BLOCK, 0);
+    archive
+}
+
+fn header(path: &str, size: usize) -> [u8; BLOCK] {
+    let mut header = [0u8; BLOCK];
+    let mut field = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+
+    field(0, &path.as_bytes()[..path.len().min(100)]);
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, b"00000000000\0");
+    // The checksum is computed with its own field filled with spaces
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+
+    let checksum: u32 = header.iter().map(|&byte| u32::from(byte)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn octal(field: &[u8]) -> usize {
+        let digits = std::str::from_utf8(field).unwrap();
+        usize::from_str_radix(digits.trim_matches(['\0', ' ']), 8).unwrap()
+    }
+
+    #[test]
+    fn entries_are_padded_to_blocks_with_valid_headers() {
+        let archive = tar(&[
+            ("a/Cargo.toml".to_string(), b"[package]\n".to_vec()),
+            ("a/empty".to_string(), Vec::new()),
+        ]);
+        // Header and one block of contents, an empty file's header, and the end marker
+        assert_eq!(archive.len(), 5 * BLOCK);
+
+        let header = &archive[..BLOCK];
+        assert_eq!(&header[..13], b"a/Cargo.toml\0");
+        assert_eq!(octal(&header[124..136]), 10);
+        assert_eq!(&header[257..263], b"ustar\0");
+        let mut unsummed = header.to_vec();
+        unsummed[148..156].fill(b' ');
+        let sum: usize = unsummed.iter().map(|&byte| usize::from(byte)).sum();
+        assert_eq!(octal(&header[148..156]), sum);
+
+        assert_eq!(&archive[BLOCK..BLOCK + 10], b"[package]\n");
+        assert!(archive[BLOCK + 10..2 * BLOCK].iter().all(|&byte| byte == 0));
+        assert_eq!(&archive[2 * BLOCK..2 * BLOCK + 7], b"a/empty");
+        assert!(archive[3 * BLOCK..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn identical_entries_give_identical_archives() {
+        let entries = [("main.rs".to_string(), b"fn main() {}".to_vec())];
+        assert_eq!(tar(&entries), tar(&entries));
+    }
+}
@@ -0,0 +1,134 @@
+//! JSON Pointer assertions checked against transformed events
+//!
+//! Assertions are evaluated by the service after user code returns, so simple
+//! checks like "`/tags/env` is `prod`" don't need any Rust.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// A check on the value at a JSON Pointer
+///
+/// With `equals`, the value must be present and equal to it (`null` included).
+/// Otherwise the value must be present, or absent with `"exists": false`.
+#[derive(Debug, Deserialize)]
+pub struct PointerAssertion {
+    pub pointer: String,
+    #[serde(default, deserialize_with = "present")]
+    pub equals: Option<Value>,
+    #[serde(default = "default_exists")]
+    pub exists: bool,
+}
+
+fn default_exists() -> bool {
+    true
+}
+
+/// Keep an explicit `null` as `Some(Value::Null)` instead of `None`
+pub fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Outcome of one assertion
+#[derive(Debug, Serialize)]
+pub struct AssertionResult {
+    pub pointer: String,
+    pub passed: bool,
+    /// The value found at the pointer, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actual: Option<Value>,
+    /// Why the assertion failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// Evaluate each assertion against `event`, in order
+pub fn evaluate(event: &Value, assertions: &[PointerAssertion]) -> Vec<AssertionResult> {
+    assertions
+        .iter()
+        .map(|assertion| {
+            let actual = event.pointer(&assertion.pointer).cloned();
+            let message = check(assertion, actual.as_ref());
+            AssertionResult {
+                pointer: assertion.pointer.clone(),
+                passed: message.is_none(),
+                actual,
+                message,
+            }
+        })
+        .collect()
+}
+
+/// Return why an assertion fails, or `None` if it passes
+fn check(assertion: &PointerAssertion, actual: Option<&Value>) -> Option<String> {
+    if !assertion.pointer.is_empty() && !assertion.pointer.starts_with('/') {
+        return Some("pointer must be empty or start with '/'".to_string());
+    }
+
+    match (&assertion.equals, actual) {
+        (Some(expected), Some(actual)) if actual == expected => None,
+        (Some(expected), Some(_)) => Some(format!("expected {}", expected)),
+        (Some(expected), None) => Some(format!("expected {}, but no value exists", expected)),
+        (None, Some(_)) if !assertion.exists => Some("expected no value".to_string()),
+        (None, None) if assertion.exists => Some("expected a value".to_string()),
+        (None, _) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn results(assertions: Value) -> Vec<(bool, Option<String>)> {
+        let event = json!({ "tags": { "env": "prod" }, "user": null });
+        let assertions: Vec<PointerAssertion> = serde_json::from_value(assertions).unwrap();
+        evaluate(&event, &assertions)
+            .into_iter()
+            .map(|result| (result.passed, result.message))
+            .collect()
+    }
+
+    #[test]
+    fn equality_compares_the_value_at_the_pointer() {
+        assert_eq!(
+            results(json!([
+                { "pointer": "/tags/env", "equals": "prod" },
+                { "pointer": "/user", "equals": null },
+                { "pointer": "/tags/env", "equals": "dev" },
+                { "pointer": "/tags/release", "equals": "1.0" }
+            ])),
+            [
+                (true, None),
+                (true, None),
+                (false, Some("expected \"dev\"".to_string())),
+                (
+                    false,
+                    Some("expected \"1.0\", but no value exists".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn existence_checks_presence_or_absence() {
+        assert_eq!(
+            results(json!([
+                { "pointer": "/tags" },
+                { "pointer": "/extra", "exists": false },
+                { "pointer": "/extra" },
+                { "pointer": "/tags", "exists": false },
+                { "pointer": "tags" }
+            ])),
+            [
+                (true, None),
+                (true, None),
+                (false, Some("expected a value".to_string())),
+                (false, Some("expected no value".to_string())),
+                (
+                    false,
+                    Some("pointer must be empty or start with '/'".to_string())
+                ),
+            ]
+        );
+    }
+}
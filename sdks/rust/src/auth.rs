@@ -0,0 +1,62 @@
+//! Optional bearer-token authentication for write endpoints
+//!
+//! When `AUTH_TOKEN` is configured, wrapped routes require an
+//! `Authorization: Bearer <token>` header. Without it, auth is disabled and
+//! every request passes through.
+
+use crate::sandbox::ErrorKind;
+use crate::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+
+/// Middleware rejecting requests without the configured bearer token
+pub async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let expected = req
+        .app_data::<web::Data<AppState>>()
+        .and_then(|state| state.config.auth_token.clone());
+
+    if let Some(expected) = expected {
+        let provided = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+
+        let authorized = provided.is_some_and(|token| constant_time_eq(token, &expected));
+        if !authorized {
+            let message = if provided.is_some() {
+                "Invalid bearer token"
+            } else {
+                "Missing bearer token"
+            };
+            let response = HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .json(serde_json::json!({
+                    "success": false,
+                    "error": message,
+                    "errorKind": ErrorKind::InvalidInput,
+                }));
+            return Ok(req.into_response(response).map_into_right_body());
+        }
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+/// Compare two secrets without short-circuiting on the first differing byte
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
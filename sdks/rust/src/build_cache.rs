@@ -0,0 +1,120 @@
+//! Cache of compiled transform binaries
+//!
+//! Builds are keyed by their generated sources and build options, so a
+//! request repeating earlier code runs the stored binary without compiling
+//! or waiting for a build slot. The least recently used entries are evicted
+//! once the cache holds `BUILD_CACHE_ENTRIES` binaries.
+
+use crate::sandbox::{BuildOutput, DepTiming, Executable};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// A binary copied out of its build directory, deleted once no longer used
+///
+/// Requests hold an `Arc` while running, so an entry evicted mid-run stays on
+/// disk until the run finishes.
+pub struct CachedBuild {
+    pub executable: Executable,
+    /// Crates compiled for the build, when build info was requested
+    pub build_info: Option<Value>,
+    /// Compile time per dependency, when timings were requested
+    pub dep_timings: Option<Vec<DepTiming>>,
+    /// Compiler warnings, when `strictUnused` was requested
+    pub warnings: Option<Vec<String>>,
+}
+
+impl Drop for CachedBuild {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(self.executable.path());
+    }
+}
+
+/// Whether a request's build came from the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheStatus {
+    /// An identical earlier build was reused
+    Hit,
+    /// The code was compiled and its binary stored for reuse
+    Miss,
+    /// The code was compiled without consulting the cache, because caching is
+    /// off (`BUILD_CACHE_ENTRIES=0`) or the code runs under Miri
+    Disabled,
+}
+
+/// LRU cache of built binaries keyed by `TransformProject::cache_key`
+pub struct BuildCache {
+    dir: TempDir,
+    capacity: usize,
+    next_file: AtomicU64,
+    /// Entries ordered from least to most recently used
+    entries: Mutex<Vec<(String, Arc<CachedBuild>)>>,
+}
+
+impl BuildCache {
+    /// Create an empty cache; a capacity of 0 disables caching
+    pub fn new(capacity: usize) -> std::io::Result<Self> {
+        Ok(BuildCache {
+            dir: tempfile::tempdir()?,
+            capacity,
+            next_file: AtomicU64::new(0),
+            entries: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Whether builds are stored at all
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Look up a binary built earlier, marking it as recently used
+    pub fn get(&self, key: &str) -> Option<Arc<CachedBuild>> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let position = entries.iter().position(|(entry_key, _)| entry_key == key)?;
+        let entry = entries.remove(position);
+        let build = entry.1.clone();
+        entries.push(entry);
+        Some(build)
+    }
+
+    /// Copy a freshly built binary into the cache and return the stored copy
+    ///
+    /// With caching disabled the copy is returned without being stored, so
+    /// callers can drop the build directory either way.
+    pub fn insert(
+        &self,
+        key: String,
+        executable: &Executable,
+        output: BuildOutput,
+    ) -> std::io::Result<Arc<CachedBuild>> {
+        let file = self.next_file.fetch_add(1, Ordering::Relaxed);
+        let extension = executable
+            .path()
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        let path = self.dir.path().join(format!("{}{}", file, extension));
+
+        let build = Arc::new(CachedBuild {
+            executable: executable.copy_to(path)?,
+            build_info: output.build_info,
+            dep_timings: output.dep_timings,
+            warnings: output.warnings,
+        });
+        if self.capacity == 0 {
+            return Ok(build);
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|(entry_key, _)| *entry_key != key);
+        if entries.len() >= self.capacity {
+            entries.remove(0);
+        }
+        entries.push((key, build.clone()));
+        Ok(build)
+    }
+}
@@ -0,0 +1,200 @@
+//! MessagePack content negotiation
+//!
+//! Request bodies sent with `Content-Type: application/msgpack` are decoded
+//! with rmp-serde, and responses are encoded as msgpack when the client sends
+//! `Accept: application/msgpack`. JSON stays the default in both directions.
+//!
+//! Bodies of either type may be compressed, e.g. `Content-Encoding: gzip` as
+//! relays send them. They are inflated as they stream in, and the size limit
+//! applies to the inflated body so a small compressed bomb is cut off early.
+
+use crate::sandbox::ErrorKind;
+use crate::TransformResponse;
+use actix_web::dev::{Decompress, Payload};
+use actix_web::error::{JsonPayloadError, PayloadError};
+use actix_web::http::header::{HeaderMap, ACCEPT, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_web::{error, web, FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+/// MessagePack media type
+const MSGPACK: &str = "application/msgpack";
+
+/// Legacy media type some msgpack clients still send
+const MSGPACK_LEGACY: &str = "application/x-msgpack";
+
+/// Largest request body accepted, after decompression; actix's default JSON limit
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Request body decoded from JSON or msgpack according to `Content-Type`
+pub struct Body<T>(pub T);
+
+impl<T> std::ops::Deref for Body<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Body<T> {
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if !has_msgpack(req.headers(), CONTENT_TYPE.as_str()) {
+            let json = web::Json::<T>::from_request(req, payload);
+            return Box::pin(async move { Ok(Body(json.await?.into_inner())) });
+        }
+
+        let encoding = content_encoding(req);
+        let mut payload = Decompress::from_headers(payload.take(), req.headers());
+        Box::pin(async move {
+            let mut body = web::BytesMut::new();
+            while let Some(chunk) = payload.next().await {
+                let chunk = match (chunk, &encoding) {
+                    (Ok(chunk), _) => chunk,
+                    (Err(e), Some(encoding)) => return Err(corrupt_body(encoding, e)),
+                    (Err(e), None) => return Err(e.into()),
+                };
+                if body.len() + chunk.len() > MAX_BODY_BYTES {
+                    return Err(match &encoding {
+                        Some(encoding) => inflated_too_large(encoding),
+                        None => error::ErrorPayloadTooLarge("msgpack body is too large"),
+                    });
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            rmp_serde::from_slice(&body)
+                .map(Body)
+                .map_err(|e| error::ErrorBadRequest(format!("Invalid msgpack body: {}", e)))
+        })
+    }
+}
+
+/// JSON extractor settings, explaining bodies that fail to decompress or
+/// nest too deeply to parse
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(MAX_BODY_BYTES)
+        .error_handler(|e, req| match (e, content_encoding(req)) {
+            (JsonPayloadError::Deserialize(e), _) if is_recursion_limit(&e) => too_deep(e),
+            (JsonPayloadError::Payload(e), Some(encoding)) => corrupt_body(&encoding, e),
+            (JsonPayloadError::Overflow { .. }, Some(encoding)) => inflated_too_large(&encoding),
+            (e, _) => e.into(),
+        })
+}
+
+/// Levels of nesting serde_json parses before giving up
+const SERDE_JSON_DEPTH: usize = 128;
+
+/// Whether parsing stopped at serde_json's nesting limit
+fn is_recursion_limit(e: &serde_json::Error) -> bool {
+    e.to_string().starts_with("recursion limit exceeded")
+}
+
+/// A body too deep to parse, rejected like an event over `MAX_EVENT_DEPTH`
+fn too_deep(e: serde_json::Error) -> actix_web::Error {
+    let response = HttpResponse::BadRequest().json(TransformResponse {
+        error_kind: Some(ErrorKind::InvalidInput),
+        ..TransformResponse::failure(
+            format!(
+                "Request body is nested more than {} levels deep",
+                SERDE_JSON_DEPTH
+            ),
+            None,
+        )
+    });
+    error::InternalError::from_response(e, response).into()
+}
+
+/// The request's `Content-Encoding`, unless it is absent or `identity`
+pub fn content_encoding(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .filter(|value| !value.is_empty() && value != "identity")
+}
+
+fn corrupt_body(encoding: &str, e: PayloadError) -> actix_web::Error {
+    error::ErrorBadRequest(format!(
+        "Request body is not valid {} data: {}",
+        encoding, e
+    ))
+}
+
+fn inflated_too_large(encoding: &str) -> actix_web::Error {
+    error::ErrorPayloadTooLarge(format!(
+        "Request body inflates to more than {} bytes once {} is decoded",
+        MAX_BODY_BYTES, encoding
+    ))
+}
+
+/// Finish a response, encoding the body as msgpack if the client accepts it
+///
+/// Maps are encoded with field names so msgpack responses mirror the JSON ones.
+/// Numbers become msgpack integers or floats, so those outside the 64-bit
+/// range lose the precision they keep in JSON.
+pub fn respond(
+    req: &HttpRequest,
+    mut builder: HttpResponseBuilder,
+    body: &impl Serialize,
+) -> HttpResponse {
+    if !has_msgpack(req.headers(), ACCEPT.as_str()) {
+        return builder.json(body);
+    }
+
+    // serde_json's arbitrary-precision numbers only serialize natively to JSON,
+    // so the body goes through a `Value` whose numbers are re-encoded
+    let encoded = serde_json::to_value(body)
+        .map_err(|e| e.to_string())
+        .and_then(|value| rmp_serde::to_vec_named(&Msgpack(&value)).map_err(|e| e.to_string()));
+    match encoded {
+        Ok(bytes) => builder.content_type(MSGPACK).body(bytes),
+        Err(e) => HttpResponse::InternalServerError()
+            .body(format!("Failed to encode msgpack response: {}", e)),
+    }
+}
+
+/// A JSON value serialized with plain numbers
+struct Msgpack<'a>(&'a Value);
+
+impl Serialize for Msgpack<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Number(number) => {
+                if let Some(n) = number.as_u64() {
+                    serializer.serialize_u64(n)
+                } else if let Some(n) = number.as_i64() {
+                    serializer.serialize_i64(n)
+                } else {
+                    serializer.serialize_f64(number.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            Value::Array(items) => serializer.collect_seq(items.iter().map(Msgpack)),
+            Value::Object(map) => {
+                serializer.collect_map(map.iter().map(|(key, value)| (key, Msgpack(value))))
+            }
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+/// Whether a header lists a msgpack media type
+fn has_msgpack(headers: &HeaderMap, name: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.split(',').any(|item| {
+                let media_type = item.split(';').next().unwrap_or_default().trim();
+                media_type.eq_ignore_ascii_case(MSGPACK)
+                    || media_type.eq_ignore_ascii_case(MSGPACK_LEGACY)
+            })
+        })
+}
@@ -0,0 +1,140 @@
+//! Service configuration loaded from environment variables
+//!
+//! Every setting has a default so the service runs unconfigured.
+
+use crate::sandbox::ExecBackend;
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Runtime settings for the service
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of cargo builds running at once (`MAX_CONCURRENT_BUILDS`)
+    pub max_concurrent_builds: usize,
+    /// Maximum number of requests waiting for a build slot (`MAX_QUEUE_DEPTH`)
+    pub max_queue_depth: usize,
+    /// Seconds clients are asked to wait when the queue is full (`QUEUE_RETRY_AFTER_SECS`)
+    pub queue_retry_after_secs: u64,
+    /// Requests allowed per client per minute, 0 disables (`RATE_LIMIT_PER_MINUTE`)
+    pub rate_limit_per_minute: u32,
+    /// Requests a client may make in a burst (`RATE_LIMIT_BURST`)
+    pub rate_limit_burst: u32,
+    /// Header holding the client IP when behind a trusted proxy (`TRUSTED_PROXY_HEADER`)
+    pub trusted_proxy_header: Option<String>,
+    /// Bearer token required by write endpoints, auth is disabled if unset (`AUTH_TOKEN`)
+    pub auth_token: Option<String>,
+    /// Free space required on the build volume before compiling (`MIN_FREE_DISK_MB`)
+    pub min_free_disk_bytes: u64,
+    /// Seconds a build may wait on cargo's package cache lock (`CARGO_LOCK_TIMEOUT_SECS`)
+    pub cargo_lock_timeout_secs: u64,
+    /// Seconds one run of user code may take before it is killed, including
+    /// Miri's interpretation when Miri is allowed (`EXEC_TIMEOUT_SECS`)
+    pub exec_timeout_secs: u64,
+    /// Largest serialized event accepted by `enforceSizeLimits` (`MAX_EVENT_BYTES`)
+    pub max_event_bytes: usize,
+    /// Longest message or exception value accepted by `enforceSizeLimits` (`MAX_MESSAGE_CHARS`)
+    pub max_message_chars: usize,
+    /// Longest tag key or value accepted by `enforceSizeLimits` (`MAX_TAG_CHARS`)
+    pub max_tag_chars: usize,
+    /// Deepest nesting of objects and arrays accepted in an input event (`MAX_EVENT_DEPTH`)
+    pub max_event_depth: usize,
+    /// Most spans accepted in an input event (`MAX_SPANS`)
+    pub max_spans: usize,
+    /// Seconds the full output of a failed request stays retrievable (`ERROR_TTL_SECS`)
+    pub error_ttl_secs: u64,
+    /// How compiled user code is executed, `native` or `wasm` (`EXEC_BACKEND`)
+    pub exec_backend: ExecBackend,
+    /// Compiled binaries kept for reuse by repeated code, 0 to disable (`BUILD_CACHE_ENTRIES`)
+    pub build_cache_entries: usize,
+    /// JSON file saved snippets persist to; in memory only when unset (`SNIPPETS_PATH`)
+    pub snippets_path: Option<PathBuf>,
+    /// JSON file of redaction rules applied to every transformed event (`REDACTION_RULES_PATH`)
+    pub redaction_rules_path: Option<PathBuf>,
+    /// Comma-separated APIs user code may use; any API is allowed when unset (`ALLOWED_APIS`)
+    pub allowed_apis: Option<Vec<String>>,
+    /// Whether requests may run their code under Miri, for trusted deployments (`ALLOW_MIRI`)
+    pub allow_miri: bool,
+    /// Builds after which the process drains and exits for its orchestrator to
+    /// restart it, resetting accumulated build state; 0 to disable (`MAX_COMPILATIONS`)
+    pub max_compilations: u64,
+    /// Base URL of a sibling SDK service `/transform/parity` compares results
+    /// with, e.g. `http://sdk-javascript:5000`; parity checks are disabled
+    /// when unset (`PARITY_SDK_URL`)
+    pub parity_sdk_url: Option<String>,
+    /// Comma-separated hosts `/transform/from-url` may fetch events from;
+    /// fetching is disabled when empty (`FETCH_ALLOWED_HOSTS`)
+    pub fetch_allowed_hosts: Vec<String>,
+    /// Seconds a client has to send the request head (`REQUEST_HEADER_TIMEOUT_SECS`)
+    pub request_header_timeout_secs: u64,
+    /// Seconds a client has to send the request body, 0 to disable (`REQUEST_BODY_TIMEOUT_SECS`)
+    pub request_body_timeout_secs: u64,
+}
+
+impl Config {
+    /// Read the configuration from the environment, falling back to defaults
+    pub fn from_env() -> Self {
+        Config {
+            max_concurrent_builds: env_or("MAX_CONCURRENT_BUILDS", 2).max(1),
+            max_queue_depth: env_or("MAX_QUEUE_DEPTH", 8),
+            queue_retry_after_secs: env_or("QUEUE_RETRY_AFTER_SECS", 5),
+            rate_limit_per_minute: env_or("RATE_LIMIT_PER_MINUTE", 0),
+            rate_limit_burst: env_or("RATE_LIMIT_BURST", 10),
+            trusted_proxy_header: env::var("TRUSTED_PROXY_HEADER")
+                .ok()
+                .filter(|header| !header.trim().is_empty()),
+            min_free_disk_bytes: env_or::<u64>("MIN_FREE_DISK_MB", 512) * 1024 * 1024,
+            cargo_lock_timeout_secs: env_or("CARGO_LOCK_TIMEOUT_SECS", 120),
+            exec_timeout_secs: env_or("EXEC_TIMEOUT_SECS", 10),
+            max_event_bytes: env_or("MAX_EVENT_BYTES", 1024 * 1024),
+            max_message_chars: env_or("MAX_MESSAGE_CHARS", 8192),
+            max_tag_chars: env_or("MAX_TAG_CHARS", 200),
+            max_event_depth: env_or("MAX_EVENT_DEPTH", 128),
+            max_spans: env_or("MAX_SPANS", 1000),
+            error_ttl_secs: env_or("ERROR_TTL_SECS", 600),
+            exec_backend: env_or("EXEC_BACKEND", ExecBackend::Native),
+            build_cache_entries: env_or("BUILD_CACHE_ENTRIES", 64),
+            snippets_path: env::var("SNIPPETS_PATH")
+                .ok()
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+            redaction_rules_path: env::var("REDACTION_RULES_PATH")
+                .ok()
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+            allowed_apis: env::var("ALLOWED_APIS")
+                .ok()
+                .filter(|apis| !apis.trim().is_empty())
+                .map(|apis| {
+                    apis.split(',')
+                        .map(|api| api.trim().to_string())
+                        .filter(|api| !api.is_empty())
+                        .collect()
+                }),
+            allow_miri: env_or("ALLOW_MIRI", false),
+            max_compilations: env_or("MAX_COMPILATIONS", 0),
+            parity_sdk_url: env::var("PARITY_SDK_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty()),
+            fetch_allowed_hosts: env::var("FETCH_ALLOWED_HOSTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            request_header_timeout_secs: env_or("REQUEST_HEADER_TIMEOUT_SECS", 5),
+            request_body_timeout_secs: env_or("REQUEST_BODY_TIMEOUT_SECS", 30),
+            auth_token: env::var("AUTH_TOKEN")
+                .ok()
+                .filter(|token| !token.trim().is_empty()),
+        }
+    }
+}
+
+/// Parse an environment variable, using `default` when unset or invalid
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
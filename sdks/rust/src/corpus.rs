@@ -0,0 +1,129 @@
+//! Curated real-world event shapes, for trying code without pasting an event
+//!
+//! Unlike `templates`, these are fixed events modeled on what SDKs actually
+//! send, anonymized: exceptions from several platforms, chained exceptions,
+//! mobile crashes with device contexts, transactions with spans and
+//! measurements, and an event full of PII for scrubbing code. Each sample
+//! lives in `src/corpus/` and is built into the binary.
+
+use serde::Serialize;
+use serde_json::Value;
+
+/// One event of the corpus
+#[derive(Debug, Serialize)]
+pub struct Sample {
+    /// Id selecting the sample, as `corpusId`
+    pub id: &'static str,
+    /// What the event is and what makes it interesting
+    pub description: &'static str,
+    /// `backend`, `browser`, or `mobile`
+    pub origin: &'static str,
+    #[serde(skip)]
+    json: &'static str,
+}
+
+impl Sample {
+    /// The sample's event
+    pub fn event(&self) -> Value {
+        serde_json::from_str(self.json).expect("corpus samples are valid JSON")
+    }
+}
+
+macro_rules! sample {
+    ($id:literal, $origin:literal, $description:literal) => {
+        Sample {
+            id: $id,
+            description: $description,
+            origin: $origin,
+            json: include_str!(concat!("corpus/", $id, ".json")),
+        }
+    };
+}
+
+/// Every sample, in the order `/corpus` lists them
+pub static SAMPLES: &[Sample] = &[
+    sample!(
+        "python-keyerror",
+        "backend",
+        "Django request failing with an unhandled KeyError, with frame vars, SQL and HTTP breadcrumbs"
+    ),
+    sample!(
+        "javascript-typeerror",
+        "browser",
+        "React TypeError from minified bundles, with navigation, click, and fetch breadcrumbs"
+    ),
+    sample!(
+        "java-chained-exception",
+        "backend",
+        "Spring job crash with a chained exception, threads, extra data, and a custom fingerprint"
+    ),
+    sample!(
+        "php-event-with-pii",
+        "backend",
+        "Laravel error whose message, headers, user, and extra data are full of PII"
+    ),
+    sample!(
+        "go-logentry-message",
+        "backend",
+        "Warning captured as a logentry with a format string and params"
+    ),
+    sample!(
+        "rust-panic",
+        "backend",
+        "Rust panic from an unwrap on None, with native frames"
+    ),
+    sample!(
+        "android-anr",
+        "mobile",
+        "Android background ANR with app, device, and OS contexts and lifecycle breadcrumbs"
+    ),
+    sample!(
+        "cocoa-crash",
+        "mobile",
+        "iOS EXC_BAD_ACCESS crash from a Mach exception, with device and OS contexts"
+    ),
+    sample!(
+        "node-transaction",
+        "backend",
+        "Express transaction with middleware, database, and cache spans"
+    ),
+    sample!(
+        "mobile-app-start-transaction",
+        "mobile",
+        "Android cold start transaction with app start spans and frame measurements"
+    ),
+];
+
+/// The sample with the given id
+pub fn get(id: &str) -> Option<&'static Sample> {
+    SAMPLES.iter().find(|sample| sample.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn samples_are_objects_with_unique_ids() {
+        let ids: HashSet<&str> = SAMPLES.iter().map(|sample| sample.id).collect();
+        assert_eq!(ids.len(), SAMPLES.len());
+        for sample in SAMPLES {
+            assert!(sample.event().is_object(), "{}", sample.id);
+            assert!(
+                matches!(sample.origin, "backend" | "browser" | "mobile"),
+                "{}",
+                sample.id
+            );
+        }
+    }
+
+    #[test]
+    fn samples_are_found_by_id() {
+        assert_eq!(
+            get("rust-panic").map(|sample| sample.origin),
+            Some("backend")
+        );
+        assert!(get("missing").is_none());
+    }
+}
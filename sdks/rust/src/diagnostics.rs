@@ -0,0 +1,223 @@
+//! Structured view of cargo, rustc, and runtime stderr
+//!
+//! Failure tracebacks mix cargo's progress lines (`Compiling`, `Finished`)
+//! and summaries with the diagnostics users care about. Each meaningful line
+//! is classified so clients can show errors without the noise; source
+//! snippets and backtrace frames stay in the raw traceback only.
+
+use serde::Serialize;
+
+/// How a stderr line should be treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Level {
+    /// Cargo progress and summary lines
+    Status,
+    Error,
+    Warning,
+    /// Notes and help attached to a diagnostic
+    Note,
+}
+
+/// One classified line of stderr
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    /// Compiler error code, e.g. `E0308`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+/// Classify captured stderr, in order
+pub fn classify(stderr: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut lines = stderr.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+
+        // `thread 'main' panicked at src/main.rs:1:2:`, message on the lines after
+        if trimmed.starts_with("thread '") && trimmed.contains("panicked at") {
+            let mut message = Vec::new();
+            while let Some(next) = lines.next_if(|next| !is_panic_trailer(next)) {
+                message.push(next.trim());
+            }
+            diagnostics.push(Diagnostic {
+                level: Level::Error,
+                message: if message.is_empty() {
+                    trimmed.to_string()
+                } else {
+                    message.join("\n")
+                },
+                code: None,
+            });
+            continue;
+        }
+
+        if let Some(diagnostic) = classify_line(line) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    diagnostics
+}
+
+/// Lines ending a panic message
+fn is_panic_trailer(line: &str) -> bool {
+    let trimmed = line.trim();
+    trimmed.is_empty() || trimmed.starts_with("note:") || trimmed == "stack backtrace:"
+}
+
+fn classify_line(line: &str) -> Option<Diagnostic> {
+    let trimmed = line.trim();
+    let status = |message: &str| Diagnostic {
+        level: Level::Status,
+        message: message.to_string(),
+        code: None,
+    };
+
+    // Indented `Compiling foo v0.1.0`, `Finished ...`, `Blocking waiting ...`
+    let first_word = trimmed.split(' ').next().unwrap_or_default();
+    if line.starts_with(' ')
+        && first_word.len() > 1
+        && first_word.starts_with(|c: char| c.is_ascii_uppercase())
+        && first_word[1..].chars().all(|c| c.is_ascii_lowercase())
+    {
+        return Some(status(trimmed));
+    }
+
+    if trimmed.starts_with("For more information about") {
+        return Some(Diagnostic {
+            level: Level::Note,
+            message: trimmed.to_string(),
+            code: None,
+        });
+    }
+
+    let note = trimmed.strip_prefix("= ").unwrap_or(trimmed);
+    if let Some(message) = note
+        .strip_prefix("note: ")
+        .or_else(|| note.strip_prefix("help: "))
+    {
+        return Some(Diagnostic {
+            level: Level::Note,
+            message: message.to_string(),
+            code: None,
+        });
+    }
+
+    let (level, rest) = if let Some(rest) = trimmed.strip_prefix("error") {
+        (Level::Error, rest)
+    } else if let Some(rest) = trimmed.strip_prefix("warning") {
+        (Level::Warning, rest)
+    } else {
+        return None;
+    };
+
+    // `error[E0308]: mismatched types`
+    let (code, rest) = match rest.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((code, rest)) => (Some(code.to_string()), rest),
+        None => (None, rest),
+    };
+    let message = rest.strip_prefix(": ")?;
+
+    // Cargo's per-crate summaries of the diagnostics above
+    if message.starts_with("could not compile")
+        || message.starts_with("aborting due to")
+        || (message.contains(" generated ") && message.contains(" warning"))
+    {
+        return Some(status(trimmed));
+    }
+
+    Some(Diagnostic {
+        level,
+        message: message.to_string(),
+        code,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(stderr: &str) -> Vec<(Level, String, Option<String>)> {
+        classify(stderr)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.level, diagnostic.message, diagnostic.code))
+            .collect()
+    }
+
+    #[test]
+    fn cargo_status_lines_are_classified_out_of_the_errors() {
+        let stderr = "   Compiling transform v0.1.0 (/tmp/build)
+warning: unused variable: `x`
+  --> src/main.rs:3:9
+   |
+3  |     let x = 1;
+   |         ^ help: prefix it with an underscore: `_x`
+   = note: `#[warn(unused_variables)]` on by default
+error[E0308]: mismatched types
+error: aborting due to 1 previous error; 1 warning emitted
+For more information about this error, try `rustc --explain E0308`.
+warning: `transform` (bin \"transform\") generated 1 warning
+error: could not compile `transform` (bin \"transform\") due to 1 previous error
+";
+        let status = |message: &str| (Level::Status, message.to_string(), None);
+        assert_eq!(
+            levels(stderr),
+            [
+                status("Compiling transform v0.1.0 (/tmp/build)"),
+                (Level::Warning, "unused variable: `x`".to_string(), None),
+                (
+                    Level::Note,
+                    "`#[warn(unused_variables)]` on by default".to_string(),
+                    None
+                ),
+                (
+                    Level::Error,
+                    "mismatched types".to_string(),
+                    Some("E0308".to_string())
+                ),
+                status("error: aborting due to 1 previous error; 1 warning emitted"),
+                (
+                    Level::Note,
+                    "For more information about this error, try `rustc --explain E0308`."
+                        .to_string(),
+                    None
+                ),
+                status("warning: `transform` (bin \"transform\") generated 1 warning"),
+                status(
+                    "error: could not compile `transform` (bin \"transform\") due to 1 previous error"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn panics_report_the_message_after_the_location() {
+        let stderr = "thread 'main' panicked at src/main.rs:10:5:
+called `Option::unwrap()` on a `None` value
+note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+";
+        assert_eq!(
+            levels(stderr),
+            [
+                (
+                    Level::Error,
+                    "called `Option::unwrap()` on a `None` value".to_string(),
+                    None
+                ),
+                (
+                    Level::Note,
+                    "run with `RUST_BACKTRACE=1` environment variable to display a backtrace"
+                        .to_string(),
+                    None
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Structural diff between two JSON values
+//!
+//! Changes are reported per leaf with a JSON Pointer path, so a client can
+//! show exactly which keys a transform added, removed, or rewrote. Arrays are
+//! compared by index.
+//!
+//! The same comparison can also be expressed as an RFC 6902 JSON Patch, for
+//! clients that apply a transform's changes to their own copy of the event,
+//! or as a compact delta of the values to set and the paths to remove.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// One difference between two values
+///
+/// `before` is absent for added values and `after` for removed ones.
+#[derive(Debug, Serialize)]
+pub struct Change {
+    /// JSON Pointer to the changed value
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<Value>,
+}
+
+/// List the changes turning `before` into `after`, empty if they're equal
+pub fn diff(before: &Value, after: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at(String::new(), Some(before), Some(after), &mut changes);
+    changes
+}
+
+fn diff_at(path: String, before: Option<&Value>, after: Option<&Value>, changes: &mut Vec<Change>) {
+    match (before, after) {
+        (Some(Value::Object(before)), Some(Value::Object(after))) => {
+            for (key, value) in before {
+                diff_at(child_path(&path, key), Some(value), after.get(key), changes);
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    diff_at(child_path(&path, key), None, Some(value), changes);
+                }
+            }
+        }
+        (Some(Value::Array(before)), Some(Value::Array(after))) => {
+            for index in 0..before.len().max(after.len()) {
+                diff_at(
+                    child_path(&path, &index.to_string()),
+                    before.get(index),
+                    after.get(index),
+                    changes,
+                );
+            }
+        }
+        (before, after) if before != after => changes.push(Change {
+            path,
+            before: before.cloned(),
+            after: after.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+/// One RFC 6902 operation
+#[derive(Debug, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+}
+
+/// A JSON Patch turning `before` into `after`, empty if they're equal
+///
+/// Operations apply in order, so array elements past the shorter array's end
+/// are removed from the back and added from the front.
+pub fn json_patch(before: &Value, after: &Value) -> Vec<PatchOperation> {
+    let mut operations = Vec::new();
+    patch_at(String::new(), before, after, &mut operations);
+    operations
+}
+
+fn patch_at(path: String, before: &Value, after: &Value, operations: &mut Vec<PatchOperation>) {
+    match (before, after) {
+        (Value::Object(before), Value::Object(after)) => {
+            for (key, value) in before {
+                match after.get(key) {
+                    Some(after) => patch_at(child_path(&path, key), value, after, operations),
+                    None => operations.push(PatchOperation::Remove {
+                        path: child_path(&path, key),
+                    }),
+                }
+            }
+            for (key, value) in after {
+                if !before.contains_key(key) {
+                    operations.push(PatchOperation::Add {
+                        path: child_path(&path, key),
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(before), Value::Array(after)) => {
+            for (index, (before, after)) in before.iter().zip(after).enumerate() {
+                patch_at(
+                    child_path(&path, &index.to_string()),
+                    before,
+                    after,
+                    operations,
+                );
+            }
+            for index in (after.len()..before.len()).rev() {
+                operations.push(PatchOperation::Remove {
+                    path: child_path(&path, &index.to_string()),
+                });
+            }
+            for (index, value) in after.iter().enumerate().skip(before.len()) {
+                operations.push(PatchOperation::Add {
+                    path: child_path(&path, &index.to_string()),
+                    value: value.clone(),
+                });
+            }
+        }
+        (before, after) if before != after => operations.push(PatchOperation::Replace {
+            path,
+            value: after.clone(),
+        }),
+        _ => {}
+    }
+}
+
+/// The values to set and the paths to remove to turn one value into another
+///
+/// Paths are JSON Pointers. Applying every `remove` in order, then every
+/// `set` in order, reproduces the target; array elements are removed from
+/// the back and set past the end appends them.
+#[derive(Debug, Default, Serialize)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub set: Map<String, Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+/// A delta turning `before` into `after`, empty if they're equal
+pub fn delta(before: &Value, after: &Value) -> Delta {
+    let mut delta = Delta::default();
+    for operation in json_patch(before, after) {
+        match operation {
+            PatchOperation::Add { path, value } | PatchOperation::Replace { path, value } => {
+                delta.set.insert(path, value);
+            }
+            PatchOperation::Remove { path } => delta.remove.push(path),
+        }
+    }
+    delta
+}
+
+/// Append a key to a JSON Pointer, escaping `~` and `/`
+fn child_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn changes(before: Value, after: Value) -> Value {
+        serde_json::to_value(diff(&before, &after)).unwrap()
+    }
+
+    #[test]
+    fn equal_values_have_no_changes() {
+        let event = json!({ "tags": { "a": 1 }, "list": [1, 2] });
+        assert_eq!(changes(event.clone(), event), json!([]));
+    }
+
+    #[test]
+    fn changes_point_at_added_removed_and_replaced_values() {
+        assert_eq!(
+            changes(
+                json!({ "level": "error", "user": { "id": 1, "ip": "::1" }, "list": [1] }),
+                json!({ "level": "info", "user": { "id": 1 }, "list": [1, 2], "a/b~": true })
+            ),
+            json!([
+                { "path": "/level", "before": "error", "after": "info" },
+                { "path": "/user/ip", "before": "::1" },
+                { "path": "/list/1", "after": 2 },
+                { "path": "/a~1b~0", "after": true }
+            ])
+        );
+    }
+
+    /// Apply RFC 6902 operations the way a client would
+    fn apply_patch(mut value: Value, patch: &[PatchOperation]) -> Value {
+        for operation in serde_json::to_value(patch).unwrap().as_array().unwrap() {
+            let path = operation["path"].as_str().unwrap();
+            let (parent, key) = path.rsplit_once('/').unwrap();
+            let key = key.replace("~1", "/").replace("~0", "~");
+            let parent = value.pointer_mut(parent).unwrap();
+            let new_value = operation.get("value").cloned();
+            match (operation["op"].as_str().unwrap(), parent) {
+                ("remove", Value::Object(map)) => {
+                    map.shift_remove(&key).unwrap();
+                }
+                ("remove", Value::Array(items)) => {
+                    items.remove(key.parse().unwrap());
+                }
+                ("add", Value::Array(items)) => {
+                    items.insert(key.parse().unwrap(), new_value.unwrap())
+                }
+                ("add" | "replace", Value::Object(map)) => {
+                    map.insert(key, new_value.unwrap());
+                }
+                ("replace", Value::Array(items)) => {
+                    items[key.parse::<usize>().unwrap()] = new_value.unwrap();
+                }
+                (op, parent) => panic!("can't apply {} at {} in {}", op, path, parent),
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn json_patches_reproduce_the_target() {
+        let cases = [
+            (
+                json!({ "level": "error", "user": { "id": 1, "ip": "::1" }, "tags": ["a", "b", "c"] }),
+                json!({ "level": "info", "user": { "id": 1 }, "tags": ["a"], "a/b~": { "x": [] } }),
+            ),
+            (
+                json!({ "list": [1], "nested": [[1, 2], { "k": null }] }),
+                json!({ "list": [2, 3, 4], "nested": [[2], { "k": "v" }, 5] }),
+            ),
+            (
+                json!({ "same": [1, { "a": 1 }] }),
+                json!({ "same": [1, { "a": 1 }] }),
+            ),
+        ];
+        for (before, after) in cases {
+            let patch = json_patch(&before, &after);
+            assert_eq!(apply_patch(before, &patch), after, "{:?}", patch);
+        }
+    }
+
+    #[test]
+    fn json_patch_operations_are_minimal_and_ordered() {
+        let patch = json_patch(
+            &json!({ "level": "error", "list": [1, 2, 3], "gone": true }),
+            &json!({ "level": "info", "list": [1], "new": 1 }),
+        );
+        assert_eq!(
+            serde_json::to_value(patch).unwrap(),
+            json!([
+                { "op": "replace", "path": "/level", "value": "info" },
+                { "op": "remove", "path": "/list/2" },
+                { "op": "remove", "path": "/list/1" },
+                { "op": "remove", "path": "/gone" },
+                { "op": "add", "path": "/new", "value": 1 }
+            ])
+        );
+    }
+
+    #[test]
+    fn deltas_split_a_patch_into_sets_and_removals() {
+        let delta = delta(
+            &json!({ "level": "error", "user": { "ip": "::1" } }),
+            &json!({ "level": "info", "user": {}, "tags": { "a": "b" } }),
+        );
+        assert_eq!(
+            serde_json::to_value(delta).unwrap(),
+            json!({ "set": { "/level": "info", "/tags": { "a": "b" } }, "remove": ["/user/ip"] })
+        );
+    }
+}
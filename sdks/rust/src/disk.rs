@@ -0,0 +1,59 @@
+//! Free disk space checks for the build volume
+//!
+//! Every transform compiles into a fresh target directory under the system
+//! temp dir. Once that filesystem fills up, cargo fails with confusing I/O
+//! errors, so builds are refused up front when space runs low.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// Bytes available to unprivileged users on the filesystem containing `path`
+pub fn available_bytes(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is a valid NUL-terminated string and `stats` is a
+    // properly sized, writable statvfs struct
+    let result = unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Check that the build volume has at least `min_free_bytes` available
+///
+/// Returns the number of free bytes, or an error describing the shortfall.
+pub fn check_build_space(min_free_bytes: u64) -> Result<u64, String> {
+    let free = available_bytes(&std::env::temp_dir())
+        .map_err(|e| format!("Failed to check free disk space: {}", e))?;
+
+    if free < min_free_bytes {
+        Err(format!(
+            "{} MiB free, {} MiB required",
+            free / (1024 * 1024),
+            min_free_bytes / (1024 * 1024)
+        ))
+    } else {
+        Ok(free)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_space_is_compared_against_the_minimum() {
+        let free = check_build_space(0).expect("any space satisfies a zero minimum");
+        assert!(free > 0);
+
+        let error = check_build_space(u64::MAX).unwrap_err();
+        assert!(error.ends_with(" MiB required"), "{}", error);
+    }
+}
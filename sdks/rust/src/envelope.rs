@@ -0,0 +1,112 @@
+//! Sentry envelopes wrapping transformed events
+//!
+//! An envelope is what SDKs POST to Sentry's `/api/{project}/envelope/`
+//! endpoint: newline-separated JSON, starting with an envelope header and
+//! followed by items, each a header and its payload. Only the minimal form is
+//! built here, with a single `event` item.
+
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Wrap an event in an envelope, sent now
+///
+/// The envelope's `event_id` is the event's own if it has one, or a new
+/// random id otherwise; the event itself isn't modified.
+pub fn wrap(event: &Value) -> String {
+    let event_id = event
+        .get("event_id")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let payload = event.to_string();
+
+    let header = json!({ "event_id": event_id, "sent_at": rfc3339_now() });
+    let item_header = json!({ "type": "event", "length": payload.len() });
+    format!("{}\n{}\n{}\n", header, item_header, payload)
+}
+
+/// The current UTC time as an RFC 3339 timestamp with millisecond precision
+fn rfc3339_now() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (year, month, day) = civil_date(secs / 86_400);
+    let time = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        now.subsec_millis()
+    )
+}
+
+/// Year, month, and day of a count of days since 1970-01-01
+///
+/// Howard Hinnant's `civil_from_days`, for dates after the epoch.
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_carry_the_event_id_and_payload_length() {
+        let event = json!({ "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0", "message": "héllo" });
+        let envelope = wrap(&event);
+        let lines: Vec<&str> = envelope.strip_suffix('\n').unwrap().split('\n').collect();
+        assert_eq!(lines.len(), 3, "{}", envelope);
+
+        let header: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(header["event_id"], "fc6d8c0c43fc4630ad850ee518f1b9d0");
+        let item_header: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(item_header["type"], "event");
+        // The length counts bytes, not characters
+        assert_eq!(item_header["length"], lines[2].len());
+        assert_eq!(serde_json::from_str::<Value>(lines[2]).unwrap(), event);
+    }
+
+    #[test]
+    fn events_without_an_id_get_a_new_one() {
+        let envelope = wrap(&json!({}));
+        let header: Value = serde_json::from_str(envelope.lines().next().unwrap()).unwrap();
+        let event_id = header["event_id"].as_str().unwrap();
+        assert_eq!(event_id.len(), 32);
+        assert!(event_id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn civil_dates_count_from_the_epoch() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(20_818), (2026, 12, 31));
+    }
+
+    #[test]
+    fn sent_at_is_an_rfc3339_utc_timestamp() {
+        let now = rfc3339_now();
+        assert_eq!(now.len(), "2024-01-01T00:00:00.000Z".len(), "{}", now);
+        assert!(now.ends_with('Z') && now.as_bytes()[10] == b'T', "{}", now);
+    }
+}
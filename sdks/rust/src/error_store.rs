@@ -0,0 +1,93 @@
+//! Short-lived storage for the full output of failed requests
+//!
+//! Failures are stored under a random id returned as `errorId`, so clients
+//! can fetch the complete compiler or runtime output from `GET /errors/{id}`
+//! without every response carrying it. Entries expire after a fixed TTL.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries kept before the oldest are evicted, regardless of TTL
+const MAX_STORED_ERRORS: usize = 1000;
+
+/// The full output of a failed request
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredError {
+    pub id: String,
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub traceback: Option<String>,
+}
+
+/// TTL cache of failures keyed by error id
+pub struct ErrorStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, StoredError)>>,
+}
+
+impl ErrorStore {
+    pub fn new(ttl: Duration) -> Self {
+        ErrorStore {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Store a failure and return its id
+    pub fn insert(&self, error: String, traceback: Option<String>) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+
+        entries.retain(|_, (stored_at, _)| now.duration_since(*stored_at) < self.ttl);
+        if entries.len() >= MAX_STORED_ERRORS {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (stored_at, _))| *stored_at)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+
+        let stored = StoredError {
+            id: id.clone(),
+            error,
+            traceback,
+        };
+        entries.insert(id.clone(), (now, stored));
+        id
+    }
+
+    /// Look up a failure that hasn't expired yet
+    pub fn get(&self, id: &str) -> Option<StoredError> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(id)
+            .filter(|(stored_at, _)| stored_at.elapsed() < self.ttl)
+            .map(|(_, stored)| stored.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_found_until_they_expire() {
+        let store = ErrorStore::new(Duration::from_secs(60));
+        let id = store.insert("failed".to_string(), Some("output".to_string()));
+        let stored = store.get(&id).unwrap();
+        assert_eq!(
+            (stored.error.as_str(), stored.traceback.as_deref()),
+            ("failed", Some("output"))
+        );
+        assert!(store.get("unknown").is_none());
+
+        let expired = ErrorStore::new(Duration::ZERO);
+        let id = expired.insert("failed".to_string(), None);
+        assert!(expired.get(&id).is_none());
+    }
+}
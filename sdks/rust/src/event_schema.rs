@@ -0,0 +1,390 @@
+//! Typed check that a transformed event is still shaped like a Sentry event
+//!
+//! `Value` accepts anything, so a transform can return an event Sentry would
+//! reject or silently mangle, like a numeric `level`. The result is
+//! deserialized into a typed [`SentryEvent`] mirroring the event protocol;
+//! unknown fields and nulls are accepted, as Sentry accepts them.
+//!
+//! Each well-known field keeps its own error instead of failing the whole
+//! event at the first one, so every invalid field is reported. The struct is
+//! compiled into the service rather than into a build per request: it
+//! doesn't depend on the user code, so only the result needs checking.
+
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Deserialize `event` as a [`SentryEvent`], returning one error per invalid field
+pub fn check(event: &Value) -> Vec<String> {
+    if !event.is_object() {
+        return vec!["the event must be a JSON object".to_string()];
+    }
+    match SentryEvent::deserialize(event) {
+        Ok(event) => event.errors(),
+        Err(e) => vec![e.to_string()],
+    }
+}
+
+/// The well-known fields of a Sentry event
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct SentryEvent {
+    event_id: Field<EventId>,
+    timestamp: Field<Timestamp>,
+    start_timestamp: Field<Timestamp>,
+    level: Field<Level>,
+    platform: Field<String>,
+    logger: Field<String>,
+    transaction: Field<String>,
+    server_name: Field<String>,
+    release: Field<String>,
+    dist: Field<String>,
+    environment: Field<String>,
+    message: Field<Message>,
+    logentry: Field<LogEntry>,
+    tags: Field<StringPairs>,
+    extra: Field<Map<String, Value>>,
+    contexts: Field<BTreeMap<String, Map<String, Value>>>,
+    user: Field<User>,
+    exception: Field<Values<Exception>>,
+    breadcrumbs: Field<Values<Breadcrumb>>,
+    fingerprint: Field<Vec<String>>,
+    request: Field<Request>,
+    sdk: Field<Sdk>,
+    modules: Field<BTreeMap<String, String>>,
+}
+
+impl SentryEvent {
+    /// The fields that didn't match their type, as `` `field`: error ``
+    fn errors(&self) -> Vec<String> {
+        [
+            ("event_id", self.event_id.error()),
+            ("timestamp", self.timestamp.error()),
+            ("start_timestamp", self.start_timestamp.error()),
+            ("level", self.level.error()),
+            ("platform", self.platform.error()),
+            ("logger", self.logger.error()),
+            ("transaction", self.transaction.error()),
+            ("server_name", self.server_name.error()),
+            ("release", self.release.error()),
+            ("dist", self.dist.error()),
+            ("environment", self.environment.error()),
+            ("message", self.message.error()),
+            ("logentry", self.logentry.error()),
+            ("tags", self.tags.error()),
+            ("extra", self.extra.error()),
+            ("contexts", self.contexts.error()),
+            ("user", self.user.error()),
+            ("exception", self.exception.error()),
+            ("breadcrumbs", self.breadcrumbs.error()),
+            ("fingerprint", self.fingerprint.error()),
+            ("request", self.request.error()),
+            ("sdk", self.sdk.error()),
+            ("modules", self.modules.error()),
+        ]
+        .into_iter()
+        .filter_map(|(key, error)| error.map(|e| format!("`{}`: {}", key, e)))
+        .collect()
+    }
+}
+
+/// A field deserialized on its own, keeping its error instead of failing the event
+#[derive(Default)]
+#[allow(dead_code)]
+enum Field<T> {
+    /// Missing or null
+    #[default]
+    Absent,
+    Valid(T),
+    Invalid(String),
+}
+
+impl<T> Field<T> {
+    fn error(&self) -> Option<&str> {
+        match self {
+            Field::Invalid(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Field<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        if value.is_null() {
+            return Ok(Field::Absent);
+        }
+        Ok(match T::deserialize(&value) {
+            Ok(field) => Field::Valid(field),
+            Err(e) => Field::Invalid(e.to_string()),
+        })
+    }
+}
+
+// The structs below are only deserialized to check their fields' types
+
+/// 32 hex characters, optionally formatted as a hyphenated UUID
+#[derive(Deserialize)]
+#[serde(try_from = "String")]
+struct EventId;
+
+impl TryFrom<String> for EventId {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, String> {
+        let hex: String = id.chars().filter(|&c| c != '-').collect();
+        if hex.len() == 32 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            Ok(EventId)
+        } else {
+            Err(format!("expected 32 hex characters, found {:?}", id))
+        }
+    }
+}
+
+/// Seconds since the epoch, or an RFC 3339 string
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a number of seconds or an RFC 3339 string")]
+#[allow(dead_code)]
+enum Timestamp {
+    Seconds(serde_json::Number),
+    Rfc3339(String),
+}
+
+/// One of Sentry's level names
+#[derive(Deserialize)]
+#[serde(try_from = "Value")]
+struct Level;
+
+impl TryFrom<Value> for Level {
+    type Error = String;
+
+    fn try_from(level: Value) -> Result<Self, String> {
+        match level.as_str() {
+            Some("fatal" | "error" | "warning" | "info" | "debug") => Ok(Level),
+            Some(other) => Err(format!(
+                "unknown level {:?}, expected fatal, error, warning, info, or debug",
+                other
+            )),
+            None => Err(format!("expected a level string, found {}", level)),
+        }
+    }
+}
+
+/// A formatted string, or a log entry as in `logentry`
+struct Message;
+
+impl<'de> Deserialize<'de> for Message {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = Value::deserialize(deserializer)?;
+        if !message.is_string() {
+            LogEntry::deserialize(message).map_err(de::Error::custom)?;
+        }
+        Ok(Message)
+    }
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct LogEntry {
+    formatted: Option<String>,
+    message: Option<String>,
+    params: Option<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct User {
+    id: Option<UserId>,
+    email: Option<String>,
+    ip_address: Option<String>,
+    username: Option<String>,
+    name: Option<String>,
+    segment: Option<String>,
+    geo: Option<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "a string or number")]
+#[allow(dead_code)]
+enum UserId {
+    String(String),
+    Number(serde_json::Number),
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Exception {
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    value: Option<String>,
+    module: Option<String>,
+    thread_id: Option<UserId>,
+    mechanism: Option<Mechanism>,
+    stacktrace: Option<Stacktrace>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Mechanism {
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    handled: Option<bool>,
+    synthetic: Option<bool>,
+    data: Option<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Stacktrace {
+    frames: Option<Vec<Frame>>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Frame {
+    filename: Option<String>,
+    abs_path: Option<String>,
+    function: Option<String>,
+    module: Option<String>,
+    package: Option<String>,
+    platform: Option<String>,
+    lineno: Option<u64>,
+    colno: Option<u64>,
+    in_app: Option<bool>,
+    context_line: Option<String>,
+    pre_context: Option<Vec<String>>,
+    post_context: Option<Vec<String>>,
+    vars: Option<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Breadcrumb {
+    timestamp: Option<Timestamp>,
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    category: Option<String>,
+    message: Option<String>,
+    level: Option<Level>,
+    data: Option<Map<String, Value>>,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Request {
+    url: Option<String>,
+    method: Option<String>,
+    headers: Option<StringPairs>,
+    env: Option<Map<String, Value>>,
+}
+
+/// An object of strings or a list of `[name, value]` pairs, as tags and
+/// headers may be sent
+struct StringPairs;
+
+impl<'de> Deserialize<'de> for StringPairs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pairs = Value::deserialize(deserializer)?;
+        let checked = if pairs.is_object() {
+            BTreeMap::<String, String>::deserialize(pairs).map(|_| ())
+        } else {
+            Vec::<(String, String)>::deserialize(pairs).map(|_| ())
+        };
+        checked.map(|()| StringPairs).map_err(de::Error::custom)
+    }
+}
+
+/// A list sent bare or as `{ "values": [...] }`
+#[allow(dead_code)]
+struct Values<T>(Vec<T>);
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Values<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = Value::deserialize(deserializer)?;
+        let list = values.get_mut("values").map(Value::take).unwrap_or(values);
+        Vec::deserialize(list)
+            .map(Values)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct Sdk {
+    name: String,
+    version: String,
+    integrations: Option<Vec<String>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn well_formed_events_pass() {
+        let event = json!({
+            "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0",
+            "timestamp": 1700000000.5,
+            "start_timestamp": "2023-11-14T22:13:20Z",
+            "level": "warning",
+            "message": "hi",
+            "tags": { "env": "prod" },
+            "user": { "id": 42, "email": "a@example.com" },
+            "exception": { "values": [{ "type": "Error", "stacktrace": { "frames": [{ "lineno": 3 }] } }] },
+            "breadcrumbs": [{ "timestamp": 1700000000, "level": "info" }],
+            "release": null,
+            "custom": [1, 2]
+        });
+        assert_eq!(check(&event), Vec::<String>::new());
+    }
+
+    #[test]
+    fn mistyped_fields_are_reported_one_per_field() {
+        let errors = check(&json!({
+            "level": 40,
+            "event_id": "not-an-id",
+            "tags": { "env": 1 },
+            "fingerprint": "{{ default }}"
+        }));
+        // In the order `SentryEvent` declares its fields
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("`event_id`: expected 32 hex characters"));
+        assert_eq!(errors[1], "`level`: expected a level string, found 40");
+        assert!(errors[2].starts_with("`tags`: "));
+        assert!(errors[3].starts_with("`fingerprint`: "));
+
+        assert_eq!(check(&json!([])), ["the event must be a JSON object"]);
+    }
+
+    #[test]
+    fn mistyped_tags_and_contexts_are_reported() {
+        for tags in [
+            json!("env:prod"),
+            json!(["env", "prod"]),
+            json!([["env", 1]]),
+        ] {
+            let errors = check(&json!({ "tags": tags }));
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert!(errors[0].starts_with("`tags`: "), "{:?}", errors);
+        }
+        assert_eq!(
+            check(&json!({ "tags": [["env", "prod"]] })),
+            Vec::<String>::new()
+        );
+
+        for contexts in [json!("os"), json!({ "os": "linux" }), json!([{ "os": {} }])] {
+            let errors = check(&json!({ "contexts": contexts }));
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert!(errors[0].starts_with("`contexts`: "), "{:?}", errors);
+        }
+    }
+
+    #[test]
+    fn timestamps_accept_any_number_of_seconds() {
+        // Written with more digits than an `f64` keeps, as from the JSON parser
+        let event: Value = serde_json::from_str(r#"{ "timestamp": 1700000000.10 }"#).unwrap();
+        assert_eq!(check(&event), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,191 @@
+//! Which top-level keys of the event user code reads and writes
+//!
+//! With `reportFootprint`, the code's input is bound as a `RecordedEvent`
+//! instead of a `Value`, and both are recorded as the code runs rather than
+//! guessed from its source or from the result.
+//!
+//! Indexing it, `get`, and `pointer` record the top-level key they read, and
+//! `IndexMut`, `get_mut`, and `pointer_mut` the key they write, whether the
+//! key is a literal or computed, and through any alias. Anything reaching the
+//! whole `Value` through `Deref` (`as_object`, `is_null`, serializing it, or
+//! passing it as `&Value`) records every key it has at that point as read.
+//! `DerefMut` (`as_object_mut`, `take`, or passing it as `&mut Value`) records
+//! every key as written, both when it is taken and once the code is done with
+//! the input, so keys it adds are included. A write counts even if it leaves
+//! the value as it was.
+//!
+//! Returning the binding hands back the `Value` inside without a read;
+//! `into_inner` does the same where a plain `Value` is needed.
+
+/// Input binding rendered into the wrapper when `record_footprint` is set
+///
+/// The wrapper reports `ACCESSED_KEYS` as `accessedPaths` and `MUTATED_KEYS`
+/// as `mutatedPaths`, sorted.
+pub const RECORDED_EVENT: &str = r#"
+/// Top-level keys of the input the code read, reported as `accessedPaths`
+static ACCESSED_KEYS: std::sync::Mutex<std::collections::BTreeSet<String>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Top-level keys of the input the code wrote, reported as `mutatedPaths`
+static MUTATED_KEYS: std::sync::Mutex<std::collections::BTreeSet<String>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+type RecordedKeys = std::sync::Mutex<std::collections::BTreeSet<String>>;
+
+fn record(keys: &RecordedKeys, key: &str) {
+    keys.lock().unwrap().insert(key.to_string());
+}
+
+fn record_all(keys: &RecordedKeys, value: &Value) {
+    if let Some(object) = value.as_object() {
+        keys.lock().unwrap().extend(object.keys().cloned());
+    }
+}
+
+/// Record the top-level key a JSON pointer starts at
+fn record_pointer(keys: &RecordedKeys, value: &Value, pointer: &str) {
+    match pointer.strip_prefix('/') {
+        Some(path) => {
+            let key = path.split('/').next().unwrap_or_default();
+            record(keys, &key.replace("~1", "/").replace("~0", "~"));
+        }
+        // "" points at the whole input; anything else isn't a pointer
+        None if pointer.is_empty() => record_all(keys, value),
+        None => {}
+    }
+}
+
+/// The input, recording which of its top-level keys the code reads and writes
+struct RecordedEvent {
+    value: Value,
+    /// Whether the code took `&mut` to the whole input, so the keys it wrote
+    /// are only all known once it is done with the input
+    written_whole: bool,
+}
+
+#[allow(dead_code)]
+impl RecordedEvent {
+    fn new(value: Value) -> Self {
+        RecordedEvent {
+            value,
+            written_whole: false,
+        }
+    }
+
+    fn get(&self, key: impl AsRef<str>) -> Option<&Value> {
+        record(&ACCESSED_KEYS, key.as_ref());
+        self.value.get(key.as_ref())
+    }
+
+    fn get_mut(&mut self, key: impl AsRef<str>) -> Option<&mut Value> {
+        record(&MUTATED_KEYS, key.as_ref());
+        self.value.get_mut(key.as_ref())
+    }
+
+    fn pointer(&self, pointer: &str) -> Option<&Value> {
+        record_pointer(&ACCESSED_KEYS, &self.value, pointer);
+        self.value.pointer(pointer)
+    }
+
+    fn pointer_mut(&mut self, pointer: &str) -> Option<&mut Value> {
+        record_pointer(&MUTATED_KEYS, &self.value, pointer);
+        self.written_whole |= pointer.is_empty();
+        self.value.pointer_mut(pointer)
+    }
+
+    /// The input as a plain `Value`, without recording a read
+    fn into_inner(mut self) -> Value {
+        self.record_whole_write();
+        std::mem::take(&mut self.value)
+    }
+
+    /// Record every key left after a write through `&mut Value`, once
+    fn record_whole_write(&mut self) {
+        if std::mem::take(&mut self.written_whole) {
+            record_all(&MUTATED_KEYS, &self.value);
+        }
+    }
+}
+
+impl Drop for RecordedEvent {
+    fn drop(&mut self) {
+        self.record_whole_write();
+    }
+}
+
+impl<K: AsRef<str>> std::ops::Index<K> for RecordedEvent {
+    type Output = Value;
+
+    fn index(&self, key: K) -> &Value {
+        record(&ACCESSED_KEYS, key.as_ref());
+        &self.value[key.as_ref()]
+    }
+}
+
+impl<K: AsRef<str>> std::ops::IndexMut<K> for RecordedEvent {
+    fn index_mut(&mut self, key: K) -> &mut Value {
+        record(&MUTATED_KEYS, key.as_ref());
+        &mut self.value[key.as_ref()]
+    }
+}
+
+impl std::ops::Deref for RecordedEvent {
+    type Target = Value;
+
+    fn deref(&self) -> &Value {
+        record_all(&ACCESSED_KEYS, &self.value);
+        &self.value
+    }
+}
+
+impl std::ops::DerefMut for RecordedEvent {
+    fn deref_mut(&mut self) -> &mut Value {
+        record_all(&MUTATED_KEYS, &self.value);
+        self.written_whole = true;
+        &mut self.value
+    }
+}
+
+impl Clone for RecordedEvent {
+    fn clone(&self) -> Self {
+        record_all(&ACCESSED_KEYS, &self.value);
+        RecordedEvent::new(self.value.clone())
+    }
+}
+
+impl std::fmt::Debug for RecordedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl std::fmt::Display for RecordedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl serde::Serialize for RecordedEvent {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde::Serialize::serialize(&**self, serializer)
+    }
+}
+
+impl From<RecordedEvent> for Value {
+    fn from(event: RecordedEvent) -> Self {
+        event.into_inner()
+    }
+}
+
+impl From<Option<RecordedEvent>> for TransformResult {
+    fn from(v: Option<RecordedEvent>) -> Self {
+        TransformResult::Event(v.map(RecordedEvent::into_inner))
+    }
+}
+
+impl From<RecordedEvent> for TransformResult {
+    fn from(v: RecordedEvent) -> Self {
+        TransformResult::Event(Some(v.into_inner()))
+    }
+}
+"#;
@@ -0,0 +1,194 @@
+//! Random mutations of an event, for finding inputs that make transforms fail
+//!
+//! Each fuzzed input is the original event with a few values removed, nulled,
+//! emptied, or replaced by a value of another type: the shapes that trip up
+//! `unwrap()` on real-world events. Mutations are seeded so a run can be
+//! reproduced.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// Small deterministic generator (SplitMix64), good enough for picking mutations
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, for `n > 0`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// How a mutation changes the value it targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MutationKind {
+    /// Delete the key or array element
+    Remove,
+    /// Replace the value with `null`
+    Null,
+    /// Replace the value with an empty one of the same type
+    Empty,
+    /// Replace the value with one of a different type
+    Retype,
+}
+
+const MUTATION_KINDS: &[MutationKind] = &[
+    MutationKind::Remove,
+    MutationKind::Null,
+    MutationKind::Empty,
+    MutationKind::Retype,
+];
+
+/// One change to the original event
+#[derive(Debug, Clone, Serialize)]
+pub struct Mutation {
+    /// JSON Pointer to the mutated value in the original event
+    pub pointer: String,
+    pub kind: MutationKind,
+}
+
+/// Most mutations combined into one fuzzed input
+const MAX_MUTATIONS: usize = 3;
+
+/// Pick a few random mutations of `event`, none if it has no nested values
+pub fn random_mutations(event: &Value, rng: &mut Rng) -> Vec<Mutation> {
+    let mut pointers = Vec::new();
+    collect_pointers(event, String::new(), &mut pointers);
+    if pointers.is_empty() {
+        return Vec::new();
+    }
+
+    (0..=rng.below(MAX_MUTATIONS))
+        .map(|_| Mutation {
+            pointer: pointers[rng.below(pointers.len())].clone(),
+            kind: MUTATION_KINDS[rng.below(MUTATION_KINDS.len())],
+        })
+        .collect()
+}
+
+fn collect_pointers(value: &Value, path: String, pointers: &mut Vec<String>) {
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), value))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (i.to_string(), item))
+            .collect(),
+        _ => return,
+    };
+
+    for (key, child) in children {
+        let child_path = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+        pointers.push(child_path.clone());
+        collect_pointers(child, child_path, pointers);
+    }
+}
+
+/// Apply mutations to a copy of `event`, in order
+///
+/// A mutation whose target an earlier one already removed is skipped.
+pub fn apply(event: &Value, mutations: &[Mutation]) -> Value {
+    let mut event = event.clone();
+    for mutation in mutations {
+        if mutation.kind == MutationKind::Remove {
+            remove(&mut event, &mutation.pointer);
+        } else if let Some(value) = event.pointer_mut(&mutation.pointer) {
+            *value = replacement(value, mutation.kind);
+        }
+    }
+    event
+}
+
+fn remove(event: &mut Value, pointer: &str) {
+    let Some((parent, key)) = pointer.rsplit_once('/') else {
+        return;
+    };
+    let key = key.replace("~1", "/").replace("~0", "~");
+    match event.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.shift_remove(&key);
+        }
+        Some(Value::Array(items)) => {
+            if let Some(index) = key.parse::<usize>().ok().filter(|&i| i < items.len()) {
+                items.remove(index);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn replacement(value: &Value, kind: MutationKind) -> Value {
+    match (kind, value) {
+        (MutationKind::Empty, Value::String(_)) => Value::String(String::new()),
+        (MutationKind::Empty, Value::Array(_)) => Value::Array(Vec::new()),
+        (MutationKind::Empty, Value::Object(_)) => Value::Object(Map::new()),
+        (MutationKind::Empty, Value::Number(_)) => Value::from(0),
+        (MutationKind::Empty, Value::Bool(_)) => Value::Bool(false),
+        (MutationKind::Retype, Value::String(_)) => Value::from(0),
+        (MutationKind::Retype, Value::Number(n)) => Value::String(n.to_string()),
+        (MutationKind::Retype, Value::Bool(b)) => Value::String(b.to_string()),
+        (MutationKind::Retype, Value::Null) => Value::Object(Map::new()),
+        (MutationKind::Retype, _) => Value::String(String::new()),
+        _ => Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mutation(pointer: &str, kind: MutationKind) -> Mutation {
+        Mutation {
+            pointer: pointer.to_string(),
+            kind,
+        }
+    }
+
+    #[test]
+    fn mutations_are_reproducible_from_the_seed() {
+        let event = json!({ "user": { "id": 1 }, "tags": ["a", "b"], "message": "hi" });
+        let pick = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..10)
+                .map(|_| random_mutations(&event, &mut rng))
+                .map(|mutations| json!(mutations))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pick(7), pick(7));
+        assert_ne!(pick(7), pick(8));
+        assert!(random_mutations(&json!({}), &mut Rng::new(7)).is_empty());
+    }
+
+    #[test]
+    fn mutations_apply_in_order_to_a_copy() {
+        let event = json!({ "user": { "id": 1, "name": "a" }, "tags": ["a", "b"], "n": 2 });
+        let mutated = apply(
+            &event,
+            &[
+                mutation("/user", MutationKind::Remove),
+                // Already removed with its parent
+                mutation("/user/id", MutationKind::Null),
+                mutation("/tags/0", MutationKind::Empty),
+                mutation("/n", MutationKind::Retype),
+            ],
+        );
+        assert_eq!(mutated, json!({ "tags": ["", "b"], "n": "2" }));
+        assert_eq!(event["user"]["id"], 1);
+    }
+}
@@ -0,0 +1,209 @@
+//! A stable grouping hash for an event, approximating Sentry's fingerprinting
+//!
+//! The hash is taken over a list of string components:
+//!
+//! - An explicit `fingerprint` array is used as given, with `{{ default }}`
+//!   expanded to the default components, `{{ type }}` and `{{ value }}` to
+//!   the top exception's type and value, and `{{ message }}`,
+//!   `{{ transaction }}`, and `{{ level }}` to those fields.
+//! - Otherwise, errors group by the type and value of every exception in the
+//!   chain, then events by their message (the template before formatting),
+//!   then transactions by their name.
+//!
+//! Components are joined with NUL bytes and hashed with SHA-256, of which the
+//! first 32 hex digits are kept, the length of Sentry's own hashes. Sentry
+//! also groups by stack trace and normalizes values server-side, so the hash
+//! is for comparing results here, not for predicting Sentry's issue ids.
+
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// What the components of a grouping hash came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    /// The event's own `fingerprint` array
+    Fingerprint,
+    /// Exception types and values
+    Exception,
+    /// The event's message
+    Message,
+    /// The transaction name
+    Transaction,
+}
+
+/// Grouping hash of an event object and the rule behind it, or `None` when
+/// there is nothing to group by
+pub fn hash(event: &Value) -> Option<(String, Rule)> {
+    let (components, rule) = match event["fingerprint"].as_array() {
+        Some(entries) if !entries.is_empty() => (
+            entries
+                .iter()
+                .flat_map(|entry| expand(event, entry))
+                .collect(),
+            Rule::Fingerprint,
+        ),
+        _ => default_components(event)?,
+    };
+
+    let digest = Sha256::digest(components.join("\0").as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some((hex[..32].to_string(), rule))
+}
+
+/// Components a `fingerprint` entry stands for
+fn expand(event: &Value, entry: &Value) -> Vec<String> {
+    let entry = match entry {
+        Value::String(entry) => entry.clone(),
+        other => other.to_string(),
+    };
+    let variable = entry
+        .strip_prefix("{{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+        .map(str::trim);
+
+    let field = match variable {
+        Some("default") => {
+            return default_components(event)
+                .map(|(components, _)| components)
+                .unwrap_or_default()
+        }
+        Some("type") => top_exception(event).and_then(|exception| text(&exception["type"])),
+        Some("value") => top_exception(event).and_then(|exception| text(&exception["value"])),
+        Some("message") => message(event),
+        Some("transaction") => text(&event["transaction"]),
+        Some("level") => text(&event["level"]),
+        _ => return vec![entry],
+    };
+    // A missing field still takes a slot, so it can't merge with the next one
+    vec![field.unwrap_or_default()]
+}
+
+/// Components Sentry groups by when an event sets no fingerprint
+fn default_components(event: &Value) -> Option<(Vec<String>, Rule)> {
+    let exceptions: Vec<String> = exceptions(event)
+        .iter()
+        .flat_map(|exception| [text(&exception["type"]), text(&exception["value"])])
+        .flatten()
+        .collect();
+    if !exceptions.is_empty() {
+        return Some((exceptions, Rule::Exception));
+    }
+    if let Some(message) = message(event) {
+        return Some((vec![message], Rule::Message));
+    }
+    text(&event["transaction"]).map(|name| (vec![name], Rule::Transaction))
+}
+
+fn exceptions(event: &Value) -> &[Value] {
+    event
+        .get("exception")
+        .map(|exception| exception.get("values").unwrap_or(exception))
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// The exception that was raised; earlier ones in the chain are its causes
+fn top_exception(event: &Value) -> Option<&Value> {
+    exceptions(event).last()
+}
+
+/// The message template, so events differing only in parameters group together
+fn message(event: &Value) -> Option<String> {
+    [
+        &event["logentry"]["message"],
+        &event["logentry"]["formatted"],
+        &event["message"]["message"],
+        &event["message"]["formatted"],
+        &event["message"],
+    ]
+    .into_iter()
+    .find_map(text)
+}
+
+/// A non-blank string value
+fn text(value: &Value) -> Option<String> {
+    value
+        .as_str()
+        .filter(|text| !text.trim().is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The hash of the given components, computed independently of `hash`
+    fn hash_of(components: &[&str]) -> String {
+        let digest = Sha256::digest(components.join("\0"));
+        digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>()[..32]
+            .to_string()
+    }
+
+    fn error_event(fingerprint: Value) -> Value {
+        json!({
+            "exception": { "values": [
+                { "type": "IoError", "value": "disk full" },
+                { "type": "SaveError", "value": "could not save order 17" }
+            ] },
+            "fingerprint": fingerprint
+        })
+    }
+
+    #[test]
+    fn errors_group_by_the_whole_exception_chain() {
+        let expected = hash_of(&[
+            "IoError",
+            "disk full",
+            "SaveError",
+            "could not save order 17",
+        ]);
+        assert_eq!(
+            hash(&error_event(Value::Null)),
+            Some((expected.clone(), Rule::Exception))
+        );
+        assert_eq!(
+            hash(&error_event(json!([]))),
+            Some((expected.clone(), Rule::Exception))
+        );
+        assert_eq!(
+            hash(&error_event(json!(["{{ default }}"]))),
+            Some((expected, Rule::Fingerprint))
+        );
+    }
+
+    #[test]
+    fn fingerprint_variables_expand_to_the_top_exception() {
+        assert_eq!(
+            hash(&error_event(json!(["{{ type }}", "{{value}}"]))),
+            Some((
+                hash_of(&["SaveError", "could not save order 17"]),
+                Rule::Fingerprint
+            ))
+        );
+        assert_eq!(
+            hash(&error_event(json!(["checkout", "{{ transaction }}", 42]))),
+            Some((hash_of(&["checkout", "", "42"]), Rule::Fingerprint))
+        );
+    }
+
+    #[test]
+    fn events_without_exceptions_group_by_message_then_transaction() {
+        let event =
+            json!({ "logentry": { "message": "order %s failed", "formatted": "order 17 failed" } });
+        assert_eq!(
+            hash(&event),
+            Some((hash_of(&["order %s failed"]), Rule::Message))
+        );
+        assert_eq!(
+            hash(&json!({ "transaction": "GET /orders", "message": " " })),
+            Some((hash_of(&["GET /orders"]), Rule::Transaction))
+        );
+        assert_eq!(hash(&json!({ "level": "info" })), None);
+    }
+}
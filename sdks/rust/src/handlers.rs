@@ -0,0 +1,161 @@
+//! Separate code per event type, dispatched at runtime
+//!
+//! Instead of one `beforeSendCode`, a submission may give any of
+//! `beforeSend`, `beforeSendTransaction`, and `beforeSendLog`, as an SDK
+//! configures them. All of them are compiled into one binary whose code
+//! matches on the input's `type`: `transaction` runs `beforeSendTransaction`,
+//! `log` runs `beforeSendLog` (bound as `log`), and anything else runs
+//! `beforeSend`. An input without a handler for its type is kept unchanged,
+//! as SDKs do when a hook isn't set.
+
+use crate::sandbox;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Code for each hook, any of which may be missing
+#[derive(Debug, Default, Deserialize)]
+pub struct Handlers {
+    #[serde(rename = "beforeSend", default)]
+    pub before_send: Option<String>,
+    #[serde(rename = "beforeSendTransaction", default)]
+    pub before_send_transaction: Option<String>,
+    #[serde(rename = "beforeSendLog", default)]
+    pub before_send_log: Option<String>,
+}
+
+/// The hook an input is routed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Handler {
+    #[serde(rename = "beforeSend")]
+    Event,
+    #[serde(rename = "beforeSendTransaction")]
+    Transaction,
+    #[serde(rename = "beforeSendLog")]
+    Log,
+}
+
+impl Handlers {
+    /// Whether no handler was given, so the submission uses `beforeSendCode`
+    pub fn is_empty(&self) -> bool {
+        self.before_send.is_none()
+            && self.before_send_transaction.is_none()
+            && self.before_send_log.is_none()
+    }
+
+    /// Which hook runs for an input, matching the generated dispatch
+    pub fn route(event: &Value) -> Handler {
+        match event.get("type").and_then(Value::as_str) {
+            Some("transaction") => Handler::Transaction,
+            Some("log") => Handler::Log,
+            _ => Handler::Event,
+        }
+    }
+
+    /// User code running the matching handler, in place of `beforeSendCode`
+    ///
+    /// Each handler runs in a closure of its own, so `return` leaves only that
+    /// handler, and its result is converted before the arms are unified. With
+    /// `capture_panic_context`, each handler snapshots its own binding for the
+    /// panic hook as it modifies it.
+    pub fn dispatch_code(&self, capture_panic_context: bool) -> String {
+        let code = |code: Option<&str>, binding: &str| {
+            code.map(|code| {
+                if capture_panic_context {
+                    sandbox::track_panic_snapshots(code, binding, binding)
+                } else {
+                    code.to_string()
+                }
+            })
+        };
+        format!(
+            "let event_type = event.get(\"type\").and_then(Value::as_str).map(str::to_owned);\n\
+             match event_type.as_deref() {{\n\
+             Some(\"transaction\") => {},\n\
+             Some(\"log\") => {},\n\
+             _ => {},\n\
+             }}",
+            arm(
+                code(self.before_send_transaction.as_deref(), "event").as_deref(),
+                "",
+                ""
+            ),
+            arm(
+                code(self.before_send_log.as_deref(), "log").as_deref(),
+                "#[allow(unused_mut)]\nlet mut log = event;\n",
+                "-> Option<Value> ",
+            ),
+            arm(
+                code(self.before_send.as_deref(), "event").as_deref(),
+                "",
+                ""
+            ),
+        )
+    }
+}
+
+/// One handler's match arm, passing the input on unchanged without code
+fn arm(code: Option<&str>, prelude: &str, return_annotation: &str) -> String {
+    match code {
+        Some(code) => format!(
+            "{{\n{}let result: TransformResult = (move || {}{{\n{}\n}})().into();\nresult\n}}",
+            prelude, return_annotation, code
+        ),
+        None => "TransformResult::from(Some(event))".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn inputs_route_by_their_type() {
+        assert_eq!(
+            Handlers::route(&json!({ "type": "transaction" })),
+            Handler::Transaction
+        );
+        assert_eq!(Handlers::route(&json!({ "type": "log" })), Handler::Log);
+        assert_eq!(Handlers::route(&json!({ "type": "error" })), Handler::Event);
+        assert_eq!(Handlers::route(&json!({ "type": 1 })), Handler::Event);
+        assert_eq!(Handlers::route(&json!({})), Handler::Event);
+    }
+
+    #[test]
+    fn missing_handlers_pass_the_input_through() {
+        let handlers = Handlers {
+            before_send: Some("None".to_string()),
+            ..Handlers::default()
+        };
+        assert!(!handlers.is_empty());
+        assert!(Handlers::default().is_empty());
+
+        let code = handlers.dispatch_code(false);
+        assert_eq!(
+            code.matches("TransformResult::from(Some(event))").count(),
+            2
+        );
+        assert!(code.contains("(move || {\nNone\n})().into()"), "{}", code);
+    }
+
+    #[test]
+    fn handlers_snapshot_their_own_binding_for_panic_context() {
+        let handlers = Handlers {
+            before_send: Some("event[\"a\"] = json!(1);\nSome(event)".to_string()),
+            before_send_log: Some("log[\"a\"] = json!(1);\nSome(log)".to_string()),
+            ..Handlers::default()
+        };
+        let code = handlers.dispatch_code(true);
+        assert!(
+            code.contains("event[\"a\"] = json!(1); track_panic_event(&event);"),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("log[\"a\"] = json!(1); track_panic_event(&log);"),
+            "{}",
+            code
+        );
+        assert!(!handlers.dispatch_code(false).contains("track_panic_event"));
+    }
+}
@@ -0,0 +1,272 @@
+//! Bounded concurrency limiter for cargo builds
+//!
+//! Compiling user code is CPU and memory heavy, so only a fixed number of
+//! builds run at once. Requests beyond that wait in a queue of bounded depth,
+//! served by priority and then in arrival order; once the queue is full new
+//! requests are rejected instead of piling up.
+
+use std::cmp::Ordering as PriorityOrdering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+/// Limits concurrent builds and tracks queue metrics
+pub struct BuildLimiter {
+    slots: Arc<Slots>,
+    max_concurrent: usize,
+    max_queue_depth: usize,
+    active: Arc<AtomicUsize>,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Free build slots and the requests waiting for one
+struct Slots {
+    state: Mutex<SlotState>,
+}
+
+struct SlotState {
+    available: usize,
+    waiters: BinaryHeap<Waiter>,
+    next_ticket: u64,
+}
+
+/// A queued request, served by priority and then in arrival order
+struct Waiter {
+    priority: i32,
+    ticket: u64,
+    sender: oneshot::Sender<()>,
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> PriorityOrdering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.ticket.cmp(&self.ticket))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<PriorityOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.ticket == other.ticket
+    }
+}
+
+impl Eq for Waiter {}
+
+/// Returned when the queue is already at its maximum depth
+#[derive(Debug)]
+pub struct QueueFull;
+
+/// Point-in-time view of the limiter
+#[derive(Debug, Clone, Copy)]
+pub struct LimiterSnapshot {
+    pub active: usize,
+    pub queued: usize,
+    pub max_concurrent: usize,
+    pub max_queue_depth: usize,
+    pub rejected: u64,
+}
+
+/// A held build slot, released when dropped
+///
+/// Permits are owned so a build can outlive the request that started it,
+/// as with streamed transforms. Dropping a permit hands the slot straight to
+/// the next waiter.
+pub struct BuildPermit {
+    slots: Arc<Slots>,
+    active: Arc<AtomicUsize>,
+}
+
+impl Drop for BuildPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+        self.slots.release();
+    }
+}
+
+impl Slots {
+    /// Pass a freed slot to the best waiter still listening, or mark it available
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(waiter) = state.waiters.pop() {
+            if waiter.sender.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// A queued request's wait for a slot
+///
+/// Decrements the queue depth even if the waiting request is cancelled, and
+/// passes on a slot that was handed over just before the cancellation.
+struct QueueSlot<'a> {
+    queued: &'a AtomicUsize,
+    slots: &'a Slots,
+    receiver: oneshot::Receiver<()>,
+    granted: bool,
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        if !self.granted && self.receiver.try_recv().is_ok() {
+            self.slots.release();
+        }
+    }
+}
+
+impl BuildLimiter {
+    pub fn new(max_concurrent: usize, max_queue_depth: usize) -> Self {
+        BuildLimiter {
+            slots: Arc::new(Slots {
+                state: Mutex::new(SlotState {
+                    available: max_concurrent,
+                    waiters: BinaryHeap::new(),
+                    next_ticket: 0,
+                }),
+            }),
+            max_concurrent,
+            max_queue_depth,
+            active: Arc::new(AtomicUsize::new(0)),
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Wait for a build slot, or fail immediately if the queue is full
+    ///
+    /// Waiting requests with a higher `priority` are served first; equal
+    /// priorities are served in arrival order.
+    pub async fn acquire(&self, priority: i32) -> Result<BuildPermit, QueueFull> {
+        let receiver = {
+            let mut state = self.slots.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.available > 0 {
+                state.available -= 1;
+                self.active.fetch_add(1, Ordering::SeqCst);
+                return Ok(BuildPermit {
+                    slots: self.slots.clone(),
+                    active: self.active.clone(),
+                });
+            }
+
+            if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queue_depth {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                self.rejected.fetch_add(1, Ordering::SeqCst);
+                return Err(QueueFull);
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.waiters.push(Waiter {
+                priority,
+                ticket,
+                sender,
+            });
+            receiver
+        };
+
+        let mut slot = QueueSlot {
+            queued: &self.queued,
+            slots: &self.slots,
+            receiver,
+            granted: false,
+        };
+        // Waiters are only removed by sending them a slot, so this cannot fail
+        (&mut slot.receiver).await.map_err(|_| QueueFull)?;
+        slot.granted = true;
+
+        self.active.fetch_add(1, Ordering::SeqCst);
+        Ok(BuildPermit {
+            slots: self.slots.clone(),
+            active: self.active.clone(),
+        })
+    }
+
+    pub fn snapshot(&self) -> LimiterSnapshot {
+        LimiterSnapshot {
+            active: self.active.load(Ordering::SeqCst),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_concurrent: self.max_concurrent,
+            max_queue_depth: self.max_queue_depth,
+            rejected: self.rejected.load(Ordering::SeqCst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Wait until `count` requests are queued
+    async fn until_queued(limiter: &BuildLimiter, count: usize) {
+        while limiter.snapshot().queued < count {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn waiters_are_served_by_priority_then_arrival() {
+        let limiter = Arc::new(BuildLimiter::new(1, 3));
+        let held = limiter.acquire(0).await.unwrap();
+
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut waiters = vec![];
+        for (id, priority) in [(0, 0), (1, 5), (2, 0)] {
+            let (waiting, order) = (limiter.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = waiting.acquire(priority).await.unwrap();
+                order.lock().unwrap().push(id);
+            }));
+            until_queued(&limiter, id + 1).await;
+        }
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), [1, 0, 2]);
+        assert_eq!(limiter.snapshot().active, 0);
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_queue_depth_are_rejected() {
+        let limiter = Arc::new(BuildLimiter::new(1, 1));
+        let held = limiter.acquire(0).await.unwrap();
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(0).await.is_ok() }
+        });
+        until_queued(&limiter, 1).await;
+        assert!(limiter.acquire(0).await.is_err());
+        assert_eq!(limiter.snapshot().rejected, 1);
+
+        drop(held);
+        assert!(waiter.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn cancelled_waiters_leave_the_queue() {
+        let limiter = BuildLimiter::new(1, 1);
+        let held = limiter.acquire(0).await.unwrap();
+
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), limiter.acquire(0)).await;
+        assert!(cancelled.is_err());
+        assert_eq!(limiter.snapshot().queued, 0);
+
+        drop(held);
+        assert!(limiter.acquire(0).await.is_ok());
+    }
+}
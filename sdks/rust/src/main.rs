@@ -3,14 +3,70 @@
 //! This service provides a REST API for executing Rust code transformations
 //! in a sandboxed environment. It supports:
 //!
-//! - **beforeSend**: Transform or drop Sentry events (returns Value or None)
+//! - **beforeSend**: Transform or drop Sentry events (returns Value or None); the
+//!   request's `hint` is bound as `hint`, as in the SDKs' `beforeSend(event, hint)`
 //! - **tracesSampler**: Return sample rates for transactions (returns f64 0.0-1.0)
+//! - **beforeSendLog**: Transform or drop Sentry structured log items, bound as `log`
+//!   (set `"mode": "beforeSendLog"`)
+//! - **Per event type**: send `beforeSend`, `beforeSendTransaction`, and/or
+//!   `beforeSendLog` instead of `beforeSendCode`, built together and dispatched
+//!   on the input's `type` (see `handlers`)
 //!
 //! ## Endpoints
 //!
-//! - `POST /transform` - Execute user code against an event
+//! - `POST /transform` - Execute user code against an event, or a `/corpus` sample by `corpusId`
+//! - `POST /transform/stream` - Like `/transform`, streaming compile progress (SSE)
+//! - `POST /transform/project` - Download the generated Cargo project (tar) for local runs
+//! - `POST /transform/batch` - Build once and run against each of `events`; with `streaming`,
+//!   each result is sent as an NDJSON line as soon as it's ready
+//! - `POST /transform/compare` - Run each of `variants` against one event and diff the first two
+//! - `POST /transform/impact` - Run `baselineCode` and `candidateCode` against one event, diffing
+//!   their outputs and flagging whether the event's disposition (kept or dropped) changed
+//! - `POST /transform/parity` - Run the code here and equivalent `siblingCode` in another
+//!   SDK service (`PARITY_SDK_URL`), diffing the results
+//! - `POST /transform/upload` - Build once and stream results for each line of a `.jsonl` file (multipart)
+//! - `POST /transform/from-url` - Build once and run against each line of NDJSON fetched from
+//!   `url`, on a host in `FETCH_ALLOWED_HOSTS`, summarizing kept and dropped events
+//! - `POST /transform/quota` - Run against `events`, the `/corpus` samples in `corpusIds`, or the
+//!   whole corpus, projecting the share of events (and so quota) the code would drop
+//! - `POST /transform/synthetic` - Run against a sample event generated from `template`
+//!   (`exception`, `message`, or `transaction`), returning it as `input`
+//! - `POST /transform/pipeline` - Run each of `stages` in order, feeding each output to the next
+//! - `POST /transform/bench` - Build once and time `iterations` runs, reporting latency percentiles
+//! - `POST /transform/fuzz` - Run against random mutations of `event`, reporting failing inputs
 //! - `POST /validate` - Validate code syntax without execution
+//! - `POST /validate/stream` - Like `/validate`, streaming each diagnostic as rustc emits it (SSE)
+//! - `POST /lint` - Check code against the API allowlist without compiling
+//! - `GET /ws` - Websocket session building code once and running each event sent
+//! - `GET /errors/{id}` - Full output of a failed transform, by its `errorId`
+//! - `POST /snippets` - Save `{ title, code, mode, event }`, returning its `id`
+//! - `GET /snippets/{id}` - Load a saved snippet
+//! - `GET /selftest` - Build and run a known transform, checking the whole pipeline
 //! - `GET /health` - Health check endpoint
+//! - `GET /status` - Build queue status (JSON)
+//! - `GET /capabilities` - The API allowlist, what `noStd` rejects, and the span limit
+//! - `GET /corpus` - The bundled real-world sample events, selectable with `corpusId`
+//! - `GET /metrics` - Build queue metrics (Prometheus text format)
+//!
+//! `/transform` and `/transform/batch` also accept and return MessagePack: send
+//! `Content-Type: application/msgpack` and/or `Accept: application/msgpack`.
+//! A streaming batch always answers `application/x-ndjson`: one /transform
+//! body per event, in order, then a `{ "done": true, "counters", "state" }` line.
+//!
+//! When `AUTH_TOKEN` is set, the `/transform` and `/validate` endpoints, `/lint`,
+//! `/ws`, `/errors/{id}`, `/snippets`, and `/selftest` require an
+//! `Authorization: Bearer <token>` header; the health and queue endpoints stay
+//! open.
+//!
+//! Every response carries an `X-Request-Id` header, echoing the client's or a
+//! generated one; `/transform` also echoes a `requestId` body field.
+//!
+//! When `ALLOWED_APIS` is set, code using a function, method, macro, or path
+//! outside that list is rejected before compiling (see `allowlist`).
+//!
+//! Operators can enforce scrubbing with redaction rules (`REDACTION_RULES_PATH`),
+//! applied to every transformed event after user code runs. Rules that matched
+//! are listed in `appliedRedactions`.
 //!
 //! ## How It Works
 //!
@@ -25,25 +81,632 @@
 //! // tracesSampler - return sample rate
 //! 0.5          // 50% sampling
 //! ```
+//!
+//! Object keys keep the order they have in the submitted event, with keys
+//! user code adds appended. Set `"sortKeys": true` for canonical output with
+//! keys sorted at every level, e.g. for snapshot tests.
+//!
+//! Numbers round-trip exactly: the API and the generated crates both enable
+//! serde_json's `arbitrary_precision`, so integers beyond 64 bits and floats
+//! with more digits than an `f64` holds come back as they were sent. Only
+//! numbers the code computes go through `f64`. MessagePack responses encode
+//! numbers natively, so there they are limited to 64 bits.
+//!
+//! User code runs in a function of its own, so `return` ends it early and `?`
+//! works on `Option`s in event hooks: `event.get("user")?;` drops events
+//! without a user. Code that modifies the event but ends in a statement
+//! (evaluating to `()`) drops it too; the response then carries a
+//! `mistakeHint`.
+//!
+//! With `EXEC_BACKEND=wasm`, user code is compiled to `wasm32-wasip1` and run
+//! in wasmtime with no filesystem or network access instead of natively. The
+//! event is passed on stdin under both backends. Runs taking longer than
+//! `EXEC_TIMEOUT_SECS` (10 by default) are killed and fail with `errorKind:
+//! "timeout"`.
+//!
+//! Built binaries are cached by code and build options (`BUILD_CACHE_ENTRIES`),
+//! so repeated code skips both compilation and the build queue. Builds waiting
+//! for a slot are served by `priority` (higher first), then in arrival order.
+//!
+//! ## Custom Signatures
+//!
+//! Hooks without a dedicated mode (e.g. beforeBreadcrumb) can describe their
+//! wrapper with `"signature": { "binding": "breadcrumb", "input": "Value",
+//! "returns": "Option<Value>" }`. Inputs may be `Value` or `Vec<Value>`, and
+//! returns `Option<Value>`, `Value`, or `f64`.
+//!
+//! ## Helpers
+//!
+//! The wrapper provides helpers for walking common event structures:
+//!
+//! - `exceptions_mut(&mut event)` - iterate over `exception.values`
+//! - `frames_mut(&mut event)` - iterate over the stack frames of every exception
+//! - `drop_with_reason("...")` - drop the event and report why as `dropReason`
+//! - `count("name")` - increment a named counter, reported as `counters`
+
+mod allowlist;
+mod archive;
+mod assertions;
+mod auth;
+mod build_cache;
+mod codec;
+mod config;
+mod corpus;
+mod diagnostics;
+mod diff;
+mod disk;
+mod envelope;
+mod error_store;
+mod event_schema;
+mod fetch;
+mod footprint;
+mod fuzz;
+mod grouping;
+mod handlers;
+mod limiter;
+mod multipart;
+mod postprocess;
+mod rate_limit;
+mod redaction;
+mod request_id;
+mod sandbox;
+mod session;
+mod sibling;
+mod size_limits;
+mod snippets;
+mod syntax;
+mod templates;
+#[cfg(test)]
+mod tests;
+mod timeouts;
+mod title;
 
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
+use actix_web::http::{header, StatusCode};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use build_cache::{BuildCache, CacheStatus, CachedBuild};
+use config::Config;
+use error_store::ErrorStore;
+use futures_util::stream;
+use limiter::{BuildLimiter, BuildPermit};
+use rate_limit::RateLimiter;
+use redaction::Redactions;
+use sandbox::{
+    cargo_command, check_toolchain, fetch_dependencies, hoist_feature_attributes, render_modules,
+    validate_modules, BuildOptions, BuildProgress, ErrorKind, Executable, Failure,
+    TransformProject, WrapperOptions, WRAPPER_HELPERS,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use size_limits::SizeLimits;
+use snippets::{SaveError, SnippetStore};
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::process::Command;
+use std::hash::{BuildHasher, Hasher};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::{mpsc, Notify};
+
+/// Shared state for all request handlers
+struct AppState {
+    config: Config,
+    limiter: BuildLimiter,
+    /// Per-client rate limiter, absent when rate limiting is disabled
+    rate_limiter: Option<RateLimiter>,
+    /// Full output of recent failures, served by /errors/{id}
+    errors: ErrorStore,
+    /// Binaries built for recent code, reused without compiling
+    builds: BuildCache,
+    /// Saved snippets, served by /snippets/{id}
+    snippets: SnippetStore<Snippet>,
+    /// Operator rules applied to every transformed event
+    redactions: Redactions,
+    /// Builds run by this process, counted towards `MAX_COMPILATIONS`
+    compilations: AtomicU64,
+    /// Notified once `MAX_COMPILATIONS` is reached, to stop the server
+    recycle: Notify,
+    /// Client for event exports and the sibling SDK (see [`fetch`])
+    http: reqwest::Client,
+}
+
+impl AppState {
+    /// State for a server with `config`, loading its snippets and redaction rules
+    fn new(config: Config) -> std::io::Result<Self> {
+        Ok(AppState {
+            limiter: BuildLimiter::new(config.max_concurrent_builds, config.max_queue_depth),
+            rate_limiter: (config.rate_limit_per_minute > 0)
+                .then(|| RateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst)),
+            errors: ErrorStore::new(Duration::from_secs(config.error_ttl_secs)),
+            builds: BuildCache::new(config.build_cache_entries)?,
+            snippets: SnippetStore::open(config.snippets_path.clone())?,
+            redactions: Redactions::load(config.redaction_rules_path.as_deref())?,
+            compilations: AtomicU64::new(0),
+            recycle: Notify::new(),
+            http: fetch::client(config.fetch_allowed_hosts.clone())
+                .map_err(std::io::Error::other)?,
+            config,
+        })
+    }
+
+    /// Count a build, asking the server to recycle the process at the threshold
+    fn record_compilation(&self) {
+        let compilations = self.compilations.fetch_add(1, Ordering::Relaxed) + 1;
+        if compilations == self.config.max_compilations {
+            println!(
+                "Reached {} compilations, draining and exiting to be restarted",
+                compilations
+            );
+            self.recycle.notify_one();
+        }
+    }
+
+    /// Spend a rate-limit token for the client, returning the wait on rejection
+    fn check_rate_limit(&self, http_req: &HttpRequest) -> Result<(), u64> {
+        let Some(rate_limiter) = &self.rate_limiter else {
+            return Ok(());
+        };
+
+        let client = client_ip(http_req, self.config.trusted_proxy_header.as_deref());
+        rate_limiter
+            .check(&client)
+            .map_err(|wait| wait.as_secs_f64().ceil().max(1.0) as u64)
+    }
+
+    /// Store a failed transform and build its response, tagged with the error id
+    fn record_failure(&self, failure: Failure, include_traceback: bool) -> TransformResponse {
+        let error_id = self
+            .errors
+            .insert(failure.error.clone(), failure.traceback.clone());
+        let diagnostics = failure
+            .traceback
+            .as_deref()
+            .map(diagnostics::classify)
+            .unwrap_or_default();
+        let traceback = failure.traceback.filter(|_| include_traceback);
+        // The captured input is redacted like a transformed event would be
+        let panic_context = failure.panic_context.map(|mut context| {
+            if let Some(event) = context.get_mut("event") {
+                self.redactions.apply(event);
+            }
+            *context
+        });
+
+        TransformResponse {
+            error_id: Some(error_id),
+            error_kind: Some(failure.kind),
+            diagnostics,
+            panic_context,
+            ..TransformResponse::failure(failure.error, traceback)
+        }
+    }
+}
+
+/// Log levels accepted for Sentry structured log items
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "fatal"];
+
+/// Which SDK hook the user code implements
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum TransformMode {
+    /// beforeSend / tracesSampler, inferred from the return type (default)
+    #[default]
+    BeforeSend,
+    /// beforeSendLog: the input is a structured log item bound as `log`
+    BeforeSendLog,
+}
+
+impl TransformMode {
+    /// Name of the variable the user code receives the input as
+    fn binding(self) -> &'static str {
+        match self {
+            TransformMode::BeforeSend => "event",
+            TransformMode::BeforeSendLog => "log",
+        }
+    }
+
+    /// Return type of the user code, or `None` to infer it (events or sample rates)
+    fn return_type(self) -> Option<&'static str> {
+        match self {
+            TransformMode::BeforeSend => None,
+            TransformMode::BeforeSendLog => Some("Option<Value>"),
+        }
+    }
+}
+
+/// Input types a custom signature may bind its input as
+const SIGNATURE_INPUT_TYPES: &[&str] = &["Value", "Vec<Value>"];
+
+/// Return types a custom signature may declare
+const SIGNATURE_RETURN_TYPES: &[&str] = &["Option<Value>", "Value", "f64"];
+
+/// Wrapper signature for hooks without a dedicated mode, e.g. beforeBreadcrumb
+#[derive(Debug, Deserialize)]
+struct Signature {
+    /// Name the input is bound to, e.g. `breadcrumb`
+    binding: String,
+    /// Rust type the input is deserialized into (defaults to `Value`)
+    #[serde(default = "default_input_type")]
+    input: String,
+    /// Rust type the code must return
+    returns: String,
+}
+
+fn default_input_type() -> String {
+    "Value".to_string()
+}
+
+/// Binding, input type, and return type of the generated wrapper
+struct WrapperSignature<'a> {
+    binding: &'a str,
+    input_type: &'a str,
+    return_type: Option<&'a str>,
+}
+
+impl<'a> WrapperSignature<'a> {
+    /// Resolve the wrapper signature from the mode and an optional custom signature
+    fn resolve(mode: TransformMode, signature: Option<&'a Signature>) -> Result<Self, String> {
+        let Some(signature) = signature else {
+            return Ok(WrapperSignature {
+                binding: mode.binding(),
+                input_type: "Value",
+                return_type: mode.return_type(),
+            });
+        };
+
+        if mode != TransformMode::BeforeSend {
+            return Err("`signature` can only be used with the default mode".to_string());
+        }
+        sandbox::validate_binding(&signature.binding)?;
+        if !SIGNATURE_INPUT_TYPES.contains(&signature.input.as_str()) {
+            return Err(format!(
+                "Unsupported signature input type '{}'. Supported types: {}",
+                signature.input,
+                SIGNATURE_INPUT_TYPES.join(", ")
+            ));
+        }
+        if !SIGNATURE_RETURN_TYPES.contains(&signature.returns.as_str()) {
+            return Err(format!(
+                "Unsupported signature return type '{}'. Supported types: {}",
+                signature.returns,
+                SIGNATURE_RETURN_TYPES.join(", ")
+            ));
+        }
+
+        Ok(WrapperSignature {
+            binding: &signature.binding,
+            input_type: &signature.input,
+            return_type: Some(&signature.returns),
+        })
+    }
+}
+
+/// User code and how to build it, shared by the transform endpoints
+#[derive(Debug, Deserialize)]
+struct CodeOptions {
+    /// User's Rust code to execute
+    #[serde(rename = "beforeSendCode", default)]
+    before_send_code: String,
+    /// Code per event type instead of `beforeSendCode`, dispatched on the input's `type`
+    #[serde(flatten)]
+    handlers: handlers::Handlers,
+    /// Hook the code implements (defaults to beforeSend)
+    #[serde(default)]
+    mode: TransformMode,
+    /// Custom input binding and return type for hooks without a dedicated mode
+    #[serde(default)]
+    signature: Option<Signature>,
+    /// Helper modules (name -> source) compiled alongside the code, usable via `use name::item`
+    #[serde(default)]
+    modules: BTreeMap<String, String>,
+    /// Rust toolchain to build with via rustup (stable, beta, or nightly)
+    #[serde(default)]
+    toolchain: Option<String>,
+    /// Include the crates and versions that were compiled for this transform
+    #[serde(rename = "includeBuildInfo", default)]
+    include_build_info: bool,
+    /// Include how long each dependency took to compile, to explain slow cold builds
+    #[serde(rename = "includeTimings", default)]
+    include_timings: bool,
+    /// Build with `panic = "abort"` (default); disable for code relying on unwinding
+    #[serde(rename = "panicAbort", default = "default_true")]
+    panic_abort: bool,
+    /// Queue position among builds waiting for a slot; higher is built first (default 0)
+    #[serde(default)]
+    priority: i32,
+    /// Global allocator to build with: system, mimalloc, or jemalloc
+    #[serde(default)]
+    allocator: Option<String>,
+    /// On a panic, report the panic message and the input binding as of its
+    /// last top-level change in place as `panicContext`, redacted like results
+    #[serde(rename = "capturePanicContext", default)]
+    capture_panic_context: bool,
+    /// Interpret the code under Miri to detect undefined behavior; slow, and only
+    /// available when the server sets `ALLOW_MIRI`. Runs always use nightly.
+    #[serde(default)]
+    miri: bool,
+    /// Extra codegen flags for the build, e.g. `-C target-cpu=native` (see
+    /// `sandbox::ALLOWED_CODEGEN_OPTIONS`)
+    #[serde(default)]
+    rustflags: Vec<String>,
+    /// Items (structs, functions, impls) placed at the crate root, in scope for the code
+    #[serde(default)]
+    preamble: Option<String>,
+    /// Bind `state: &mut State` for the code, where `State` is defined in the
+    /// preamble and implements `Default`, `Serialize`, and `Deserialize`. The
+    /// state persists across the events of a batch or websocket session, so
+    /// code can e.g. drop duplicates; each other run starts from the default.
+    #[serde(rename = "sharedState", default)]
+    shared_state: bool,
+    /// Count heap allocations made by the code, reported as `allocations` and `bytesAllocated`
+    #[serde(rename = "countAllocations", default)]
+    count_allocations: bool,
+    /// Split the run's wall time into process spawn overhead (`spawnMs`) and
+    /// time in the code (`userMs`); not available with `miri`
+    #[serde(rename = "measureSpawn", default)]
+    measure_spawn: bool,
+    /// Reject std APIs unavailable on embedded or WASM-constrained targets
+    /// (`allowlist::NO_STD_DENIED_PATHS`, listed by `/capabilities`)
+    #[serde(rename = "noStd", default)]
+    no_std: bool,
+    /// Warn about unused imports, variables, and `mut`s instead of allowing
+    /// them, reporting the build's warnings as `compilerWarnings`
+    #[serde(rename = "strictUnused", default)]
+    strict_unused: bool,
+    /// Report the top-level keys the code reads (`accessedPaths`) and writes
+    /// (`mutatedPaths`) as it runs. `event` is then bound as a `RecordedEvent`
+    /// that records lookups and derefs to `Value` (see [`footprint`]). Return it
+    /// as usual; code that also returns other values needs
+    /// `Some(event.into_inner())`, and code that only drops `None::<Value>`.
+    /// Only for `beforeSendCode` in the default mode, without a signature.
+    #[serde(rename = "reportFootprint", default)]
+    report_footprint: bool,
+}
+
+impl CodeOptions {
+    /// The code the wrapper runs: `beforeSendCode`, or the per-type dispatch
+    fn source(&self) -> Cow<'_, str> {
+        if self.handlers.is_empty() {
+            Cow::Borrowed(&self.before_send_code)
+        } else {
+            Cow::Owned(self.handlers.dispatch_code(self.capture_panic_context))
+        }
+    }
+
+    /// Check that per-type handlers, if any, aren't mixed with other shapes of code
+    fn check_handlers(&self) -> Result<(), String> {
+        if self.handlers.is_empty() {
+            return Ok(());
+        }
+        if !self.before_send_code.is_empty() {
+            return Err(
+                "Send either beforeSendCode or per-type handlers (beforeSend, \
+                 beforeSendTransaction, beforeSendLog), not both"
+                    .to_string(),
+            );
+        }
+        if self.mode != TransformMode::BeforeSend || self.signature.is_some() {
+            return Err(
+                "Per-type handlers choose the hook from the input's `type`; \
+                 they can't be combined with `mode` or `signature`"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Code with every build option at its default, for endpoints taking only code and a mode
+    fn plain(before_send_code: String, mode: TransformMode) -> Self {
+        CodeOptions {
+            before_send_code,
+            handlers: handlers::Handlers::default(),
+            mode,
+            signature: None,
+            modules: BTreeMap::new(),
+            toolchain: None,
+            include_build_info: false,
+            include_timings: false,
+            panic_abort: true,
+            priority: 0,
+            allocator: None,
+            capture_panic_context: false,
+            miri: false,
+            rustflags: Vec::new(),
+            preamble: None,
+            shared_state: false,
+            count_allocations: false,
+            measure_spawn: false,
+            no_std: false,
+            strict_unused: false,
+            report_footprint: false,
+        }
+    }
+}
+
+/// What to do with each transformed event, shared by the transform endpoints
+#[derive(Debug, Default, Deserialize)]
+struct OutputOptions {
+    /// Also return the transformed event as a pretty-printed JSON string
+    #[serde(default)]
+    pretty: bool,
+    /// Keep only these keys in the transformed event (dotted paths keep nested keys)
+    #[serde(rename = "keepKeys", default)]
+    keep_keys: Option<Vec<String>>,
+    /// Keep only the last N breadcrumbs of the transformed event
+    #[serde(rename = "maxBreadcrumbs", default)]
+    max_breadcrumbs: Option<usize>,
+    /// Convert numeric ids (`event_id`, `user.id`, trace and span ids, thread
+    /// ids) to strings, as Sentry does; see `postprocess::ID_FIELDS`
+    #[serde(rename = "coerceIds", default)]
+    coerce_ids: bool,
+    /// Check the transformed event against Sentry-like size limits
+    #[serde(rename = "enforceSizeLimits", default)]
+    enforce_size_limits: bool,
+    /// Report the compact JSON size of the event before and after the transform
+    #[serde(rename = "includeSizes", default)]
+    include_sizes: bool,
+    /// Run the code again on its own result and report whether the output changed
+    #[serde(rename = "checkIdempotent", default)]
+    check_idempotent: bool,
+    /// JSON Pointer checks evaluated against the transformed event
+    #[serde(rename = "pointerAssertions", default)]
+    pointer_assertions: Vec<assertions::PointerAssertion>,
+    /// Recursively remove empty values from the transformed event
+    #[serde(rename = "pruneEmpty", default)]
+    prune_empty: bool,
+    /// Which values `pruneEmpty` treats as empty: null, emptyString, emptyArray,
+    /// and/or emptyObject (default: all of them)
+    #[serde(rename = "emptyValues", default)]
+    empty_values: Option<Vec<postprocess::EmptyValue>>,
+    /// Fill in the defaults an SDK sets before sending: `event_id`, `timestamp`,
+    /// `level`, and `platform` (see `postprocess::normalize`)
+    #[serde(default)]
+    normalize: bool,
+    /// Recursively sort object keys instead of keeping the input's order
+    #[serde(rename = "sortKeys", default)]
+    sort_keys: bool,
+    /// Check the transformed event's well-known fields against the Sentry event schema
+    #[serde(rename = "strictEventOutput", default)]
+    strict_event_output: bool,
+    /// Treat a dropped event (`None` or `()`) as a runtime failure instead of a
+    /// result; the response is still a 200, with `success: false`
+    #[serde(rename = "disallowDrop", default)]
+    disallow_drop: bool,
+    /// Golden value the transformed event must equal exactly; `null` expects a drop
+    #[serde(
+        rename = "expectedOutput",
+        default,
+        deserialize_with = "assertions::present"
+    )]
+    expected_output: Option<Value>,
+    /// Report the title Sentry would derive for the transformed event
+    #[serde(rename = "includeTitle", default)]
+    include_title: bool,
+    /// Report a grouping hash for the transformed event and the rule it came
+    /// from (see `grouping`)
+    #[serde(rename = "includeFingerprint", default)]
+    include_fingerprint: bool,
+    /// Report a hash of the result, for telling whether a re-run changed it
+    #[serde(rename = "includeOutputHash", default)]
+    include_output_hash: bool,
+    /// Report whether the code returned its input unchanged
+    #[serde(rename = "flagNoop", default)]
+    flag_noop: bool,
+    /// Return the transformed event itself (default) or a JSON Patch from the input to it
+    #[serde(rename = "responseFormat", default)]
+    response_format: ResponseFormat,
+    /// Return only the values to set and the paths to remove (`diff::Delta`)
+    /// instead of the transformed event; on /transform the body is then just
+    /// `DeltaResponse`
+    #[serde(rename = "responseDelta", default)]
+    response_delta: bool,
+    /// Also return the transformed event wrapped in a Sentry envelope, ready
+    /// to POST to Sentry's envelope endpoint (see `envelope`)
+    #[serde(rename = "wrapEnvelope", default)]
+    wrap_envelope: bool,
+    /// Soft budget for the code's execution time; exceeding it sets `exceededBudget`
+    /// without failing the transform. Excludes process startup and parsing the input.
+    #[serde(rename = "maxExecMs", default, deserialize_with = "optional_f64")]
+    max_exec_ms: Option<f64>,
+}
+
+/// Deserialize an optional `f64` by way of `serde_json::Number`
+///
+/// With `arbitrary_precision`, a fraction in a flattened struct reaches its
+/// field as serde_json's internal number map, which a plain `f64` rejects.
+fn optional_f64<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    Option::<serde_json::Number>::deserialize(deserializer)?
+        .map(|number| {
+            number
+                .as_f64()
+                .ok_or_else(|| serde::de::Error::custom(format!("{} is out of range", number)))
+        })
+        .transpose()
+}
+
+/// How a successful transform's result is returned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+enum ResponseFormat {
+    /// The transformed event, in `transformedEvent`
+    #[default]
+    #[serde(rename = "event")]
+    Event,
+    /// RFC 6902 operations turning the input into the transformed event, in
+    /// `patch`; a dropped event replaces the root with null
+    #[serde(rename = "jsonpatch")]
+    JsonPatch,
+}
 
 /// Request body for the /transform endpoint
 #[derive(Debug, Deserialize)]
 struct TransformRequest {
-    /// The Sentry event to transform
+    /// The Sentry event to transform; may be omitted for `corpusId`
+    #[serde(default)]
     event: Value,
-    /// User's Rust code to execute
-    #[serde(rename = "beforeSendCode")]
-    before_send_code: String,
+    /// Id of a `/corpus` sample to transform instead of `event`
+    #[serde(rename = "corpusId", default)]
+    corpus_id: Option<String>,
+    /// The SDK's hint (e.g. `originalException`), bound as `hint` for the code;
+    /// null if omitted. It reaches the code through the `TRANSFORM_HINT`
+    /// environment variable, so it's limited to 64KB of JSON.
+    #[serde(default)]
+    hint: Option<Value>,
+    #[serde(flatten)]
+    code: CodeOptions,
+    #[serde(flatten)]
+    output: OutputOptions,
+    /// Inline the full output of failures; it stays available via /errors/{id} either way
+    #[serde(rename = "includeTraceback", default = "default_true")]
+    include_traceback: bool,
+    /// Id echoed back as `requestId`, instead of the `X-Request-Id` header or a generated one
+    #[serde(rename = "requestId", default)]
+    request_id: Option<String>,
+}
+
+/// Request body for the /transform/project endpoint
+#[derive(Debug, Deserialize)]
+struct ProjectRequest {
+    /// Event written to `event.json`, for running the project locally
+    event: Value,
+    /// Hint written to `hint.json`, passed via the environment when running
+    /// locally; at most 64KB of JSON, as for /transform
+    #[serde(default)]
+    hint: Option<Value>,
+    #[serde(flatten)]
+    code: CodeOptions,
+}
+
+/// Request body for the /transform/batch endpoint
+#[derive(Debug, Deserialize)]
+struct BatchRequest {
+    /// The Sentry events to transform, each in its own run of the compiled code
+    events: Vec<Value>,
+    #[serde(flatten)]
+    code: CodeOptions,
+    #[serde(flatten)]
+    output: OutputOptions,
+    /// Inline the full output of failures; it stays available via /errors/{id} either way
+    #[serde(rename = "includeTraceback", default = "default_true")]
+    include_traceback: bool,
+    /// Stream each result as an NDJSON line instead of buffering them all,
+    /// for batches too big to wait for
+    #[serde(default)]
+    streaming: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 /// Response from the /transform endpoint
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 struct TransformResponse {
     /// Whether the transformation succeeded
     success: bool,
@@ -52,339 +715,3011 @@ struct TransformResponse {
     /// - For tracesSampler: a number between 0.0 and 1.0
     #[serde(rename = "transformedEvent", skip_serializing_if = "Option::is_none")]
     transformed_event: Option<Value>,
+    /// Whether the code returned an event, dropped it, or returned a sample rate,
+    /// as the wrapper converted its return value
+    #[serde(rename = "resultType", skip_serializing_if = "Option::is_none")]
+    result_type: Option<sandbox::ResultType>,
+    /// JSON Patch from the input to the transformed event, returned instead of
+    /// `transformedEvent` when `responseFormat` is `jsonpatch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    patch: Option<Vec<diff::PatchOperation>>,
+    /// Delta from the input to the transformed event, returned instead of
+    /// `transformedEvent` when `responseDelta` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<diff::Delta>,
     /// Error message if transformation failed
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
+    /// Stable category of the failure: compile, runtime, timeout, oom, internal, or invalid_input
+    #[serde(rename = "errorKind", skip_serializing_if = "Option::is_none")]
+    error_kind: Option<ErrorKind>,
     /// Full error traceback for debugging
     #[serde(skip_serializing_if = "Option::is_none")]
     traceback: Option<String>,
+    /// Errors, warnings, and notes from the traceback, with cargo's status lines marked
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    diagnostics: Vec<diagnostics::Diagnostic>,
+    /// State of the input binding when the code panicked, with the panic message
+    /// and location (only when `capturePanicContext` is set)
+    #[serde(rename = "panicContext", skip_serializing_if = "Option::is_none")]
+    panic_context: Option<Value>,
+    /// Crates compiled for the transform (only when `includeBuildInfo` is set)
+    #[serde(rename = "buildInfo", skip_serializing_if = "Option::is_none")]
+    build_info: Option<Value>,
+    /// Seconds spent compiling each dependency, slowest first (only when
+    /// `includeTimings` is set; a cached build reports its original timings)
+    #[serde(rename = "depTimings", skip_serializing_if = "Option::is_none")]
+    dep_timings: Option<Vec<sandbox::DepTiming>>,
+    /// Warnings from compiling the code (only when `strictUnused` is set; a
+    /// cached build reports its original warnings)
+    #[serde(rename = "compilerWarnings", skip_serializing_if = "Option::is_none")]
+    compiler_warnings: Option<Vec<String>>,
+    /// Heap allocations made by the code, including `realloc`s (only when
+    /// `countAllocations` is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allocations: Option<u64>,
+    /// Bytes requested by those allocations (only when `countAllocations` is set)
+    #[serde(rename = "bytesAllocated", skip_serializing_if = "Option::is_none")]
+    bytes_allocated: Option<u64>,
+    /// How long the code ran, in milliseconds (only when `maxExecMs` is set)
+    #[serde(rename = "execMs", skip_serializing_if = "Option::is_none")]
+    exec_ms: Option<f64>,
+    /// Whether the code ran longer than `maxExecMs` (only when it is set)
+    #[serde(rename = "exceededBudget", skip_serializing_if = "Option::is_none")]
+    exceeded_budget: Option<bool>,
+    /// Milliseconds from spawning the transform until its `main` ran (only
+    /// when `measureSpawn` is set)
+    #[serde(rename = "spawnMs", skip_serializing_if = "Option::is_none")]
+    spawn_ms: Option<f64>,
+    /// Milliseconds spent inside the code (only when `measureSpawn` is set)
+    #[serde(rename = "userMs", skip_serializing_if = "Option::is_none")]
+    user_ms: Option<f64>,
+    /// Milliseconds from spawning the transform until it exited, including
+    /// reading the event and writing the result (only when `measureSpawn` is set)
+    #[serde(rename = "processMs", skip_serializing_if = "Option::is_none")]
+    process_ms: Option<f64>,
+    /// Whether the build was reused from the cache (/transform and
+    /// /transform/stream, when the code built)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache: Option<CacheInfo>,
+    /// Size of the compiled transform binary (or wasm module) the event ran through
+    #[serde(rename = "binaryBytes", skip_serializing_if = "Option::is_none")]
+    binary_bytes: Option<u64>,
+    /// The transformed event as 2-space indented JSON (only when `pretty` is set)
+    #[serde(rename = "prettyJson", skip_serializing_if = "Option::is_none")]
+    pretty_json: Option<String>,
+    /// The transformed event as a Sentry envelope (only when `wrapEnvelope` is
+    /// set and the result is an event)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    envelope: Option<String>,
+    /// Which per-type handler ran, when the code was sent as handlers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handler: Option<handlers::Handler>,
+    /// Why the event was dropped, when user code used `drop_with_reason`
+    #[serde(rename = "dropReason", skip_serializing_if = "Option::is_none")]
+    drop_reason: Option<String>,
+    /// The input's `event_id`, when the code dropped an event that had one
+    #[serde(rename = "droppedEventId", skip_serializing_if = "Option::is_none")]
+    dropped_event_id: Option<String>,
+    /// Issue title Sentry would show for the transformed event (only when
+    /// `includeTitle` is set and the result is an event)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    /// Grouping hash of the transformed event (only when `includeFingerprint`
+    /// is set and the result is an event with something to group by)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fingerprint: Option<String>,
+    /// What `fingerprint` was computed from
+    #[serde(rename = "fingerprintRule", skip_serializing_if = "Option::is_none")]
+    fingerprint_rule: Option<grouping::Rule>,
+    /// SHA-256 of the result serialized with sorted keys, so it doesn't depend
+    /// on key order, leaving out the `event_id` and `timestamp` that `normalize`
+    /// generates (only when `includeOutputHash` is set)
+    #[serde(rename = "outputHash", skip_serializing_if = "Option::is_none")]
+    output_hash: Option<String>,
+    /// Likely mistake in the user code, e.g. modifying the event but not returning it
+    #[serde(rename = "mistakeHint", skip_serializing_if = "Option::is_none")]
+    mistake_hint: Option<String>,
+    /// Top-level keys of the input the code read while it ran (only when
+    /// `reportFootprint` is set)
+    #[serde(rename = "accessedPaths", skip_serializing_if = "Option::is_none")]
+    accessed_paths: Option<Vec<String>>,
+    /// Top-level keys of the input the code wrote while it ran, even if it
+    /// left the value unchanged (only when `reportFootprint` is set)
+    #[serde(rename = "mutatedPaths", skip_serializing_if = "Option::is_none")]
+    mutated_paths: Option<Vec<String>>,
+    /// Whether the code returned the input unchanged, before redaction and output
+    /// options (only when `flagNoop` is set; a drop is never a no-op)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    noop: Option<bool>,
+    /// Size limits the transformed event exceeds (only when `enforceSizeLimits` is set)
+    #[serde(rename = "sizeWarnings", skip_serializing_if = "Option::is_none")]
+    size_warnings: Option<Vec<String>>,
+    /// Fields of the transformed event that aren't shaped like Sentry expects
+    /// (only when `strictEventOutput` is set; empty when the event is valid)
+    #[serde(rename = "strictEventErrors", skip_serializing_if = "Option::is_none")]
+    strict_event_errors: Option<Vec<String>>,
+    /// Id of the request this responds to (/transform only), see `request_id`
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    /// Id for fetching the failure's full output from /errors/{id}
+    #[serde(rename = "errorId", skip_serializing_if = "Option::is_none")]
+    error_id: Option<String>,
+    /// Compact JSON size of the input event (only when `includeSizes` is set)
+    #[serde(rename = "inputBytes", skip_serializing_if = "Option::is_none")]
+    input_bytes: Option<usize>,
+    /// Compact JSON size of the result, 0 if dropped (only when `includeSizes` is set)
+    #[serde(rename = "outputBytes", skip_serializing_if = "Option::is_none")]
+    output_bytes: Option<usize>,
+    /// Counters incremented by user code via `count`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    counters: BTreeMap<String, u64>,
+    /// The shared state after this event (only when `sharedState` is set)
+    #[serde(rename = "state", skip_serializing_if = "Option::is_none")]
+    shared_state: Option<Value>,
+    /// Whether a second pass over the result left it unchanged (only when
+    /// `checkIdempotent` is set and the result is an event of the input's shape)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    idempotent: Option<bool>,
+    /// Changes the second pass made to the first pass's result, when not idempotent
+    #[serde(rename = "idempotencyDiff", skip_serializing_if = "Option::is_none")]
+    idempotency_diff: Option<Vec<diff::Change>>,
+    /// Whether the transformed event equals `expectedOutput` (only when it is set)
+    #[serde(rename = "matchedExpected", skip_serializing_if = "Option::is_none")]
+    matched_expected: Option<bool>,
+    /// Changes from `expectedOutput` to the transformed event, when they differ
+    #[serde(rename = "expectedDiff", skip_serializing_if = "Option::is_none")]
+    expected_diff: Option<Vec<diff::Change>>,
+    /// Outcome of each of `pointerAssertions`, in order
+    #[serde(
+        rename = "pointerAssertionResults",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pointer_assertion_results: Option<Vec<assertions::AssertionResult>>,
+    /// Operator redaction rules that matched the transformed event
+    #[serde(rename = "appliedRedactions", skip_serializing_if = "Vec::is_empty")]
+    applied_redactions: Vec<String>,
 }
 
-/// Request body for the /validate endpoint
+impl TransformResponse {
+    /// Build a failed response with an error message and optional traceback
+    fn failure(error: String, traceback: Option<String>) -> Self {
+        TransformResponse {
+            success: false,
+            error: Some(error),
+            traceback,
+            ..Default::default()
+        }
+    }
+}
+
+/// Request body for the /transform/compare endpoint
 #[derive(Debug, Deserialize)]
-struct ValidationRequest {
-    /// Code to validate
+struct CompareRequest {
+    /// The Sentry event every variant runs against
+    event: Value,
+    /// Implementations to compare, in order
+    variants: Vec<Variant>,
+    /// Hook every variant implements (defaults to beforeSend)
+    #[serde(default)]
+    mode: TransformMode,
+}
+
+/// One named implementation in a /transform/compare request
+#[derive(Debug, Deserialize)]
+struct Variant {
+    name: String,
+    /// User code, as sent in `beforeSendCode`
     code: String,
 }
 
-/// A single validation error
+/// Response body for the /transform/compare endpoint
 #[derive(Debug, Serialize)]
-struct ValidationError {
-    /// Line number where error occurred (if available)
+struct CompareResponse {
+    success: bool,
+    /// One result per variant, in order
+    results: Vec<VariantResult>,
+    /// Changes from the first variant's result to the second's, when both succeeded
     #[serde(skip_serializing_if = "Option::is_none")]
-    line: Option<usize>,
-    /// Column number where error occurred (if available)
+    diff: Option<Vec<diff::Change>>,
+}
+
+/// How one variant handled the event
+#[derive(Debug, Serialize)]
+struct VariantResult {
+    name: String,
+    /// Whether the variant dropped the event
+    dropped: bool,
+    #[serde(flatten)]
+    outcome: TransformResponse,
+}
+
+/// Request body for the /transform/impact endpoint
+#[derive(Debug, Deserialize)]
+struct ImpactRequest {
+    /// The Sentry event both versions run against
+    event: Value,
+    /// The code as it is now
+    #[serde(rename = "baselineCode")]
+    baseline_code: String,
+    /// The code with the proposed change
+    #[serde(rename = "candidateCode")]
+    candidate_code: String,
+    /// Hook both versions implement (defaults to beforeSend)
+    #[serde(default)]
+    mode: TransformMode,
+}
+
+/// Response body for the /transform/impact endpoint
+#[derive(Debug, Serialize)]
+struct ImpactResponse {
+    success: bool,
+    baseline: ImpactResult,
+    candidate: ImpactResult,
+    /// Changes from the baseline's output to the candidate's, when both succeeded
     #[serde(skip_serializing_if = "Option::is_none")]
-    column: Option<usize>,
-    /// Error message
-    message: String,
+    diff: Option<Vec<diff::Change>>,
+    /// Whether one version kept the event and the other dropped it, when both succeeded
+    #[serde(rename = "dispositionChanged", skip_serializing_if = "Option::is_none")]
+    disposition_changed: Option<bool>,
 }
 
-/// Response from the /validate endpoint
+/// How one version of the code handled the event
 #[derive(Debug, Serialize)]
-struct ValidationResponse {
-    /// Whether the code is valid
-    valid: bool,
-    /// List of validation errors
-    errors: Vec<ValidationError>,
+struct ImpactResult {
+    /// Whether this version dropped the event
+    dropped: bool,
+    #[serde(flatten)]
+    outcome: TransformResponse,
 }
 
-/// Response from the /health endpoint
+/// Request body for the /transform/parity endpoint
+#[derive(Debug, Deserialize)]
+struct ParityRequest {
+    /// The Sentry event both SDKs run against
+    event: Value,
+    /// Rust beforeSend code, run here
+    #[serde(rename = "beforeSendCode")]
+    before_send_code: String,
+    /// Equivalent beforeSend code in the sibling SDK's language
+    #[serde(rename = "siblingCode")]
+    sibling_code: String,
+}
+
+/// Response body for the /transform/parity endpoint
 #[derive(Debug, Serialize)]
-struct HealthResponse {
-    /// Service status
-    status: String,
-    /// SDK identifier
-    sdk: String,
+struct ParityResponse {
+    success: bool,
+    /// This SDK's result, as /transform would return it
+    rust: TransformResponse,
+    /// The sibling SDK's /transform response body, or a failure in the same
+    /// shape if it couldn't be reached
+    sibling: Value,
+    /// Whether both SDKs produced the same event (only when both succeeded)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matches: Option<bool>,
+    /// Changes from this SDK's event to the sibling's, when both succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<Vec<diff::Change>>,
 }
 
-/// Execute user code transformation
+/// Request body for the /transform/pipeline endpoint
+#[derive(Debug, Deserialize)]
+struct PipelineRequest {
+    /// The Sentry event the first stage runs against
+    event: Value,
+    /// Transforms to chain, each run against the previous one's output
+    stages: Vec<Stage>,
+}
+
+/// One transform in a /transform/pipeline request
+#[derive(Debug, Deserialize)]
+struct Stage {
+    /// User code, as sent in `beforeSendCode`
+    code: String,
+    /// Hook the stage implements (defaults to beforeSend)
+    #[serde(default)]
+    mode: TransformMode,
+}
+
+/// Response body for the /transform/pipeline endpoint
+#[derive(Debug, Serialize)]
+struct PipelineResponse {
+    /// Whether every stage that ran succeeded
+    success: bool,
+    /// The last stage's output, null if a stage dropped the event (absent if a stage failed)
+    #[serde(rename = "finalEvent", skip_serializing_if = "Option::is_none")]
+    final_event: Option<Value>,
+    /// Index of the stage that dropped the event; later stages don't run
+    #[serde(rename = "droppedAt", skip_serializing_if = "Option::is_none")]
+    dropped_at: Option<usize>,
+    /// One result per stage that ran, in order
+    stages: Vec<TransformResponse>,
+}
+
+/// Request body for the /transform/bench endpoint
+#[derive(Debug, Deserialize)]
+struct BenchRequest {
+    /// The Sentry event every iteration runs against
+    event: Value,
+    #[serde(flatten)]
+    code: CodeOptions,
+    /// Runs to time, at most `MAX_BENCH_ITERATIONS`
+    #[serde(default = "default_bench_iterations")]
+    iterations: usize,
+}
+
+fn default_bench_iterations() -> usize {
+    100
+}
+
+/// Response body for the /transform/bench endpoint
 ///
-/// Compiles and runs user-provided Rust code in a sandboxed Cargo project.
-/// Supports both beforeSend (event transformation) and tracesSampler (sample rates).
-async fn transform(req: web::Json<TransformRequest>) -> impl Responder {
-    // Create a temporary directory for compilation
-    let temp_dir = match tempfile::tempdir() {
-        Ok(dir) => dir,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(TransformResponse {
-                success: false,
-                transformed_event: None,
-                error: Some(format!("Failed to create temp directory: {}", e)),
-                traceback: None,
-            });
-        }
-    };
+/// Code that fails to build, or a run that fails, is reported like a failed
+/// /transform instead.
+#[derive(Debug, Serialize)]
+struct BenchResponse {
+    success: bool,
+    iterations: usize,
+    /// Wall time of each run, from spawning the transform until it exited
+    total: LatencyStats,
+    /// Time from spawning the transform until its `main` ran
+    spawn: LatencyStats,
+    /// Time spent inside the code
+    user: LatencyStats,
+}
 
-    let project_path = temp_dir.path();
-    let src_path = project_path.join("src");
+/// Distribution of one latency over the benchmark's runs, in milliseconds
+#[derive(Debug, Serialize)]
+struct LatencyStats {
+    #[serde(rename = "meanMs")]
+    mean_ms: f64,
+    #[serde(rename = "p50Ms")]
+    p50_ms: f64,
+    #[serde(rename = "p95Ms")]
+    p95_ms: f64,
+    #[serde(rename = "p99Ms")]
+    p99_ms: f64,
+    #[serde(rename = "minMs")]
+    min_ms: f64,
+    #[serde(rename = "maxMs")]
+    max_ms: f64,
+}
 
-    // Create project structure
-    if let Err(e) = fs::create_dir(&src_path) {
-        return HttpResponse::InternalServerError().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Failed to create src directory: {}", e)),
-            traceback: None,
-        });
+impl LatencyStats {
+    /// Summarize at least one sample, using nearest-rank percentiles
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * samples.len() as f64).ceil() as usize;
+            millis(samples[rank.clamp(1, samples.len()) - 1])
+        };
+        let total: Duration = samples.iter().sum();
+        LatencyStats {
+            mean_ms: millis(total) / samples.len() as f64,
+            p50_ms: percentile(50.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            min_ms: millis(samples[0]),
+            max_ms: millis(samples[samples.len() - 1]),
+        }
     }
+}
 
-    // Serialize event to JSON (escape for raw string literal)
-    let event_json = match serde_json::to_string(&req.event) {
-        Ok(json) => json,
-        Err(e) => {
-            return HttpResponse::BadRequest().json(TransformResponse {
-                success: false,
-                transformed_event: None,
-                error: Some(format!("Failed to serialize event: {}", e)),
-                traceback: None,
-            });
-        }
+/// Request body for the /transform/fuzz endpoint
+#[derive(Debug, Deserialize)]
+struct FuzzRequest {
+    /// The Sentry event fuzzed inputs are derived from
+    event: Value,
+    #[serde(flatten)]
+    code: CodeOptions,
+    /// Fuzzed inputs to run, at most `MAX_FUZZ_ITERATIONS`
+    #[serde(default = "default_fuzz_iterations")]
+    iterations: usize,
+    /// Seed for choosing mutations, random when unset
+    seed: Option<u64>,
+}
+
+fn default_fuzz_iterations() -> usize {
+    50
+}
+
+/// Response body for the /transform/fuzz endpoint
+///
+/// Code that fails to build is reported like a failed /transform.
+#[derive(Debug, Serialize)]
+struct FuzzResponse {
+    /// Whether the code built; failing inputs are listed in `failures`
+    success: bool,
+    /// Seed the mutations were chosen with, to reproduce the run
+    seed: u64,
+    /// Fuzzed inputs run, not counting those tried while minimizing
+    iterations: usize,
+    /// Distinct failures found, each with its smallest failing input
+    failures: Vec<FuzzFailure>,
+}
+
+/// An input the user code panicked or errored on
+#[derive(Debug, Serialize)]
+struct FuzzFailure {
+    /// Changes to `event` that reproduce the failure; empty if `event` itself fails
+    mutations: Vec<fuzz::Mutation>,
+    input: Value,
+    #[serde(flatten)]
+    outcome: TransformResponse,
+}
+
+/// One line of a /transform/upload file, streamed as a `result` event
+#[derive(Debug, Serialize)]
+struct UploadLineResult {
+    /// 1-based line number in the uploaded file
+    line: usize,
+    #[serde(flatten)]
+    outcome: TransformResponse,
+}
+
+/// Request body for the /transform/synthetic endpoint
+#[derive(Debug, Deserialize)]
+struct SyntheticRequest {
+    /// Kind of sample event to run the code against
+    template: templates::Template,
+    #[serde(flatten)]
+    code: CodeOptions,
+    #[serde(flatten)]
+    output: OutputOptions,
+}
+
+/// Response body for the /transform/synthetic endpoint
+#[derive(Debug, Serialize)]
+struct SyntheticResponse {
+    /// The generated event the code ran against
+    input: Value,
+    #[serde(flatten)]
+    outcome: TransformResponse,
+}
+
+/// Request body for the /transform/from-url endpoint
+#[derive(Debug, Deserialize)]
+struct FromUrlRequest {
+    /// `http://` or `https://` URL of newline-delimited events, on an allowed host
+    url: String,
+    #[serde(flatten)]
+    code: CodeOptions,
+}
+
+/// Response body for the /transform/from-url endpoint
+///
+/// Code that fails to build is reported like a failed /transform instead.
+#[derive(Debug, Serialize)]
+struct FromUrlResponse {
+    success: bool,
+    /// Non-blank lines fetched
+    events: usize,
+    /// Events the code returned
+    kept: usize,
+    /// Events the code dropped
+    dropped: usize,
+    /// Lines that were malformed or failed to transform
+    failed: usize,
+    /// The first `MAX_FROM_URL_FAILURES` failed lines
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failures: Vec<UploadLineResult>,
+}
+
+/// Request body for the /transform/quota endpoint
+///
+/// Events come from `events` or `corpusIds`; with neither, the whole
+/// `/corpus` is used.
+#[derive(Debug, Deserialize)]
+struct QuotaRequest {
+    #[serde(default)]
+    events: Option<Vec<Value>>,
+    /// Ids of `/corpus` samples to run against
+    #[serde(rename = "corpusIds", default)]
+    corpus_ids: Option<Vec<String>>,
+    #[serde(flatten)]
+    code: CodeOptions,
+}
+
+/// Response body for the /transform/quota endpoint
+///
+/// Code that fails to build is reported like a failed /transform instead.
+#[derive(Debug, Serialize)]
+struct QuotaResponse {
+    success: bool,
+    /// Events run
+    total: usize,
+    /// Events the code returned, which would still count against the quota
+    kept: usize,
+    /// Events the code dropped
+    dropped: usize,
+    /// Events the code failed on
+    failed: usize,
+    /// Percentage of events dropped, the quota the code would save
+    #[serde(rename = "dropRate")]
+    drop_rate: f64,
+}
+
+/// Final `done` event of a /transform/upload stream
+#[derive(Debug, Serialize)]
+struct UploadSummary {
+    /// Non-blank lines in the file
+    lines: usize,
+    /// Lines that were malformed or failed to transform
+    failed: usize,
+}
+
+/// Response body for /transform with `responseDelta`
+#[derive(Debug, Serialize)]
+struct DeltaResponse<'a> {
+    success: bool,
+    #[serde(rename = "requestId")]
+    request_id: &'a str,
+    delta: &'a diff::Delta,
+}
+
+/// How the build cache served a request, in `TransformResponse::cache`
+#[derive(Debug, Serialize)]
+struct CacheInfo {
+    status: CacheStatus,
+}
+
+/// Response body for the /transform/batch endpoint
+///
+/// A batch whose code fails to build is reported like a failed /transform.
+#[derive(Debug, Serialize)]
+struct BatchResponse {
+    /// Whether the code built; each result reports its own event's outcome
+    success: bool,
+    /// One result per input event, in order
+    results: Vec<TransformResponse>,
+    /// Counters incremented by user code via `count`, summed over all events
+    counters: BTreeMap<String, u64>,
+    /// The shared state after the last event (only when `sharedState` is set)
+    #[serde(rename = "state", skip_serializing_if = "Option::is_none")]
+    shared_state: Option<Value>,
+}
+
+/// Last line of a streaming /transform/batch response
+#[derive(Debug, Serialize)]
+struct BatchDone {
+    /// Always true, telling this line apart from the results before it
+    done: bool,
+    /// Counters incremented by user code via `count`, summed over all events
+    counters: BTreeMap<String, u64>,
+    /// The shared state after the last event (only when `sharedState` is set)
+    #[serde(rename = "state", skip_serializing_if = "Option::is_none")]
+    shared_state: Option<Value>,
+}
+
+/// A saved reproduction, as posted to /snippets and served from /snippets/{id}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Snippet {
+    title: String,
+    /// User code, as sent in `beforeSendCode`
+    code: String,
+    #[serde(default)]
+    mode: TransformMode,
+    /// Event or log item to run the code against
+    #[serde(default)]
+    event: Value,
+}
+
+impl Snippet {
+    /// Check the snippet against the size limits for stored snippets
+    fn validate(&self) -> Result<(), String> {
+        if self.title.trim().is_empty() {
+            return Err("title must not be empty".to_string());
+        }
+        if self.title.chars().count() > MAX_SNIPPET_TITLE_CHARS {
+            return Err(format!("title exceeds {} chars", MAX_SNIPPET_TITLE_CHARS));
+        }
+        if self.code.len() > MAX_SNIPPET_CODE_BYTES {
+            return Err(format!("code exceeds {} bytes", MAX_SNIPPET_CODE_BYTES));
+        }
+        if json_size(&self.event) > MAX_SNIPPET_EVENT_BYTES {
+            return Err(format!("event exceeds {} bytes", MAX_SNIPPET_EVENT_BYTES));
+        }
+        Ok(())
+    }
+}
+
+/// Response from the /snippets endpoints
+#[derive(Debug, Serialize)]
+struct SnippetResponse {
+    /// Id to load the snippet from /snippets/{id}
+    id: String,
+    /// The saved snippet (only when loading)
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    snippet: Option<Snippet>,
+}
+
+/// Request body for the /validate endpoint
+#[derive(Debug, Deserialize)]
+struct ValidationRequest {
+    /// Code to validate
+    code: String,
+    /// Hook the code implements (defaults to beforeSend)
+    #[serde(default)]
+    mode: TransformMode,
+    /// Custom input binding and return type for hooks without a dedicated mode
+    #[serde(default)]
+    signature: Option<Signature>,
+    /// Helper modules (name -> source) compiled alongside the code
+    #[serde(default)]
+    modules: BTreeMap<String, String>,
+    /// Rust toolchain to check with via rustup (stable, beta, or nightly)
+    #[serde(default)]
+    toolchain: Option<String>,
+    /// Event to seed the input with, matching what /transform will see
+    #[serde(default)]
+    event: Option<Value>,
+    /// Report unused imports, variables, and `mut`s as `warnings`
+    #[serde(rename = "strictUnused", default)]
+    strict_unused: bool,
+    /// Attach the surrounding source lines to each error and warning as `snippet`
+    #[serde(rename = "includeSnippets", default)]
+    include_snippets: bool,
+}
+
+/// One diagnostic streamed by /validate/stream
+#[derive(Debug, Serialize)]
+struct StreamedDiagnostic {
+    /// `error` or `warning`
+    level: String,
+    #[serde(flatten)]
+    error: ValidationError,
+}
+
+/// Final event of /validate/stream
+#[derive(Debug, Serialize)]
+struct ValidationDone {
+    valid: bool,
+    /// Cargo's output when the check failed without a diagnostic to stream
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Request body for the /lint endpoint
+#[derive(Debug, Deserialize)]
+struct LintRequest {
+    /// Code to check
+    code: String,
+    /// Helper modules (name -> source), checked like the code
+    #[serde(default)]
+    modules: BTreeMap<String, String>,
+    /// Also check for std APIs unavailable with `noStd`
+    #[serde(rename = "noStd", default)]
+    no_std: bool,
+}
+
+/// A single validation error
+#[derive(Debug, Default, Serialize)]
+struct ValidationError {
+    /// Line number where error occurred (if available)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    /// Column number where error occurred (if available)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+    /// Error message
+    message: String,
+    /// All source ranges involved in the error (primary and secondary labels)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    spans: Vec<ErrorSpan>,
+    /// rustc's help for the error, e.g. "there is a method `as_str` with a similar name"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+    /// Source lines around `line` as `"> 3 | code"`, the error line marked with
+    /// `>` (only when `includeSnippets` is set)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    snippet: Vec<String>,
+}
+
+impl From<allowlist::Violation> for ValidationError {
+    fn from(violation: allowlist::Violation) -> Self {
+        let message = match violation.module {
+            Some(module) => format!("In module `{}`: {}", module, violation.message),
+            None => violation.message,
+        };
+        ValidationError {
+            line: Some(violation.line),
+            column: Some(violation.column),
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+impl ValidationError {
+    /// Build an error that carries only a message, without source location
+    fn message_only(message: String) -> Self {
+        ValidationError {
+            message,
+            ..Default::default()
+        }
+    }
+
+    /// Attach the lines of `code` around the error's line, if it has one
+    fn with_snippet(mut self, code: &str) -> Self {
+        let Some(line) = self.line else {
+            return self;
+        };
+        let lines: Vec<&str> = code.lines().collect();
+        let first = line.saturating_sub(SNIPPET_CONTEXT_LINES).max(1);
+        let last = (line + SNIPPET_CONTEXT_LINES).min(lines.len());
+        let width = last.to_string().len();
+        self.snippet = (first..=last)
+            .map(|number| {
+                let marker = if number == line { '>' } else { ' ' };
+                format!(
+                    "{} {:>width$} | {}",
+                    marker,
+                    number,
+                    lines[number - 1],
+                    width = width
+                )
+            })
+            .collect();
+        self
+    }
+}
+
+/// A labeled source range of a compiler error, in user code coordinates
+#[derive(Debug, Serialize)]
+struct ErrorSpan {
+    #[serde(rename = "startLine")]
+    start_line: usize,
+    #[serde(rename = "startCol")]
+    start_col: usize,
+    #[serde(rename = "endLine")]
+    end_line: usize,
+    #[serde(rename = "endCol")]
+    end_col: usize,
+    /// Label rustc attached to the range (e.g. "first mutable borrow occurs here")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    /// Whether this is the primary range of the error
+    primary: bool,
+}
+
+/// Response from the /validate endpoint
+#[derive(Debug, Default, Serialize)]
+struct ValidationResponse {
+    /// Whether the code is valid
+    valid: bool,
+    /// List of validation errors
+    errors: Vec<ValidationError>,
+    /// Warnings about the code, reported with `strictUnused`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<ValidationError>,
+    /// Without `strictUnused`, the unused-code warnings the wrapper's
+    /// `#![allow]`s hide, e.g. an unused variable left behind by an edit
+    #[serde(rename = "suppressedWarnings", skip_serializing_if = "Vec::is_empty")]
+    suppressed_warnings: Vec<String>,
+}
+
+/// Response from the /selftest endpoint
+#[derive(Serialize)]
+struct SelfTestResponse {
+    /// Whether the known transform produced the expected event
+    ok: bool,
+    details: SelfTestDetails,
+}
+
+#[derive(Serialize)]
+struct SelfTestDetails {
+    expected: Value,
+    /// Event the transform returned, if it ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    actual: Option<Value>,
+    /// What went wrong, when not ok
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// Time taken to build (or find the cached build) and run the transform
+    #[serde(rename = "durationMs")]
+    duration_ms: u64,
+}
+
+/// Response from the /health endpoint
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    /// Service status
+    status: String,
+    /// SDK identifier
+    sdk: String,
+    /// Free bytes on the build volume
+    #[serde(rename = "diskFreeBytes", skip_serializing_if = "Option::is_none")]
+    disk_free_bytes: Option<u64>,
+    /// Why the service is unhealthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Response from the /status endpoint
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    /// Builds currently running
+    #[serde(rename = "activeBuilds")]
+    active_builds: usize,
+    /// Requests waiting for a build slot
+    #[serde(rename = "queueDepth")]
+    queue_depth: usize,
+    /// Maximum number of builds running at once
+    #[serde(rename = "maxConcurrentBuilds")]
+    max_concurrent_builds: usize,
+    /// Maximum number of requests allowed to wait
+    #[serde(rename = "maxQueueDepth")]
+    max_queue_depth: usize,
+    /// Requests rejected because the queue was full
+    #[serde(rename = "rejectedRequests")]
+    rejected_requests: u64,
+    /// Builds run since the process started
+    compilations: u64,
+}
+
+/// Response from the /capabilities endpoint
+#[derive(Debug, Serialize)]
+struct CapabilitiesResponse {
+    /// APIs code may use (`ALLOWED_APIS`); any API is allowed when absent
+    #[serde(rename = "allowedApis", skip_serializing_if = "Option::is_none")]
+    allowed_apis: Option<Vec<String>>,
+    /// std paths, and everything under them, rejected with `noStd`
+    #[serde(rename = "noStdDeniedPaths")]
+    no_std_denied_paths: &'static [&'static str],
+    /// Most spans accepted in an input event (`MAX_SPANS`)
+    #[serde(rename = "maxSpans")]
+    max_spans: usize,
+}
+
+/// Response from the /corpus endpoint
+#[derive(Debug, Serialize)]
+struct CorpusResponse {
+    samples: Vec<CorpusEntry>,
+}
+
+/// A corpus sample, with its event
+#[derive(Debug, Serialize)]
+struct CorpusEntry {
+    #[serde(flatten)]
+    sample: &'static corpus::Sample,
+    event: Value,
+}
+
+impl TransformRequest {
+    /// The event to transform: `event`, or the `corpusId` sample's
+    fn input_event(&self) -> Result<Cow<'_, Value>, Failure> {
+        match &self.corpus_id {
+            None if self.event.is_null() => Err(Failure::bad_request(
+                "Missing event; send one, or a corpusId from GET /corpus".to_string(),
+            )),
+            None => Ok(Cow::Borrowed(&self.event)),
+            Some(_) if !self.event.is_null() => Err(Failure::bad_request(
+                "Send either event or corpusId, not both".to_string(),
+            )),
+            Some(id) => corpus::get(id)
+                .map(|sample| Cow::Owned(sample.event()))
+                .ok_or_else(|| {
+                    Failure::bad_request(format!(
+                        "Unknown corpusId {:?}; GET /corpus lists the samples",
+                        id
+                    ))
+                }),
+        }
+    }
+}
+
+/// Execute user code transformation
+///
+/// Compiles and runs user-provided Rust code in a sandboxed Cargo project.
+/// Supports both beforeSend (event transformation) and tracesSampler (sample rates).
+/// Bodies may be JSON (default) or msgpack, negotiated via `Content-Type` and `Accept`.
+async fn transform(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: codec::Body<TransformRequest>,
+) -> impl Responder {
+    let request_id = request_id::resolve(&http_req, req.request_id.as_deref());
+
+    let (status, response) = if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        (rate_limited_response(retry_after), rate_limited_failure())
+    } else {
+        let outcome = match admit(&state, &req.code).await {
+            Ok((prepared, _permit)) => run_transform(&state, &req, prepared, None).await,
+            Err(failure) => Err(failure),
+        };
+        match outcome {
+            Ok(response) => (HttpResponse::Ok(), response),
+            Err(failure) => (
+                failure_response(&state.config, &failure),
+                state.record_failure(failure, req.include_traceback),
+            ),
+        }
+    };
+
+    // A delta replaces the rest of the body to keep it small
+    if let Some(delta) = &response.delta {
+        let response = DeltaResponse {
+            success: response.success,
+            request_id: &request_id,
+            delta,
+        };
+        return codec::respond(&http_req, status, &response);
+    }
+
+    let response = TransformResponse {
+        request_id: Some(request_id),
+        ..response
+    };
+    codec::respond(&http_req, status, &response)
+}
+
+/// Export the Cargo project generated for user code as a tar archive
+///
+/// The archive holds `transform/Cargo.toml`, `transform/src/main.rs`, and
+/// `transform/event.json`, exactly as the service would build and run them,
+/// so a sandbox limitation can be reproduced with a local `cargo run`.
+async fn export_project(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ProjectRequest>,
+) -> impl Responder {
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let files = match generate(&state, &req.code).await {
+        Ok(project) => project
+            .files()
+            .map_err(|e| Failure::internal(format!("Failed to read generated project: {}", e))),
+        Err(failure) => Err(failure),
+    };
+    let mut files = match files {
+        Ok(files) => files,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, true))
+        }
+    };
+    files.push((
+        "event.json".to_string(),
+        serde_json::to_vec_pretty(&req.event).unwrap_or_default(),
+    ));
+
+    // The wrapper expects a result sentinel, so tell users how to provide one
+    let hint_env = match &req.hint {
+        Some(hint) => {
+            files.push((
+                "hint.json".to_string(),
+                serde_json::to_vec_pretty(hint).unwrap_or_default(),
+            ));
+            format!("{}=\"$(cat hint.json)\" ", sandbox::HINT_VAR)
+        }
+        None => String::new(),
+    };
+    let usage = format!(
+        "// Run locally with: {}{}=--- cargo run --release < event.json\n\
+         // The result is printed on the line after `---`, followed by a JSON run report.\n\n",
+        hint_env,
+        sandbox::RESULT_SENTINEL_VAR
+    );
+    let files: Vec<(String, Vec<u8>)> = files
+        .into_iter()
+        .map(|(path, contents)| {
+            let contents = if path == "src/main.rs" {
+                [usage.as_bytes(), &contents].concat()
+            } else {
+                contents
+            };
+            (format!("transform/{}", path), contents)
+        })
+        .collect();
+
+    HttpResponse::Ok()
+        .content_type("application/x-tar")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            r#"attachment; filename="transform.tar""#,
+        ))
+        .body(archive::tar(&files))
+}
+
+/// Build user code once and run it against each event in a batch
+///
+/// A runtime failure only fails that event's result. The counters of every
+/// run are summed into the batch's `counters`, and with `sharedState` each
+/// run starts from the state the previous one left.
+async fn transform_batch(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: codec::Body<BatchRequest>,
+) -> impl Responder {
+    if req.events.is_empty() || req.events.len() > MAX_BATCH_EVENTS {
+        return codec::respond(
+            &http_req,
+            HttpResponse::BadRequest(),
+            &TransformResponse {
+                error_kind: Some(ErrorKind::InvalidInput),
+                ..TransformResponse::failure(
+                    format!("events must contain 1 to {} events", MAX_BATCH_EVENTS),
+                    None,
+                )
+            },
+        );
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return codec::respond(
+            &http_req,
+            rate_limited_response(retry_after),
+            &rate_limited_failure(),
+        );
+    }
+
+    let build = match admit(&state, &req.code).await {
+        Ok((prepared, _permit)) => compile(&state, prepared, &req.code, None).await,
+        Err(failure) => Err(failure),
+    };
+    let build = match build {
+        Ok(build) => build,
+        Err(failure) => {
+            return codec::respond(
+                &http_req,
+                failure_response(&state.config, &failure),
+                &state.record_failure(failure, req.include_traceback),
+            )
+        }
+    };
+
+    if req.streaming {
+        let req = req.0;
+        let (lines, receiver) = mpsc::unbounded_channel();
+        actix_web::rt::spawn(async move {
+            let (counters, shared_state) = run_batch(&state, &build.executable, &req, |result| {
+                lines.send(ndjson_line(&result)).is_ok()
+            })
+            .await;
+            let done = BatchDone {
+                done: true,
+                counters,
+                shared_state,
+            };
+            let _ = lines.send(ndjson_line(&done));
+        });
+
+        let body = stream::unfold(receiver, |mut receiver| async move {
+            let line = receiver.recv().await?;
+            Some((Ok::<_, actix_web::Error>(line), receiver))
+        });
+        return HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(body);
+    }
+
+    let mut results = Vec::with_capacity(req.events.len());
+    let (counters, shared_state) = run_batch(&state, &build.executable, &req, |result| {
+        results.push(result);
+        true
+    })
+    .await;
+
+    codec::respond(
+        &http_req,
+        HttpResponse::Ok(),
+        &BatchResponse {
+            success: true,
+            results,
+            counters,
+            shared_state,
+        },
+    )
+}
+
+/// Estimate how much of a Sentry quota code saves by dropping events
+///
+/// Runs the events like /transform/batch and counts which were kept and
+/// which dropped. Events the code fails on count towards the total but are
+/// neither kept nor dropped, as SDKs differ on sending them.
+async fn transform_quota(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<QuotaRequest>,
+) -> impl Responder {
+    let invalid = |message: String| {
+        HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(message, None)
+        })
+    };
+
+    let req = req.into_inner();
+    let events = match (req.events, req.corpus_ids) {
+        (Some(_), Some(_)) => {
+            return invalid("Send either events or corpusIds, not both".to_string())
+        }
+        (Some(events), None) => events,
+        (None, Some(ids)) => {
+            let samples: Result<Vec<Value>, String> = ids
+                .iter()
+                .map(|id| {
+                    corpus::get(id).map(corpus::Sample::event).ok_or_else(|| {
+                        format!("Unknown corpusId {:?}; GET /corpus lists the samples", id)
+                    })
+                })
+                .collect();
+            match samples {
+                Ok(samples) => samples,
+                Err(e) => return invalid(e),
+            }
+        }
+        (None, None) => corpus::SAMPLES.iter().map(corpus::Sample::event).collect(),
+    };
+    if events.is_empty() || events.len() > MAX_BATCH_EVENTS {
+        return invalid(format!(
+            "events must contain 1 to {} events",
+            MAX_BATCH_EVENTS
+        ));
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let batch = BatchRequest {
+        events,
+        code: req.code,
+        output: OutputOptions::default(),
+        include_traceback: false,
+        streaming: false,
+    };
+    let build = match admit(&state, &batch.code).await {
+        Ok((prepared, _permit)) => compile(&state, prepared, &batch.code, None).await,
+        Err(failure) => Err(failure),
+    };
+    let build = match build {
+        Ok(build) => build,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, true))
+        }
+    };
+
+    let (mut kept, mut dropped, mut failed) = (0, 0, 0);
+    run_batch(&state, &build.executable, &batch, |result| {
+        if !result.success {
+            failed += 1;
+        } else if result.transformed_event == Some(Value::Null) {
+            dropped += 1;
+        } else {
+            kept += 1;
+        }
+        true
+    })
+    .await;
+
+    let total = batch.events.len();
+    HttpResponse::Ok().json(QuotaResponse {
+        success: true,
+        total,
+        kept,
+        dropped,
+        failed,
+        drop_rate: dropped as f64 * 100.0 / total as f64,
+    })
+}
+
+/// Run each event of a batch in order, handing each result to `emit`
+///
+/// Stops early once `emit` returns false. Returns the counters summed over
+/// the events run and the shared state after the last one; a failed event
+/// leaves the shared state as the previous event left it.
+async fn run_batch(
+    state: &AppState,
+    executable: &Executable,
+    req: &BatchRequest,
+    mut emit: impl FnMut(TransformResponse) -> bool,
+) -> (BTreeMap<String, u64>, Option<Value>) {
+    let mut counters = BTreeMap::new();
+    let mut shared_state = None;
+    for event in &req.events {
+        let mut result = match transform_event(
+            state,
+            executable,
+            &req.code,
+            &req.output,
+            event,
+            None,
+            shared_state.as_ref(),
+        )
+        .await
+        {
+            Ok(result) => result,
+            Err(failure) => state.record_failure(failure, req.include_traceback),
+        };
+        for (name, value) in std::mem::take(&mut result.counters) {
+            *counters.entry(name).or_insert(0) += value;
+        }
+        if let Some(next) = result.shared_state.take() {
+            shared_state = Some(next);
+        }
+        if !emit(result) {
+            break;
+        }
+    }
+    (counters, shared_state)
+}
+
+/// Build code once and stream its results for each event in an uploaded `.jsonl` file
+///
+/// Takes a multipart form with a `file` of newline-delimited events, the
+/// `beforeSendCode`, and an optional `mode`. Each non-blank line is answered
+/// as it finishes with a `result` server-sent event, `{ "line", ... }` plus
+/// the body /transform would return; lines that aren't JSON objects are
+/// reported as failures without running. A final `done` event carries
+/// `{ "lines", "failed" }`. Code that fails to build is reported like a
+/// failed /transform, before any events.
+async fn transform_upload(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    payload: web::Payload,
+) -> impl Responder {
+    let invalid = |message: String| {
+        HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(message, None)
+        })
+    };
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let parts = match multipart::read_form(&http_req, payload, MAX_UPLOAD_BYTES).await {
+        Ok(parts) => parts,
+        Err(e) => return invalid(e),
+    };
+    let field = |name: &str| {
+        parts
+            .iter()
+            .find(|part| part.name == name)
+            .map(|part| String::from_utf8_lossy(&part.data).into_owned())
+    };
+
+    let Some(file) = field("file") else {
+        return invalid("A `file` field with newline-delimited events is required".to_string());
+    };
+    let Some(before_send_code) = field("beforeSendCode") else {
+        return invalid("A `beforeSendCode` field is required".to_string());
+    };
+    let mode = match field("mode") {
+        Some(mode) => match serde_json::from_value(Value::String(mode.clone())) {
+            Ok(mode) => mode,
+            Err(_) => return invalid(format!("Unknown mode '{}'", mode)),
+        },
+        None => TransformMode::default(),
+    };
+
+    let events = match ndjson_events(&file) {
+        Ok(events) => events,
+        Err(e) => return invalid(format!("file {}", e)),
+    };
+
+    let code = CodeOptions::plain(before_send_code, mode);
+    let build = match admit(&state, &code).await {
+        Ok((prepared, _permit)) => compile(&state, prepared, &code, None).await,
+        Err(failure) => Err(failure),
+    };
+    let build = match build {
+        Ok(build) => build,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, true))
+        }
+    };
+
+    let (results, receiver) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(async move {
+        let output = OutputOptions::default();
+        let mut summary = UploadSummary {
+            lines: events.len(),
+            failed: 0,
+        };
+        for (line, event) in events {
+            let outcome = match event {
+                Ok(event) => transform_event(
+                    &state,
+                    &build.executable,
+                    &code,
+                    &output,
+                    &event,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap_or_else(|failure| state.record_failure(failure, true)),
+                Err(message) => TransformResponse {
+                    error_kind: Some(ErrorKind::InvalidInput),
+                    ..TransformResponse::failure(message, None)
+                },
+            };
+            if !outcome.success {
+                summary.failed += 1;
+            }
+            let result = UploadLineResult { line, outcome };
+            if results.send(sse_event("result", &result)).is_err() {
+                return;
+            }
+        }
+        let _ = results.send(sse_event("done", &summary));
+    });
+
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, actix_web::Error>(event), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
+
+/// Fetch newline-delimited events and summarize how the code treats them
+///
+/// The `http://` or `https://` URL's host must be in `FETCH_ALLOWED_HOSTS`,
+/// so the endpoint can't be used to reach other services on the network, and
+/// redirects are only followed to those hosts. Lines are parsed as in /transform/upload, and the code is built once.
+/// A host that can't be reached or answers with an error is reported as a
+/// 502, and one that doesn't answer in time as a 504.
+async fn transform_from_url(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<FromUrlRequest>,
+) -> impl Responder {
+    let invalid = |message: String| {
+        HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(message, None)
+        })
+    };
+
+    if state.config.fetch_allowed_hosts.is_empty() {
+        return invalid(
+            "Fetching events is disabled on this server (FETCH_ALLOWED_HOSTS is unset)".to_string(),
+        );
+    }
+    let url = match fetch::parse_url(&req.url) {
+        Ok(url) => url,
+        Err(e) => return invalid(e),
+    };
+    let host = fetch::host(&url).unwrap_or_default();
+    if !state.config.fetch_allowed_hosts.contains(&host) {
+        return invalid(format!("Host '{}' is not in FETCH_ALLOWED_HOSTS", host));
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    // A host that fails isn't the client's mistake, so it's a 502 (504 on a timeout)
+    let upstream = |status: StatusCode, kind: ErrorKind, message: String| {
+        HttpResponse::build(status).json(TransformResponse {
+            error_kind: Some(kind),
+            ..TransformResponse::failure(message, None)
+        })
+    };
+    let fetch = async {
+        let response = state
+            .http
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| format!("Fetching {} failed: {}", req.url, fetch::describe(&e)))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Fetching {} failed with status {}",
+                req.url,
+                response.status().as_u16()
+            ));
+        }
+        fetch::read_body(response, MAX_FETCH_BYTES).await
+    };
+    let body = match tokio::time::timeout(FETCH_TIMEOUT, fetch).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return upstream(StatusCode::BAD_GATEWAY, ErrorKind::Upstream, e),
+        Err(_) => {
+            return upstream(
+                StatusCode::GATEWAY_TIMEOUT,
+                ErrorKind::Timeout,
+                format!("{} didn't answer within {}s", host, FETCH_TIMEOUT.as_secs()),
+            )
+        }
+    };
+    let events = match ndjson_events(&String::from_utf8_lossy(&body)) {
+        Ok(events) => events,
+        Err(e) => return invalid(format!("The fetched body {}", e)),
+    };
+
+    let build = match admit(&state, &req.code).await {
+        Ok((prepared, _permit)) => compile(&state, prepared, &req.code, None).await,
+        Err(failure) => Err(failure),
+    };
+    let build = match build {
+        Ok(build) => build,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, true))
+        }
+    };
+
+    let output = OutputOptions::default();
+    let mut summary = FromUrlResponse {
+        success: true,
+        events: events.len(),
+        kept: 0,
+        dropped: 0,
+        failed: 0,
+        failures: Vec::new(),
+    };
+    for (line, event) in events {
+        let outcome = match event {
+            Ok(event) => transform_event(
+                &state,
+                &build.executable,
+                &req.code,
+                &output,
+                &event,
+                None,
+                None,
+            )
+            .await
+            .unwrap_or_else(|failure| state.record_failure(failure, true)),
+            Err(message) => TransformResponse {
+                error_kind: Some(ErrorKind::InvalidInput),
+                ..TransformResponse::failure(message, None)
+            },
+        };
+        if !outcome.success {
+            summary.failed += 1;
+            if summary.failures.len() < MAX_FROM_URL_FAILURES {
+                summary.failures.push(UploadLineResult { line, outcome });
+            }
+        } else if outcome.transformed_event == Some(Value::Null) {
+            summary.dropped += 1;
+        } else {
+            summary.kept += 1;
+        }
+    }
+
+    HttpResponse::Ok().json(summary)
+}
+
+/// A 1-based line number and its event, or why the line isn't one
+type NdjsonLine = (usize, Result<Value, String>);
+
+/// Parse newline-delimited events, numbering lines from 1 and skipping blank ones
+///
+/// Lines that aren't JSON objects are kept as errors to report. Fails if
+/// there are no events or more than `MAX_UPLOAD_EVENTS`.
+fn ndjson_events(text: &str) -> Result<Vec<NdjsonLine>, String> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect();
+    if lines.is_empty() || lines.len() > MAX_UPLOAD_EVENTS {
+        return Err(format!("must contain 1 to {} events", MAX_UPLOAD_EVENTS));
+    }
+    Ok(lines
+        .into_iter()
+        .map(|(line, text)| {
+            let event = match serde_json::from_str::<Value>(text) {
+                Ok(event) if event.is_object() => Ok(event),
+                Ok(_) => Err(format!("Line {}: expected a JSON object", line)),
+                Err(e) => Err(format!("Line {}: invalid JSON: {}", line, e)),
+            };
+            (line, event)
+        })
+        .collect())
+}
+
+/// Run user code against a freshly generated sample event
+///
+/// Works like /transform without an event to paste; the generated event is
+/// returned alongside the result, whether or not the code built.
+async fn transform_synthetic(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<SyntheticRequest>,
+) -> impl Responder {
+    if req.code.mode == TransformMode::BeforeSendLog {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                "Templates generate events, not log items; use beforeSend or tracesSampler"
+                    .to_string(),
+                None,
+            )
+        });
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let input = templates::generate(req.template);
+    let outcome = async {
+        let (prepared, _permit) = admit(&state, &req.code).await?;
+        let build = compile(&state, prepared, &req.code, None).await?;
+        transform_event(
+            &state,
+            &build.executable,
+            &req.code,
+            &req.output,
+            &input,
+            None,
+            None,
+        )
+        .await
+    }
+    .await;
+
+    match outcome {
+        Ok(outcome) => HttpResponse::Ok().json(SyntheticResponse { input, outcome }),
+        Err(failure) => failure_response(&state.config, &failure).json(SyntheticResponse {
+            outcome: state.record_failure(failure, true),
+            input,
+        }),
+    }
+}
+
+/// Run user code against random mutations of an event to find failing inputs
+///
+/// The code is built once. Each distinct failure is minimized by dropping
+/// mutations while the input still fails, so a report points at the field the
+/// code can't cope with.
+async fn transform_fuzz(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<FuzzRequest>,
+) -> impl Responder {
+    if req.iterations == 0 || req.iterations > MAX_FUZZ_ITERATIONS {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                format!("iterations must be 1 to {}", MAX_FUZZ_ITERATIONS),
+                None,
+            )
+        });
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let build = match admit(&state, &req.code).await {
+        Ok((prepared, _permit)) => compile(&state, prepared, &req.code, None).await,
+        Err(failure) => Err(failure),
+    };
+    let build = match build {
+        Ok(build) => build,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, true))
+        }
+    };
+
+    // Random seeds fit in 53 bits so JavaScript clients can send them back exactly
+    let seed = req
+        .seed
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish() >> 11);
+    let mut response = FuzzResponse {
+        success: true,
+        seed,
+        iterations: 0,
+        failures: Vec::new(),
+    };
+
+    if let Some(failure) = fuzz_run(&state, &build.executable, &req.code, &req.event).await {
+        response.failures.push(FuzzFailure {
+            mutations: Vec::new(),
+            input: req.event.clone(),
+            outcome: state.record_failure(failure, true),
+        });
+        return HttpResponse::Ok().json(response);
+    }
+
+    let mut rng = fuzz::Rng::new(seed);
+    let mut seen = HashSet::new();
+    for _ in 0..req.iterations {
+        response.iterations += 1;
+        let mutations = fuzz::random_mutations(&req.event, &mut rng);
+        let input = fuzz::apply(&req.event, &mutations);
+        let Some(failure) = fuzz_run(&state, &build.executable, &req.code, &input).await else {
+            continue;
+        };
+        if response.failures.len() >= MAX_FUZZ_FAILURES || !seen.insert(failure.error.clone()) {
+            continue;
+        }
+
+        // Drop mutations one at a time, keeping each drop after which the input still fails
+        let (mut mutations, mut input, mut failure) = (mutations, input, failure);
+        let mut index = 0;
+        while index < mutations.len() {
+            let mut fewer = mutations.clone();
+            fewer.remove(index);
+            let candidate = fuzz::apply(&req.event, &fewer);
+            match fuzz_run(&state, &build.executable, &req.code, &candidate).await {
+                Some(smaller) => (mutations, input, failure) = (fewer, candidate, smaller),
+                None => index += 1,
+            }
+        }
+
+        seen.insert(failure.error.clone());
+        response.failures.push(FuzzFailure {
+            mutations,
+            input,
+            outcome: state.record_failure(failure, true),
+        });
+    }
+
+    HttpResponse::Ok().json(response)
+}
+
+/// Build code once, then time it over repeated runs against one event
+///
+/// Each run is split into spawn overhead and time in the code, as with
+/// `measureSpawn`. The first run pays for a cold page cache like any other.
+async fn transform_bench(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<BenchRequest>,
+) -> impl Responder {
+    let req = req.into_inner();
+    if req.iterations == 0 || req.iterations > MAX_BENCH_ITERATIONS {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                format!("iterations must be 1 to {}", MAX_BENCH_ITERATIONS),
+                None,
+            )
+        });
+    }
+    if req.code.miri {
+        let failure = Failure::bad_request("Benchmarks can't run under miri".to_string());
+        return failure_response(&state.config, &failure).json(state.record_failure(failure, true));
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let code = CodeOptions {
+        measure_spawn: true,
+        ..req.code
+    };
+    match bench(&state, &code, &req.event, req.iterations).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(failure) => {
+            failure_response(&state.config, &failure).json(state.record_failure(failure, true))
+        }
+    }
+}
+
+async fn bench(
+    state: &AppState,
+    code: &CodeOptions,
+    event: &Value,
+    iterations: usize,
+) -> Result<BenchResponse, Failure> {
+    check_input(&state.config, code, event)?;
+
+    let (prepared, _permit) = admit(state, code).await?;
+    let build = compile(state, prepared, code, None).await?;
+
+    let mut total = Vec::with_capacity(iterations);
+    let mut spawn = Vec::with_capacity(iterations);
+    let mut user = Vec::with_capacity(iterations);
+    let timeout = Duration::from_secs(state.config.exec_timeout_secs);
+    for _ in 0..iterations {
+        let output = build.executable.run(event, None, None, timeout).await?;
+        total.push(output.process_time);
+        spawn.push(output.spawn_time.unwrap_or_default());
+        user.push(output.exec_time);
+    }
+
+    Ok(BenchResponse {
+        success: true,
+        iterations,
+        total: LatencyStats::from_samples(total),
+        spawn: LatencyStats::from_samples(spawn),
+        user: LatencyStats::from_samples(user),
+    })
+}
+
+/// Run one fuzzed input, returning the failure if the user code panicked or errored
+///
+/// Inputs rejected before running (e.g. invalid log items) don't count.
+async fn fuzz_run(
+    state: &AppState,
+    executable: &Executable,
+    code: &CodeOptions,
+    input: &Value,
+) -> Option<Failure> {
+    let failure = transform_event(
+        state,
+        executable,
+        code,
+        &OutputOptions::default(),
+        input,
+        None,
+        None,
+    )
+    .await
+    .err()?;
+    matches!(failure.kind, ErrorKind::Runtime | ErrorKind::Oom).then_some(failure)
+}
+
+/// Run several implementations against the same event side by side
+///
+/// Each variant is built and run like /transform; one failing variant
+/// doesn't fail the others.
+async fn transform_compare(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<CompareRequest>,
+) -> impl Responder {
+    if !(2..=MAX_COMPARE_VARIANTS).contains(&req.variants.len()) {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                format!(
+                    "variants must contain 2 to {} variants",
+                    MAX_COMPARE_VARIANTS
+                ),
+                None,
+            )
+        });
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let mut results = Vec::with_capacity(req.variants.len());
+    for variant in &req.variants {
+        let code = CodeOptions::plain(variant.code.clone(), req.mode);
+        let outcome = run_variant(&state, &code, &req.event).await;
+
+        results.push(VariantResult {
+            name: variant.name.clone(),
+            dropped: outcome.transformed_event == Some(Value::Null),
+            outcome,
+        });
+    }
+
+    let diff = match (
+        &results[0].outcome.transformed_event,
+        &results[1].outcome.transformed_event,
+    ) {
+        (Some(first), Some(second)) => Some(diff::diff(first, second)),
+        _ => None,
+    };
+
+    HttpResponse::Ok().json(CompareResponse {
+        success: true,
+        results,
+        diff,
+    })
+}
+
+/// Run the code as it is and with a proposed change against the same event
+///
+/// Like /transform/compare with two variants, but also reports whether the
+/// change flips the event between kept and dropped, the impact that matters
+/// most for a filtering rule.
+async fn transform_impact(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ImpactRequest>,
+) -> impl Responder {
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let baseline = CodeOptions::plain(req.baseline_code.clone(), req.mode);
+    let baseline = run_variant(&state, &baseline, &req.event).await;
+    let candidate = CodeOptions::plain(req.candidate_code.clone(), req.mode);
+    let candidate = run_variant(&state, &candidate, &req.event).await;
+
+    let (diff, disposition_changed) =
+        match (&baseline.transformed_event, &candidate.transformed_event) {
+            (Some(before), Some(after)) => (
+                Some(diff::diff(before, after)),
+                Some(before.is_null() != after.is_null()),
+            ),
+            _ => (None, None),
+        };
+
+    let result = |outcome: TransformResponse| ImpactResult {
+        dropped: outcome.transformed_event == Some(Value::Null),
+        outcome,
+    };
+    HttpResponse::Ok().json(ImpactResponse {
+        success: true,
+        baseline: result(baseline),
+        candidate: result(candidate),
+        diff,
+        disposition_changed,
+    })
+}
+
+/// Build and run code against an event with default output options
+///
+/// A failure is recorded and returned as the response, so one version of the
+/// code failing doesn't fail the others it is compared with.
+async fn run_variant(state: &AppState, code: &CodeOptions, event: &Value) -> TransformResponse {
+    let outcome = async {
+        let (prepared, _permit) = admit(state, code).await?;
+        let build = compile(state, prepared, code, None).await?;
+        transform_event(
+            state,
+            &build.executable,
+            code,
+            &OutputOptions::default(),
+            event,
+            None,
+            None,
+        )
+        .await
+    };
+    outcome
+        .await
+        .unwrap_or_else(|failure| state.record_failure(failure, true))
+}
+
+/// Run equivalent code here and in a sibling SDK service, diffing the events
+///
+/// Both run concurrently; either failing is reported in its own result
+/// rather than failing the request.
+async fn transform_parity(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ParityRequest>,
+) -> impl Responder {
+    let Some(sibling_url) = &state.config.parity_sdk_url else {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                "Parity checks are disabled on this server (PARITY_SDK_URL is unset)".to_string(),
+                None,
+            )
+        });
+    };
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let code = CodeOptions::plain(req.before_send_code.clone(), TransformMode::BeforeSend);
+    let rust = async {
+        let (prepared, _permit) = admit(&state, &code).await?;
+        let build = compile(&state, prepared, &code, None).await?;
+        transform_event(
+            &state,
+            &build.executable,
+            &code,
+            &OutputOptions::default(),
+            &req.event,
+            None,
+            None,
+        )
+        .await
+    };
+    let sibling = sibling::transform(&state.http, sibling_url, &req.event, &req.sibling_code);
+    let (rust, sibling) = futures_util::join!(rust, sibling);
+
+    let rust = rust.unwrap_or_else(|failure| state.record_failure(failure, true));
+    let sibling = sibling.unwrap_or_else(|e| json!({ "success": false, "error": e }));
+
+    let diff = match (&rust.transformed_event, sibling.get("success")) {
+        (Some(event), Some(Value::Bool(true))) if rust.success => Some(diff::diff(
+            event,
+            sibling.get("transformedEvent").unwrap_or(&Value::Null),
+        )),
+        _ => None,
+    };
+
+    HttpResponse::Ok().json(ParityResponse {
+        success: true,
+        rust,
+        sibling,
+        matches: diff.as_ref().map(Vec::is_empty),
+        diff,
+    })
+}
+
+/// Run stages in order, feeding each one's output to the next
+///
+/// Each stage is built and run like /transform. The pipeline stops at the
+/// first stage that fails or drops the event.
+async fn transform_pipeline(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<PipelineRequest>,
+) -> impl Responder {
+    if req.stages.is_empty() || req.stages.len() > MAX_PIPELINE_STAGES {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(
+                format!("stages must contain 1 to {} stages", MAX_PIPELINE_STAGES),
+                None,
+            )
+        });
+    }
+
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let output = OutputOptions::default();
+
+    let mut event = req.event.clone();
+    let mut stages = Vec::with_capacity(req.stages.len());
+    let mut dropped_at = None;
+    for (index, stage) in req.stages.iter().enumerate() {
+        let code = CodeOptions::plain(stage.code.clone(), stage.mode);
+        let result = match admit(&state, &code).await {
+            Ok((prepared, _permit)) => match compile(&state, prepared, &code, None).await {
+                Ok(build) => {
+                    transform_event(
+                        &state,
+                        &build.executable,
+                        &code,
+                        &output,
+                        &event,
+                        None,
+                        None,
+                    )
+                    .await
+                }
+                Err(failure) => Err(failure),
+            },
+            Err(failure) => Err(failure),
+        };
+
+        let result = match result {
+            Ok(result) => result,
+            Err(failure) => {
+                stages.push(state.record_failure(failure, true));
+                return HttpResponse::Ok().json(PipelineResponse {
+                    success: false,
+                    final_event: None,
+                    dropped_at: None,
+                    stages,
+                });
+            }
+        };
+
+        event = result.transformed_event.clone().unwrap_or(Value::Null);
+        stages.push(result);
+        if event.is_null() {
+            dropped_at = Some(index);
+            break;
+        }
+    }
+
+    HttpResponse::Ok().json(PipelineResponse {
+        success: true,
+        final_event: Some(event),
+        dropped_at,
+        stages,
+    })
+}
+
+/// Execute user code transformation, streaming progress as server-sent events
+///
+/// Emits `progress` events (`{ "phase": "compiling", "done", "total" }`) as
+/// cargo compiles each crate, then a single `result` event carrying the same
+/// body /transform would return.
+async fn transform_stream(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<TransformRequest>,
+) -> impl Responder {
+    if let Err(retry_after) = state.check_rate_limit(&http_req) {
+        return rate_limited_response(retry_after).json(rate_limited_failure());
+    }
+
+    let (prepared, permit) = match admit(&state, &req.code).await {
+        Ok(admitted) => admitted,
+        Err(failure) => {
+            return failure_response(&state.config, &failure)
+                .json(state.record_failure(failure, req.include_traceback))
+        }
+    };
+
+    let (events, receiver) = mpsc::unbounded_channel();
+    let req = req.into_inner();
+
+    // The build keeps its slot until it finishes, even if the client goes away
+    actix_web::rt::spawn(async move {
+        let _permit = permit;
+        let report = |progress: BuildProgress| {
+            let _ = events.send(sse_event("progress", &progress));
+        };
+
+        let response = run_transform(&state, &req, prepared, Some(&report))
+            .await
+            .unwrap_or_else(|failure| state.record_failure(failure, req.include_traceback));
+        let _ = events.send(sse_event("result", &response));
+    });
+
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, actix_web::Error>(event), receiver))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
+
+/// Format a server-sent event with a JSON payload
+fn sse_event(event: &str, data: &impl Serialize) -> web::Bytes {
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "null".to_string());
+    web::Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Format a value as one line of newline-delimited JSON
+fn ndjson_line(value: &impl Serialize) -> web::Bytes {
+    let mut line = serde_json::to_vec(value).unwrap_or_else(|_| b"null".to_vec());
+    line.push(b'\n');
+    web::Bytes::from(line)
+}
+
+/// Build and run a transform, shared by the plain and streaming endpoints
+async fn run_transform(
+    state: &AppState,
+    req: &TransformRequest,
+    prepared: Prepared,
+    progress: Option<&dyn Fn(BuildProgress)>,
+) -> Result<TransformResponse, Failure> {
+    let event = req.input_event()?;
+
+    // Reject a malformed event or log item before spending time on the build
+    check_input(&state.config, &req.code, &event)?;
+
+    let cache = CacheInfo {
+        status: prepared.cache_status(state, &req.code),
+    };
+    let build = compile(state, prepared, &req.code, progress).await?;
+    let response = transform_checked_event(
+        state,
+        &build.executable,
+        &req.code,
+        &req.output,
+        &event,
+        req.hint.as_ref(),
+        None,
+    )
+    .await?;
+
+    Ok(TransformResponse {
+        build_info: build.build_info.clone(),
+        dep_timings: build.dep_timings.clone(),
+        compiler_warnings: build.warnings.clone(),
+        cache: Some(cache),
+        ..response
+    })
+}
+
+/// User code that passed its checks, either already built or still to compile
+enum Prepared {
+    /// A binary built earlier for identical code and build options
+    Cached(Arc<CachedBuild>),
+    /// A generated project, and the cache key its binary is stored under
+    Uncached {
+        project: TransformProject,
+        key: String,
+    },
+}
+
+impl Prepared {
+    /// How the build cache served this code
+    fn cache_status(&self, state: &AppState, code: &CodeOptions) -> CacheStatus {
+        match self {
+            Prepared::Cached(_) => CacheStatus::Hit,
+            Prepared::Uncached { .. } if code.miri || !state.builds.enabled() => {
+                CacheStatus::Disabled
+            }
+            Prepared::Uncached { .. } => CacheStatus::Miss,
+        }
+    }
+}
+
+/// Check and generate user code, taking a build slot only if it must compile
+///
+/// Code with a cached build bypasses the build queue entirely, so repeated
+/// code stays responsive while cold compiles wait for a slot.
+async fn admit(
+    state: &AppState,
+    code: &CodeOptions,
+) -> Result<(Prepared, Option<BuildPermit>), Failure> {
+    let project = generate(state, code).await?;
+
+    // Miri interprets the sources on every run, so there is no binary to reuse
+    let key = project.cache_key(&build_options(&state.config, code));
+    if let Some(build) = state.builds.get(&key).filter(|_| !code.miri) {
+        return Ok((Prepared::Cached(build), None));
+    }
+
+    let permit = state
+        .limiter
+        .acquire(code.priority)
+        .await
+        .map_err(|_| Failure::retryable(QUEUE_FULL_MESSAGE.to_string()))?;
+    Ok((Prepared::Uncached { project, key }, Some(permit)))
+}
+
+/// Check user code and its options, then generate its Cargo project
+async fn generate(state: &AppState, code: &CodeOptions) -> Result<TransformProject, Failure> {
+    if code.miri {
+        if !state.config.allow_miri {
+            return Err(Failure::bad_request(
+                "Miri runs are disabled on this server".to_string(),
+            ));
+        }
+        if code
+            .toolchain
+            .as_deref()
+            .is_some_and(|toolchain| toolchain != sandbox::MIRI_TOOLCHAIN)
+        {
+            return Err(Failure::bad_request(format!(
+                "Miri runs use the {} toolchain",
+                sandbox::MIRI_TOOLCHAIN
+            )));
+        }
+        if code.measure_spawn {
+            // Miri's isolation hides the wall clock the entry time is read from
+            return Err(Failure::bad_request(
+                "measureSpawn is not available with miri".to_string(),
+            ));
+        }
+        sandbox::check_miri().await.map_err(Failure::bad_request)?;
+    }
+
+    if let Some(toolchain) = &code.toolchain {
+        check_toolchain(toolchain)
+            .await
+            .map_err(Failure::bad_request)?;
+    }
+
+    validate_modules(&code.modules).map_err(Failure::bad_request)?;
+    code.check_handlers().map_err(Failure::bad_request)?;
+    let source = code.source();
+
+    if code.shared_state && code.preamble.is_none() {
+        return Err(Failure::bad_request(
+            "sharedState needs a preamble defining `State`".to_string(),
+        ));
+    }
+
+    // The preamble is checked like a helper module; the name is reserved
+    let mut checked_modules = code.modules.clone();
+    if let Some(preamble) = &code.preamble {
+        checked_modules.insert("preamble".to_string(), preamble.clone());
+    }
+    if let Some(error) = syntax_error(&source, &checked_modules) {
+        return Err(
+            Failure::bad_request(format!("Code has a syntax error: {}", error))
+                .with_kind(ErrorKind::Compile),
+        );
+    }
+    let violations = api_violations(&state.config, &source, &checked_modules);
+    if !violations.is_empty() {
+        let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+        return Err(Failure::bad_request(format!(
+            "Code uses APIs outside the allowlist: {}",
+            violations.join("; ")
+        )));
+    }
+    if code.no_std {
+        let violations = no_std_violations(&source, &checked_modules);
+        if !violations.is_empty() {
+            let violations: Vec<String> = violations.iter().map(ToString::to_string).collect();
+            return Err(Failure::bad_request(format!(
+                "Code uses std APIs unavailable with noStd: {}",
+                violations.join("; ")
+            )));
+        }
+    }
+
+    let signature = WrapperSignature::resolve(code.mode, code.signature.as_ref())
+        .map_err(Failure::bad_request)?;
+    // The recorded input stands in for a `Value` only where the return type is inferred
+    if code.report_footprint && (signature.return_type.is_some() || !code.handlers.is_empty()) {
+        return Err(Failure::bad_request(
+            "reportFootprint needs beforeSendCode in the default mode, without a signature"
+                .to_string(),
+        ));
+    }
+
+    let allocator = code
+        .allocator
+        .as_deref()
+        .map(|name| sandbox::find_allocator(name, state.config.exec_backend))
+        .transpose()
+        .map_err(Failure::bad_request)?;
+
+    sandbox::validate_rustflags(&code.rustflags).map_err(Failure::bad_request)?;
+
+    TransformProject::create(&WrapperOptions {
+        code: &source,
+        binding: signature.binding,
+        input_type: signature.input_type,
+        return_type: signature.return_type,
+        modules: &code.modules,
+        panic_abort: code.panic_abort,
+        backend: state.config.exec_backend,
+        allocator,
+        capture_panic_context: code.capture_panic_context,
+        preamble: code.preamble.as_deref(),
+        shared_state: code.shared_state,
+        count_allocations: code.count_allocations,
+        report_entry_time: code.measure_spawn,
+        strict_unused: code.strict_unused,
+        record_footprint: code.report_footprint,
+    })
+}
+
+/// Build prepared code unless it was cached, storing new binaries for reuse
+async fn compile(
+    state: &AppState,
+    prepared: Prepared,
+    code: &CodeOptions,
+    progress: Option<&dyn Fn(BuildProgress)>,
+) -> Result<Arc<CachedBuild>, Failure> {
+    let (project, key) = match prepared {
+        Prepared::Cached(build) => return Ok(build),
+        Prepared::Uncached { project, key } => (project, key),
+    };
+
+    disk::check_build_space(state.config.min_free_disk_bytes).map_err(|e| {
+        Failure::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            INSUFFICIENT_DISK_MESSAGE.to_string(),
+        )
+        .with_traceback(e)
+    })?;
+
+    if code.miri {
+        let lock_timeout = Duration::from_secs(state.config.cargo_lock_timeout_secs);
+        return Ok(Arc::new(CachedBuild {
+            executable: project.into_miri_executable(lock_timeout).await?,
+            build_info: None,
+            dep_timings: None,
+            warnings: None,
+        }));
+    }
+
+    let output = project
+        .build(&build_options(&state.config, code), progress)
+        .await;
+    state.record_compilation();
+    let output = output?;
+
+    state
+        .builds
+        .insert(key, &project.executable(), output)
+        .map_err(|e| Failure::internal(format!("Failed to store compiled transform: {}", e)))
+}
+
+/// How to build code with the given options
+fn build_options<'a>(config: &Config, code: &'a CodeOptions) -> BuildOptions<'a> {
+    BuildOptions {
+        toolchain: code.toolchain.as_deref(),
+        include_build_info: code.include_build_info,
+        include_timings: code.include_timings,
+        lock_timeout: Duration::from_secs(config.cargo_lock_timeout_secs),
+        rustflags: &code.rustflags,
+    }
+}
+
+/// Run a built transform against one event and apply the output options
+async fn transform_event(
+    state: &AppState,
+    executable: &Executable,
+    code: &CodeOptions,
+    options: &OutputOptions,
+    event: &Value,
+    sdk_hint: Option<&Value>,
+    shared_state: Option<&Value>,
+) -> Result<TransformResponse, Failure> {
+    check_input(&state.config, code, event)?;
+    transform_checked_event(
+        state,
+        executable,
+        code,
+        options,
+        event,
+        sdk_hint,
+        shared_state,
+    )
+    .await
+}
+
+/// Reject an event that is too large or nested, or not a valid log item in log mode
+fn check_input(config: &Config, code: &CodeOptions, event: &Value) -> Result<(), Failure> {
+    check_event_limits(config, event)?;
+    if code.mode == TransformMode::BeforeSendLog {
+        validate_log_item(event)
+            .map_err(|e| Failure::bad_request(format!("Invalid log item: {}", e)))?;
+    }
+    Ok(())
+}
+
+/// Like `transform_event`, for an event `check_input` has already accepted
+async fn transform_checked_event(
+    state: &AppState,
+    executable: &Executable,
+    code: &CodeOptions,
+    options: &OutputOptions,
+    event: &Value,
+    sdk_hint: Option<&Value>,
+    shared_state: Option<&Value>,
+) -> Result<TransformResponse, Failure> {
+    if options.response_delta && options.response_format == ResponseFormat::JsonPatch {
+        return Err(Failure::bad_request(
+            "responseDelta replaces the response format; it can't be combined with jsonpatch"
+                .to_string(),
+        ));
+    }
+
+    let timeout = Duration::from_secs(state.config.exec_timeout_secs);
+    let output = executable
+        .run(event, sdk_hint, shared_state, timeout)
+        .await?;
+    let millis = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    let exec_ms = options.max_exec_ms.map(|_| millis(output.exec_time));
+    let (spawn_ms, user_ms, process_ms) = if code.measure_spawn {
+        (
+            output.spawn_time.map(millis),
+            Some(millis(output.exec_time)),
+            Some(millis(output.process_time)),
+        )
+    } else {
+        (None, None, None)
+    };
+    let transformed_event = output.value;
+
+    let binding = code
+        .signature
+        .as_ref()
+        .map_or(code.mode.binding(), |signature| &signature.binding);
+    let source = code.source();
+    let mistake_hint =
+        (output.returned_unit && sandbox::mutates_binding(&source, binding)).then(|| {
+            format!(
+                "You modified {binding} but returned (); did you forget to return Some({binding})?"
+            )
+        });
+
+    // The code ran and chose to drop, so the request itself succeeded
+    if options.disallow_drop && transformed_event.is_none() {
+        return Err(Failure::new(
+            StatusCode::OK,
+            "Dropping events is not permitted".to_string(),
+        )
+        .with_kind(ErrorKind::Runtime));
+    }
+
+    let noop = options
+        .flag_noop
+        .then(|| transformed_event.as_ref() == Some(event));
+
+    let accessed_paths = output.accessed_paths;
+    let mutated_paths = output.mutated_paths;
+
+    let (idempotent, idempotency_diff) = if options.check_idempotent {
+        check_idempotent(
+            executable,
+            event,
+            sdk_hint,
+            transformed_event.as_ref(),
+            timeout,
+        )
+        .await?
+    } else {
+        (None, None)
+    };
+
+    if code.mode == TransformMode::BeforeSendLog {
+        if let Some(log) = &transformed_event {
+            validate_log_item(log).map_err(|e| {
+                Failure::bad_request(format!(
+                    "beforeSendLog must return a valid log item or None: {}",
+                    e
+                ))
+                .with_kind(ErrorKind::Runtime)
+            })?;
+        }
+    }
+
+    let dropped_event_id = transformed_event
+        .is_none()
+        .then(|| event.get("event_id").and_then(Value::as_str))
+        .flatten()
+        .map(str::to_string);
+
+    // Dropped events are reported as an explicit null rather than omitted
+    let mut transformed_event = transformed_event.unwrap_or(Value::Null);
+    let applied_redactions = state.redactions.apply(&mut transformed_event);
+
+    // Custom signatures return other payloads, like breadcrumbs. The fields
+    // filled with per-run values are left out of the output hash.
+    let generated_fields = if options.normalize
+        && code.mode == TransformMode::BeforeSend
+        && code.signature.is_none()
+    {
+        postprocess::normalize(&mut transformed_event)
+    } else {
+        Vec::new()
+    };
+
+    if options.coerce_ids {
+        postprocess::coerce_ids(&mut transformed_event);
+    }
+
+    if let Some(max) = options.max_breadcrumbs {
+        postprocess::trim_breadcrumbs(&mut transformed_event, max);
+    }
+
+    if let Some(keep_keys) = &options.keep_keys {
+        postprocess::keep_keys(&mut transformed_event, keep_keys);
+    }
+
+    if options.prune_empty {
+        let empty_values = options
+            .empty_values
+            .as_deref()
+            .unwrap_or(postprocess::ALL_EMPTY_VALUES);
+        postprocess::prune_empty(&mut transformed_event, empty_values);
+    }
+
+    if options.sort_keys {
+        transformed_event.sort_all_objects();
+    }
+
+    let size_warnings = options.enforce_size_limits.then(|| {
+        let limits = SizeLimits {
+            max_event_bytes: state.config.max_event_bytes,
+            max_message_chars: state.config.max_message_chars,
+            max_tag_chars: state.config.max_tag_chars,
+        };
+        size_limits::check(&transformed_event, &limits)
+    });
+
+    // Dropping is always valid
+    let strict_event_errors = options
+        .strict_event_output
+        .then(|| match &transformed_event {
+            Value::Null => Vec::new(),
+            event => event_schema::check(event),
+        });
+
+    let title = (options.include_title && transformed_event.is_object())
+        .then(|| title::derive(&transformed_event));
+    let (fingerprint, fingerprint_rule) = match options.include_fingerprint {
+        true if transformed_event.is_object() => grouping::hash(&transformed_event).unzip(),
+        _ => (None, None),
+    };
+
+    let pointer_assertion_results = (!options.pointer_assertions.is_empty())
+        .then(|| assertions::evaluate(&transformed_event, &options.pointer_assertions));
+
+    let expected_diff = options
+        .expected_output
+        .as_ref()
+        .map(|expected| diff::diff(expected, &transformed_event));
+    let matched_expected = expected_diff.as_ref().map(Vec::is_empty);
+    let expected_diff = expected_diff.filter(|changes| !changes.is_empty());
+
+    let (input_bytes, output_bytes) = if options.include_sizes {
+        let output_bytes = match &transformed_event {
+            Value::Null => 0,
+            transformed => json_size(transformed),
+        };
+        (Some(json_size(event)), Some(output_bytes))
+    } else {
+        (None, None)
+    };
+
+    let pretty_json = if options.pretty {
+        let json = serde_json::to_string_pretty(&transformed_event)
+            .map_err(|e| Failure::internal(format!("Failed to pretty-print result: {}", e)))?;
+        Some(json)
+    } else {
+        None
+    };
+
+    let envelope = (options.wrap_envelope && transformed_event.is_object())
+        .then(|| envelope::wrap(&transformed_event));
+    let output_hash = options
+        .include_output_hash
+        .then(|| canonical_hash(&transformed_event, &generated_fields));
+
+    let (transformed_event, patch, delta) = match options.response_format {
+        _ if options.response_delta => (None, None, Some(diff::delta(event, &transformed_event))),
+        ResponseFormat::Event => (Some(transformed_event), None, None),
+        ResponseFormat::JsonPatch => (
+            None,
+            Some(diff::json_patch(event, &transformed_event)),
+            None,
+        ),
+    };
+
+    Ok(TransformResponse {
+        success: true,
+        transformed_event,
+        patch,
+        delta,
+        binary_bytes: fs::metadata(executable.path()).ok().map(|m| m.len()),
+        pretty_json,
+        envelope,
+        handler: (!code.handlers.is_empty()).then(|| handlers::Handlers::route(event)),
+        drop_reason: output.drop_reason,
+        dropped_event_id,
+        title,
+        fingerprint,
+        fingerprint_rule,
+        output_hash,
+        mistake_hint,
+        accessed_paths,
+        mutated_paths,
+        noop,
+        size_warnings,
+        strict_event_errors,
+        input_bytes,
+        output_bytes,
+        counters: output.counters,
+        shared_state: output.state,
+        result_type: output.result_type,
+        exec_ms,
+        exceeded_budget: exec_ms
+            .zip(options.max_exec_ms)
+            .map(|(ms, budget)| ms > budget),
+        spawn_ms,
+        user_ms,
+        process_ms,
+        allocations: output.allocations.map(|counts| counts.count),
+        bytes_allocated: output.allocations.map(|counts| counts.bytes),
+        idempotent,
+        idempotency_diff,
+        pointer_assertion_results,
+        matched_expected,
+        expected_diff,
+        applied_redactions,
+        ..Default::default()
+    })
+}
+
+/// Run a transform on its own result and compare the two passes
+///
+/// Dropping is trivially idempotent. Results that can't be fed back in, such
+/// as a sample rate returned for an event, are not checked.
+async fn check_idempotent(
+    executable: &Executable,
+    input: &Value,
+    hint: Option<&Value>,
+    first: Option<&Value>,
+    timeout: Duration,
+) -> Result<(Option<bool>, Option<Vec<diff::Change>>), Failure> {
+    let Some(first) = first else {
+        return Ok((Some(true), None));
+    };
+    if std::mem::discriminant(first) != std::mem::discriminant(input) {
+        return Ok((None, None));
+    }
+
+    let second = executable
+        .run(first, hint, None, timeout)
+        .await
+        .map_err(|failure| Failure {
+            error: format!("Idempotency check failed: {}", failure.error),
+            ..failure
+        })?;
+    let changes = diff::diff(first, second.value.as_ref().unwrap_or(&Value::Null));
+
+    if changes.is_empty() {
+        Ok((Some(true), None))
+    } else {
+        Ok((Some(false), Some(changes)))
+    }
+}
+
+/// Refuse events nested deeper than `MAX_EVENT_DEPTH` or with more spans than `MAX_SPANS`
+///
+/// serde_json stops parsing at 128 levels, in the request and in the
+/// transform binary alike, so deeper events never reach user code. A
+/// transaction with a huge number of spans parses, but makes the binary's
+/// parse and any loop over the spans needlessly slow.
+fn check_event_limits(config: &Config, event: &Value) -> Result<(), Failure> {
+    let depth = json_depth(event);
+    if depth > config.max_event_depth {
+        return Err(Failure::bad_request(format!(
+            "Event is nested {} levels deep; at most {} levels are allowed",
+            depth, config.max_event_depth
+        )));
+    }
+    let spans = event["spans"].as_array().map_or(0, Vec::len);
+    if spans > config.max_spans {
+        return Err(Failure::bad_request(format!(
+            "Event has {} spans; at most {} spans are allowed",
+            spans, config.max_spans
+        )));
+    }
+    Ok(())
+}
+
+/// Deepest nesting of objects and arrays in a value, 0 for a scalar
+fn json_depth(value: &Value) -> usize {
+    let children: Box<dyn Iterator<Item = &Value>> = match value {
+        Value::Object(map) => Box::new(map.values()),
+        Value::Array(items) => Box::new(items.iter()),
+        _ => return 0,
+    };
+    1 + children.map(json_depth).max().unwrap_or(0)
+}
+
+/// Hex SHA-256 of a value serialized as compact JSON with every object's keys sorted
+///
+/// The top-level `skip_keys` are left out, like the random `event_id` that
+/// `normalize` fills in, which would otherwise change the hash on every run.
+fn canonical_hash(value: &Value, skip_keys: &[&str]) -> String {
+    let mut canonical = value.clone();
+    if let Some(object) = canonical.as_object_mut() {
+        for key in skip_keys {
+            object.remove(*key);
+        }
+    }
+    canonical.sort_all_objects();
+    Sha256::digest(canonical.to_string().as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Size of a value serialized as compact JSON
+fn json_size(value: &Value) -> usize {
+    serde_json::to_vec(value).map_or(0, |json| json.len())
+}
+
+/// Check that a value has the shape of a Sentry structured log item
+///
+/// A log item needs a string `body`, a known `level`, and, if present,
+/// an object of `attributes`.
+fn validate_log_item(log: &Value) -> Result<(), String> {
+    let obj = log.as_object().ok_or("log item must be a JSON object")?;
+
+    if !obj.get("body").is_some_and(Value::is_string) {
+        return Err("`body` must be a string".to_string());
+    }
+
+    match obj.get("level").and_then(Value::as_str) {
+        Some(level) if LOG_LEVELS.contains(&level) => {}
+        _ => return Err(format!("`level` must be one of: {}", LOG_LEVELS.join(", "))),
+    }
+
+    if obj.get("attributes").is_some_and(|a| !a.is_object()) {
+        return Err("`attributes` must be an object".to_string());
+    }
+
+    Ok(())
+}
+
+/// Validate code syntax without execution
+async fn validate(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ValidationRequest>,
+) -> impl Responder {
+    let (temp_dir, _permit) = match prepare_validation(&state, &http_req, &req).await {
+        Ok(prepared) => prepared,
+        Err(response) => return response,
+    };
+
+    // Check syntax without full compilation
+    // JSON diagnostics are written to stdout and carry every labeled span
+    let check_output = cargo_command(req.toolchain.as_deref())
+        .args(["check", "--quiet", "--offline", "--message-format=json"])
+        .current_dir(temp_dir.path())
+        .output()
+        .await;
+
+    let check_result = match check_output {
+        Ok(output) => output,
+        Err(e) => return validation_service_error(e),
     };
 
-    // Create Cargo.toml for the temporary project
-    let cargo_toml = r#"[package]
-name = "transform"
-version = "0.1.0"
-edition = "2021"
-
-[dependencies]
-serde = { version = "1.0", features = ["derive"] }
-serde_json = "1.0"
-"#;
+    let cargo_stdout = String::from_utf8_lossy(&check_result.stdout);
+    let user_lines = req.code.lines().count();
+    let snippets = |errors: Vec<ValidationError>| -> Vec<ValidationError> {
+        if req.include_snippets {
+            errors
+                .into_iter()
+                .map(|error| error.with_snippet(&req.code))
+                .collect()
+        } else {
+            errors
+        }
+    };
+    let (warnings, suppressed_warnings) = if req.strict_unused {
+        (
+            snippets(parse_rust_warnings(&cargo_stdout, user_lines)),
+            Vec::new(),
+        )
+    } else {
+        (Vec::new(), suppressed_warnings(&cargo_stdout, user_lines))
+    };
 
-    if let Err(e) = fs::write(project_path.join("Cargo.toml"), cargo_toml) {
-        return HttpResponse::InternalServerError().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Failed to write Cargo.toml: {}", e)),
-            traceback: None,
-        });
-    }
+    if !check_result.status.success() {
+        let error_msg = String::from_utf8_lossy(&check_result.stderr).to_string();
+        let errors = snippets(parse_rust_errors(&cargo_stdout, user_lines));
 
-    // Write event JSON to a separate file to avoid escaping issues
-    // This is cleaner than embedding JSON in a Rust string literal
-    if let Err(e) = fs::write(project_path.join("event.json"), &event_json) {
-        return HttpResponse::InternalServerError().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Failed to write event.json: {}", e)),
-            traceback: None,
+        return HttpResponse::Ok().json(ValidationResponse {
+            valid: false,
+            errors: if errors.is_empty() {
+                vec![ValidationError::message_only(error_msg)]
+            } else {
+                errors
+            },
+            warnings,
+            suppressed_warnings,
         });
     }
 
-    // Create main.rs with user's code
-    //
-    // The generated code supports two return types:
-    // 1. Option<Value> - for beforeSend (Some(event), None to drop)
-    // 2. f64 - for tracesSampler (sample rate 0.0-1.0)
-    //
-    // We use a TransformResult enum to unify these at compile time,
-    // and output JSON that the parent process can parse.
-    let main_rs = format!(
-        r##"#![allow(unused_imports)]
-#![allow(unused_variables)]
-#![allow(unused_mut)]
-
-use serde_json::{{json, Value}};
-
-/// Result type that supports both event transforms and sample rates
-enum TransformResult {{
-    Event(Option<Value>),
-    SampleRate(f64),
-}}
+    HttpResponse::Ok().json(ValidationResponse {
+        valid: true,
+        errors: vec![],
+        warnings,
+        suppressed_warnings,
+    })
+}
 
-impl From<Option<Value>> for TransformResult {{
-    fn from(v: Option<Value>) -> Self {{
-        TransformResult::Event(v)
-    }}
-}}
+/// Like /validate, streaming each diagnostic as rustc emits it (SSE)
+///
+/// Sends a `diagnostic` event per error or warning in the user's code, then a
+/// `done` event with the overall `valid` flag. Checks rejected before cargo
+/// runs are answered with a plain /validate response instead.
+async fn validate_stream(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    req: web::Json<ValidationRequest>,
+) -> impl Responder {
+    let (temp_dir, permit) = match prepare_validation(&state, &http_req, &req).await {
+        Ok(prepared) => prepared,
+        Err(response) => return response,
+    };
 
-impl From<Value> for TransformResult {{
-    fn from(v: Value) -> Self {{
-        TransformResult::Event(Some(v))
-    }}
-}}
+    let child = cargo_command(req.toolchain.as_deref())
+        .args(["check", "--quiet", "--offline", "--message-format=json"])
+        .current_dir(temp_dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return validation_service_error(e),
+    };
 
-impl From<f64> for TransformResult {{
-    fn from(v: f64) -> Self {{
-        TransformResult::SampleRate(v)
-    }}
-}}
+    let (events, receiver) = mpsc::unbounded_channel();
+    let user_lines = req.code.lines().count();
+    let snippet_code = req.include_snippets.then(|| req.code.clone());
+    let strict_unused = req.strict_unused;
 
-impl From<f32> for TransformResult {{
-    fn from(v: f32) -> Self {{
-        TransformResult::SampleRate(v as f64)
-    }}
-}}
+    // The check keeps its slot until it finishes, even if the client goes away
+    actix_web::rt::spawn(async move {
+        let _permit = permit;
+        let _temp_dir = temp_dir;
 
-impl From<i32> for TransformResult {{
-    fn from(v: i32) -> Self {{
-        TransformResult::SampleRate(v as f64)
-    }}
-}}
+        let mut stderr = child.stderr.take();
+        let stderr = async move {
+            let mut output = String::new();
+            if let Some(stderr) = &mut stderr {
+                let _ = stderr.read_to_string(&mut output).await;
+            }
+            output
+        };
+        let stdout = child.stdout.take().map(BufReader::new);
+        let diagnostics = async {
+            let mut streamed = 0;
+            let Some(stdout) = stdout else {
+                return streamed;
+            };
+            let mut lines = stdout.lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+                let diag = &message["message"];
+                let level = diag["level"].as_str().unwrap_or_default();
+                // Summaries like "aborting due to 1 previous error" have no spans
+                if message["reason"] != "compiler-message"
+                    || !matches!(level, "error" | "warning")
+                    || diag["spans"].as_array().is_none_or(Vec::is_empty)
+                    || (!strict_unused && wrapper_allowed_lint(diag).is_some())
+                {
+                    continue;
+                }
+                let mut error = diagnostic_error(diag, user_lines);
+                if let Some(code) = &snippet_code {
+                    error = error.with_snippet(code);
+                }
+                let diagnostic = StreamedDiagnostic {
+                    level: level.to_string(),
+                    error,
+                };
+                let _ = events.send(sse_event("diagnostic", &diagnostic));
+                streamed += 1;
+            }
+            streamed
+        };
+        let (stderr, streamed) = futures_util::join!(stderr, diagnostics);
 
-impl From<i64> for TransformResult {{
-    fn from(v: i64) -> Self {{
-        TransformResult::SampleRate(v as f64)
-    }}
-}}
+        let done = match child.wait().await {
+            Ok(status) if status.success() => ValidationDone {
+                valid: true,
+                error: None,
+            },
+            Ok(_) => ValidationDone {
+                valid: false,
+                error: (streamed == 0).then_some(stderr),
+            },
+            Err(e) => ValidationDone {
+                valid: false,
+                error: Some(format!("Validation service error: {}", e)),
+            },
+        };
+        let _ = events.send(sse_event("done", &done));
+    });
 
-impl From<()> for TransformResult {{
-    fn from(_: ()) -> Self {{
-        TransformResult::Event(None)
-    }}
-}}
+    let body = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<_, actix_web::Error>(event), receiver))
+    });
 
-fn main() {{
-    // Read event from file (avoids string escaping issues)
-    let event_json = std::fs::read_to_string("event.json").expect("Failed to read event.json");
-    let mut event: Value = serde_json::from_str(&event_json).expect("Failed to parse event JSON");
-
-    // Execute user's code and convert result to TransformResult
-    // The .into() call handles type conversion automatically
-    let result: TransformResult = (|| {{
-        {}
-    }})().into();
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(body)
+}
 
-    // Output result as JSON
-    match result {{
-        TransformResult::Event(Some(transformed)) => {{
-            println!("{{}}", serde_json::to_string(&transformed).unwrap());
-        }}
-        TransformResult::Event(None) => {{
-            println!("null");
-        }}
-        TransformResult::SampleRate(rate) => {{
-            println!("{{}}", rate);
-        }}
-    }}
-}}
-"##,
-        req.before_send_code
-    );
+fn validation_service_error(e: impl std::fmt::Display) -> HttpResponse {
+    HttpResponse::InternalServerError().json(ValidationResponse {
+        valid: false,
+        errors: vec![ValidationError::message_only(format!(
+            "Validation service error: {}",
+            e
+        ))],
+        ..Default::default()
+    })
+}
 
-    if let Err(e) = fs::write(src_path.join("main.rs"), main_rs) {
-        return HttpResponse::InternalServerError().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Failed to write main.rs: {}", e)),
-            traceback: None,
-        });
+/// Run the checks /validate makes before cargo and write the project to check
+///
+/// Returns the project directory and the build slot checking it holds, or the
+/// response to send if the code was rejected.
+async fn prepare_validation(
+    state: &AppState,
+    http_req: &HttpRequest,
+    req: &ValidationRequest,
+) -> Result<(tempfile::TempDir, BuildPermit), HttpResponse> {
+    if let Err(retry_after) = state.check_rate_limit(http_req) {
+        return Err(rate_limited_response(retry_after).json(ValidationResponse {
+            valid: false,
+            errors: vec![ValidationError::message_only(
+                RATE_LIMITED_MESSAGE.to_string(),
+            )],
+            ..Default::default()
+        }));
     }
 
-    // Compile the user's code
-    let compile_output = Command::new("cargo")
-        .args(["build", "--release", "--quiet"])
-        .current_dir(project_path)
-        .output();
-
-    let compile_result = match compile_output {
-        Ok(output) => output,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(TransformResponse {
-                success: false,
-                transformed_event: None,
-                error: Some(format!("Failed to run cargo: {}", e)),
-                traceback: None,
-            });
+    // Answered like rustc's errors, without waiting for a build slot
+    if let Some(error) = syntax_error(&req.code, &req.modules) {
+        let mut error = ValidationError::from(error);
+        if req.include_snippets {
+            error = error.with_snippet(&req.code);
         }
-    };
-
-    if !compile_result.status.success() {
-        let error_msg = String::from_utf8_lossy(&compile_result.stderr).to_string();
-        return HttpResponse::BadRequest().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Compilation error: {}", extract_error_summary(&error_msg))),
-            traceback: Some(error_msg),
-        });
+        return Err(HttpResponse::Ok().json(ValidationResponse {
+            valid: false,
+            errors: vec![error],
+            ..Default::default()
+        }));
     }
 
-    // Execute the compiled binary from the project directory
-    // This is needed so the binary can find event.json
-    let exec_output = Command::new(project_path.join("target/release/transform"))
-        .current_dir(project_path)
-        .output();
+    let Ok(permit) = state.limiter.acquire(0).await else {
+        return Err(queue_full_response(&state.config).json(ValidationResponse {
+            valid: false,
+            errors: vec![ValidationError::message_only(
+                QUEUE_FULL_MESSAGE.to_string(),
+            )],
+            ..Default::default()
+        }));
+    };
 
-    let exec_result = match exec_output {
-        Ok(output) => output,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(TransformResponse {
-                success: false,
-                transformed_event: None,
-                error: Some(format!("Failed to execute transform: {}", e)),
-                traceback: None,
-            });
+    if let Some(toolchain) = &req.toolchain {
+        if let Err(e) = check_toolchain(toolchain).await {
+            return Err(HttpResponse::BadRequest().json(ValidationResponse {
+                valid: false,
+                errors: vec![ValidationError::message_only(e)],
+                ..Default::default()
+            }));
         }
-    };
+    }
 
-    if !exec_result.status.success() {
-        let error_msg = String::from_utf8_lossy(&exec_result.stderr).to_string();
-        return HttpResponse::InternalServerError().json(TransformResponse {
-            success: false,
-            transformed_event: None,
-            error: Some(format!("Runtime error: {}", error_msg)),
-            traceback: Some(error_msg),
-        });
+    if let Err(e) = validate_modules(&req.modules) {
+        return Err(HttpResponse::BadRequest().json(ValidationResponse {
+            valid: false,
+            errors: vec![ValidationError::message_only(e)],
+            ..Default::default()
+        }));
     }
 
-    // Parse output - can be JSON object, "null", or a number
-    let output_str = String::from_utf8_lossy(&exec_result.stdout).trim().to_string();
+    let violations = api_violations(&state.config, &req.code, &req.modules);
+    if !violations.is_empty() {
+        return Err(HttpResponse::BadRequest().json(ValidationResponse {
+            valid: false,
+            errors: violations.into_iter().map(ValidationError::from).collect(),
+            ..Default::default()
+        }));
+    }
 
-    let transformed_event: Option<Value> = if output_str == "null" {
-        None
-    } else {
-        // Try to parse as JSON (handles both objects and numbers)
-        match serde_json::from_str(&output_str) {
-            Ok(value) => Some(value),
-            Err(e) => {
-                return HttpResponse::InternalServerError().json(TransformResponse {
-                    success: false,
-                    transformed_event: None,
-                    error: Some(format!("Failed to parse result '{}': {}", output_str, e)),
-                    traceback: None,
-                });
-            }
+    let signature = match WrapperSignature::resolve(req.mode, req.signature.as_ref()) {
+        Ok(signature) => signature,
+        Err(e) => {
+            return Err(HttpResponse::BadRequest().json(ValidationResponse {
+                valid: false,
+                errors: vec![ValidationError::message_only(e)],
+                ..Default::default()
+            }));
         }
     };
 
-    HttpResponse::Ok().json(TransformResponse {
-        success: true,
-        transformed_event,
-        error: None,
-        traceback: None,
-    })
-}
-
-/// Extract a concise error summary from Rust compiler output
-fn extract_error_summary(error_msg: &str) -> String {
-    // Find the first "error[E...]:" line for a concise message
-    for line in error_msg.lines() {
-        if line.starts_with("error[E") || line.starts_with("error:") {
-            return line.to_string();
+    if req.mode == TransformMode::BeforeSendLog {
+        if let Some(Err(e)) = req.event.as_ref().map(validate_log_item) {
+            return Err(HttpResponse::BadRequest().json(ValidationResponse {
+                valid: false,
+                errors: vec![ValidationError::message_only(format!(
+                    "Invalid log item: {}",
+                    e
+                ))],
+                ..Default::default()
+            }));
         }
     }
-    // Fallback to first non-empty line
-    error_msg.lines().find(|l| !l.trim().is_empty())
-        .unwrap_or("Unknown compilation error")
-        .to_string()
-}
 
-/// Validate code syntax without execution
-async fn validate(req: web::Json<ValidationRequest>) -> impl Responder {
+    if let Err(e) = disk::check_build_space(state.config.min_free_disk_bytes) {
+        return Err(HttpResponse::ServiceUnavailable().json(ValidationResponse {
+            valid: false,
+            errors: vec![ValidationError::message_only(format!(
+                "{}: {}",
+                INSUFFICIENT_DISK_MESSAGE, e
+            ))],
+            ..Default::default()
+        }));
+    }
+
     // Create a temporary directory for validation
     let temp_dir = match tempfile::tempdir() {
         Ok(dir) => dir,
         Err(e) => {
-            return HttpResponse::InternalServerError().json(ValidationResponse {
-                valid: false,
-                errors: vec![ValidationError {
-                    line: None,
-                    column: None,
-                    message: format!("Validation service error: {}", e),
-                }],
-            });
+            return Err(validation_service_error(e));
         }
     };
 
@@ -393,14 +3728,7 @@ async fn validate(req: web::Json<ValidationRequest>) -> impl Responder {
 
     // Create project structure
     if let Err(e) = fs::create_dir(&src_path) {
-        return HttpResponse::InternalServerError().json(ValidationResponse {
-            valid: false,
-            errors: vec![ValidationError {
-                line: None,
-                column: None,
-                message: format!("Validation service error: {}", e),
-            }],
-        });
+        return Err(validation_service_error(e));
     }
 
     // Create minimal Cargo.toml
@@ -410,143 +3738,702 @@ version = "0.1.0"
 edition = "2021"
 
 [dependencies]
-serde_json = "1.0"
+serde_json = { version = "1.0", features = ["preserve_order", "arbitrary_precision"] }
 "#;
 
     if let Err(e) = fs::write(project_path.join("Cargo.toml"), cargo_toml) {
-        return HttpResponse::InternalServerError().json(ValidationResponse {
-            valid: false,
-            errors: vec![ValidationError {
-                line: None,
-                column: None,
-                message: format!("Validation service error: {}", e),
-            }],
-        });
+        return Err(validation_service_error(e));
     }
 
+    // Seed the input from the provided event, like the transform wrapper does
+    let seed = match &req.event {
+        Some(event) => {
+            if let Err(e) = fs::write(project_path.join("event.json"), event.to_string()) {
+                return Err(validation_service_error(e));
+            }
+            r#"serde_json::from_str(include_str!("../event.json")).expect("Failed to parse event JSON")"#
+        }
+        None if signature.input_type == "Value" => "serde_json::json!({})",
+        None => "Default::default()",
+    };
+
     // Create main.rs with user's code for syntax checking
+    let (crate_attributes, user_code) = hoist_feature_attributes(&req.code);
     let main_rs = format!(
-        r#"#![allow(unused_imports)]
-#![allow(unused_variables)]
-#![allow(unused_mut)]
+        r#"{crate_attributes}
+#![{unused_level}(unused_imports)]
+#![{unused_level}(unused_variables)]
+#![{unused_level}(unused_mut)]
+#![allow(dead_code)]
 
-use serde_json::Value;
+#[allow(unused_imports)] use serde_json::{{json, Value}};
 
 fn main() {{
-    let mut event: Value = serde_json::json!({{}});
-    let _result = (|| {{
-        {}
-    }})();
+    #[allow(unused_mut, unused_variables)] let mut {binding}: {input_type} = {seed};
+    #[allow(unused_variables)] let hint = Value::Null;
+    // Converted like the transform wrapper converts it, so returns no mode accepts fail here too
+    let _result: TransformResult = (|| {return_annotation}{{
+        {code}
+    }})().into();
 }}
-"#,
-        req.code
+{transform_result}{helpers}{modules}"#,
+        transform_result = sandbox::TRANSFORM_RESULT,
+        helpers = WRAPPER_HELPERS,
+        // Always warned about here: without strictUnused they're reported as
+        // suppressed instead of failing anything
+        unused_level = sandbox::unused_lint_level(true),
+        modules = render_modules(&req.modules),
+        binding = signature.binding,
+        input_type = signature.input_type,
+        return_annotation = sandbox::return_annotation(signature.return_type),
+        code = user_code
     );
 
     if let Err(e) = fs::write(src_path.join("main.rs"), main_rs) {
-        return HttpResponse::InternalServerError().json(ValidationResponse {
-            valid: false,
-            errors: vec![ValidationError {
-                line: None,
-                column: None,
-                message: format!("Validation service error: {}", e),
-            }],
-        });
+        return Err(validation_service_error(e));
     }
 
-    // Check syntax without full compilation
-    let check_output = Command::new("cargo")
-        .args(["check", "--quiet"])
-        .current_dir(project_path)
-        .output();
-
-    let check_result = match check_output {
-        Ok(output) => output,
-        Err(e) => {
-            return HttpResponse::InternalServerError().json(ValidationResponse {
+    if let Err(failure) = fetch_dependencies(
+        project_path,
+        req.toolchain.as_deref(),
+        Duration::from_secs(state.config.cargo_lock_timeout_secs),
+    )
+    .await
+    {
+        return Err(
+            failure_response(&state.config, &failure).json(ValidationResponse {
                 valid: false,
-                errors: vec![ValidationError {
-                    line: None,
-                    column: None,
-                    message: format!("Validation service error: {}", e),
-                }],
-            });
-        }
+                errors: vec![ValidationError::message_only(failure.error)],
+                ..Default::default()
+            }),
+        );
+    }
+
+    Ok((temp_dir, permit))
+}
+
+/// Lines of wrapper code preceding the user's code in the validate wrapper
+const VALIDATE_PREAMBLE_LINES: usize = 13;
+
+/// Lines of code shown before and after an error's line in its snippet
+const SNIPPET_CONTEXT_LINES: usize = 2;
+
+/// Indentation the wrapper adds before the first line of user code
+const WRAPPER_INDENT: usize = 8;
+
+/// Parse cargo's JSON diagnostics to extract line/column information
+///
+/// Only the first error is reported, but with all of its labeled spans so the
+/// editor can underline every range involved. Spans outside the user's
+/// `user_lines` lines of code (wrapper or helper modules) are dropped.
+fn parse_rust_errors(cargo_stdout: &str, user_lines: usize) -> Vec<ValidationError> {
+    let first_error = cargo_stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg["reason"] == "compiler-message")
+        .map(|msg| msg["message"].clone())
+        .find(|diag| diag["level"] == "error");
+
+    match first_error {
+        Some(diag) => vec![diagnostic_error(&diag, user_lines)],
+        None => vec![],
+    }
+}
+
+/// Warnings from cargo's JSON diagnostics that point into the user's code
+fn parse_rust_warnings(cargo_stdout: &str, user_lines: usize) -> Vec<ValidationError> {
+    cargo_stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg["reason"] == "compiler-message")
+        .filter(|msg| msg["message"]["level"] == "warning")
+        .map(|msg| diagnostic_error(&msg["message"], user_lines))
+        .filter(|warning| warning.line.is_some())
+        .collect()
+}
+
+/// Lints the transform wrapper allows unless `strictUnused` is set
+const WRAPPER_ALLOWED_LINTS: [&str; 3] = ["unused_imports", "unused_variables", "unused_mut"];
+
+/// The lint behind a diagnostic, if the transform wrapper allows it
+fn wrapper_allowed_lint(diag: &Value) -> Option<&'static str> {
+    let code = diag["code"]["code"].as_str()?;
+    WRAPPER_ALLOWED_LINTS.into_iter().find(|lint| *lint == code)
+}
+
+/// Warnings in the user's code that the transform wrapper's allows would hide
+///
+/// Each names the `#![allow]`, the warning it hides, and the warning's line
+/// in the user's code.
+fn suppressed_warnings(cargo_stdout: &str, user_lines: usize) -> Vec<String> {
+    cargo_stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg["reason"] == "compiler-message")
+        .filter(|msg| msg["message"]["level"] == "warning")
+        .filter_map(|msg| {
+            let diag = &msg["message"];
+            let lint = wrapper_allowed_lint(diag)?;
+            let line = diagnostic_error(diag, user_lines).line?;
+            Some(format!(
+                "#![allow({})] hides {:?} at line {}",
+                lint,
+                diag["message"].as_str().unwrap_or_default(),
+                line
+            ))
+        })
+        .collect()
+}
+
+/// A rustc diagnostic as a validation error in user code coordinates
+fn diagnostic_error(diag: &Value, user_lines: usize) -> ValidationError {
+    let message = diag["rendered"]
+        .as_str()
+        .and_then(|r| r.lines().next())
+        .map(str::to_string)
+        .unwrap_or_else(|| diag["message"].as_str().unwrap_or_default().to_string());
+
+    let spans: Vec<ErrorSpan> = diag["spans"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|span| span["file_name"] == "src/main.rs")
+        .filter_map(to_user_span)
+        .filter(|span| span.end_line <= user_lines)
+        .collect();
+
+    let primary = spans.iter().find(|span| span.primary);
+
+    let help: Vec<&str> = diag["children"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|child| child["level"] == "help")
+        .filter_map(|child| child["message"].as_str())
+        .collect();
+
+    ValidationError {
+        line: primary.map(|span| span.start_line),
+        column: primary.map(|span| span.start_col),
+        message,
+        spans,
+        hint: (!help.is_empty()).then(|| help.join("; ")),
+        snippet: Vec::new(),
+    }
+}
+
+/// Remap a rustc span from wrapper coordinates to the user's code
+///
+/// Returns `None` for spans that fall inside the generated wrapper.
+fn to_user_span(span: &Value) -> Option<ErrorSpan> {
+    let (start_line, start_col) = to_user_position(
+        span["line_start"].as_u64()? as usize,
+        span["column_start"].as_u64()? as usize,
+    )?;
+    let (end_line, end_col) = to_user_position(
+        span["line_end"].as_u64()? as usize,
+        span["column_end"].as_u64()? as usize,
+    )?;
+
+    Some(ErrorSpan {
+        start_line,
+        start_col,
+        end_line,
+        end_col,
+        label: span["label"].as_str().map(str::to_string),
+        primary: span["is_primary"].as_bool().unwrap_or(false),
+    })
+}
+
+/// Convert a 1-based line/column in the wrapper to one in the user's code
+fn to_user_position(line: usize, column: usize) -> Option<(usize, usize)> {
+    let user_line = line
+        .checked_sub(VALIDATE_PREAMBLE_LINES)
+        .filter(|&l| l > 0)?;
+    let user_column = if user_line == 1 {
+        column.saturating_sub(WRAPPER_INDENT).max(1)
+    } else {
+        column
     };
+    Some((user_line, user_column))
+}
 
-    if !check_result.status.success() {
-        let error_msg = String::from_utf8_lossy(&check_result.stderr).to_string();
-        let errors = parse_rust_errors(&error_msg);
+/// Most events accepted by one /transform/batch request
+const MAX_BATCH_EVENTS: usize = 100;
 
-        return HttpResponse::Ok().json(ValidationResponse {
-            valid: false,
-            errors: if errors.is_empty() {
-                vec![ValidationError {
-                    line: None,
-                    column: None,
-                    message: error_msg,
-                }]
-            } else {
-                errors
-            },
+/// Most fuzzed inputs run by one /transform/fuzz request
+const MAX_FUZZ_ITERATIONS: usize = 200;
+
+/// Most runs timed by one /transform/bench request
+const MAX_BENCH_ITERATIONS: usize = 1000;
+
+/// Most distinct failures reported by one /transform/fuzz request
+const MAX_FUZZ_FAILURES: usize = 10;
+
+/// Most variants accepted by one /transform/compare request
+const MAX_COMPARE_VARIANTS: usize = 4;
+
+/// Largest file body accepted by /transform/upload
+const MAX_UPLOAD_BYTES: usize = 8 * 1024 * 1024;
+
+/// Most events accepted in one /transform/upload file or /transform/from-url body
+const MAX_UPLOAD_EVENTS: usize = 1000;
+
+/// Largest body /transform/from-url fetches
+const MAX_FETCH_BYTES: u64 = 8 * 1024 * 1024;
+
+/// How long /transform/from-url waits for the events to download
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most failed lines listed by one /transform/from-url request
+const MAX_FROM_URL_FAILURES: usize = 10;
+
+/// Most stages accepted by one /transform/pipeline request
+const MAX_PIPELINE_STAGES: usize = 8;
+
+/// Error returned when the build queue is at capacity
+const QUEUE_FULL_MESSAGE: &str = "Server is busy: build queue is full, please retry later";
+
+/// Start a 503 response asking the client to retry once the queue drains
+fn queue_full_response(config: &Config) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::ServiceUnavailable();
+    response.insert_header(("Retry-After", config.queue_retry_after_secs.to_string()));
+    response
+}
+
+/// Start the response for a failed sandbox step
+///
+/// Retryable failures carry the same `Retry-After` hint as a full queue.
+fn failure_response(config: &Config, failure: &Failure) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::build(failure.status);
+    if failure.retryable {
+        response.insert_header(("Retry-After", config.queue_retry_after_secs.to_string()));
+    }
+    response
+}
+
+/// Error returned when a client exceeds its request budget
+const RATE_LIMITED_MESSAGE: &str = "Rate limit exceeded, please retry later";
+
+/// Body of a 429 response; the request was rejected before anything ran
+fn rate_limited_failure() -> TransformResponse {
+    TransformResponse {
+        error_kind: Some(ErrorKind::RateLimited),
+        ..TransformResponse::failure(RATE_LIMITED_MESSAGE.to_string(), None)
+    }
+}
+
+/// Start a 429 response telling the client when its next request is allowed
+fn rate_limited_response(retry_after_secs: u64) -> actix_web::HttpResponseBuilder {
+    let mut response = HttpResponse::TooManyRequests();
+    response.insert_header(("Retry-After", retry_after_secs.to_string()));
+    response
+}
+
+/// Determine the client IP used as the rate-limit key
+///
+/// The proxy header is only consulted when configured, since clients can set
+/// it freely when talking to the service directly.
+fn client_ip(http_req: &HttpRequest, trusted_proxy_header: Option<&str>) -> String {
+    let forwarded = trusted_proxy_header
+        .and_then(|header| http_req.headers().get(header))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(|ip| ip.trim().to_string())
+        .filter(|ip| !ip.is_empty());
+
+    forwarded
+        .or_else(|| http_req.peer_addr().map(|addr| addr.ip().to_string()))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Build queue status endpoint
+async fn status(state: web::Data<AppState>) -> impl Responder {
+    let snapshot = state.limiter.snapshot();
+    HttpResponse::Ok().json(StatusResponse {
+        active_builds: snapshot.active,
+        queue_depth: snapshot.queued,
+        max_concurrent_builds: snapshot.max_concurrent,
+        max_queue_depth: snapshot.max_queue_depth,
+        rejected_requests: snapshot.rejected,
+        compilations: state.compilations.load(Ordering::Relaxed),
+    })
+}
+
+/// Build queue metrics in Prometheus text exposition format
+async fn metrics(state: web::Data<AppState>) -> impl Responder {
+    let snapshot = state.limiter.snapshot();
+    let body = format!(
+        "# HELP rust_sdk_active_builds Builds currently running.\n\
+         # TYPE rust_sdk_active_builds gauge\n\
+         rust_sdk_active_builds {}\n\
+         # HELP rust_sdk_queue_depth Requests waiting for a build slot.\n\
+         # TYPE rust_sdk_queue_depth gauge\n\
+         rust_sdk_queue_depth {}\n\
+         # HELP rust_sdk_max_queue_depth Maximum number of requests allowed to wait.\n\
+         # TYPE rust_sdk_max_queue_depth gauge\n\
+         rust_sdk_max_queue_depth {}\n\
+         # HELP rust_sdk_rejected_requests_total Requests rejected because the queue was full.\n\
+         # TYPE rust_sdk_rejected_requests_total counter\n\
+         rust_sdk_rejected_requests_total {}\n",
+        snapshot.active, snapshot.queued, snapshot.max_queue_depth, snapshot.rejected
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+/// Error returned when the build volume is too full to compile
+const INSUFFICIENT_DISK_MESSAGE: &str = "Insufficient disk space for compilation";
+
+/// Longest snippet title accepted, in chars
+const MAX_SNIPPET_TITLE_CHARS: usize = 200;
+
+/// Largest snippet code accepted, in bytes
+const MAX_SNIPPET_CODE_BYTES: usize = 64 * 1024;
+
+/// Largest snippet event accepted, in bytes of compact JSON
+const MAX_SNIPPET_EVENT_BYTES: usize = 64 * 1024;
+
+/// Save a snippet and return the id to share it by
+async fn save_snippet(state: web::Data<AppState>, snippet: web::Json<Snippet>) -> impl Responder {
+    let snippet = snippet.into_inner();
+    if let Err(e) = snippet.validate() {
+        return HttpResponse::BadRequest().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure(format!("Invalid snippet: {}", e), None)
         });
     }
 
+    match state.snippets.insert(snippet) {
+        Ok(id) => HttpResponse::Created().json(SnippetResponse { id, snippet: None }),
+        Err(SaveError::Full) => HttpResponse::InsufficientStorage().json(TransformResponse {
+            error_kind: Some(ErrorKind::Internal),
+            ..TransformResponse::failure("Snippet storage is full".to_string(), None)
+        }),
+        Err(SaveError::Io(e)) => HttpResponse::InternalServerError().json(TransformResponse {
+            error_kind: Some(ErrorKind::Internal),
+            ..TransformResponse::failure(format!("Failed to save snippet: {}", e), None)
+        }),
+    }
+}
+
+/// Load a saved snippet by id
+async fn load_snippet(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    let id = id.into_inner();
+    match state.snippets.get(&id) {
+        Some(snippet) => HttpResponse::Ok().json(SnippetResponse {
+            id,
+            snippet: Some(snippet),
+        }),
+        None => HttpResponse::NotFound().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure("Unknown snippet id".to_string(), None)
+        }),
+    }
+}
+
+/// Check code against the API allowlist without compiling it
+///
+/// Code is always valid when no allowlist is configured and `noStd` is unset.
+async fn lint(state: web::Data<AppState>, req: web::Json<LintRequest>) -> impl Responder {
+    let mut violations = api_violations(&state.config, &req.code, &req.modules);
+    if req.no_std {
+        violations.extend(no_std_violations(&req.code, &req.modules));
+    }
+    let errors: Vec<ValidationError> = violations.into_iter().map(ValidationError::from).collect();
     HttpResponse::Ok().json(ValidationResponse {
-        valid: true,
-        errors: vec![],
+        valid: errors.is_empty(),
+        errors,
+        ..Default::default()
     })
 }
 
-/// Parse Rust compiler errors to extract line/column information
-fn parse_rust_errors(error_msg: &str) -> Vec<ValidationError> {
-    let mut errors = vec![];
-
-    // Rust errors look like: "error[E0308]: ... --> src/main.rs:10:5"
-    for line in error_msg.lines() {
-        if line.contains("error") && line.contains("-->") {
-            // Try to extract line:column from " --> file:line:column"
-            if let Some(pos) = line.find("-->") {
-                let location = &line[pos + 4..];
-                let parts: Vec<&str> = location.split(':').collect();
-                if parts.len() >= 2 {
-                    // Adjust line number to account for wrapper code (8 lines of boilerplate)
-                    let line_num = parts[1].trim().parse::<usize>().ok()
-                        .map(|n| n.saturating_sub(8));
-                    let col_num = parts.get(2).and_then(|c| c.trim().parse::<usize>().ok());
-
-                    errors.push(ValidationError {
-                        line: line_num,
-                        column: col_num,
-                        message: extract_error_summary(error_msg),
-                    });
-                    break;  // Only report first error
-                }
+/// Uses of APIs outside `ALLOWED_APIS`, none when no allowlist is configured
+fn api_violations(
+    config: &Config,
+    code: &str,
+    modules: &BTreeMap<String, String>,
+) -> Vec<allowlist::Violation> {
+    let Some(allowed) = &config.allowed_apis else {
+        return Vec::new();
+    };
+    let (_, user_code) = hoist_feature_attributes(code);
+    allowlist::check(&user_code, modules, allowed)
+}
+
+/// The first error in code or modules that don't tokenize, found without cargo
+fn syntax_error(code: &str, modules: &BTreeMap<String, String>) -> Option<allowlist::Violation> {
+    let (_, user_code) = hoist_feature_attributes(code);
+    syntax::check(&user_code, modules)
+}
+
+/// Uses of std APIs unavailable with `noStd`
+fn no_std_violations(code: &str, modules: &BTreeMap<String, String>) -> Vec<allowlist::Violation> {
+    let (_, user_code) = hoist_feature_attributes(code);
+    allowlist::check_no_std(&user_code, modules)
+}
+
+/// What code may use on this server
+async fn capabilities(state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(CapabilitiesResponse {
+        allowed_apis: state.config.allowed_apis.clone(),
+        no_std_denied_paths: allowlist::NO_STD_DENIED_PATHS,
+        max_spans: state.config.max_spans,
+    })
+}
+
+/// The bundled sample events
+async fn corpus_samples() -> impl Responder {
+    HttpResponse::Ok().json(CorpusResponse {
+        samples: corpus::SAMPLES
+            .iter()
+            .map(|sample| CorpusEntry {
+                sample,
+                event: sample.event(),
+            })
+            .collect(),
+    })
+}
+
+/// Full output of a failed request, by the `errorId` from its response
+async fn error_output(state: web::Data<AppState>, id: web::Path<String>) -> impl Responder {
+    match state.errors.get(&id) {
+        Some(stored) => HttpResponse::Ok().json(stored),
+        None => HttpResponse::NotFound().json(TransformResponse {
+            error_kind: Some(ErrorKind::InvalidInput),
+            ..TransformResponse::failure("Unknown or expired error id".to_string(), None)
+        }),
+    }
+}
+
+/// Health check endpoint
+///
+/// Reports unhealthy (503) when the build volume is too full to compile.
+async fn health(state: web::Data<AppState>) -> impl Responder {
+    let disk_free_bytes = disk::available_bytes(&std::env::temp_dir()).ok();
+
+    match disk::check_build_space(state.config.min_free_disk_bytes) {
+        Ok(_) => HttpResponse::Ok().json(HealthResponse {
+            status: "healthy".to_string(),
+            sdk: "rust".to_string(),
+            disk_free_bytes,
+            error: None,
+        }),
+        Err(e) => HttpResponse::ServiceUnavailable().json(HealthResponse {
+            status: "unhealthy".to_string(),
+            sdk: "rust".to_string(),
+            disk_free_bytes,
+            error: Some(format!("{}: {}", INSUFFICIENT_DISK_MESSAGE, e)),
+        }),
+    }
+}
+
+/// Canonical transform run by /selftest, adding a tag to [`selftest_event`]
+const SELFTEST_CODE: &str = r#"event["tags"]["selftest"] = json!("ok"); Some(event)"#;
+
+fn selftest_event() -> Value {
+    json!({ "message": "selftest", "tags": { "env": "test" } })
+}
+
+/// Build and run a known transform, checking the whole pipeline end to end
+///
+/// Unlike /health this compiles and executes code, so it catches a broken
+/// toolchain or exec backend. The build is cached like any other, so only
+/// the first call pays for compiling. Reports 503 when the output is wrong
+/// or the transform fails, including when `ALLOWED_APIS` rejects `json!`.
+async fn selftest(state: web::Data<AppState>) -> impl Responder {
+    let code = CodeOptions::plain(SELFTEST_CODE.to_string(), TransformMode::default());
+    let event = selftest_event();
+    let mut expected = event.clone();
+    expected["tags"]["selftest"] = json!("ok");
+
+    let started = Instant::now();
+    let outcome = match admit(&state, &code).await {
+        Ok((prepared, _permit)) => match compile(&state, prepared, &code, None).await {
+            Ok(build) => {
+                transform_event(
+                    &state,
+                    &build.executable,
+                    &code,
+                    &OutputOptions::default(),
+                    &event,
+                    None,
+                    None,
+                )
+                .await
             }
-        }
+            Err(failure) => Err(failure),
+        },
+        Err(failure) => Err(failure),
     }
+    .unwrap_or_else(|failure| state.record_failure(failure, true));
+
+    let ok = outcome.transformed_event.as_ref() == Some(&expected);
+    let details = SelfTestDetails {
+        error: outcome
+            .error
+            .or_else(|| (!ok).then(|| "Transformed event did not match".to_string())),
+        expected,
+        actual: outcome.transformed_event,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
 
-    errors
+    let mut response = if ok {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    response.json(SelfTestResponse { ok, details })
 }
 
-/// Health check endpoint
-async fn health() -> impl Responder {
-    HttpResponse::Ok().json(HealthResponse {
-        status: "healthy".to_string(),
-        sdk: "rust".to_string(),
-    })
+/// Every endpoint; all but the health and queue endpoints require the token
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/transform")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform)),
+    )
+    .service(
+        web::resource("/transform/stream")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_stream)),
+    )
+    .service(
+        web::resource("/transform/project")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(export_project)),
+    )
+    .service(
+        web::resource("/transform/batch")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_batch)),
+    )
+    .service(
+        web::resource("/transform/compare")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_compare)),
+    )
+    .service(
+        web::resource("/transform/bench")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_bench)),
+    )
+    .service(
+        web::resource("/transform/quota")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_quota)),
+    )
+    .service(
+        web::resource("/transform/impact")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_impact)),
+    )
+    .service(
+        web::resource("/transform/parity")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_parity)),
+    )
+    .service(
+        web::resource("/transform/synthetic")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_synthetic)),
+    )
+    .service(
+        web::resource("/transform/from-url")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_from_url)),
+    )
+    .service(
+        web::resource("/transform/upload")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_upload)),
+    )
+    .service(
+        web::resource("/transform/pipeline")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_pipeline)),
+    )
+    .service(
+        web::resource("/transform/fuzz")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(transform_fuzz)),
+    )
+    .service(
+        web::resource("/validate")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(validate)),
+    )
+    .service(
+        web::resource("/validate/stream")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(validate_stream)),
+    )
+    .service(
+        web::resource("/ws")
+            .wrap(from_fn(auth::require_token))
+            .route(web::get().to(session::connect)),
+    )
+    .service(
+        web::resource("/lint")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(lint)),
+    )
+    .service(
+        web::resource("/errors/{id}")
+            .wrap(from_fn(auth::require_token))
+            .route(web::get().to(error_output)),
+    )
+    .service(
+        web::resource("/snippets")
+            .wrap(from_fn(auth::require_token))
+            .route(web::post().to(save_snippet)),
+    )
+    .service(
+        web::resource("/snippets/{id}")
+            .wrap(from_fn(auth::require_token))
+            .route(web::get().to(load_snippet)),
+    )
+    .service(
+        web::resource("/selftest")
+            .wrap(from_fn(auth::require_token))
+            .route(web::get().to(selftest)),
+    )
+    .route("/health", web::get().to(health))
+    .route("/status", web::get().to(status))
+    .route("/capabilities", web::get().to(capabilities))
+    .route("/corpus", web::get().to(corpus_samples))
+    .route("/metrics", web::get().to(metrics));
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("Rust SDK service listening on port 5010");
 
-    HttpServer::new(|| {
+    let state = web::Data::new(AppState::new(Config::from_env())?);
+
+    let header_timeout = Duration::from_secs(state.config.request_header_timeout_secs);
+    let recycle_state = state.clone();
+
+    let server = HttpServer::new(move || {
         App::new()
-            .route("/transform", web::post().to(transform))
-            .route("/validate", web::post().to(validate))
-            .route("/health", web::get().to(health))
+            .app_data(state.clone())
+            .app_data(codec::json_config())
+            .wrap(from_fn(timeouts::body_deadline))
+            .wrap(from_fn(request_id::assign))
+            .configure(routes)
     })
+    .client_request_timeout(header_timeout)
     .bind(("0.0.0.0", 5010))?
-    .run()
-    .await
+    .run();
+
+    // Stopping gracefully finishes in-flight requests before the process exits
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        recycle_state.recycle.notified().await;
+        handle.stop(true).await;
+    });
+
+    server.await
 }
@@ -0,0 +1,160 @@
+//! Minimal `multipart/form-data` parsing for file uploads
+//!
+//! Only what browser forms and `curl -F` send is supported: a body buffered
+//! in memory, split on its boundary into parts named by their
+//! `Content-Disposition` header. Part bodies are kept as raw bytes. A body
+//! sent with a `Content-Encoding` is inflated first, as JSON bodies are.
+
+use crate::codec;
+use actix_web::dev::Decompress;
+use actix_web::http::header::CONTENT_TYPE;
+use actix_web::{web, HttpRequest};
+use futures_util::StreamExt;
+
+/// One field or file of a form
+pub struct Part {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Read and split a multipart body, rejecting bodies over `max_bytes` once inflated
+pub async fn read_form(
+    http_req: &HttpRequest,
+    payload: web::Payload,
+    max_bytes: usize,
+) -> Result<Vec<Part>, String> {
+    let boundary = http_req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(boundary)
+        .ok_or_else(|| "Expected a multipart/form-data body with a boundary".to_string())?;
+
+    let encoding = codec::content_encoding(http_req);
+    let mut payload = Decompress::from_headers(payload.into_inner(), http_req.headers());
+    let mut body = Vec::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| match &encoding {
+            Some(encoding) => format!("Upload is not valid {} data: {}", encoding, e),
+            None => format!("Failed to read upload: {}", e),
+        })?;
+        if body.len() + chunk.len() > max_bytes {
+            return Err(match &encoding {
+                Some(encoding) => format!(
+                    "Upload inflates to more than {} bytes once {} is decoded",
+                    max_bytes, encoding
+                ),
+                None => format!("Upload must be at most {} bytes", max_bytes),
+            });
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    parse(&body, &boundary)
+}
+
+/// The boundary parameter of a `multipart/form-data` content type
+fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Split a body into its parts
+fn parse(body: &[u8], boundary: &str) -> Result<Vec<Part>, String> {
+    let malformed = || "Malformed multipart body".to_string();
+    let delimiter = format!("--{}", boundary);
+    let part_end = format!("\r\n{}", delimiter);
+
+    // Anything before the first delimiter is a preamble to ignore
+    let start = find(body, delimiter.as_bytes()).ok_or_else(malformed)?;
+    let mut rest = &body[start + delimiter.len()..];
+
+    let mut parts = Vec::new();
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n").ok_or_else(malformed)?;
+        let headers_end = find(rest, b"\r\n\r\n").ok_or_else(malformed)?;
+        let headers = String::from_utf8_lossy(&rest[..headers_end]);
+        rest = &rest[headers_end + 4..];
+
+        let data_end = find(rest, part_end.as_bytes()).ok_or_else(malformed)?;
+        let data = rest[..data_end].to_vec();
+        rest = &rest[data_end + part_end.len()..];
+
+        let disposition = headers
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, value)| value)
+            .ok_or_else(|| "Every multipart part needs a Content-Disposition header".to_string())?;
+        let name = disposition_param(disposition, "name")
+            .ok_or_else(|| "Every multipart part needs a name".to_string())?;
+        parts.push(Part { name, data });
+    }
+
+    Ok(parts)
+}
+
+/// A parameter like `name="file"` from a `Content-Disposition` value
+fn disposition_param(disposition: &str, param: &str) -> Option<String> {
+    disposition
+        .split(';')
+        .filter_map(|item| item.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(param))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boundaries_come_from_form_data_content_types() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"abc\"").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+    }
+
+    #[test]
+    fn parts_keep_their_names_and_raw_data() {
+        let body = b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.jsonl\"\r\n\r\n{}\r\n{}\r\n--xyz\r\ncontent-disposition: form-data; name=mode\r\n\r\nbeforeSendLog\r\n--xyz--\r\n";
+        let parts = parse(body, "xyz").unwrap();
+        let parts: Vec<_> = parts
+            .iter()
+            .map(|part| (part.name.as_str(), part.data.as_slice()))
+            .collect();
+        assert_eq!(
+            parts,
+            [("file", &b"{}\r\n{}"[..]), ("mode", &b"beforeSendLog"[..])]
+        );
+    }
+
+    #[test]
+    fn parts_need_headers_and_a_disposition() {
+        let body = b"--xyz\r\nContent-Type: text/plain\r\n\r\nhi\r\n--xyz--";
+        assert_eq!(
+            parse(body, "xyz").err().unwrap(),
+            "Every multipart part needs a Content-Disposition header"
+        );
+        assert_eq!(
+            parse(b"--xyz\r\nno headers", "xyz").err().unwrap(),
+            "Malformed multipart body"
+        );
+    }
+}
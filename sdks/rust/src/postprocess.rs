@@ -0,0 +1,389 @@
+//! Post-processing applied to transformed events
+//!
+//! These options run in the service after user code returns, so they are
+//! deterministic and don't require writing any Rust.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A kind of value `pruneEmpty` removes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EmptyValue {
+    Null,
+    EmptyString,
+    EmptyArray,
+    EmptyObject,
+}
+
+/// Everything `pruneEmpty` removes unless told otherwise
+pub const ALL_EMPTY_VALUES: &[EmptyValue] = &[
+    EmptyValue::Null,
+    EmptyValue::EmptyString,
+    EmptyValue::EmptyArray,
+    EmptyValue::EmptyObject,
+];
+
+impl EmptyValue {
+    fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (EmptyValue::Null, Value::Null) => true,
+            (EmptyValue::EmptyString, Value::String(s)) => s.is_empty(),
+            (EmptyValue::EmptyArray, Value::Array(items)) => items.is_empty(),
+            (EmptyValue::EmptyObject, Value::Object(map)) => map.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+/// Which parts of an object survive a `keepKeys` allowlist
+#[derive(Debug)]
+enum KeepTree {
+    /// Keep the whole value
+    All,
+    /// Keep only these keys, each filtered by its own subtree
+    Keys(BTreeMap<String, KeepTree>),
+}
+
+impl KeepTree {
+    fn from_paths(paths: &[String]) -> Self {
+        let mut root = KeepTree::Keys(BTreeMap::new());
+        for path in paths {
+            root.insert(path.split('.').filter(|segment| !segment.is_empty()));
+        }
+        root
+    }
+
+    fn insert<'a>(&mut self, mut segments: impl Iterator<Item = &'a str>) {
+        let KeepTree::Keys(children) = self else {
+            // Already keeping everything below this point
+            return;
+        };
+
+        match segments.next() {
+            None => *self = KeepTree::All,
+            Some(segment) => children
+                .entry(segment.to_string())
+                .or_insert_with(|| KeepTree::Keys(BTreeMap::new()))
+                .insert(segments),
+        }
+    }
+
+    fn apply(&self, value: &mut Value) {
+        let KeepTree::Keys(children) = self else {
+            return;
+        };
+
+        match value {
+            Value::Object(map) => {
+                let kept: Map<String, Value> = std::mem::take(map)
+                    .into_iter()
+                    .filter_map(|(key, mut child)| {
+                        let tree = children.get(&key)?;
+                        tree.apply(&mut child);
+                        Some((key, child))
+                    })
+                    .collect();
+                *map = kept;
+            }
+            // Nested paths apply to every element, e.g. `breadcrumbs.values.message`
+            Value::Array(items) => items.iter_mut().for_each(|item| self.apply(item)),
+            _ => {}
+        }
+    }
+}
+
+/// Remove every key not covered by the allowlist
+///
+/// Plain entries (`"user"`) keep a top-level key entirely; dotted entries
+/// (`"user.id"`) keep only that nested path within the key.
+pub fn keep_keys(event: &mut Value, paths: &[String]) {
+    KeepTree::from_paths(paths).apply(event);
+}
+
+/// Id fields Sentry stores as strings, as JSON Pointers where `*` matches
+/// every element of an array
+pub const ID_FIELDS: &[&str] = &[
+    "/event_id",
+    "/user/id",
+    "/contexts/trace/trace_id",
+    "/contexts/trace/span_id",
+    "/contexts/trace/parent_span_id",
+    "/exception/values/*/thread_id",
+    "/threads/values/*/id",
+    "/spans/*/trace_id",
+    "/spans/*/span_id",
+    "/spans/*/parent_span_id",
+];
+
+/// Convert numeric values of the `ID_FIELDS` to strings, as Sentry's
+/// normalization does; other numbers are left alone
+pub fn coerce_ids(event: &mut Value) {
+    for pointer in ID_FIELDS {
+        let segments: Vec<&str> = pointer.split('/').skip(1).collect();
+        coerce_at(event, &segments);
+    }
+}
+
+fn coerce_at(value: &mut Value, segments: &[&str]) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if let Value::Number(number) = value {
+            *value = Value::String(number.to_string());
+        }
+        return;
+    };
+    match (value, *segment) {
+        (Value::Array(items), "*") => items.iter_mut().for_each(|item| coerce_at(item, rest)),
+        (Value::Object(map), key) => {
+            if let Some(child) = map.get_mut(key) {
+                coerce_at(child, rest);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keep only the last `max` breadcrumbs, the most recent ones
+///
+/// Breadcrumbs are read from `breadcrumbs.values`, or from `breadcrumbs`
+/// itself when sent as a bare list. Events without breadcrumbs are left alone.
+pub fn trim_breadcrumbs(event: &mut Value, max: usize) {
+    let Some(breadcrumbs) = event.get_mut("breadcrumbs") else {
+        return;
+    };
+    let breadcrumbs = match breadcrumbs {
+        Value::Object(map) => map.get_mut("values"),
+        list => Some(list),
+    };
+    if let Some(Value::Array(items)) = breadcrumbs {
+        let excess = items.len().saturating_sub(max);
+        items.drain(..excess);
+    }
+}
+
+/// Recursively remove empty values from objects and arrays
+///
+/// Children are pruned first, so an object left empty by pruning is removed
+/// too when `EmptyObject` is selected. The top-level value itself is kept.
+pub fn prune_empty(value: &mut Value, empty: &[EmptyValue]) {
+    let is_empty = |value: &Value| empty.iter().any(|kind| kind.matches(value));
+    match value {
+        Value::Object(map) => {
+            map.values_mut().for_each(|child| prune_empty(child, empty));
+            map.retain(|_, child| !is_empty(child));
+        }
+        Value::Array(items) => {
+            items.iter_mut().for_each(|item| prune_empty(item, empty));
+            items.retain(|item| !is_empty(item));
+        }
+        _ => {}
+    }
+}
+
+/// Platform the Rust SDK reports for its events
+const SDK_PLATFORM: &str = "native";
+
+/// Fill in the fields a Sentry SDK sets on every event before sending it
+///
+/// Only missing or null fields are set: `event_id` (a new random id),
+/// `timestamp` (now, in seconds since the epoch), `level` (`error`), and
+/// `platform` (`native`). Values that aren't events, like a dropped event or
+/// a sample rate, are left alone.
+///
+/// Returns the fields it filled with a value that differs on every run, the
+/// `event_id` and `timestamp`, so they can be left out of comparisons.
+pub fn normalize(event: &mut Value) -> Vec<&'static str> {
+    let Some(event) = event.as_object_mut() else {
+        return Vec::new();
+    };
+
+    let mut set_default = |key: &'static str, default: fn() -> Value| {
+        let value = event.entry(key).or_insert(Value::Null);
+        let missing = value.is_null();
+        if missing {
+            *value = default();
+        }
+        missing.then_some(key)
+    };
+    let generated = [
+        set_default("event_id", || {
+            Value::from(uuid::Uuid::new_v4().simple().to_string())
+        }),
+        set_default("timestamp", || {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Value::from(now.as_secs_f64())
+        }),
+    ];
+    set_default("level", || Value::from("error"));
+    set_default("platform", || Value::from(SDK_PLATFORM));
+    generated.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn keep_keys_removes_unlisted_top_level_keys() {
+        let mut event = json!({ "message": "hi", "user": { "id": 1 }, "extra": { "a": 1 } });
+        keep_keys(&mut event, &paths(&["message", "user", "missing"]));
+        assert_eq!(event, json!({ "message": "hi", "user": { "id": 1 } }));
+    }
+
+    #[test]
+    fn keep_keys_follows_dotted_paths_into_objects_and_arrays() {
+        let mut event = json!({
+            "user": { "id": 1, "email": "a@example.com" },
+            "breadcrumbs": { "values": [
+                { "message": "click", "data": { "x": 1 } },
+                { "message": "fetch", "category": "http" }
+            ] },
+            "tags": { "env": "prod", "release": "1.0" }
+        });
+        // A whole key wins over a nested path below it, in either order
+        keep_keys(
+            &mut event,
+            &paths(&["user.id", "breadcrumbs.values.message", "tags.env", "tags"]),
+        );
+        assert_eq!(
+            event,
+            json!({
+                "user": { "id": 1 },
+                "breadcrumbs": { "values": [{ "message": "click" }, { "message": "fetch" }] },
+                "tags": { "env": "prod", "release": "1.0" }
+            })
+        );
+    }
+
+    #[test]
+    fn prune_empty_removes_nested_empty_values_but_keeps_siblings() {
+        let mut event = json!({
+            "message": "hi",
+            "culprit": "",
+            "user": { "id": null, "email": "" },
+            "tags": { "env": "prod", "release": null },
+            "extra": { "nested": { "list": [null, "", {}, []] } },
+            "values": [1, null, { "a": [] }, "x"],
+            "zero": 0,
+            "no": false
+        });
+        prune_empty(&mut event, ALL_EMPTY_VALUES);
+        assert_eq!(
+            event,
+            json!({
+                "message": "hi",
+                "tags": { "env": "prod" },
+                "values": [1, "x"],
+                "zero": 0,
+                "no": false
+            })
+        );
+    }
+
+    #[test]
+    fn prune_empty_only_removes_the_selected_kinds() {
+        let mut event = json!({ "a": null, "b": "", "c": [], "d": { "e": null } });
+        prune_empty(&mut event, &[EmptyValue::Null]);
+        assert_eq!(event, json!({ "b": "", "c": [], "d": {} }));
+
+        let mut event = json!({ "a": null, "b": "", "c": [""], "d": {} });
+        prune_empty(
+            &mut event,
+            &[EmptyValue::EmptyString, EmptyValue::EmptyArray],
+        );
+        assert_eq!(event, json!({ "a": null, "d": {} }));
+    }
+
+    #[test]
+    fn prune_empty_keeps_the_top_level_value() {
+        let mut event = json!({ "a": null });
+        prune_empty(&mut event, ALL_EMPTY_VALUES);
+        assert_eq!(event, json!({}));
+    }
+
+    #[test]
+    fn normalize_fills_in_missing_sdk_defaults() {
+        let mut event = json!({ "message": "hi", "level": null });
+        assert_eq!(normalize(&mut event), ["event_id", "timestamp"]);
+
+        let event_id = event["event_id"].as_str().unwrap();
+        assert_eq!(event_id.len(), 32);
+        assert!(event_id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert!(event["timestamp"].as_f64().unwrap() > 1_600_000_000.0);
+        assert_eq!(event["level"], "error");
+        assert_eq!(event["platform"], "native");
+        assert_eq!(event["message"], "hi");
+    }
+
+    #[test]
+    fn normalize_keeps_existing_values_and_non_events() {
+        let original = json!({
+            "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0",
+            "timestamp": 1.5,
+            "level": "info",
+            "platform": "python"
+        });
+        let mut event = original.clone();
+        assert!(normalize(&mut event).is_empty());
+        assert_eq!(event, original);
+
+        let mut rate = json!(0.5);
+        assert!(normalize(&mut rate).is_empty());
+        assert_eq!(rate, json!(0.5));
+    }
+
+    #[test]
+    fn trim_breadcrumbs_keeps_the_most_recent() {
+        let mut event = json!({ "breadcrumbs": { "values": (0..10).collect::<Vec<_>>() } });
+        trim_breadcrumbs(&mut event, 3);
+        assert_eq!(event, json!({ "breadcrumbs": { "values": [7, 8, 9] } }));
+
+        let mut event = json!({ "breadcrumbs": [1, 2, 3, 4] });
+        trim_breadcrumbs(&mut event, 2);
+        assert_eq!(event, json!({ "breadcrumbs": [3, 4] }));
+    }
+
+    #[test]
+    fn trim_breadcrumbs_leaves_short_or_missing_lists_alone() {
+        for original in [
+            json!({ "breadcrumbs": { "values": [1, 2] } }),
+            json!({ "breadcrumbs": {} }),
+            json!({ "message": "hi" }),
+        ] {
+            let mut event = original.clone();
+            trim_breadcrumbs(&mut event, 3);
+            assert_eq!(event, original);
+        }
+    }
+
+    #[test]
+    fn coerce_ids_stringifies_only_numeric_id_fields() {
+        let mut event = json!({
+            "event_id": 42,
+            "user": { "id": 12345678901234567890u64, "age": 7 },
+            "threads": { "values": [{ "id": 1 }, { "id": "main" }] },
+            "spans": [{ "span_id": 3, "start_timestamp": 1.5 }],
+            "extra": { "id": 9 }
+        });
+        coerce_ids(&mut event);
+        assert_eq!(
+            event,
+            json!({
+                "event_id": "42",
+                "user": { "id": "12345678901234567890", "age": 7 },
+                "threads": { "values": [{ "id": "1" }, { "id": "main" }] },
+                "spans": [{ "span_id": "3", "start_timestamp": 1.5 }],
+                "extra": { "id": 9 }
+            })
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Per-client token-bucket rate limiting
+//!
+//! Each client key (normally the client IP) gets a bucket holding up to
+//! `burst` tokens that refills at a steady rate. Every request spends one
+//! token; a request arriving at an empty bucket is rejected with the time
+//! until the next token becomes available.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before idle, fully refilled ones are pruned
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token-bucket rate limiter keyed by client
+pub struct RateLimiter {
+    tokens_per_sec: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create a limiter allowing `per_minute` requests with bursts of `burst`
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        RateLimiter {
+            tokens_per_sec: f64::from(per_minute) / 60.0,
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Spend a token for `key`, or return how long to wait for the next one
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            let (rate, burst) = (self.tokens_per_sec, self.burst);
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens + elapsed * rate < burst
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            last_refill: now,
+        });
+
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.tokens_per_sec).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.tokens_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_are_allowed_then_rejected_until_refill() {
+        let limiter = RateLimiter::new(60, 2);
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_ok());
+
+        let wait = limiter.check("a").unwrap_err();
+        assert!(
+            wait > Duration::from_millis(900) && wait <= Duration::from_secs(1),
+            "{:?}",
+            wait
+        );
+    }
+
+    #[test]
+    fn clients_have_separate_buckets() {
+        let limiter = RateLimiter::new(1, 1);
+        assert!(limiter.check("a").is_ok());
+        assert!(limiter.check("a").is_err());
+        assert!(limiter.check("b").is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+//! Operator-configured redaction of transformed events
+//!
+//! Rules are loaded from the JSON file at `REDACTION_RULES_PATH` and applied
+//! to every transformed event after user code runs, so org-wide scrubbing
+//! holds whatever the submitted code does. Each rule is a JSON Pointer, where
+//! a `*` segment matches every key or array index, plus an action:
+//!
+//! ```json
+//! [
+//!   { "name": "user-email", "pointer": "/user/email", "action": "mask" },
+//!   { "pointer": "/request/headers/*", "action": "remove" }
+//! ]
+//! ```
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Replacement for masked values, matching Sentry's own scrubbing
+const MASK: &str = "[Filtered]";
+
+/// What a rule does to the values it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RedactionAction {
+    /// Delete the key or array element
+    Remove,
+    /// Replace the value with `[Filtered]`
+    Mask,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleFile {
+    /// Reported in `appliedRedactions`, defaults to the pointer
+    name: Option<String>,
+    pointer: String,
+    action: RedactionAction,
+}
+
+#[derive(Debug)]
+struct RedactionRule {
+    name: String,
+    segments: Vec<String>,
+    action: RedactionAction,
+}
+
+/// Redaction rules applied to every transformed event
+#[derive(Debug, Default)]
+pub struct Redactions {
+    rules: Vec<RedactionRule>,
+}
+
+impl Redactions {
+    /// Load the rules at `path`, or none when unset
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Redactions::default());
+        };
+
+        let rules: Vec<RuleFile> = serde_json::from_slice(&fs::read(path)?)?;
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let Some(pointer) = rule.pointer.strip_prefix('/') else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("redaction pointer must start with '/': {:?}", rule.pointer),
+                    ));
+                };
+                Ok(RedactionRule {
+                    segments: pointer
+                        .split('/')
+                        .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+                        .collect(),
+                    name: rule.name.unwrap_or(rule.pointer),
+                    action: rule.action,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Redactions { rules })
+    }
+
+    /// Apply every rule to `event`, returning the names of those that matched
+    pub fn apply(&self, event: &mut Value) -> Vec<String> {
+        self.rules
+            .iter()
+            .filter(|rule| redact(event, &rule.segments, rule.action))
+            .map(|rule| rule.name.clone())
+            .collect()
+    }
+}
+
+/// Apply an action to everything the remaining segments match below `value`
+fn redact(value: &mut Value, segments: &[String], action: RedactionAction) -> bool {
+    match segments {
+        [] => false,
+        [last] if action == RedactionAction::Remove => remove_children(value, last),
+        [last] => {
+            let children = matching_children(value, last);
+            let fired = !children.is_empty();
+            for child in children {
+                *child = Value::String(MASK.to_string());
+            }
+            fired
+        }
+        [segment, rest @ ..] => {
+            let mut fired = false;
+            for child in matching_children(value, segment) {
+                fired |= redact(child, rest, action);
+            }
+            fired
+        }
+    }
+}
+
+fn matching_children<'a>(value: &'a mut Value, segment: &str) -> Vec<&'a mut Value> {
+    let wildcard = segment == "*";
+    match value {
+        Value::Object(map) => map
+            .iter_mut()
+            .filter(|(key, _)| wildcard || *key == segment)
+            .map(|(_, child)| child)
+            .collect(),
+        Value::Array(items) => items
+            .iter_mut()
+            .enumerate()
+            .filter(|(index, _)| wildcard || index.to_string() == segment)
+            .map(|(_, item)| item)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn remove_children(value: &mut Value, segment: &str) -> bool {
+    match value {
+        Value::Object(map) if segment == "*" => {
+            let fired = !map.is_empty();
+            map.clear();
+            fired
+        }
+        Value::Object(map) => map.shift_remove(segment).is_some(),
+        Value::Array(items) if segment == "*" => {
+            let fired = !items.is_empty();
+            items.clear();
+            fired
+        }
+        Value::Array(items) => match segment.parse::<usize>() {
+            Ok(index) if index < items.len() => {
+                items.remove(index);
+                true
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redactions(rules: Value) -> io::Result<Redactions> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.json");
+        fs::write(&path, rules.to_string()).unwrap();
+        Redactions::load(Some(&path))
+    }
+
+    #[test]
+    fn rules_mask_or_remove_what_they_match() {
+        let redactions = redactions(json!([
+            { "name": "user-email", "pointer": "/user/email", "action": "mask" },
+            { "pointer": "/request/headers/*", "action": "remove" },
+            { "pointer": "/breadcrumbs/*/data", "action": "mask" },
+            { "pointer": "/extra/missing", "action": "remove" }
+        ]))
+        .unwrap();
+        let mut event = json!({
+            "user": { "id": 1, "email": "a@example.com" },
+            "request": { "url": "/", "headers": { "Cookie": "x", "Host": "y" } },
+            "breadcrumbs": [{ "data": { "q": 1 } }, { "message": "hi" }]
+        });
+
+        assert_eq!(
+            redactions.apply(&mut event),
+            ["user-email", "/request/headers/*", "/breadcrumbs/*/data"]
+        );
+        assert_eq!(
+            event,
+            json!({
+                "user": { "id": 1, "email": "[Filtered]" },
+                "request": { "url": "/", "headers": {} },
+                "breadcrumbs": [{ "data": "[Filtered]" }, { "message": "hi" }]
+            })
+        );
+    }
+
+    #[test]
+    fn pointers_must_be_absolute() {
+        let error = redactions(json!([{ "pointer": "user/email", "action": "mask" }])).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(Redactions::load(None).unwrap().rules.is_empty());
+    }
+}
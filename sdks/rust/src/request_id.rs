@@ -0,0 +1,59 @@
+//! Request ids for correlating responses with the requests that caused them
+//!
+//! Every response carries an `X-Request-Id` header: the one the client sent,
+//! or a generated id. /transform also accepts a `requestId` body field, which
+//! takes precedence, and echoes the id in its response body.
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest};
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied id accepted; longer ones are replaced
+const MAX_REQUEST_ID_CHARS: usize = 128;
+
+/// The id assigned to the current request, kept in its extensions
+#[derive(Clone)]
+struct RequestId(String);
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_CHARS && id.chars().all(|c| c.is_ascii_graphic())
+}
+
+/// Middleware assigning each request an id and returning it as `X-Request-Id`
+pub async fn assign(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req
+        .headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    req.extensions_mut().insert(RequestId(id));
+
+    let mut res = next.call(req).await?;
+    // Read back after the handler, which may have replaced it from the body
+    let id = res.request().extensions().get::<RequestId>().cloned();
+    if let Some(value) = id.and_then(|RequestId(id)| HeaderValue::from_str(&id).ok()) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    Ok(res)
+}
+
+/// The request's id, replaced by `body_id` when the body supplied a valid one
+pub fn resolve(req: &HttpRequest, body_id: Option<&str>) -> String {
+    if let Some(id) = body_id.filter(|id| is_valid(id)) {
+        req.extensions_mut().insert(RequestId(id.to_string()));
+        return id.to_string();
+    }
+    req.extensions()
+        .get::<RequestId>()
+        .map(|RequestId(id)| id.clone())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
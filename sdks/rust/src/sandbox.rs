@@ -0,0 +1,2551 @@
+//! Temporary Cargo projects that compile and run user code
+//!
+//! A [`TransformProject`] wraps the user's code in a generated `main.rs`,
+//! builds it in release mode, and runs the binary against an input event.
+//! Failures carry the HTTP status they should be reported with.
+
+use crate::diagnostics::{self, Level};
+use crate::footprint;
+use actix_web::http::StatusCode;
+use proc_macro2::LineColumn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use syn::parse::Parser;
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{Expr, ExprClosure, ExprReturn, Item, Pat, PatIdent, Stmt};
+use tempfile::TempDir;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+/// Toolchain Miri runs with, as it is only distributed for nightly
+pub const MIRI_TOOLCHAIN: &str = "nightly";
+
+/// Environment variable carrying the per-request result sentinel to the binary
+pub const RESULT_SENTINEL_VAR: &str = "TRANSFORM_RESULT_SENTINEL";
+
+/// Environment variable carrying the SDK hint, bound as `hint` for the user code
+pub const HINT_VAR: &str = "TRANSFORM_HINT";
+
+/// Largest hint accepted, as serialized JSON; it travels in the environment
+const MAX_HINT_BYTES: usize = 64 * 1024;
+
+/// Toolchain channels that may be requested via the `toolchain` field
+const SUPPORTED_TOOLCHAINS: &[&str] = &["stable", "beta", "nightly"];
+
+/// A global allocator that may be requested via the `allocator` field
+pub struct Allocator {
+    name: &'static str,
+    /// Line added to the generated crate's `[dependencies]`, if any
+    dependency: Option<&'static str>,
+    /// Type installed as the `#[global_allocator]`, constructed as a unit value
+    global: &'static str,
+}
+
+/// Allocators the transform can be built with; only `system` supports wasm
+const SUPPORTED_ALLOCATORS: &[Allocator] = &[
+    Allocator {
+        name: "system",
+        dependency: None,
+        global: "std::alloc::System",
+    },
+    Allocator {
+        name: "mimalloc",
+        dependency: Some(r#"mimalloc = { version = "0.1", default-features = false }"#),
+        global: "mimalloc::MiMalloc",
+    },
+    Allocator {
+        name: "jemalloc",
+        dependency: Some(r#"tikv-jemallocator = "0.6""#),
+        global: "tikv_jemallocator::Jemalloc",
+    },
+];
+
+/// Maximum number of helper modules a request may supply
+const MAX_MODULES: usize = 16;
+
+/// Names helper modules may not take because they shadow crates in scope
+const RESERVED_MODULE_NAMES: &[&str] = &["std", "core", "alloc", "serde", "serde_json", "preamble"];
+
+/// Rust keywords, which are never valid module names
+pub const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "try", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Target user code is compiled for under the wasm backend
+const WASM_TARGET: &str = "wasm32-wasip1";
+
+/// Message cargo prints while another process holds one of its file locks
+const LOCK_CONTENTION_MARKER: &str = "Blocking waiting for file lock";
+
+/// Held while fetching dependencies, so concurrent cold builds take turns
+/// instead of contending on cargo's package cache lock
+static FETCH_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Helper functions available to user code in every generated wrapper
+///
+/// Appended after `main` so they don't shift line numbers of user code.
+pub const WRAPPER_HELPERS: &str = r#"
+/// Iterate mutably over the exceptions in `exception.values`
+#[allow(dead_code)]
+fn exceptions_mut(event: &mut Value) -> impl Iterator<Item = &mut Value> {
+    event
+        .pointer_mut("/exception/values")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+}
+
+/// Iterate mutably over the stack frames of every exception in the chain
+#[allow(dead_code)]
+fn frames_mut(event: &mut Value) -> impl Iterator<Item = &mut Value> {
+    exceptions_mut(event).flat_map(|exception| {
+        exception
+            .pointer_mut("/stacktrace/frames")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
+    })
+}
+
+/// Reason recorded by `drop_with_reason`, reported when the input is dropped
+#[allow(dead_code)]
+static DROP_REASON: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Drop the input and record why, e.g. `return drop_with_reason("level too low");`
+#[allow(dead_code)]
+fn drop_with_reason(reason: impl Into<String>) -> Option<Value> {
+    *DROP_REASON.lock().unwrap() = Some(reason.into());
+    None
+}
+
+/// Counters incremented by `count`, reported after every run
+#[allow(dead_code)]
+static COUNTERS: std::sync::Mutex<std::collections::BTreeMap<String, u64>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// Increment a named counter, e.g. `count("dropped_db_errors");`
+#[allow(dead_code)]
+fn count(name: impl Into<String>) {
+    *COUNTERS.lock().unwrap().entry(name.into()).or_insert(0) += 1;
+}
+"#;
+
+/// Stable category of a failure, reported as `errorKind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The user's code failed to compile
+    Compile,
+    /// The user's code panicked, exited abnormally, or returned an invalid result
+    Runtime,
+    /// A step gave up waiting, or user code ran past the execution timeout
+    Timeout,
+    /// The user's code, or the build compiling it, ran out of memory
+    Oom,
+    /// The server failed or was too busy, independent of the user's code
+    Internal,
+    /// The request itself was rejected before anything ran
+    InvalidInput,
+    /// The client sent too many requests; retry after `Retry-After` seconds
+    RateLimited,
+    /// A host the server fetched from on the client's behalf failed
+    Upstream,
+}
+
+/// A sandbox step that failed, with the status to report it under
+#[derive(Debug)]
+pub struct Failure {
+    pub status: StatusCode,
+    pub error: String,
+    pub traceback: Option<String>,
+    /// Whether the client should retry later (reported with `Retry-After`)
+    pub retryable: bool,
+    /// Category of the failure
+    pub kind: ErrorKind,
+    /// The input binding and message at the moment user code panicked, when captured
+    pub panic_context: Option<Box<Value>>,
+}
+
+impl Failure {
+    /// A failure categorized by its status: invalid input for 4xx, internal otherwise
+    pub fn new(status: StatusCode, error: String) -> Self {
+        let kind = if status.is_client_error() {
+            ErrorKind::InvalidInput
+        } else {
+            ErrorKind::Internal
+        };
+        Failure {
+            status,
+            error,
+            traceback: None,
+            retryable: false,
+            kind,
+            panic_context: None,
+        }
+    }
+
+    /// A transient failure, reported as a 503 the client should retry
+    pub fn retryable(error: String) -> Self {
+        Failure {
+            retryable: true,
+            ..Failure::new(StatusCode::SERVICE_UNAVAILABLE, error)
+        }
+    }
+
+    pub fn bad_request(error: String) -> Self {
+        Failure::new(StatusCode::BAD_REQUEST, error)
+    }
+
+    pub fn internal(error: String) -> Self {
+        Failure::new(StatusCode::INTERNAL_SERVER_ERROR, error)
+    }
+
+    pub fn with_traceback(mut self, traceback: String) -> Self {
+        self.traceback = Some(traceback);
+        self
+    }
+
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.kind = kind;
+        self
+    }
+}
+
+/// Compile progress, reported as cargo starts compiling each crate
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BuildProgress {
+    pub phase: &'static str,
+    pub done: usize,
+    pub total: usize,
+}
+
+/// What goes into the generated transform crate
+pub struct WrapperOptions<'a> {
+    /// User code, run as the body of a closure
+    pub code: &'a str,
+    /// Name the input is bound to (`event`, `log`, or a custom signature's binding)
+    pub binding: &'a str,
+    /// Rust type the input is deserialized into
+    pub input_type: &'a str,
+    /// Return type to annotate the user code with, inferred when `None`
+    pub return_type: Option<&'a str>,
+    /// Helper modules (name -> source)
+    pub modules: &'a BTreeMap<String, String>,
+    /// Build with `panic = "abort"` instead of unwinding
+    pub panic_abort: bool,
+    /// How the compiled code is executed
+    pub backend: ExecBackend,
+    /// Global allocator to build with instead of the default
+    pub allocator: Option<&'static Allocator>,
+    /// Install a panic hook reporting the input binding's state when user code panics
+    pub capture_panic_context: bool,
+    /// Items placed at the crate root, in scope for the user code
+    pub preamble: Option<&'a str>,
+    /// Bind `state: &mut State` for the user code, read before the run and reported after it
+    pub shared_state: bool,
+    /// Wrap the global allocator to count allocations made while the user code runs
+    pub count_allocations: bool,
+    /// Report the wall-clock time `main` was entered, to measure spawn overhead
+    pub report_entry_time: bool,
+    /// Warn about unused imports, variables, and `mut`s instead of allowing them
+    pub strict_unused: bool,
+    /// Bind a `Value` input as a [`footprint::RECORDED_EVENT`], reporting the
+    /// top-level keys the code reads and writes
+    pub record_footprint: bool,
+}
+
+/// How compiled user code is executed (`EXEC_BACKEND`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecBackend {
+    /// A native binary run as a child process (default)
+    #[default]
+    Native,
+    /// A `wasm32-wasip1` module run in wasmtime with no filesystem or network access
+    Wasm,
+}
+
+impl FromStr for ExecBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "native" => Ok(ExecBackend::Native),
+            "wasm" => Ok(ExecBackend::Wasm),
+            other => Err(format!("Unknown exec backend '{}'", other)),
+        }
+    }
+}
+
+/// What a run of the transform binary printed
+pub struct RunOutput {
+    /// The transformed input, a sample rate, or `None` when it was dropped
+    pub value: Option<Value>,
+    /// Reason recorded via `drop_with_reason`, only set when the input was dropped
+    pub drop_reason: Option<String>,
+    /// Counters incremented via `count` during the run
+    pub counters: BTreeMap<String, u64>,
+    /// Whether the user code evaluated to `()`, which drops the input
+    pub returned_unit: bool,
+    /// The shared state after the run, for builds with `shared_state`
+    pub state: Option<Value>,
+    /// Allocations made by the user code, for builds with `count_allocations`
+    pub allocations: Option<AllocationCounts>,
+    /// How long the user code ran, excluding process startup and (de)serialization
+    pub exec_time: Duration,
+    /// What the user code produced, as the wrapper converted it
+    pub result_type: Option<ResultType>,
+    /// Wall time from spawning the process until it exited
+    pub process_time: Duration,
+    /// Time from spawning the process until `main` was entered, for builds with
+    /// `report_entry_time`
+    pub spawn_time: Option<Duration>,
+    /// Top-level keys of the input the code read, sorted, for builds with
+    /// `record_footprint`
+    pub accessed_paths: Option<Vec<String>>,
+    /// Top-level keys of the input the code wrote, sorted, for builds with
+    /// `record_footprint`
+    pub mutated_paths: Option<Vec<String>>,
+}
+
+/// Which kind of result the user code's return value converted to
+///
+/// A bare number is a sample rate while `Some(json!(0.5))` is an event, which
+/// the printed result alone can't tell apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ResultType {
+    Event,
+    /// `None`, or code ending in a statement
+    Dropped,
+    SampleRate,
+}
+
+/// Heap allocations made while the user code ran
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct AllocationCounts {
+    /// Calls to `alloc`, `alloc_zeroed`, and `realloc`
+    pub count: u64,
+    /// Bytes requested by those calls; a `realloc` counts its full new size
+    pub bytes: u64,
+}
+
+/// Metadata the wrapper prints after the result line
+#[derive(Debug, Default, Deserialize)]
+struct RunReport {
+    #[serde(rename = "dropReason")]
+    drop_reason: Option<String>,
+    #[serde(default)]
+    counters: BTreeMap<String, u64>,
+    #[serde(rename = "returnedUnit", default)]
+    returned_unit: bool,
+    state: Option<Value>,
+    allocations: Option<AllocationCounts>,
+    #[serde(rename = "execNanos", default)]
+    exec_nanos: u64,
+    #[serde(rename = "resultType")]
+    result_type: Option<ResultType>,
+    /// Nanoseconds since the Unix epoch when `main` was entered
+    #[serde(rename = "enteredAtNanos")]
+    entered_at_nanos: Option<u64>,
+    #[serde(rename = "accessedPaths")]
+    accessed_paths: Option<Vec<String>>,
+    #[serde(rename = "mutatedPaths")]
+    mutated_paths: Option<Vec<String>>,
+}
+
+/// How to build a transform crate
+pub struct BuildOptions<'a> {
+    /// Toolchain to build with via rustup
+    pub toolchain: Option<&'a str>,
+    /// Collect the crates compiled for the build
+    pub include_build_info: bool,
+    /// Record how long each dependency took to compile
+    pub include_timings: bool,
+    /// How long to wait for cargo's package cache before giving up
+    pub lock_timeout: Duration,
+    /// Codegen flags passed via `RUSTFLAGS`, already checked by [`validate_rustflags`]
+    pub rustflags: &'a [String],
+}
+
+/// What a successful build reports besides the binary
+#[derive(Debug, Default)]
+pub struct BuildOutput {
+    /// Crates compiled for the build, when `include_build_info` is set
+    pub build_info: Option<Value>,
+    /// Compile time per dependency, slowest first, when `include_timings` is set
+    pub dep_timings: Option<Vec<DepTiming>>,
+    /// Compiler warnings for the generated crate, for projects with `strict_unused`
+    pub warnings: Option<Vec<String>>,
+}
+
+/// Time spent compiling one dependency, including its build script
+#[derive(Debug, Clone, Serialize)]
+pub struct DepTiming {
+    #[serde(rename = "crate")]
+    pub name: String,
+    pub seconds: f64,
+}
+
+/// A generated transform crate in a temporary directory
+pub struct TransformProject {
+    dir: TempDir,
+    backend: ExecBackend,
+    /// Whether runs read and report shared state
+    shared_state: bool,
+    /// Name the user code's input is bound to, for error messages
+    binding: String,
+    /// Whether builds report compiler warnings, which the wrapper otherwise mostly allows
+    strict_unused: bool,
+    /// Hash of the generated sources, extended with build options for cache keys
+    fingerprint: Sha256,
+}
+
+/// A built transform binary, runnable against any number of inputs
+pub struct Executable {
+    path: PathBuf,
+    backend: ExecBackend,
+    shared_state: bool,
+    /// Project interpreted by Miri on each run instead of running `path`
+    miri_project: Option<TempDir>,
+}
+
+impl TransformProject {
+    /// Write `Cargo.toml` and the wrapper `main.rs` for the user's code
+    pub fn create(options: &WrapperOptions) -> Result<Self, Failure> {
+        // Create a temporary directory for compilation
+        let dir = tempfile::tempdir()
+            .map_err(|e| Failure::internal(format!("Failed to create temp directory: {}", e)))?;
+
+        let project_path = dir.path();
+        let src_path = project_path.join("src");
+
+        // Create project structure
+        fs::create_dir(&src_path)
+            .map_err(|e| Failure::internal(format!("Failed to create src directory: {}", e)))?;
+
+        // Create Cargo.toml for the temporary project
+        //
+        // The sandbox never needs unwinding, so panic = "abort" is the default:
+        // it builds faster and produces smaller binaries. The wasm target only
+        // supports aborting.
+        let panic_strategy = if options.panic_abort || options.backend == ExecBackend::Wasm {
+            "abort"
+        } else {
+            "unwind"
+        };
+        let allocator_dependency = options
+            .allocator
+            .and_then(|allocator| allocator.dependency)
+            .map(|dependency| format!("{}\n", dependency))
+            .unwrap_or_default();
+        let cargo_toml = format!(
+            r#"[package]
+name = "transform"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+serde = {{ version = "1.0", features = ["derive"] }}
+serde_json = {{ version = "1.0", features = ["preserve_order", "arbitrary_precision"] }}
+{allocator_dependency}
+[profile.release]
+panic = "{panic_strategy}"
+"#
+        );
+
+        fs::write(project_path.join("Cargo.toml"), &cargo_toml)
+            .map_err(|e| Failure::internal(format!("Failed to write Cargo.toml: {}", e)))?;
+
+        let main_rs = render_wrapper(options);
+        fs::write(src_path.join("main.rs"), &main_rs)
+            .map_err(|e| Failure::internal(format!("Failed to write main.rs: {}", e)))?;
+
+        let mut fingerprint = Sha256::new();
+        for part in [&cargo_toml, &main_rs] {
+            fingerprint.update(part.as_bytes());
+            fingerprint.update([0]);
+        }
+        fingerprint.update([options.backend as u8]);
+
+        Ok(TransformProject {
+            dir,
+            backend: options.backend,
+            shared_state: options.shared_state,
+            binding: options.binding.to_string(),
+            strict_unused: options.strict_unused,
+            fingerprint,
+        })
+    }
+
+    /// The generated `Cargo.toml` and `src/main.rs`, by path relative to the project
+    pub fn files(&self) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        ["Cargo.toml", "src/main.rs"]
+            .into_iter()
+            .map(|path| Ok((path.to_string(), fs::read(self.dir.path().join(path))?)))
+            .collect()
+    }
+
+    /// Key identifying the binary this project builds with the given options
+    ///
+    /// Projects with identical sources, backend, and build options get the
+    /// same key, so a binary built for one can be reused for the others.
+    pub fn cache_key(&self, options: &BuildOptions) -> String {
+        let mut fingerprint = self.fingerprint.clone();
+        fingerprint.update(options.toolchain.unwrap_or_default().as_bytes());
+        fingerprint.update([
+            0,
+            u8::from(options.include_build_info),
+            u8::from(options.include_timings),
+        ]);
+        for flag in options.rustflags {
+            fingerprint.update([0]);
+            fingerprint.update(flag.as_bytes());
+        }
+        format!("{:x}", fingerprint.finalize())
+    }
+
+    /// The binary produced by a successful `build`
+    pub fn executable(&self) -> Executable {
+        let path = match self.backend {
+            ExecBackend::Native => self.dir.path().join("target/release/transform"),
+            ExecBackend::Wasm => self
+                .dir
+                .path()
+                .join(format!("target/{}/release/transform.wasm", WASM_TARGET)),
+        };
+        Executable {
+            path,
+            backend: self.backend,
+            shared_state: self.shared_state,
+            miri_project: None,
+        }
+    }
+
+    /// Prepare the project to be run under Miri, which interprets it from source
+    ///
+    /// Nothing is compiled up front; each run has Miri check the code as it
+    /// executes, reporting undefined behavior as a runtime failure.
+    pub async fn into_miri_executable(self, lock_timeout: Duration) -> Result<Executable, Failure> {
+        fetch_dependencies(self.dir.path(), Some(MIRI_TOOLCHAIN), lock_timeout).await?;
+        Ok(Executable {
+            path: self.dir.path().to_path_buf(),
+            backend: self.backend,
+            shared_state: self.shared_state,
+            miri_project: Some(self.dir),
+        })
+    }
+
+    /// Compile the project in release mode
+    ///
+    /// Returns the compiled crates when `include_build_info` is set, and
+    /// per-dependency compile times from cargo's `--timings` report when
+    /// `include_timings` is. When a progress callback is given, it is called
+    /// each time cargo starts compiling another crate.
+    pub async fn build(
+        &self,
+        options: &BuildOptions<'_>,
+        progress: Option<&dyn Fn(BuildProgress)>,
+    ) -> Result<BuildOutput, Failure> {
+        let project_path = self.dir.path();
+        let toolchain = options.toolchain;
+
+        fetch_dependencies(project_path, toolchain, options.lock_timeout).await?;
+
+        // Dependencies are already fetched, so the build never touches the index.
+        // When build info is requested, cargo emits JSON artifact messages on stdout
+        // while still rendering diagnostics to stderr, so error handling is unchanged.
+        // The build's stdout is only ever read as cargo messages: the binary's own
+        // stdout comes from a separate process when it runs.
+        let mut build_args = vec!["build", "--release", "--offline"];
+        let target = (self.backend == ExecBackend::Wasm).then_some(WASM_TARGET);
+        if let Some(target) = target {
+            build_args.extend(["--target", target]);
+        }
+        if progress.is_none() {
+            build_args.push("--quiet");
+        }
+        if options.include_build_info {
+            build_args.push("--message-format=json-render-diagnostics");
+        }
+        if options.include_timings {
+            build_args.push("--timings");
+        }
+
+        let mut command = cargo_command(toolchain);
+        command.args(&build_args).current_dir(project_path);
+        if !options.rustflags.is_empty() {
+            command.env("RUSTFLAGS", options.rustflags.join(" "));
+        }
+
+        let output = match progress {
+            Some(progress) => {
+                let total = count_crates(project_path, toolchain, target).await?;
+                build_with_progress(&mut command, total, progress).await
+            }
+            None => command.output().await,
+        }
+        .map_err(|e| Failure::internal(format!("Failed to run cargo: {}", e)))?;
+
+        if !output.status.success() {
+            // Diagnostics cargo left as JSON go first, ahead of its own
+            // "could not compile" line on stderr
+            let error_msg = format!(
+                "{}{}",
+                rendered_diagnostics(&String::from_utf8_lossy(&output.stdout)),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            // A build killed under load, or failing without a word, says
+            // nothing about the code, so the client should try it again
+            if let Some(signal) = build_signal(&output.status, &error_msg) {
+                // The kernel's OOM killer sends SIGKILL
+                let kind = if signal == libc::SIGKILL {
+                    ErrorKind::Oom
+                } else {
+                    ErrorKind::Internal
+                };
+                return Err(Failure::retryable(format!(
+                    "The build was killed by signal {}, please retry later",
+                    signal
+                ))
+                .with_kind(kind)
+                .with_traceback(error_msg));
+            }
+            if error_msg.trim().is_empty() {
+                return Err(Failure::retryable(format!(
+                    "The build failed without output ({}), please retry later",
+                    output.status
+                ))
+                .with_kind(ErrorKind::Internal));
+            }
+            let main_rs = fs::read_to_string(project_path.join("src/main.rs")).unwrap_or_default();
+            let summary = describe_borrowed_return(&error_msg, &self.binding, &main_rs)
+                .or_else(|| describe_missing_json(&error_msg))
+                .unwrap_or_else(|| extract_error_summary(&error_msg));
+            return Err(
+                Failure::bad_request(format!("Compilation error: {}", summary))
+                    .with_kind(ErrorKind::Compile)
+                    .with_traceback(error_msg),
+            );
+        }
+
+        let dep_timings = if options.include_timings {
+            let report = project_path.join("target/cargo-timings/cargo-timing.html");
+            let report = fs::read_to_string(report).map_err(|e| {
+                Failure::internal(format!("Failed to read cargo timings report: {}", e))
+            })?;
+            Some(parse_dep_timings(&report))
+        } else {
+            None
+        };
+
+        Ok(BuildOutput {
+            build_info: options
+                .include_build_info
+                .then(|| parse_build_info(&String::from_utf8_lossy(&output.stdout))),
+            dep_timings,
+            warnings: self.strict_unused.then(|| {
+                diagnostics::classify(&String::from_utf8_lossy(&output.stderr))
+                    .into_iter()
+                    .filter(|diagnostic| diagnostic.level == Level::Warning)
+                    .map(|diagnostic| diagnostic.message)
+                    .collect()
+            }),
+        })
+    }
+}
+
+impl Executable {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Copy the binary to `path`, keeping it runnable after its project is gone
+    pub fn copy_to(&self, path: PathBuf) -> std::io::Result<Executable> {
+        fs::copy(&self.path, &path)?;
+        Ok(Executable {
+            path,
+            backend: self.backend,
+            shared_state: self.shared_state,
+            miri_project: None,
+        })
+    }
+
+    /// Run the binary against an input value
+    ///
+    /// The user code sees `hint` as its hint, or `null` when it is `None`.
+    /// Builds with shared state start from `state`, or `State::default()`
+    /// when it is `None`; other builds ignore it. A run still going after
+    /// `timeout` is killed and reported as a timeout.
+    pub async fn run(
+        &self,
+        input: &Value,
+        hint: Option<&Value>,
+        state: Option<&Value>,
+        timeout: Duration,
+    ) -> Result<RunOutput, Failure> {
+        // The event is passed on stdin, so neither backend needs filesystem access
+        let mut event_json = serde_json::to_string(input)
+            .map_err(|e| Failure::internal(format!("Failed to serialize event: {}", e)))?;
+        // Shared state goes on the line before the event; compact JSON has no newlines
+        if self.shared_state {
+            let state = serde_json::to_string(state.unwrap_or(&Value::Null)).map_err(|e| {
+                Failure::internal(format!("Failed to serialize shared state: {}", e))
+            })?;
+            event_json = format!("{}\n{}", state, event_json);
+        }
+
+        // A per-request sentinel marks where the result starts, so anything the
+        // user code prints (including a forged sentinel) can't spoof the result
+        let sentinel = uuid::Uuid::new_v4().to_string();
+        let mut env = vec![(RESULT_SENTINEL_VAR, sentinel.clone())];
+        if let Some(hint) = hint {
+            let hint = serde_json::to_string(hint)
+                .map_err(|e| Failure::internal(format!("Failed to serialize hint: {}", e)))?;
+            if hint.len() > MAX_HINT_BYTES {
+                return Err(Failure::bad_request(format!(
+                    "hint must be at most {} bytes of JSON; it is passed to the code in the {} \
+                     environment variable",
+                    MAX_HINT_BYTES, HINT_VAR
+                )));
+            }
+            env.push((HINT_VAR, hint));
+        }
+
+        let mut command = match self.backend {
+            // Miri isolates the program from the host environment except for forwarded variables
+            _ if self.miri_project.is_some() => {
+                let forward: Vec<String> = env
+                    .iter()
+                    .map(|(name, _)| format!("-Zmiri-env-forward={}", name))
+                    .collect();
+                let mut command = cargo_command(Some(MIRI_TOOLCHAIN));
+                command
+                    .args(["miri", "run", "--offline", "--quiet"])
+                    .envs(env)
+                    .env("MIRIFLAGS", forward.join(" "));
+                command
+            }
+            ExecBackend::Native => {
+                let mut command = Command::new(&self.path);
+                command.envs(env);
+                command
+            }
+            // No --dir or network flags: the module gets no preopened directories or sockets
+            ExecBackend::Wasm => {
+                let mut command = Command::new("wasmtime");
+                command.arg("run");
+                for (name, value) in env {
+                    command.arg("--env").arg(format!("{}={}", name, value));
+                }
+                command.arg(&self.path);
+                command
+            }
+        };
+
+        let work_dir = match &self.miri_project {
+            Some(project) => project.path(),
+            None => self.path.parent().unwrap_or(Path::new(".")),
+        };
+        // The spawn time is wall-clock so the wrapper's entry time can be compared to it
+        let spawned_at = SystemTime::now();
+        let started = Instant::now();
+        let run = run_with_stdin(
+            command.current_dir(work_dir).kill_on_drop(true),
+            event_json.as_bytes(),
+        );
+        let exec_result = tokio::time::timeout(timeout, run)
+            .await
+            .map_err(|_| {
+                Failure::internal(format!(
+                    "Runtime error: transform did not finish within {}s",
+                    timeout.as_secs_f64()
+                ))
+                .with_kind(ErrorKind::Timeout)
+            })?
+            .map_err(|e| Failure::internal(format!("Failed to execute transform: {}", e)))?;
+        let process_time = started.elapsed();
+
+        if !exec_result.status.success() {
+            let error_msg = String::from_utf8_lossy(&exec_result.stderr).to_string();
+            let kind = if is_out_of_memory(&error_msg) {
+                ErrorKind::Oom
+            } else {
+                ErrorKind::Runtime
+            };
+            // Written by the panic context hook, if the build installed it
+            let panic_context =
+                extract_result(&String::from_utf8_lossy(&exec_result.stdout), &sentinel)
+                    .and_then(|lines| serde_json::from_str::<Value>(lines[0]).ok())
+                    .and_then(|mut line| line.get_mut("panicContext").map(Value::take))
+                    .map(Box::new);
+            return Err(Failure {
+                panic_context,
+                ..Failure::internal(format!(
+                    "Runtime error: {}",
+                    describe_runtime_failure(&exec_result.status, &error_msg)
+                ))
+                .with_kind(kind)
+                .with_traceback(error_msg)
+            });
+        }
+
+        // Parse output - can be JSON object, "null", or a number
+        let stdout = String::from_utf8_lossy(&exec_result.stdout);
+        let lines = extract_result(&stdout, &sentinel).ok_or_else(|| {
+            Failure::internal(
+                "Transform produced no result (result sentinel not found in output)".to_string(),
+            )
+            .with_traceback(stdout.to_string())
+        })?;
+        let output_str = lines[0];
+        let report: RunReport = lines
+            .get(1)
+            .and_then(|report| serde_json::from_str(report).ok())
+            .unwrap_or_default();
+        let spawn_time = report.entered_at_nanos.map(|entered_at| {
+            let spawned_at = spawned_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64;
+            Duration::from_nanos(entered_at.saturating_sub(spawned_at))
+        });
+
+        if output_str == "null" {
+            return Ok(RunOutput {
+                value: None,
+                drop_reason: report.drop_reason,
+                counters: report.counters,
+                returned_unit: report.returned_unit,
+                state: report.state,
+                allocations: report.allocations,
+                exec_time: Duration::from_nanos(report.exec_nanos),
+                result_type: report.result_type,
+                process_time,
+                spawn_time,
+                accessed_paths: report.accessed_paths,
+                mutated_paths: report.mutated_paths,
+            });
+        }
+
+        // Try to parse as JSON (handles both objects and numbers)
+        let value = serde_json::from_str(output_str).map_err(|e| {
+            Failure::internal(format!("Failed to parse result '{}': {}", output_str, e))
+        })?;
+
+        Ok(RunOutput {
+            value: Some(value),
+            drop_reason: None,
+            counters: report.counters,
+            returned_unit: false,
+            state: report.state,
+            allocations: report.allocations,
+            exec_time: Duration::from_nanos(report.exec_nanos),
+            result_type: report.result_type,
+            process_time,
+            spawn_time,
+            accessed_paths: report.accessed_paths,
+            mutated_paths: report.mutated_paths,
+        })
+    }
+}
+
+/// Run a command to completion, writing `input` to its stdin
+async fn run_with_stdin(
+    command: &mut Command,
+    input: &[u8],
+) -> std::io::Result<std::process::Output> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let write_input = async move {
+        // The process may exit without reading its input, which is not an error here
+        let _ = stdin.write_all(input).await;
+    };
+
+    let (_, output) = tokio::join!(write_input, child.wait_with_output());
+    output
+}
+
+/// Level of the unused lints the wrappers otherwise allow, as an attribute name
+///
+/// Quick edits often leave unused bindings behind, so they're allowed unless
+/// `strict_unused` asks to see them.
+pub fn unused_lint_level(strict_unused: bool) -> &'static str {
+    if strict_unused {
+        "warn"
+    } else {
+        "allow"
+    }
+}
+
+/// What user code's return value is converted into, in the transform and
+/// validate wrappers alike, so both accept the same return types
+pub const TRANSFORM_RESULT: &str = r#"
+/// Result type that supports both event transforms and sample rates
+enum TransformResult {
+    Event(Option<Value>),
+    SampleRate(f64),
+    /// The code ended in a statement; dropped like `None`, but reported separately
+    Unit,
+}
+
+impl From<Option<Value>> for TransformResult {
+    fn from(v: Option<Value>) -> Self {
+        TransformResult::Event(v)
+    }
+}
+
+impl From<Value> for TransformResult {
+    fn from(v: Value) -> Self {
+        TransformResult::Event(Some(v))
+    }
+}
+
+impl From<f64> for TransformResult {
+    fn from(v: f64) -> Self {
+        TransformResult::SampleRate(v)
+    }
+}
+
+impl From<f32> for TransformResult {
+    fn from(v: f32) -> Self {
+        TransformResult::SampleRate(v as f64)
+    }
+}
+
+impl From<i32> for TransformResult {
+    fn from(v: i32) -> Self {
+        TransformResult::SampleRate(v as f64)
+    }
+}
+
+impl From<i64> for TransformResult {
+    fn from(v: i64) -> Self {
+        TransformResult::SampleRate(v as f64)
+    }
+}
+
+impl From<()> for TransformResult {
+    fn from(_: ()) -> Self {
+        TransformResult::Unit
+    }
+}
+"#;
+
+/// Generate `main.rs` for the transform crate
+///
+/// The user code's return value is converted into a `TransformResult`:
+/// 1. Option<Value> or Value - an event for beforeSend (Some(event), None to drop)
+/// 2. f64 (or f32, i32, i64) - a sample rate for tracesSampler (0.0-1.0)
+/// 3. () - code ending in a statement; dropped like `None`, but reported
+///    as `returnedUnit` so a forgotten `Some(event)` can be pointed out
+///
+/// The enum unifies these at compile time, and the wrapper outputs JSON
+/// that the parent process can parse.
+fn render_wrapper(options: &WrapperOptions) -> String {
+    let (crate_attributes, user_code) = hoist_feature_attributes(options.code);
+    // The panic hook snapshots the `Value` inside a recorded input, which
+    // serializing the input itself would record as read
+    let snapshot_target = if options.record_footprint {
+        format!("{}.value", options.binding)
+    } else {
+        options.binding.to_string()
+    };
+    let user_code = if options.capture_panic_context {
+        track_panic_snapshots(&user_code, options.binding, &snapshot_target)
+    } else {
+        user_code
+    };
+    format!(
+        r##"{crate_attributes}
+#![{unused_level}(unused_imports)]
+#![{unused_level}(unused_variables)]
+#![{unused_level}(unused_mut)]
+
+use serde_json::{{json, Value}};
+
+{transform_result}
+fn main() {{
+{record_entry}    // Take the result sentinel out of the environment before user code runs
+    let sentinel = std::env::var("{sentinel_var}").expect("Missing result sentinel");
+    std::env::remove_var("{sentinel_var}");
+    let hint: Value = match std::env::var("{hint_var}") {{
+        Ok(hint) => serde_json::from_str(&hint).expect("Failed to parse hint JSON"),
+        Err(_) => Value::Null,
+    }};
+    std::env::remove_var("{hint_var}");
+
+    // Read event from stdin (avoids string escaping issues)
+    let event_json = std::io::read_to_string(std::io::stdin()).expect("Failed to read event");
+{read_state}    let input: {input_type} = serde_json::from_str(&event_json).expect("Failed to parse event JSON");
+{install_panic_hook}
+{start_counting}    let started = std::time::Instant::now();
+    let result = user_transform({input_arg}, hint{state_arg});
+    let exec_nanos = started.elapsed().as_nanos() as u64;
+{stop_counting}{clear_panic_event}
+    // Only a dropped input keeps the reason recorded by drop_with_reason
+    let drop_reason = match result {{
+        TransformResult::Event(None) => DROP_REASON.lock().unwrap().take(),
+        _ => None,
+    }};
+    // Only mutated when the state or allocation counts are added
+    #[allow(unused_mut)]
+    let mut report = json!({{
+        "dropReason": drop_reason,
+        "counters": *COUNTERS.lock().unwrap(),
+        "returnedUnit": matches!(result, TransformResult::Unit),
+        "execNanos": exec_nanos,{report_entry}
+        "resultType": match result {{
+            TransformResult::Event(Some(_)) => "event",
+            TransformResult::Event(None) | TransformResult::Unit => "dropped",
+            TransformResult::SampleRate(_) => "sampleRate",
+        }},
+    }});
+{report_state}{report_allocations}{report_footprint}
+    // Output result as JSON on the line following the sentinel, then the run report.
+    // Holding the lock keeps threads spawned by user code from printing in between.
+    use std::io::Write;
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{{}}", sentinel).unwrap();
+    match result {{
+        TransformResult::Event(Some(transformed)) => {{
+            writeln!(stdout, "{{}}", serde_json::to_string(&transformed).unwrap()).unwrap();
+        }}
+        TransformResult::Event(None) | TransformResult::Unit => {{
+            writeln!(stdout, "null").unwrap();
+        }}
+        TransformResult::SampleRate(rate) => {{
+            writeln!(stdout, "{{}}", rate).unwrap();
+        }}
+    }}
+    writeln!(stdout, "{{}}", report).unwrap();
+}}
+
+/// The user's code, in its own function so it can't reach the wrapper's locals
+///
+/// The closure gives `return` and `?` a target of its own. Its return type is
+/// explicit when the mode or signature fixes it, and inferred otherwise so
+/// events and sample rates both convert via `.into()`.
+fn user_transform(
+    #[allow(unused_mut, unused_variables)] mut {binding}: {binding_type},
+    #[allow(unused_variables)] hint: Value{state_param}
+) -> TransformResult {{
+    (move || {return_annotation}{{
+        {track_panic_event}{code}
+    }})()
+    .into()
+}}
+{helpers}{recorded_event}{panic_context_helpers}{global_allocator}{preamble}{modules}"##,
+        helpers = WRAPPER_HELPERS,
+        transform_result = TRANSFORM_RESULT.trim_start(),
+        unused_level = unused_lint_level(options.strict_unused),
+        // The state line comes first; `None` is sent as `null` and starts from the default
+        read_state = if options.shared_state {
+            r#"    let (state_json, event_json) = event_json.split_once('\n').expect("Missing shared state");
+    let mut state: State = serde_json::from_str::<Option<State>>(state_json)
+        .expect("Failed to parse shared state")
+        .unwrap_or_default();
+"#
+        } else {
+            ""
+        },
+        state_arg = if options.shared_state {
+            ", &mut state"
+        } else {
+            ""
+        },
+        state_param = if options.shared_state {
+            ",\n    #[allow(unused_variables)] state: &mut State"
+        } else {
+            ""
+        },
+        report_state = if options.shared_state {
+            r#"    report["state"] = serde_json::to_value(&state).expect("Failed to serialize shared state");
+"#
+        } else {
+            ""
+        },
+        preamble = options
+            .preamble
+            .map(|preamble| format!("\n// Preamble\n{}\n", preamble))
+            .unwrap_or_default(),
+        install_panic_hook = if options.capture_panic_context {
+            "    install_panic_context_hook(sentinel.clone());\n"
+        } else {
+            ""
+        },
+        clear_panic_event = if options.capture_panic_context {
+            "    PANIC_EVENT.with(|slot| slot.take());\n"
+        } else {
+            ""
+        },
+        track_panic_event = if options.capture_panic_context {
+            format!("track_panic_event(&{});\n        ", snapshot_target)
+        } else {
+            String::new()
+        },
+        panic_context_helpers = if options.capture_panic_context {
+            PANIC_CONTEXT_HELPERS
+        } else {
+            ""
+        },
+        record_entry = if options.report_entry_time {
+            "    let entered_at = std::time::SystemTime::now()\n        .duration_since(std::time::UNIX_EPOCH)\n        .map_or(0, |since| since.as_nanos() as u64);\n"
+        } else {
+            ""
+        },
+        report_entry = if options.report_entry_time {
+            "\n        \"enteredAtNanos\": entered_at,"
+        } else {
+            ""
+        },
+        start_counting = if options.count_allocations {
+            "    COUNT_ALLOCATIONS.store(true, std::sync::atomic::Ordering::Relaxed);\n"
+        } else {
+            ""
+        },
+        stop_counting = if options.count_allocations {
+            "    COUNT_ALLOCATIONS.store(false, std::sync::atomic::Ordering::Relaxed);\n"
+        } else {
+            ""
+        },
+        report_allocations = if options.count_allocations {
+            "    report[\"allocations\"] = allocation_counts();\n"
+        } else {
+            ""
+        },
+        recorded_event = if options.record_footprint {
+            footprint::RECORDED_EVENT
+        } else {
+            ""
+        },
+        input_arg = if options.record_footprint {
+            "RecordedEvent::new(input)"
+        } else {
+            "input"
+        },
+        binding_type = if options.record_footprint {
+            "RecordedEvent"
+        } else {
+            options.input_type
+        },
+        report_footprint = if options.record_footprint {
+            "    report[\"accessedPaths\"] = json!(*ACCESSED_KEYS.lock().unwrap());\n    report[\"mutatedPaths\"] = json!(*MUTATED_KEYS.lock().unwrap());\n"
+        } else {
+            ""
+        },
+        global_allocator = render_global_allocator(options.allocator, options.count_allocations),
+        modules = render_modules(options.modules),
+        sentinel_var = RESULT_SENTINEL_VAR,
+        hint_var = HINT_VAR,
+        binding = options.binding,
+        input_type = options.input_type,
+        return_annotation = return_annotation(options.return_type),
+        code = user_code
+    )
+}
+
+/// Panic hook support rendered when `capture_panic_context` is set
+///
+/// The wrapper snapshots the input binding when the user code starts and
+/// after each top-level statement modifying it in place (see
+/// [`track_panic_snapshots`]); on a panic in that thread, the hook serializes
+/// the latest snapshot after the result sentinel as
+/// `{"panicContext": {"event", "message", "location"}}`. Changes made after
+/// the code moves the input into another variable aren't seen.
+const PANIC_CONTEXT_HELPERS: &str = r#"
+thread_local! {
+    static PANIC_EVENT: std::cell::RefCell<Option<Value>> = const { std::cell::RefCell::new(None) };
+}
+
+fn track_panic_event(input: &impl serde::Serialize) {
+    let snapshot = serde_json::to_value(input).ok();
+    PANIC_EVENT.with(|slot| *slot.borrow_mut() = snapshot);
+}
+
+fn install_panic_context_hook(sentinel: String) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let Some(event) = PANIC_EVENT.with(|slot| slot.try_borrow_mut().ok()?.take()) else {
+            return;
+        };
+        let context = json!({
+            "event": event,
+            "message": info.payload_as_str().unwrap_or_default(),
+            "location": info.location().map(|location| location.to_string()),
+        });
+
+        use std::io::Write;
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", sentinel);
+        let _ = writeln!(stdout, "{}", json!({ "panicContext": context }));
+    }));
+}
+"#;
+
+/// Methods on `Value` that modify it in place
+const MUTATING_METHODS: &[&str] = &["insert", "remove", "take", "push", "retain", "clear"];
+
+/// Whether user code appears to modify its input binding in place
+///
+/// A heuristic over the source: index assignments (`event["tags"] = ...`),
+/// mutating method calls (`event.as_object_mut()`, `event.take()`), and
+/// mutable borrows (`&mut event`) all count.
+pub fn mutates_binding(code: &str, binding: &str) -> bool {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    code.match_indices(binding).any(|(start, _)| {
+        let before = &code[..start];
+        let rest = &code[start + binding.len()..];
+        if before.ends_with(is_ident) || rest.starts_with(is_ident) {
+            return false;
+        }
+        if before.trim_end().ends_with("&mut") {
+            return true;
+        }
+
+        if let Some(method) = rest.strip_prefix('.') {
+            let name: String = method.chars().take_while(|c| is_ident(*c)).collect();
+            return name.ends_with("_mut") || MUTATING_METHODS.contains(&name.as_str());
+        }
+
+        // `binding[...] = value`, skipping over any chained indexes
+        let mut rest = rest.trim_start();
+        let mut indexed = false;
+        while let Some(index) = rest.strip_prefix('[') {
+            let Some(end) = index.find(']') else {
+                return false;
+            };
+            rest = index[end + 1..].trim_start();
+            indexed = true;
+        }
+        indexed && rest.starts_with('=') && !rest.starts_with("==")
+    })
+}
+
+/// User code snapshotting its input binding for the panic hook after each
+/// top-level statement that modifies the binding in place
+///
+/// Index assignments (`event["tags"] = ...;`) and mutating method chains
+/// (`event.as_object_mut().unwrap().remove("user");`) count. The call is
+/// added after the statement's `;` on the same line, so compiler diagnostics
+/// keep their line numbers. Statements after a `let` rebinding the name are
+/// left alone, and code that doesn't parse is returned unchanged for the
+/// compiler to report. `target` is what's snapshotted, e.g. `event.0` for a
+/// recorded input.
+pub fn track_panic_snapshots(code: &str, binding: &str, target: &str) -> String {
+    let Ok(stmts) = syn::Block::parse_within.parse_str(code) else {
+        return code.to_string();
+    };
+
+    let mut ends = Vec::new();
+    for stmt in &stmts {
+        match stmt {
+            Stmt::Local(local) if binds_name(&local.pat, binding) => break,
+            Stmt::Expr(expr, Some(semi)) if modifies_binding(expr, binding) => {
+                ends.push(semi.span().end());
+            }
+            _ => {}
+        }
+    }
+
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(code.match_indices('\n').map(|(index, _)| index + 1))
+        .collect();
+    let mut code = code.to_string();
+    // From the end, so earlier offsets stay valid
+    for end in ends.iter().rev() {
+        let start = line_starts[end.line - 1];
+        let offset = code[start..]
+            .char_indices()
+            .nth(end.column)
+            .map_or(code.len(), |(index, _)| start + index);
+        code.insert_str(offset, &format!(" track_panic_event(&{});", target));
+    }
+    code
+}
+
+/// Whether a statement's expression modifies `binding` without moving it
+fn modifies_binding(expr: &Expr, binding: &str) -> bool {
+    match expr {
+        Expr::Assign(assign) => is_rooted_at(&assign.left, binding),
+        Expr::MethodCall(_) => {
+            let mut methods = Vec::new();
+            let mut receiver = expr;
+            while let Expr::MethodCall(call) = receiver {
+                methods.push(call.method.to_string());
+                receiver = &call.receiver;
+            }
+            // `into_*` methods and `into` take the binding by value
+            is_rooted_at(receiver, binding)
+                && !methods.iter().any(|method| method.starts_with("into"))
+                && methods.iter().any(|method| {
+                    method.ends_with("_mut") || MUTATING_METHODS.contains(&method.as_str())
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Whether a place expression like `event["tags"]["id"]` starts at `binding`
+fn is_rooted_at(expr: &Expr, binding: &str) -> bool {
+    match expr {
+        Expr::Path(path) => path.qself.is_none() && path.path.is_ident(binding),
+        Expr::Index(index) => is_rooted_at(&index.expr, binding),
+        Expr::Field(field) => is_rooted_at(&field.base, binding),
+        Expr::MethodCall(call) => is_rooted_at(&call.receiver, binding),
+        Expr::Try(attempt) => is_rooted_at(&attempt.expr, binding),
+        Expr::Paren(paren) => is_rooted_at(&paren.expr, binding),
+        _ => false,
+    }
+}
+
+/// Whether a `let` pattern binds `name`, shadowing it
+fn binds_name(pat: &Pat, name: &str) -> bool {
+    struct Binds<'a> {
+        name: &'a str,
+        found: bool,
+    }
+
+    impl<'ast> Visit<'ast> for Binds<'_> {
+        fn visit_pat_ident(&mut self, pat: &'ast PatIdent) {
+            self.found |= pat.ident == self.name;
+            visit::visit_pat_ident(self, pat);
+        }
+    }
+
+    let mut binds = Binds { name, found: false };
+    binds.visit_pat(pat);
+    binds.found
+}
+
+/// `#[global_allocator]` item installing the requested allocator, if any
+///
+/// When counting allocations, the requested allocator (or the system one) is
+/// wrapped in [`COUNTING_ALLOCATOR`].
+fn render_global_allocator(allocator: Option<&Allocator>, count_allocations: bool) -> String {
+    let global = allocator.map(|allocator| allocator.global);
+    if count_allocations {
+        let inner = global.unwrap_or("std::alloc::System");
+        return format!(
+            "{COUNTING_ALLOCATOR}\n#[global_allocator]\nstatic GLOBAL_ALLOCATOR: CountingAllocator<{inner}> = CountingAllocator({inner});\n"
+        );
+    }
+
+    global
+        .map(|global| {
+            format!("\n#[global_allocator]\nstatic GLOBAL_ALLOCATOR: {global} = {global};\n")
+        })
+        .unwrap_or_default()
+}
+
+/// Allocator wrapper tallying allocations while `COUNT_ALLOCATIONS` is set
+///
+/// The wrapper turns counting on around the user code only, so parsing the
+/// input and printing the result aren't included. Allocations on threads the
+/// user code spawns are counted too.
+const COUNTING_ALLOCATOR: &str = r#"
+static COUNT_ALLOCATIONS: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+static ALLOCATION_COUNT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static ALLOCATED_BYTES: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+struct CountingAllocator<A>(A);
+
+impl<A> CountingAllocator<A> {
+    fn record(&self, size: usize) {
+        use std::sync::atomic::Ordering::Relaxed;
+        if COUNT_ALLOCATIONS.load(Relaxed) {
+            ALLOCATION_COUNT.fetch_add(1, Relaxed);
+            ALLOCATED_BYTES.fetch_add(size as u64, Relaxed);
+        }
+    }
+}
+
+unsafe impl<A: std::alloc::GlobalAlloc> std::alloc::GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        self.record(layout.size());
+        self.0.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: std::alloc::Layout) -> *mut u8 {
+        self.record(layout.size());
+        self.0.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        self.record(new_size);
+        self.0.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        self.0.dealloc(ptr, layout)
+    }
+}
+
+fn allocation_counts() -> Value {
+    use std::sync::atomic::Ordering::Relaxed;
+    json!({
+        "count": ALLOCATION_COUNT.load(Relaxed),
+        "bytes": ALLOCATED_BYTES.load(Relaxed),
+    })
+}
+"#;
+
+/// Look up a requested allocator, checking that the backend supports it
+pub fn find_allocator(name: &str, backend: ExecBackend) -> Result<&'static Allocator, String> {
+    let allocator = SUPPORTED_ALLOCATORS
+        .iter()
+        .find(|allocator| allocator.name == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = SUPPORTED_ALLOCATORS.iter().map(|a| a.name).collect();
+            format!(
+                "Unsupported allocator '{}'. Supported allocators: {}",
+                name,
+                names.join(", ")
+            )
+        })?;
+
+    if backend == ExecBackend::Wasm && allocator.dependency.is_some() {
+        return Err(format!(
+            "Allocator '{}' is not available with the wasm backend",
+            name
+        ));
+    }
+    Ok(allocator)
+}
+
+/// Codegen options that may be passed via `rustflags`
+pub const ALLOWED_CODEGEN_OPTIONS: &[&str] = &[
+    "target-cpu",
+    "target-feature",
+    "opt-level",
+    "lto",
+    "embed-bitcode",
+    "codegen-units",
+];
+
+/// Most rustflags a request may pass
+const MAX_RUSTFLAGS: usize = 16;
+
+/// Check requested rustflags, allowing only the codegen options above
+///
+/// Each flag is `-C name=value` or `-Cname=value`, and may also be split
+/// across two entries. Anything else, including other `-C` options and
+/// values outside a small set, is rejected so flags can't load plugins,
+/// change linkers, or write files outside the project.
+pub fn validate_rustflags(flags: &[String]) -> Result<(), String> {
+    let mut tokens = flags.iter().flat_map(|flag| flag.split_whitespace());
+    let mut count = 0;
+    while let Some(token) = tokens.next() {
+        count += 1;
+        if count > MAX_RUSTFLAGS {
+            return Err(format!("At most {} rustflags are allowed", MAX_RUSTFLAGS));
+        }
+
+        let option = match token.strip_prefix("-C") {
+            Some("") => tokens
+                .next()
+                .ok_or_else(|| "`-C` must be followed by a codegen option".to_string())?,
+            Some(option) => option,
+            None => {
+                return Err(format!(
+                    "Unsupported rustflag '{}': only `-C` codegen options are allowed",
+                    token
+                ))
+            }
+        };
+        let (name, value) = option.split_once('=').unwrap_or((option, ""));
+        if !is_allowed_codegen_option(name, value) {
+            return Err(format!(
+                "Unsupported rustflag '-C {}'. Allowed codegen options: {}",
+                option,
+                ALLOWED_CODEGEN_OPTIONS.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_allowed_codegen_option(name: &str, value: &str) -> bool {
+    let is_word = |word: &str| {
+        !word.is_empty()
+            && word
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    match name {
+        "target-cpu" => is_word(value),
+        "target-feature" => value
+            .split(',')
+            .all(|feature| feature.strip_prefix(['+', '-']).is_some_and(is_word)),
+        "opt-level" => matches!(value, "0" | "1" | "2" | "3" | "s" | "z"),
+        "lto" => matches!(
+            value,
+            "" | "y" | "yes" | "on" | "true" | "fat" | "thin" | "n" | "no" | "off" | "false"
+        ),
+        "embed-bitcode" => matches!(
+            value,
+            "y" | "yes" | "on" | "true" | "n" | "no" | "off" | "false"
+        ),
+        "codegen-units" => value
+            .parse::<u16>()
+            .is_ok_and(|units| (1..=256).contains(&units)),
+        _ => false,
+    }
+}
+
+/// Closure return type annotation for an optional return type
+pub fn return_annotation(return_type: Option<&str>) -> String {
+    return_type
+        .map(|return_type| format!("-> {} ", return_type))
+        .unwrap_or_default()
+}
+
+/// Resolve and download a project's dependencies ahead of building it
+///
+/// Fetches run one at a time behind [`FETCH_LOCK`]. Waiting longer than
+/// `timeout` for the lock or for cargo, or cargo itself reporting that it is
+/// blocked on a file lock, is reported as a retryable failure.
+pub async fn fetch_dependencies(
+    project_path: &Path,
+    toolchain: Option<&str>,
+    timeout: Duration,
+) -> Result<(), Failure> {
+    let fetch = async {
+        let _guard = FETCH_LOCK.lock().await;
+        cargo_command(toolchain)
+            .arg("fetch")
+            .current_dir(project_path)
+            .kill_on_drop(true)
+            .output()
+            .await
+    };
+
+    let output = match tokio::time::timeout(timeout, fetch).await {
+        Ok(output) => {
+            output.map_err(|e| Failure::internal(format!("Failed to run cargo: {}", e)))?
+        }
+        Err(_) => {
+            return Err(Failure::retryable(format!(
+                "Timed out after {}s waiting for the cargo package cache, please retry later",
+                timeout.as_secs()
+            ))
+            .with_kind(ErrorKind::Timeout));
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let failure = if stderr.contains(LOCK_CONTENTION_MARKER) {
+            Failure::retryable(
+                "The cargo package cache is locked by another build, please retry later"
+                    .to_string(),
+            )
+            .with_kind(ErrorKind::Timeout)
+        } else {
+            Failure::internal(format!(
+                "Failed to fetch dependencies: {}",
+                extract_error_summary(&stderr)
+            ))
+        };
+        return Err(failure.with_traceback(stderr));
+    }
+
+    Ok(())
+}
+
+/// Count the crates a build will compile, including the transform crate
+///
+/// Uses the resolved dependency graph for the build target (the host unless
+/// given), so it matches the crates cargo reports as `Compiling`.
+async fn count_crates(
+    project_path: &Path,
+    toolchain: Option<&str>,
+    target: Option<&str>,
+) -> Result<usize, Failure> {
+    let mut command = cargo_command(toolchain);
+    command.args([
+        "tree",
+        "--quiet",
+        "--offline",
+        "--prefix",
+        "none",
+        "--edges",
+        "normal,build",
+    ]);
+    if let Some(target) = target {
+        command.args(["--target", target]);
+    }
+
+    let output = command
+        .current_dir(project_path)
+        .output()
+        .await
+        .map_err(|e| Failure::internal(format!("Failed to run cargo: {}", e)))?;
+
+    // Lines look like "serde v1.0.200", "serde_derive v1.0.200 (proc-macro)",
+    // or end in "(*)" when the crate was already listed
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let crates: BTreeSet<(&str, &str)> = listing
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?, parts.next()?))
+        })
+        .collect();
+
+    Ok(crates.len())
+}
+
+/// Run a build, reporting progress from cargo's `Compiling` status lines
+///
+/// Status lines are left out of the captured stderr so compile errors read
+/// the same as a quiet build.
+async fn build_with_progress(
+    command: &mut Command,
+    total: usize,
+    progress: &dyn Fn(BuildProgress),
+) -> std::io::Result<std::process::Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let stderr_pipe = child.stderr.take().expect("stderr is piped");
+
+    let read_stdout = async {
+        let mut stdout = vec![];
+        stdout_pipe.read_to_end(&mut stdout).await.map(|_| stdout)
+    };
+
+    let read_stderr = async {
+        let mut stderr = String::new();
+        let mut done = 0;
+        let mut lines = BufReader::new(stderr_pipe).lines();
+
+        progress(BuildProgress {
+            phase: "compiling",
+            done,
+            total,
+        });
+
+        while let Some(line) = lines.next_line().await? {
+            match line.trim_start().strip_prefix("Compiling ") {
+                Some(_) => {
+                    done += 1;
+                    progress(BuildProgress {
+                        phase: "compiling",
+                        done: done.min(total),
+                        total,
+                    });
+                }
+                None if is_cargo_status(&line) => {}
+                None => {
+                    stderr.push_str(&line);
+                    stderr.push('\n');
+                }
+            }
+        }
+        Ok::<_, std::io::Error>(stderr)
+    };
+
+    let (stdout, stderr) = tokio::try_join!(read_stdout, read_stderr)?;
+    let status = child.wait().await?;
+
+    Ok(std::process::Output {
+        status,
+        stdout,
+        stderr: stderr.into_bytes(),
+    })
+}
+
+/// Whether a stderr line is one of cargo's right-aligned status messages
+fn is_cargo_status(line: &str) -> bool {
+    [
+        "Updating ",
+        "Locking ",
+        "Downloading ",
+        "Downloaded ",
+        "Finished ",
+    ]
+    .iter()
+    .any(|status| line.trim_start().starts_with(status))
+}
+
+/// Summarize why the transform binary exited unsuccessfully
+///
+/// Panics are reported by their message regardless of strategy: with
+/// `panic = "abort"` the message is printed before the process receives
+/// SIGABRT, so the signal itself is not interesting to the user.
+fn describe_runtime_failure(status: &std::process::ExitStatus, stderr: &str) -> String {
+    use std::os::unix::process::ExitStatusExt;
+
+    if let Some(message) = stderr
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("error: Undefined Behavior: "))
+    {
+        return format!("Miri detected undefined behavior: {}", message);
+    }
+
+    if let Some(message) = extract_panic_message(stderr) {
+        return format!("user code panicked: {}", message);
+    }
+
+    match (status.code(), status.signal()) {
+        (_, Some(signal)) => format!("process terminated by signal {}", signal),
+        (Some(code), _) if stderr.trim().is_empty() => format!("process exited with code {}", code),
+        _ => stderr.trim().to_string(),
+    }
+}
+
+/// The signal that killed cargo, or a compiler it ran, if one did
+///
+/// Cargo reports a killed compiler on stderr as
+/// ``process didn't exit successfully: `rustc ...` (signal: 9, SIGKILL: kill)``.
+fn build_signal(status: &std::process::ExitStatus, stderr: &str) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal().or_else(|| {
+        stderr
+            .lines()
+            .filter(|line| line.contains("process didn't exit successfully"))
+            .find_map(|line| {
+                let (_, signal) = line.rsplit_once("(signal: ")?;
+                signal.split(',').next()?.trim().parse().ok()
+            })
+    })
+}
+
+/// Whether the transform aborted because an allocation failed
+///
+/// Rust's default allocation error handler prints this before aborting, both
+/// natively and under wasmtime.
+fn is_out_of_memory(stderr: &str) -> bool {
+    stderr
+        .lines()
+        .any(|line| line.starts_with("memory allocation of") && line.ends_with("failed"))
+}
+
+/// Extract the message from Rust's default panic output
+///
+/// The output looks like:
+/// ```text
+/// thread 'main' panicked at src/main.rs:70:9:
+/// called `Option::unwrap()` on a `None` value
+/// note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
+/// ```
+fn extract_panic_message(stderr: &str) -> Option<String> {
+    let mut lines = stderr
+        .lines()
+        .skip_while(|line| !line.contains("panicked at"));
+    lines.next()?;
+
+    let message: Vec<&str> = lines
+        .take_while(|line| !line.starts_with("note:") && !line.starts_with("stack backtrace:"))
+        .collect();
+    Some(message.join("\n").trim().to_string())
+}
+
+/// Find the lines printed by the wrapper after the sentinel
+///
+/// The result line comes first, followed by the JSON run report; anything
+/// after those, like output from threads still running when the wrapper
+/// finished, is ignored. The sentinel may end a line user code started with
+/// `print!`. The wrapper prints the sentinel last, so the final occurrence is
+/// used; `None` means the sentinel or the result line is missing.
+fn extract_result<'a>(stdout: &'a str, sentinel: &str) -> Option<Vec<&'a str>> {
+    let lines: Vec<&str> = stdout.lines().map(str::trim).collect();
+    let position = lines.iter().rposition(|line| line.ends_with(sentinel))?;
+    let trailing: Vec<&str> = lines[position + 1..].iter().take(2).copied().collect();
+    (!trailing.is_empty()).then_some(trailing)
+}
+
+/// Collect the crates compiled during a build from cargo's JSON messages
+///
+/// Returns `{ "crates": [{ "name", "version" }] }` sorted by name, excluding
+/// the generated transform crate itself.
+fn parse_build_info(cargo_stdout: &str) -> Value {
+    let mut crates: Vec<(String, String)> = cargo_stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg["reason"] == "compiler-artifact")
+        .filter_map(|msg| msg["package_id"].as_str().and_then(parse_package_id))
+        .collect();
+
+    crates.sort();
+    crates.dedup();
+
+    let crates: Vec<Value> = crates
+        .into_iter()
+        .map(|(name, version)| serde_json::json!({ "name": name, "version": version }))
+        .collect();
+
+    serde_json::json!({ "crates": crates })
+}
+
+/// Rendered rustc diagnostics among cargo's JSON messages
+///
+/// Empty with `--message-format=json-render-diagnostics`, where cargo renders
+/// them to stderr; any left in the JSON stream, which never holds the
+/// binary's output, are recovered so a failed build still explains itself.
+fn rendered_diagnostics(cargo_stdout: &str) -> String {
+    cargo_stdout
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|msg| msg["reason"] == "compiler-message")
+        .filter_map(|msg| msg["message"]["rendered"].as_str().map(str::to_string))
+        .collect()
+}
+
+/// Sum compile time per dependency from cargo's HTML timings report
+///
+/// The report embeds one entry per compiled unit as a JSON array assigned to
+/// `UNIT_DATA`; a crate's build script and library are separate units. The
+/// transform crate itself is left out.
+fn parse_dep_timings(report: &str) -> Vec<DepTiming> {
+    #[derive(Deserialize)]
+    struct Unit {
+        name: String,
+        duration: f64,
+    }
+
+    let units = report
+        .split_once("const UNIT_DATA = ")
+        .and_then(|(_, data)| {
+            serde_json::Deserializer::from_str(data)
+                .into_iter::<Vec<Unit>>()
+                .next()?
+                .ok()
+        })
+        .unwrap_or_default();
+
+    let mut seconds: BTreeMap<String, f64> = BTreeMap::new();
+    for unit in units.into_iter().filter(|unit| unit.name != "transform") {
+        *seconds.entry(unit.name).or_default() += unit.duration;
+    }
+
+    let mut timings: Vec<DepTiming> = seconds
+        .into_iter()
+        .map(|(name, seconds)| DepTiming {
+            name,
+            seconds: (seconds * 100.0).round() / 100.0,
+        })
+        .collect();
+    timings.sort_by(|a, b| b.seconds.total_cmp(&a.seconds));
+    timings
+}
+
+/// Extract `(name, version)` from a cargo package id for registry crates
+///
+/// Handles both the current spec format
+/// (`registry+https://github.com/rust-lang/crates.io-index#serde@1.0.200`)
+/// and the legacy format (`serde 1.0.200 (registry+https://...)`).
+fn parse_package_id(package_id: &str) -> Option<(String, String)> {
+    if !package_id.contains("registry+") {
+        return None;
+    }
+
+    if let Some((_, spec)) = package_id.split_once('#') {
+        let (name, version) = spec.split_once('@')?;
+        return Some((name.to_string(), version.to_string()));
+    }
+
+    let mut parts = package_id.split_whitespace();
+    let name = parts.next()?;
+    let version = parts.next()?;
+    Some((name.to_string(), version.to_string()))
+}
+
+/// Build a cargo command, optionally pinned to a toolchain via `rustup run`
+pub fn cargo_command(toolchain: Option<&str>) -> Command {
+    match toolchain {
+        Some(toolchain) => {
+            let mut command = Command::new("rustup");
+            command.args(["run", toolchain, "cargo"]);
+            command
+        }
+        None => Command::new("cargo"),
+    }
+}
+
+/// Ensure a requested toolchain is supported and installed
+pub async fn check_toolchain(toolchain: &str) -> Result<(), String> {
+    if !SUPPORTED_TOOLCHAINS.contains(&toolchain) {
+        return Err(format!(
+            "Unsupported toolchain '{}'. Supported toolchains: {}",
+            toolchain,
+            SUPPORTED_TOOLCHAINS.join(", ")
+        ));
+    }
+
+    let output = Command::new("rustup")
+        .args(["toolchain", "list"])
+        .output()
+        .await
+        .map_err(|e| format!("Failed to list installed toolchains: {}", e))?;
+
+    // Lines look like "nightly-x86_64-unknown-linux-gnu (default)"
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let installed: Vec<&str> = listing
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter_map(|name| name.split('-').next())
+        .collect();
+
+    if installed.contains(&toolchain) {
+        Ok(())
+    } else {
+        Err(format!(
+            "Toolchain '{}' is not installed. Installed toolchains: {}",
+            toolchain,
+            installed.join(", ")
+        ))
+    }
+}
+
+/// Ensure Miri is installed for [`MIRI_TOOLCHAIN`]
+pub async fn check_miri() -> Result<(), String> {
+    check_toolchain(MIRI_TOOLCHAIN).await?;
+
+    let installed = cargo_command(Some(MIRI_TOOLCHAIN))
+        .args(["miri", "--version"])
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success());
+    if installed {
+        Ok(())
+    } else {
+        Err(format!(
+            "Miri is not installed for the {} toolchain",
+            MIRI_TOOLCHAIN
+        ))
+    }
+}
+
+/// Check helper module names and sources before generating the crate
+///
+/// Names must be lowercase identifiers that don't shadow a keyword or a crate
+/// the wrapper relies on, and modules may not define their own `fn main`.
+pub fn validate_modules(modules: &BTreeMap<String, String>) -> Result<(), String> {
+    if modules.len() > MAX_MODULES {
+        return Err(format!("At most {} modules may be supplied", MAX_MODULES));
+    }
+
+    for (name, source) in modules {
+        if !is_lowercase_identifier(name) {
+            return Err(format!(
+                "Invalid module name '{}': use a lowercase identifier like `rules`",
+                name
+            ));
+        }
+        if RUST_KEYWORDS.contains(&name.as_str()) || RESERVED_MODULE_NAMES.contains(&name.as_str())
+        {
+            return Err(format!("Module name '{}' is reserved", name));
+        }
+
+        let tokens: Vec<&str> = source
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|token| !token.is_empty())
+            .collect();
+        if tokens.windows(2).any(|pair| pair == ["fn", "main"]) {
+            return Err(format!("Module '{}' must not define `fn main`", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the name a custom signature binds its input to
+pub fn validate_binding(name: &str) -> Result<(), String> {
+    if !is_lowercase_identifier(name) {
+        return Err(format!(
+            "Invalid binding '{}': use a lowercase identifier like `breadcrumb`",
+            name
+        ));
+    }
+    // `hint` is always bound alongside the input
+    if RUST_KEYWORDS.contains(&name) || name == "hint" {
+        return Err(format!("Binding name '{}' is reserved", name));
+    }
+    Ok(())
+}
+
+/// Whether a name is a lowercase Rust identifier other than `_`
+fn is_lowercase_identifier(name: &str) -> bool {
+    name != "_"
+        && name
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Render helper modules as crate-level `mod` items
+///
+/// Each module gets the same serde_json imports as the main code.
+pub fn render_modules(modules: &BTreeMap<String, String>) -> String {
+    modules
+        .iter()
+        .map(|(name, source)| {
+            format!(
+                "\nmod {} {{\n    #[allow(unused_imports)]\n    use serde_json::{{json, Value}};\n\n{}\n}}\n",
+                name, source
+            )
+        })
+        .collect()
+}
+
+/// Move leading `#![feature(...)]` lines out of user code
+///
+/// Feature gates must sit at the crate root, so they are returned separately
+/// for the wrapper header. Blank lines are left in their place to keep error
+/// line numbers aligned with the submitted code.
+pub fn hoist_feature_attributes(code: &str) -> (String, String) {
+    let mut attributes = vec![];
+    let mut body = vec![];
+
+    let mut in_header = true;
+
+    for line in code.lines() {
+        let trimmed = line.trim();
+        if in_header && trimmed.starts_with("#![feature(") {
+            attributes.push(trimmed);
+            body.push("");
+            continue;
+        }
+        if !trimmed.is_empty() {
+            in_header = false;
+        }
+        body.push(line);
+    }
+
+    (attributes.join(" "), body.join("\n"))
+}
+
+/// Explain a first compile error caused by returning a borrow instead of owned data
+///
+/// Returning `Some(&event)` or `event.get("tags")` fails with a missing
+/// `From` impl, a type mismatch, or a lifetime error, none of which mention
+/// ownership. A type mismatch only counts where it points into a value the
+/// code returns in the generated `main_rs`. Later errors are ignored, as they
+/// often follow from the first.
+fn describe_borrowed_return(error_msg: &str, binding: &str, main_rs: &str) -> Option<String> {
+    let mut lines = error_msg
+        .lines()
+        .skip_while(|line| !line.starts_with("error"));
+    let first = lines.next()?;
+    let first_error: Vec<&str> = std::iter::once(first)
+        .chain(lines.take_while(|line| !line.starts_with("error")))
+        .collect();
+    let returns_borrow = first_error.iter().any(|line| {
+        line.contains("TransformResult: From<&")
+            || line.contains("TransformResult: From<Option<&")
+            || line.contains("returns a value referencing")
+            || line.contains("returns a reference to a captured variable")
+    });
+    let mismatched_return = first_error.iter().any(|line| is_borrowed_mismatch(line))
+        && error_location(&first_error).is_some_and(|location| {
+            returned_spans(main_rs)
+                .iter()
+                .any(|(start, end)| *start <= location && location < *end)
+        });
+
+    (returns_borrow || mismatched_return).then(|| {
+        format!(
+            "the code returns a borrowed value, not one it owns. Return owned data: \
+             use {binding}.clone() or Some({binding}) by value"
+        )
+    })
+}
+
+/// Where in `src/main.rs` an error's `-->` marker points, with a 0-based column
+fn error_location(error: &[&str]) -> Option<LineColumn> {
+    let location = error
+        .iter()
+        .find_map(|line| line.trim_start().strip_prefix("--> src/main.rs:"))?;
+    let (line, column) = location.split_once(':')?;
+    Some(LineColumn {
+        line: line.parse().ok()?,
+        column: column.parse::<usize>().ok()?.checked_sub(1)?,
+    })
+}
+
+/// Start and end of each value the user code returns in the generated `main_rs`
+///
+/// Those are the closure's tail expression and each `return`, followed down
+/// through `if`, `match`, and blocks to the expressions producing the value.
+/// Nested closures and items return values of their own and are skipped.
+fn returned_spans(main_rs: &str) -> Vec<(LineColumn, LineColumn)> {
+    let Ok(file) = syn::parse_file(main_rs) else {
+        return Vec::new();
+    };
+    let Some(body) = file.items.iter().find_map(user_closure_body) else {
+        return Vec::new();
+    };
+    let mut returned = ReturnedSpans::default();
+    returned.tail(body);
+    returned.visit_expr(body);
+    returned.spans
+}
+
+/// Body of the closure `user_transform` runs the user code in, as
+/// `(move || { ... })().into()`
+fn user_closure_body(item: &Item) -> Option<&Expr> {
+    let Item::Fn(function) = item else {
+        return None;
+    };
+    if function.sig.ident != "user_transform" {
+        return None;
+    }
+    let Some(Stmt::Expr(Expr::MethodCall(into), None)) = function.block.stmts.last() else {
+        return None;
+    };
+    let Expr::Call(call) = &*into.receiver else {
+        return None;
+    };
+    let Expr::Paren(paren) = &*call.func else {
+        return None;
+    };
+    let Expr::Closure(closure) = &*paren.expr else {
+        return None;
+    };
+    Some(&closure.body)
+}
+
+#[derive(Default)]
+struct ReturnedSpans {
+    spans: Vec<(LineColumn, LineColumn)>,
+}
+
+impl ReturnedSpans {
+    /// Record the expressions producing the value of `expr`
+    fn tail(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Block(block) => self.block_tail(&block.block),
+            Expr::Paren(paren) => self.tail(&paren.expr),
+            Expr::If(branch) => {
+                self.block_tail(&branch.then_branch);
+                if let Some((_, otherwise)) = &branch.else_branch {
+                    self.tail(otherwise);
+                }
+            }
+            Expr::Match(choice) => choice.arms.iter().for_each(|arm| self.tail(&arm.body)),
+            // Recorded when the visit reaches it
+            Expr::Return(_) => {}
+            _ => self.spans.push((expr.span().start(), expr.span().end())),
+        }
+    }
+
+    fn block_tail(&mut self, block: &syn::Block) {
+        if let Some(Stmt::Expr(expr, None)) = block.stmts.last() {
+            self.tail(expr);
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for ReturnedSpans {
+    fn visit_expr_return(&mut self, expr: &'ast ExprReturn) {
+        if let Some(value) = &expr.expr {
+            self.tail(value);
+        }
+        visit::visit_expr_return(self, expr);
+    }
+
+    fn visit_expr_closure(&mut self, _: &'ast ExprClosure) {}
+
+    fn visit_item(&mut self, _: &'ast Item) {}
+}
+
+/// Explain a first compile error about `json!` not being in scope
+///
+/// The wrapper imports `json!` for the code itself and for helper modules,
+/// so this only happens inside a `mod` the code declares, which needs its
+/// own import.
+fn describe_missing_json(error_msg: &str) -> Option<String> {
+    let first = error_msg.lines().find(|line| line.starts_with("error"))?;
+    first.contains("cannot find macro `json`").then(|| {
+        "the json! macro is imported for the code itself, but not inside a `mod` it \
+         declares; add `use serde_json::json;` there, and call it as json!({...})"
+            .to_string()
+    })
+}
+
+/// Whether a type mismatch is an owned event expected and a reference found,
+/// as in ``expected `Option<Value>`, found `Option<&Value>` ``
+///
+/// Only the types code returns count; other mismatches, like a `&str`
+/// assigned to a `u32`, aren't about the return value. A `Value` expected in
+/// a `let` matches too, so where the mismatch is has to be checked as well.
+fn is_borrowed_mismatch(line: &str) -> bool {
+    let Some((expected, found)) = line
+        .split_once("expected `")
+        .and_then(|(_, rest)| rest.split_once("`, found `"))
+    else {
+        return false;
+    };
+    let is_returned_value = matches!(
+        expected,
+        "Value" | "Option<Value>" | "serde_json::Value" | "Option<serde_json::Value>"
+    );
+    is_returned_value && found.trim_start_matches("Option<").starts_with('&')
+}
+
+/// Extract a concise error summary from Rust compiler output
+fn extract_error_summary(error_msg: &str) -> String {
+    // Find the first "error[E...]:" line for a concise message
+    for line in error_msg.lines() {
+        if line.starts_with("error[E") || line.starts_with("error:") {
+            return line.to_string();
+        }
+    }
+    // Fallback to first non-empty line
+    error_msg.lines().find(|l| !l.trim().is_empty())
+        .unwrap_or("Unknown compilation error")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENTINEL: &str = "6f1c3e0a-2b7d-4c59-9a8e-1d2f3b4c5d6e";
+
+    #[test]
+    fn result_follows_the_last_sentinel() {
+        let stdout = format!(
+            "debug output\n{s}\n{{\"forged\":true}}\n{{}}\n{s}\n{{\"real\":true}}\n{{\"execNanos\":1}}\n",
+            s = SENTINEL
+        );
+        assert_eq!(
+            extract_result(&stdout, SENTINEL),
+            Some(vec!["{\"real\":true}", "{\"execNanos\":1}"])
+        );
+    }
+
+    #[test]
+    fn result_sentinel_may_end_a_printed_line() {
+        let stdout = format!("no newline{}\nnull\n{{}}\n", SENTINEL);
+        assert_eq!(extract_result(&stdout, SENTINEL), Some(vec!["null", "{}"]));
+    }
+
+    #[test]
+    fn output_after_the_run_report_is_ignored() {
+        let stdout = format!(
+            "{}\n{{\"a\":1}}\n{{}}\nlate thread output\n{{\"not\":\"the result\"}}\n",
+            SENTINEL
+        );
+        assert_eq!(
+            extract_result(&stdout, SENTINEL),
+            Some(vec!["{\"a\":1}", "{}"])
+        );
+    }
+
+    #[test]
+    fn aborted_panics_report_the_message_not_the_signal() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let sigabrt = std::process::ExitStatus::from_raw(libc::SIGABRT);
+        let stderr = "\nthread 'main' panicked at src/main.rs:3:5:\nboom\nnote: run with `RUST_BACKTRACE=1`\n";
+        assert_eq!(
+            describe_runtime_failure(&sigabrt, stderr),
+            "user code panicked: boom"
+        );
+        assert_eq!(
+            describe_runtime_failure(&sigabrt, ""),
+            "process terminated by signal 6"
+        );
+    }
+
+    #[test]
+    fn killed_builds_are_told_apart_from_compile_errors() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let sigkill = std::process::ExitStatus::from_raw(libc::SIGKILL);
+        assert_eq!(build_signal(&sigkill, ""), Some(libc::SIGKILL));
+
+        let exit_101 = std::process::ExitStatus::from_raw(101 << 8);
+        let killed_rustc = "error: could not compile `transform` (bin \"transform\")\n\nCaused by:\n  \
+            process didn't exit successfully: `rustc --crate-name transform` (signal: 9, SIGKILL: kill)\n";
+        assert_eq!(build_signal(&exit_101, killed_rustc), Some(libc::SIGKILL));
+
+        let compile_error = "error[E0425]: cannot find function `helper`\n --> src/main.rs:3:5\n";
+        assert_eq!(build_signal(&exit_101, compile_error), None);
+    }
+
+    #[test]
+    fn miri_undefined_behavior_is_reported_over_the_exit_code() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let exit_1 = std::process::ExitStatus::from_raw(1 << 8);
+        let stderr =
+            "error: Undefined Behavior: out-of-bounds pointer use\n  --> src/main.rs:4:13\n";
+        assert_eq!(
+            describe_runtime_failure(&exit_1, stderr),
+            "Miri detected undefined behavior: out-of-bounds pointer use"
+        );
+    }
+
+    #[test]
+    fn dep_timings_sum_units_per_crate_slowest_first() {
+        let report = r#"<script>
+const UNIT_DATA = [
+  {"i": 0, "name": "serde", "version": "1.0.0", "mode": "run-custom-build", "duration": 0.254},
+  {"i": 1, "name": "serde", "version": "1.0.0", "mode": "todo", "duration": 1.5},
+  {"i": 2, "name": "serde_json", "version": "1.0.0", "mode": "todo", "duration": 2.0},
+  {"i": 3, "name": "transform", "version": "0.1.0", "mode": "todo", "duration": 9.0}
+];
+const CONCURRENCY_DATA = [];
+</script>"#;
+        let timings: Vec<(String, f64)> = parse_dep_timings(report)
+            .into_iter()
+            .map(|timing| (timing.name, timing.seconds))
+            .collect();
+        assert_eq!(
+            timings,
+            [("serde_json".to_string(), 2.0), ("serde".to_string(), 1.75)]
+        );
+        assert!(parse_dep_timings("<html></html>").is_empty());
+    }
+
+    #[test]
+    fn borrowed_returns_get_an_ownership_explanation() {
+        let friendly = Some(
+            "the code returns a borrowed value, not one it owns. Return owned data: \
+             use event.clone() or Some(event) by value"
+                .to_string(),
+        );
+        let missing_from = "error[E0277]: the trait bound `TransformResult: From<Option<&Value>>` is not satisfied\n";
+        assert_eq!(
+            describe_borrowed_return(missing_from, "event", ""),
+            friendly
+        );
+        let main_rs = "fn user_transform(event: Value) -> TransformResult {\n    (move || -> Option<Value> {\n        if event.is_null() { return event.get(\"x\"); }\n        event.get(\"y\")\n    })()\n    .into()\n}\n";
+        let mismatch = |location: &str| {
+            format!("   Compiling transform\nerror[E0308]: mismatched types\n  --> src/main.rs:{location}\n  = note: expected `Option<Value>`, found `Option<&Value>`\n")
+        };
+        assert_eq!(
+            describe_borrowed_return(&mismatch("3:37"), "event", main_rs),
+            friendly
+        );
+        assert_eq!(
+            describe_borrowed_return(&mismatch("4:9"), "event", main_rs),
+            friendly
+        );
+
+        // Only the first error is considered
+        let later = "error[E0425]: cannot find value `x`\nerror[E0308]: mismatched types\n  --> src/main.rs:4:9\n  = note: expected `Value`, found `&Value`\n";
+        assert_eq!(describe_borrowed_return(later, "event", main_rs), None);
+        assert!(!is_borrowed_mismatch("expected `&str`, found `String`"));
+        assert!(!is_borrowed_mismatch("expected `u32`, found `&str`"));
+    }
+
+    #[test]
+    fn borrowed_mismatches_outside_returned_values_keep_the_summary() {
+        let main_rs = "fn user_transform(event: Value) -> TransformResult {\n    (move || {\n        let tags: Value = &event[\"tags\"];\n        let keep = |e: &Value| -> Option<Value> { e.get(\"x\") };\n        Some(event)\n    })()\n    .into()\n}\n";
+        let mismatch = |location: &str| {
+            format!("error[E0308]: mismatched types\n  --> src/main.rs:{location}\n  |     ^^^ expected `Value`, found `&Value`\n")
+        };
+        // A `let` annotated with `Value`, and a nested closure's own return
+        assert_eq!(
+            describe_borrowed_return(&mismatch("3:27"), "event", main_rs),
+            None
+        );
+        assert_eq!(
+            describe_borrowed_return(&mismatch("4:51"), "event", main_rs),
+            None
+        );
+        // No location to check
+        let unplaced =
+            "error[E0308]: mismatched types\n  = note: expected `Value`, found `&Value`\n";
+        assert_eq!(describe_borrowed_return(unplaced, "event", main_rs), None);
+    }
+
+    #[test]
+    fn missing_json_macros_get_a_hint_only_as_the_first_error() {
+        let missing = "error: cannot find macro `json` in this scope\n --> src/main.rs:3:9\n";
+        assert!(describe_missing_json(missing)
+            .unwrap()
+            .contains("add `use serde_json::json;` there"));
+        let later =
+            "error[E0425]: cannot find value `x`\nerror: cannot find macro `json` in this scope\n";
+        assert_eq!(describe_missing_json(later), None);
+    }
+
+    #[test]
+    fn allocators_are_allowlisted_per_backend() {
+        assert!(find_allocator("mimalloc", ExecBackend::Native).is_ok());
+        assert!(find_allocator("system", ExecBackend::Wasm).is_ok());
+        assert_eq!(
+            find_allocator("jemalloc", ExecBackend::Wasm).err().unwrap(),
+            "Allocator 'jemalloc' is not available with the wasm backend"
+        );
+        assert!(find_allocator("dlmalloc", ExecBackend::Native)
+            .err()
+            .unwrap()
+            .starts_with("Unsupported allocator 'dlmalloc'"));
+    }
+
+    #[test]
+    fn missing_sentinel_or_result_is_none() {
+        assert_eq!(extract_result("{\"event\":1}\n", SENTINEL), None);
+        assert_eq!(extract_result(&format!("{}\n", SENTINEL), SENTINEL), None);
+    }
+
+    fn modules(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, source)| (name.to_string(), source.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn module_names_must_be_unreserved_lowercase_identifiers() {
+        assert!(validate_modules(&modules(&[("rules", ""), ("pii_v2", "")])).is_ok());
+        assert!(validate_modules(&modules(&[("Rules", "")]))
+            .unwrap_err()
+            .starts_with("Invalid module name 'Rules'"));
+        assert_eq!(
+            validate_modules(&modules(&[("serde_json", "")])).unwrap_err(),
+            "Module name 'serde_json' is reserved"
+        );
+        assert_eq!(
+            validate_modules(&modules(&[("match", "")])).unwrap_err(),
+            "Module name 'match' is reserved"
+        );
+    }
+
+    #[test]
+    fn modules_may_not_define_main() {
+        assert_eq!(
+            validate_modules(&modules(&[("rules", "pub fn main() {}")])).unwrap_err(),
+            "Module 'rules' must not define `fn main`"
+        );
+        assert!(validate_modules(&modules(&[("rules", "pub fn main_tag() {}")])).is_ok());
+    }
+
+    #[test]
+    fn index_assignments_and_mutating_calls_count_as_mutation() {
+        assert!(mutates_binding(
+            r#"event["tags"]["x"] = json!(1);"#,
+            "event"
+        ));
+        assert!(mutates_binding("event.as_object_mut();", "event"));
+        assert!(mutates_binding("event.take();", "event"));
+        assert!(mutates_binding("std::mem::take(&mut event);", "event"));
+        assert!(!mutates_binding(r#"event["level"] == "info""#, "event"));
+        assert!(!mutates_binding("event.get(\"user\")", "event"));
+        assert!(!mutates_binding("my_event.take();", "event"));
+    }
+
+    #[test]
+    fn panic_snapshots_follow_in_place_modifications() {
+        let code = r#"event["tags"] = json!({});
+let id = event["user"]["id"].as_u64().unwrap();
+event.as_object_mut().unwrap().remove("user"); event["id"] = json!(id);
+println!("{}", event);
+Some(event)"#;
+        assert_eq!(
+            track_panic_snapshots(code, "event", "event"),
+            r#"event["tags"] = json!({}); track_panic_event(&event);
+let id = event["user"]["id"].as_u64().unwrap();
+event.as_object_mut().unwrap().remove("user"); track_panic_event(&event); event["id"] = json!(id); track_panic_event(&event);
+println!("{}", event);
+Some(event)"#
+        );
+        assert_eq!(
+            track_panic_snapshots("event[\"é\"] = json!(1);", "event", "event.0"),
+            "event[\"é\"] = json!(1); track_panic_event(&event.0);"
+        );
+    }
+
+    #[test]
+    fn panic_snapshots_stop_where_the_binding_is_moved_or_rebound() {
+        let moved = "let mut other = event;\nother[\"a\"] = json!(1);\nSome(other)";
+        assert_eq!(track_panic_snapshots(moved, "event", "event"), moved);
+
+        let rebound = "let event = event.to_string();\nevent.clear();\nNone";
+        assert_eq!(track_panic_snapshots(rebound, "event", "event"), rebound);
+
+        let consumed = "event.into_iter().map(|e| e).clear();\nNone";
+        assert_eq!(track_panic_snapshots(consumed, "event", "event"), consumed);
+
+        let unparsable = "event[\"a\"] = ;";
+        assert_eq!(
+            track_panic_snapshots(unparsable, "event", "event"),
+            unparsable
+        );
+    }
+
+    #[test]
+    fn rustflags_allow_only_listed_codegen_options() {
+        let flags = |flags: &[&str]| {
+            validate_rustflags(
+                &flags
+                    .iter()
+                    .map(|flag| flag.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        assert!(flags(&["-C target-cpu=native", "-Copt-level=3", "-C", "lto=thin"]).is_ok());
+        assert!(flags(&["-C target-feature=+sse3,-avx2"]).is_ok());
+
+        assert_eq!(
+            flags(&["-C linker=/tmp/evil"]).unwrap_err(),
+            format!(
+                "Unsupported rustflag '-C linker=/tmp/evil'. Allowed codegen options: {}",
+                ALLOWED_CODEGEN_OPTIONS.join(", ")
+            )
+        );
+        assert!(flags(&["-C opt-level=9"]).is_err());
+        assert!(flags(&["-C target-cpu=$(whoami)"]).is_err());
+        assert_eq!(
+            flags(&["--emit=asm"]).unwrap_err(),
+            "Unsupported rustflag '--emit=asm': only `-C` codegen options are allowed"
+        );
+        assert_eq!(
+            flags(&["-C"]).unwrap_err(),
+            "`-C` must be followed by a codegen option"
+        );
+        assert_eq!(
+            flags(&["-Copt-level=3"; MAX_RUSTFLAGS + 1]).unwrap_err(),
+            format!("At most {} rustflags are allowed", MAX_RUSTFLAGS)
+        );
+    }
+
+    #[test]
+    fn rendered_diagnostics_come_only_from_compiler_messages() {
+        let stdout = [
+            r#"{"reason":"compiler-artifact","target":{"name":"serde"}}"#,
+            r#"{"reason":"compiler-message","message":{"rendered":"error[E0308]: mismatched types\n"}}"#,
+            r#"__TRANSFORM_RESULT__{"transformed":{"level":"info"}}"#,
+            r#"{"reason":"compiler-message","message":{"rendered":"error: aborting due to 1 previous error\n"}}"#,
+            r#"{"reason":"build-finished","success":false}"#,
+        ]
+        .join("\n");
+        assert_eq!(
+            rendered_diagnostics(&stdout),
+            "error[E0308]: mismatched types\nerror: aborting due to 1 previous error\n"
+        );
+        assert_eq!(rendered_diagnostics(""), "");
+    }
+}
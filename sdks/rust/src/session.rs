@@ -0,0 +1,303 @@
+//! Interactive websocket sessions reusing one build
+//!
+//! `GET /ws` upgrades to a websocket speaking JSON text messages. A `code`
+//! message takes the same code and output options as /transform and builds
+//! the code once:
+//!
+//! ```json
+//! { "type": "code", "beforeSendCode": "Some(event)", "pretty": true }
+//! ```
+//!
+//! It is answered with `{ "type": "ready", "cached": false }`, or an `error`
+//! if the code doesn't build. Each `event` message then runs the current
+//! build and is answered with a `result` carrying the body /transform would
+//! return:
+//!
+//! ```json
+//! { "type": "event", "event": { "message": "hi" } }
+//! ```
+//!
+//! With `sharedState`, the state each event leaves is passed to the next.
+//! Sending new code replaces the build and resets the state. A message that
+//! can't be handled is answered with an `error` and leaves the session open.
+
+use crate::build_cache::CachedBuild;
+use crate::sandbox::{DepTiming, Failure};
+use crate::{
+    admit, compile, rate_limited_failure, transform_event, AppState, CodeOptions, OutputOptions,
+    Prepared, TransformResponse,
+};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, CloseCode, Codec, Frame, Item, Message};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::BytesMut;
+use actix_web::{web, HttpRequest, HttpResponse};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Largest message accepted from a client, whole or reassembled from fragments
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    /// Build new code, replacing the current build
+    Code {
+        #[serde(flatten)]
+        code: Box<CodeOptions>,
+        #[serde(flatten)]
+        output: OutputOptions,
+    },
+    /// Run the current build against an event, with an optional SDK hint
+    Event {
+        event: Value,
+        #[serde(default)]
+        hint: Option<Value>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage {
+    /// The code is built and events can be sent
+    Ready {
+        /// Whether an earlier build of the same code was reused
+        cached: bool,
+        #[serde(rename = "buildInfo", skip_serializing_if = "Option::is_none")]
+        build_info: Option<Value>,
+        #[serde(rename = "depTimings", skip_serializing_if = "Option::is_none")]
+        dep_timings: Option<Vec<DepTiming>>,
+        #[serde(rename = "compilerWarnings", skip_serializing_if = "Option::is_none")]
+        compiler_warnings: Option<Vec<String>>,
+    },
+    /// Outcome of one event, as /transform would report it
+    Result(TransformResponse),
+    /// A message that couldn't be handled
+    Error(TransformResponse),
+}
+
+/// The build events currently run against
+struct Session {
+    build: Arc<CachedBuild>,
+    code: CodeOptions,
+    output: OutputOptions,
+    /// State left by the last event, with `sharedState`
+    shared_state: Option<Value>,
+}
+
+/// Accept a websocket connection and serve its session until it closes
+pub async fn connect(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    payload: web::Payload,
+) -> Result<HttpResponse, actix_web::Error> {
+    ws::verify_handshake(http_req.head())?;
+    let key = http_req
+        .headers()
+        .get(header::SEC_WEBSOCKET_KEY)
+        .map(|key| ws::hash_key(key.as_bytes()))
+        .unwrap_or_default();
+    let accept = HeaderValue::from_bytes(&key).map_err(|_| ws::HandshakeError::BadWebsocketKey)?;
+
+    let (replies, receiver) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(serve(state, http_req, payload, replies));
+
+    let body = stream::unfold(
+        (receiver, Codec::new()),
+        |(mut receiver, mut codec)| async move {
+            let message = receiver.recv().await?;
+            let mut frame = BytesMut::new();
+            codec.encode(message, &mut frame).ok()?;
+            Some((Ok::<_, actix_web::Error>(frame.freeze()), (receiver, codec)))
+        },
+    );
+
+    Ok(HttpResponse::SwitchingProtocols()
+        .upgrade("websocket")
+        .insert_header((header::SEC_WEBSOCKET_ACCEPT, accept))
+        .streaming(body))
+}
+
+/// Read client frames, answering each text message in order
+async fn serve(
+    state: web::Data<AppState>,
+    http_req: HttpRequest,
+    mut payload: web::Payload,
+    replies: mpsc::UnboundedSender<Message>,
+) {
+    let mut codec = Codec::new().max_size(MAX_MESSAGE_BYTES);
+    let mut buffer = BytesMut::new();
+    let mut fragments: Option<BytesMut> = None;
+    let mut session = None;
+
+    loop {
+        let frame = match codec.decode(&mut buffer) {
+            Ok(Some(frame)) => frame,
+            Ok(None) => match payload.next().await {
+                Some(Ok(chunk)) => {
+                    buffer.extend_from_slice(&chunk);
+                    continue;
+                }
+                _ => return,
+            },
+            Err(_) => {
+                let _ = replies.send(Message::Close(Some(CloseCode::Protocol.into())));
+                return;
+            }
+        };
+
+        let text = match frame {
+            Frame::Text(text) => text,
+            Frame::Continuation(Item::FirstText(part)) => {
+                fragments = Some(BytesMut::from(&part[..]));
+                continue;
+            }
+            Frame::Continuation(Item::Continue(part)) => {
+                if let Some(fragments) = &mut fragments {
+                    if !append_fragment(fragments, &part) {
+                        let _ = replies.send(Message::Close(Some(CloseCode::Size.into())));
+                        return;
+                    }
+                }
+                continue;
+            }
+            Frame::Continuation(Item::Last(part)) => {
+                let Some(mut text) = fragments.take() else {
+                    continue;
+                };
+                if !append_fragment(&mut text, &part) {
+                    let _ = replies.send(Message::Close(Some(CloseCode::Size.into())));
+                    return;
+                }
+                text.freeze()
+            }
+            Frame::Binary(_) | Frame::Continuation(Item::FirstBinary(_)) => {
+                let failure = Failure::bad_request("Only text messages are supported".to_string());
+                if send(
+                    &replies,
+                    &ServerMessage::Error(state.record_failure(failure, true)),
+                ) {
+                    continue;
+                }
+                return;
+            }
+            Frame::Ping(data) => {
+                let _ = replies.send(Message::Pong(data));
+                continue;
+            }
+            Frame::Pong(_) => continue,
+            Frame::Close(reason) => {
+                let _ = replies.send(Message::Close(reason));
+                return;
+            }
+        };
+
+        let reply = handle(&state, &http_req, &mut session, &text).await;
+        if !send(&replies, &reply) {
+            return;
+        }
+    }
+}
+
+/// Add a continuation frame to a fragmented message, returning false instead
+/// if the message would grow past `MAX_MESSAGE_BYTES`
+///
+/// The codec only limits each frame, so the reassembled length is checked here.
+fn append_fragment(fragments: &mut BytesMut, part: &[u8]) -> bool {
+    if fragments.len() + part.len() > MAX_MESSAGE_BYTES {
+        return false;
+    }
+    fragments.extend_from_slice(part);
+    true
+}
+
+/// Queue a reply, returning false once the client has gone away
+fn send(replies: &mpsc::UnboundedSender<Message>, reply: &ServerMessage) -> bool {
+    let text = serde_json::to_string(reply).unwrap_or_else(|_| "null".to_string());
+    replies.send(Message::Text(text.into())).is_ok()
+}
+
+async fn handle(
+    state: &AppState,
+    http_req: &HttpRequest,
+    session: &mut Option<Session>,
+    text: &[u8],
+) -> ServerMessage {
+    let message = match serde_json::from_slice(text) {
+        Ok(message) => message,
+        Err(e) => {
+            let failure = Failure::bad_request(format!("Invalid message: {}", e));
+            return ServerMessage::Error(state.record_failure(failure, true));
+        }
+    };
+
+    match message {
+        ClientMessage::Code { code, output } => {
+            if state.check_rate_limit(http_req).is_err() {
+                return ServerMessage::Error(rate_limited_failure());
+            }
+
+            // Events sent after code that fails to build are rejected, not run
+            // against the previous build
+            *session = None;
+            let build = match admit(state, &code).await {
+                Ok((prepared, _permit)) => {
+                    let cached = matches!(prepared, Prepared::Cached(_));
+                    compile(state, prepared, &code, None)
+                        .await
+                        .map(|build| (build, cached))
+                }
+                Err(failure) => Err(failure),
+            };
+
+            match build {
+                Ok((build, cached)) => {
+                    let reply = ServerMessage::Ready {
+                        cached,
+                        build_info: build.build_info.clone(),
+                        dep_timings: build.dep_timings.clone(),
+                        compiler_warnings: build.warnings.clone(),
+                    };
+                    *session = Some(Session {
+                        build,
+                        code: *code,
+                        output,
+                        shared_state: None,
+                    });
+                    reply
+                }
+                Err(failure) => ServerMessage::Error(state.record_failure(failure, true)),
+            }
+        }
+        ClientMessage::Event { event, hint } => {
+            let Some(session) = session else {
+                let failure = Failure::bad_request("Send code before events".to_string());
+                return ServerMessage::Error(state.record_failure(failure, true));
+            };
+
+            let response = transform_event(
+                state,
+                &session.build.executable,
+                &session.code,
+                &session.output,
+                &event,
+                hint.as_ref(),
+                session.shared_state.as_ref(),
+            )
+            .await;
+            let response = match response {
+                Ok(response) => {
+                    if response.shared_state.is_some() {
+                        session.shared_state = response.shared_state.clone();
+                    }
+                    response
+                }
+                Err(failure) => state.record_failure(failure, true),
+            };
+            ServerMessage::Result(response)
+        }
+    }
+}
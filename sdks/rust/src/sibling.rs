@@ -0,0 +1,62 @@
+//! Client for a sibling SDK service, for cross-SDK parity checks
+//!
+//! Every SDK service in the playground speaks the same `POST /transform`
+//! protocol, so an event can be run through equivalent code in another SDK
+//! (e.g. JavaScript) and compared with this one's result. The sibling is
+//! called through the server's shared client (see [`crate::fetch`]), over
+//! `http://` or `https://`.
+
+use crate::fetch;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// How long the sibling has to answer, including running the code
+const SIBLING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response read from the sibling
+const MAX_RESPONSE_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Run `code` against `event` in the sibling SDK at `base_url`
+///
+/// Returns the sibling's response body, whatever its status: SDK services
+/// answer failed transforms with a JSON body carrying `success: false`.
+pub async fn transform(
+    client: &reqwest::Client,
+    base_url: &str,
+    event: &Value,
+    code: &str,
+) -> Result<Value, String> {
+    let body = json!({ "event": event, "beforeSendCode": code }).to_string();
+    tokio::time::timeout(SIBLING_TIMEOUT, post(client, base_url, "/transform", body))
+        .await
+        .map_err(|_| {
+            format!(
+                "Sibling SDK didn't answer within {}s",
+                SIBLING_TIMEOUT.as_secs()
+            )
+        })?
+}
+
+async fn post(
+    client: &reqwest::Client,
+    base_url: &str,
+    path: &str,
+    body: String,
+) -> Result<Value, String> {
+    let mut url = fetch::parse_url(base_url).map_err(|e| format!("Sibling SDK: {}", e))?;
+    url.set_path(&format!("{}{}", url.path().trim_end_matches('/'), path));
+
+    let response = client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("Sibling SDK: {}", fetch::describe(&e)))?;
+    let body = fetch::read_body(response, MAX_RESPONSE_BYTES)
+        .await
+        .map_err(|e| format!("Sibling SDK: {}", e))?;
+    serde_json::from_slice(&body)
+        .map_err(|e| format!("Sibling SDK returned a body that isn't JSON: {}", e))
+}
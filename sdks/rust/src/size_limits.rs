@@ -0,0 +1,154 @@
+//! Sentry-like size limits checked against transformed events
+//!
+//! Sentry drops events whose payload is too large and truncates oversized
+//! fields. These checks report both cases as warnings without modifying the
+//! event.
+
+use serde_json::Value;
+
+/// Limits applied when `enforceSizeLimits` is set
+#[derive(Debug, Clone, Copy)]
+pub struct SizeLimits {
+    /// Maximum size of the serialized event
+    pub max_event_bytes: usize,
+    /// Maximum length of messages and exception values
+    pub max_message_chars: usize,
+    /// Maximum length of tag keys and values
+    pub max_tag_chars: usize,
+}
+
+/// Check an event against the limits, returning one warning per violation
+pub fn check(event: &Value, limits: &SizeLimits) -> Vec<String> {
+    let mut warnings = vec![];
+
+    let size = serde_json::to_vec(event).map_or(0, |json| json.len());
+    if size > limits.max_event_bytes {
+        warnings.push(format!(
+            "event is {} bytes, exceeding the {} byte limit",
+            size, limits.max_event_bytes
+        ));
+    }
+
+    for (path, text, limit) in limited_fields(event, limits) {
+        let chars = text.chars().count();
+        if chars > limit {
+            warnings.push(format!("{} exceeds {} chars ({})", path, limit, chars));
+        }
+    }
+
+    warnings
+}
+
+/// Collect `(path, text, limit)` for every length-limited string in the event
+fn limited_fields<'a>(event: &'a Value, limits: &SizeLimits) -> Vec<(String, &'a str, usize)> {
+    let mut fields = vec![];
+    let mut add = |path: String, text: Option<&'a str>, limit: usize| {
+        if let Some(text) = text {
+            fields.push((path, text, limit));
+        }
+    };
+
+    // `message` may be a plain string or an object shaped like `logentry`
+    let message_limit = limits.max_message_chars;
+    add(
+        "message".to_string(),
+        event["message"].as_str(),
+        message_limit,
+    );
+    for entry in ["message", "logentry"] {
+        for key in ["message", "formatted"] {
+            let path = format!("{}.{}", entry, key);
+            add(path, event[entry][key].as_str(), message_limit);
+        }
+    }
+
+    let exceptions = event["exception"]["values"]
+        .as_array()
+        .into_iter()
+        .flatten();
+    for (index, exception) in exceptions.enumerate() {
+        let path = format!("exception.values[{}].value", index);
+        add(path, exception["value"].as_str(), message_limit);
+    }
+
+    // Tags are either an object or a list of `[key, value]` pairs
+    let tags: Vec<(&str, &Value)> = match &event["tags"] {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.as_str(), value))
+            .collect(),
+        Value::Array(pairs) => pairs
+            .iter()
+            .filter_map(|pair| Some((pair[0].as_str()?, &pair[1])))
+            .collect(),
+        _ => vec![],
+    };
+    for (key, value) in tags {
+        add(
+            format!("tag key '{}'", key),
+            Some(key),
+            limits.max_tag_chars,
+        );
+        add(
+            format!("tags.{}", key),
+            value.as_str(),
+            limits.max_tag_chars,
+        );
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const LIMITS: SizeLimits = SizeLimits {
+        max_event_bytes: 1024,
+        max_message_chars: 16,
+        max_tag_chars: 8,
+    };
+
+    #[test]
+    fn oversized_messages_are_reported() {
+        let event = json!({
+            "logentry": { "message": "a".repeat(20) },
+            "exception": { "values": [{ "value": "short" }, { "value": "é".repeat(17) }] }
+        });
+        assert_eq!(
+            check(&event, &LIMITS),
+            [
+                "logentry.message exceeds 16 chars (20)",
+                "exception.values[1].value exceeds 16 chars (17)",
+            ]
+        );
+    }
+
+    #[test]
+    fn oversized_events_are_reported() {
+        let event = json!({ "extra": { "blob": "x".repeat(2000) } });
+        let warnings = check(&event, &LIMITS);
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(
+            warnings[0].ends_with("bytes, exceeding the 1024 byte limit"),
+            "{}",
+            warnings[0]
+        );
+    }
+
+    #[test]
+    fn tags_are_checked_in_either_shape() {
+        let as_object = json!({ "tags": { "environment": "production" } });
+        let as_pairs = json!({ "tags": [["environment", "production"]] });
+        for event in [as_object, as_pairs] {
+            assert_eq!(
+                check(&event, &LIMITS),
+                [
+                    "tag key 'environment' exceeds 8 chars (11)",
+                    "tags.environment exceeds 8 chars (10)",
+                ]
+            );
+        }
+    }
+}
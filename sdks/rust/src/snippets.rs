@@ -0,0 +1,97 @@
+//! Saved snippets, shareable by id
+//!
+//! Snippets are kept in memory and, when `SNIPPETS_PATH` is set, persisted
+//! to that JSON file after every save so they survive restarts. The file is
+//! replaced atomically, so a crash mid-write never corrupts existing snippets.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Snippets stored before new saves are refused
+const MAX_SNIPPETS: usize = 1000;
+
+/// Returned when a snippet can't be saved
+#[derive(Debug)]
+pub enum SaveError {
+    /// The store already holds `MAX_SNIPPETS` snippets
+    Full,
+    /// Writing the snippets file failed
+    Io(io::Error),
+}
+
+/// Snippets keyed by id, optionally backed by a JSON file
+pub struct SnippetStore<T> {
+    path: Option<PathBuf>,
+    entries: Mutex<HashMap<String, T>>,
+}
+
+impl<T: Clone + Serialize + DeserializeOwned> SnippetStore<T> {
+    /// Open the store, loading any snippets already saved at `path`
+    pub fn open(path: Option<PathBuf>) -> io::Result<Self> {
+        let entries = match &path {
+            Some(path) if path.exists() => serde_json::from_slice(&fs::read(path)?)?,
+            _ => HashMap::new(),
+        };
+
+        Ok(SnippetStore {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Save a snippet and return its id
+    pub fn insert(&self, snippet: T) -> Result<String, SaveError> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_SNIPPETS {
+            return Err(SaveError::Full);
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        entries.insert(id.clone(), snippet);
+        if let Err(e) = self.persist(&entries) {
+            entries.remove(&id);
+            return Err(SaveError::Io(e));
+        }
+        Ok(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<T> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(id).cloned()
+    }
+
+    /// Write all snippets to the backing file, if there is one
+    fn persist(&self, entries: &HashMap<String, T>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let staging = path.with_extension("json.tmp");
+        fs::write(&staging, serde_json::to_vec(entries)?)?;
+        fs::rename(&staging, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_snippets_survive_reopening_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snippets.json");
+
+        let store = SnippetStore::open(Some(path.clone())).unwrap();
+        let id = store.insert("fn scrub".to_string()).unwrap();
+        assert_eq!(store.get(&id).as_deref(), Some("fn scrub"));
+
+        let reopened = SnippetStore::<String>::open(Some(path)).unwrap();
+        assert_eq!(reopened.get(&id).as_deref(), Some("fn scrub"));
+        assert_eq!(reopened.get("missing"), None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Quick syntax check run before cargo
+//!
+//! Code that doesn't even tokenize, with an unbalanced delimiter, an
+//! unterminated literal or comment, or a character Rust has no token for,
+//! would otherwise take a full cargo invocation to reject. Submissions are
+//! lexed with proc_macro2, syn's tokenizer, and such errors reported straight
+//! away. Only tokenizing is checked: grammar and types are left to rustc, so
+//! valid code syn can't parse, like nightly-only syntax, is never rejected.
+
+use crate::allowlist::Violation;
+use proc_macro2::TokenStream;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// The first tokenizing error in user code or its helper modules
+///
+/// `code` should already have its feature attributes hoisted. Code without
+/// any tokens is reported too, as it can only ever return `()`.
+pub fn check(code: &str, modules: &BTreeMap<String, String>) -> Option<Violation> {
+    match TokenStream::from_str(code) {
+        Ok(tokens) if tokens.is_empty() => {
+            return Some(Violation {
+                module: None,
+                line: 1,
+                column: 1,
+                message: "the code is empty".to_string(),
+            })
+        }
+        Ok(_) => {}
+        Err(e) => return Some(lex_violation(code, &e, None)),
+    }
+
+    modules.iter().find_map(|(name, source)| {
+        TokenStream::from_str(source)
+            .err()
+            .map(|e| lex_violation(source, &e, Some(name)))
+    })
+}
+
+fn lex_violation(
+    source: &str,
+    error: &proc_macro2::LexError,
+    module: Option<&String>,
+) -> Violation {
+    let start = error.span().start();
+    let rest: String = source
+        .lines()
+        .nth(start.line.saturating_sub(1))
+        .unwrap_or_default()
+        .chars()
+        .skip(start.column)
+        .collect();
+    Violation {
+        module: module.cloned(),
+        line: start.line,
+        column: start.column + 1,
+        message: describe(&rest),
+    }
+}
+
+/// What went wrong, from the source starting where the lexer gave up
+fn describe(rest: &str) -> String {
+    match rest.chars().next() {
+        Some(open @ ('{' | '(' | '[')) => format!("unclosed delimiter `{}`", open),
+        Some(close @ ('}' | ')' | ']')) => format!("unexpected closing delimiter `{}`", close),
+        Some('"') => "unterminated string literal".to_string(),
+        Some('\'') => "unterminated character literal".to_string(),
+        Some('/') if rest.starts_with("/*") => "unterminated block comment".to_string(),
+        // `r#"...`, `b"...`, `c"...`
+        Some('r' | 'b' | 'c')
+            if rest
+                .trim_start_matches(['r', 'b', 'c', '#'])
+                .starts_with('"') =>
+        {
+            "unterminated string literal".to_string()
+        }
+        Some(_) => {
+            let token: String = rest
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take(20)
+                .collect();
+            format!("invalid token {:?}", token)
+        }
+        None => "unexpected end of input".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(code: &str) -> Option<(usize, usize, String)> {
+        check(code, &BTreeMap::new())
+            .map(|violation| (violation.line, violation.column, violation.message))
+    }
+
+    #[test]
+    fn tokenizing_errors_are_located_and_described() {
+        assert_eq!(
+            error("let a = 1;\nif a > 0 {\nSome(event)"),
+            Some((2, 10, "unclosed delimiter `{`".to_string()))
+        );
+        assert_eq!(
+            error("Some(event))"),
+            Some((1, 12, "unexpected closing delimiter `)`".to_string()))
+        );
+        assert_eq!(
+            error("let s = \"open;\nSome(event)"),
+            Some((1, 9, "unterminated string literal".to_string()))
+        );
+        assert_eq!(
+            error("/* note\nSome(event)"),
+            Some((1, 1, "unterminated block comment".to_string()))
+        );
+        assert_eq!(error("   "), Some((1, 1, "the code is empty".to_string())));
+    }
+
+    #[test]
+    fn code_that_tokenizes_is_left_to_rustc() {
+        assert_eq!(error("let x: u32 = \"not a number\";\nSome(event)"), None);
+        assert_eq!(error("Some(event) +"), None);
+    }
+
+    #[test]
+    fn errors_in_helper_modules_name_the_module() {
+        let modules = BTreeMap::from([("rules".to_string(), "pub fn f() {".to_string())]);
+        let violation = check("Some(event)", &modules).unwrap();
+        assert_eq!(violation.module.as_deref(), Some("rules"));
+        assert_eq!(violation.message, "unclosed delimiter `{`");
+    }
+}
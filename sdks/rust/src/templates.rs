@@ -0,0 +1,218 @@
+//! Sample events for trying out code without pasting an event
+//!
+//! Each template is shaped like what a Sentry SDK sends for that kind of
+//! event, with the fields transforms most often touch: tags, user, request,
+//! breadcrumbs, and contexts. Ids and timestamps are fresh on every call.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Kind of sample event to generate
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Template {
+    /// An error event with an exception and stack trace
+    Exception,
+    /// A `capture_message` event
+    Message,
+    /// A transaction with a few spans
+    Transaction,
+}
+
+/// Generate a sample event of the given kind
+pub fn generate(template: Template) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64();
+    let id = || uuid::Uuid::new_v4().simple().to_string();
+
+    let mut event = json!({
+        "event_id": id(),
+        "timestamp": now,
+        "platform": "native",
+        "release": "checkout@2.4.1",
+        "environment": "production",
+        "server_name": "web-3",
+        "tags": {
+            "region": "eu-west-1",
+            "feature": "checkout"
+        },
+        "user": {
+            "id": "4711",
+            "email": "jane.doe@example.com",
+            "ip_address": "203.0.113.7",
+            "username": "jdoe"
+        },
+        "request": {
+            "url": "https://shop.example.com/api/checkout?session=a1b2c3",
+            "method": "POST",
+            "headers": {
+                "User-Agent": "Mozilla/5.0 (X11; Linux x86_64)",
+                "Cookie": "session=a1b2c3; theme=dark"
+            }
+        },
+        "contexts": {
+            "os": { "name": "Linux", "version": "6.1" },
+            "runtime": { "name": "rustc", "version": "1.80.0" }
+        },
+        "sdk": { "name": "sentry.rust", "version": "0.34.0" }
+    });
+
+    let fields = match template {
+        Template::Exception => json!({
+            "level": "error",
+            "exception": {
+                "values": [{
+                    "type": "PaymentError",
+                    "value": "card declined for order 9182",
+                    "module": "checkout::payment",
+                    "mechanism": { "type": "panic", "handled": false },
+                    "stacktrace": {
+                        "frames": [
+                            {
+                                "function": "main",
+                                "filename": "src/main.rs",
+                                "lineno": 42,
+                                "in_app": true
+                            },
+                            {
+                                "function": "checkout::submit_order",
+                                "filename": "src/checkout.rs",
+                                "lineno": 118,
+                                "in_app": true
+                            },
+                            {
+                                "function": "checkout::payment::charge",
+                                "filename": "src/checkout/payment.rs",
+                                "lineno": 57,
+                                "in_app": true
+                            }
+                        ]
+                    }
+                }]
+            },
+            "breadcrumbs": {
+                "values": [
+                    {
+                        "timestamp": now - 2.5,
+                        "category": "navigation",
+                        "message": "/cart -> /checkout",
+                        "level": "info"
+                    },
+                    {
+                        "timestamp": now - 0.4,
+                        "type": "http",
+                        "category": "http",
+                        "level": "error",
+                        "data": {
+                            "method": "POST",
+                            "url": "https://payments.example.com/charge",
+                            "status_code": 402
+                        }
+                    }
+                ]
+            }
+        }),
+        Template::Message => json!({
+            "level": "warning",
+            "logger": "checkout::inventory",
+            "message": "Stock for SKU 7731 is below the reorder threshold",
+            "breadcrumbs": {
+                "values": [{
+                    "timestamp": now - 1.0,
+                    "category": "query",
+                    "message": "SELECT quantity FROM stock WHERE sku = $1",
+                    "level": "info"
+                }]
+            }
+        }),
+        Template::Transaction => {
+            let trace_id = id();
+            let root_span = id()[..16].to_string();
+            json!({
+                "type": "transaction",
+                "transaction": "POST /api/checkout",
+                "start_timestamp": now - 0.35,
+                "contexts": {
+                    "trace": {
+                        "trace_id": trace_id,
+                        "span_id": root_span,
+                        "op": "http.server",
+                        "status": "ok"
+                    }
+                },
+                "spans": [
+                    {
+                        "trace_id": trace_id,
+                        "span_id": id()[..16].to_string(),
+                        "parent_span_id": root_span,
+                        "op": "db.query",
+                        "description": "SELECT * FROM orders WHERE id = $1",
+                        "start_timestamp": now - 0.3,
+                        "timestamp": now - 0.22,
+                        "status": "ok"
+                    },
+                    {
+                        "trace_id": trace_id,
+                        "span_id": id()[..16].to_string(),
+                        "parent_span_id": root_span,
+                        "op": "http.client",
+                        "description": "POST https://payments.example.com/charge",
+                        "start_timestamp": now - 0.2,
+                        "timestamp": now - 0.02,
+                        "status": "ok"
+                    }
+                ]
+            })
+        }
+    };
+
+    if let (Some(event), Value::Object(fields)) = (event.as_object_mut(), fields) {
+        event.extend(fields);
+    }
+    event
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_schema;
+
+    const TEMPLATES: [Template; 3] = [
+        Template::Exception,
+        Template::Message,
+        Template::Transaction,
+    ];
+
+    #[test]
+    fn templates_generate_well_formed_events() {
+        for template in TEMPLATES {
+            let event = generate(template);
+            assert_eq!(
+                event_schema::check(&event),
+                Vec::<String>::new(),
+                "{:?}",
+                template
+            );
+            assert_eq!(event["user"]["id"], "4711");
+        }
+        assert!(generate(Template::Exception)["exception"]["values"][0]["stacktrace"].is_object());
+        assert!(generate(Template::Message)["message"].is_string());
+        let transaction = generate(Template::Transaction);
+        assert_eq!(transaction["type"], "transaction");
+        assert_eq!(
+            transaction["spans"][0]["trace_id"],
+            transaction["contexts"]["trace"]["trace_id"]
+        );
+    }
+
+    #[test]
+    fn every_event_gets_a_fresh_id() {
+        let ids: Vec<Value> = (0..2)
+            .map(|_| generate(Template::Message)["event_id"].clone())
+            .collect();
+        assert_ne!(ids[0], ids[1]);
+    }
+}
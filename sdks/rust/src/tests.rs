@@ -0,0 +1,3779 @@
+//! Endpoint tests, run against the full app as the server configures it
+//!
+//! Most tests share one `AppState`, so tests running the same code reuse
+//! its build from the cache; builds are slow enough that tests stick to a
+//! handful of distinct snippets. Tests that need their own configuration
+//! build a fresh state with [`state_with`].
+
+use super::*;
+use actix_web::dev::ServiceResponse;
+use actix_web::error::PayloadError;
+use actix_web::test::{self, TestRequest};
+use futures_util::StreamExt;
+use std::sync::OnceLock;
+
+/// Code passing every event through unchanged
+const IDENTITY: &str = "Some(event)";
+
+/// Code dropping `error`-level events and tagging the rest
+const DROP_ERRORS: &str = r#"if event["level"] == "error" {
+    return None;
+}
+event["tags"]["seen"] = json!("yes");
+Some(event)"#;
+
+/// The state shared by tests that don't change the configuration
+fn state() -> web::Data<AppState> {
+    static STATE: OnceLock<web::Data<AppState>> = OnceLock::new();
+    STATE.get_or_init(|| state_with(|_| {})).clone()
+}
+
+/// A fresh state, with the default configuration adjusted by `configure`
+fn state_with(configure: impl FnOnce(&mut Config)) -> web::Data<AppState> {
+    let mut config = Config::from_env();
+    config.auth_token = None;
+    config.rate_limit_per_minute = 0;
+    config.parity_sdk_url = None;
+    configure(&mut config);
+    web::Data::new(AppState::new(config).expect("test state"))
+}
+
+/// Send a request through the app with the given state
+async fn send(state: &web::Data<AppState>, request: TestRequest) -> ServiceResponse {
+    send_request(state, request.to_request()).await
+}
+
+/// Send an already built request, e.g. one with a streamed payload
+async fn send_request(
+    state: &web::Data<AppState>,
+    request: actix_http::Request,
+) -> ServiceResponse {
+    let app = test::init_service(
+        App::new()
+            .app_data(state.clone())
+            .app_data(codec::json_config())
+            .wrap(from_fn(timeouts::body_deadline))
+            .wrap(from_fn(request_id::assign))
+            .configure(routes),
+    )
+    .await;
+    test::call_service(&app, request)
+        .await
+        .map_into_boxed_body()
+}
+
+/// Status and JSON body of a request
+async fn json_response(state: &web::Data<AppState>, request: TestRequest) -> (StatusCode, Value) {
+    let response = send(state, request).await;
+    let status = response.status();
+    let body = test::read_body(response).await;
+    let body = serde_json::from_slice(&body).unwrap_or_else(|e| {
+        panic!(
+            "response isn't JSON ({}): {}",
+            e,
+            String::from_utf8_lossy(&body)
+        )
+    });
+    (status, body)
+}
+
+/// POST a JSON body to the shared state's app
+async fn post(path: &str, body: Value) -> (StatusCode, Value) {
+    post_to(&state(), path, body).await
+}
+
+/// POST a JSON body to an app with the given state
+async fn post_to(state: &web::Data<AppState>, path: &str, body: Value) -> (StatusCode, Value) {
+    json_response(state, TestRequest::post().uri(path).set_json(body)).await
+}
+
+/// Crate names in a `buildInfo` value
+fn crate_names(build_info: &Value) -> Vec<&str> {
+    build_info["crates"]
+        .as_array()
+        .expect("buildInfo lists crates")
+        .iter()
+        .filter_map(|krate| krate["name"].as_str())
+        .collect()
+}
+
+#[actix_web::test]
+async fn build_info_lists_the_default_dependencies() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": DROP_ERRORS, "includeBuildInfo": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let crates = crate_names(&body["buildInfo"]);
+    assert!(crates.contains(&"serde"), "{:?}", crates);
+    assert!(crates.contains(&"serde_json"), "{:?}", crates);
+    assert!(!crates.contains(&"transform"), "{:?}", crates);
+}
+
+#[actix_web::test]
+async fn build_info_is_omitted_unless_requested() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body.get("buildInfo").is_none(), "{}", body);
+}
+
+/// Code for `beforeSendLog` dropping trace logs and marking the rest
+const SCRUB_LOG: &str = r#"if log["level"] == "trace" {
+    return None;
+}
+log["attributes"]["scrubbed"] = json!(true);
+Some(log)"#;
+
+#[actix_web::test]
+async fn log_mode_modifies_attributes() {
+    let log = json!({ "body": "user signed in", "level": "info", "attributes": { "user": 7 } });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": log, "beforeSendCode": SCRUB_LOG, "mode": "beforeSendLog" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"]["attributes"],
+        json!({ "user": 7, "scrubbed": true })
+    );
+}
+
+#[actix_web::test]
+async fn log_mode_drops_trace_logs_as_null() {
+    let log = json!({ "body": "entering loop", "level": "trace" });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": log, "beforeSendCode": SCRUB_LOG, "mode": "beforeSendLog" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+}
+
+#[actix_web::test]
+async fn log_mode_rejects_malformed_log_items() {
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "body": "no level" },
+            "beforeSendCode": SCRUB_LOG,
+            "mode": "beforeSendLog"
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid log item"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn validate_reports_every_labeled_span_of_a_borrow_error() {
+    let code = "let tags = &mut event[\"tags\"];\n\
+                let level = &event[\"level\"];\n\
+                tags[\"level\"] = level.clone();\n\
+                Some(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false);
+    let error = &body["errors"][0];
+    assert!(
+        error["message"].as_str().unwrap().contains("E0502"),
+        "{}",
+        error
+    );
+    let spans: Vec<(u64, bool, &str)> = error["spans"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|span| {
+            (
+                span["startLine"].as_u64().unwrap(),
+                span["primary"].as_bool().unwrap(),
+                span["label"].as_str().unwrap_or_default(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        spans,
+        [
+            (2, true, "immutable borrow occurs here"),
+            (1, false, "mutable borrow occurs here"),
+            (3, false, "mutable borrow later used here"),
+        ]
+    );
+    assert_eq!(error["line"], 2);
+    assert_eq!(error["column"], 14);
+}
+
+/// Code that only builds on nightly
+const NIGHTLY_ONLY: &str = "#![feature(never_type)]\n\
+                            let _unreachable: Option<!> = None;\n\
+                            Some(event)";
+
+#[actix_web::test]
+async fn nightly_features_build_only_on_nightly() {
+    if check_toolchain("nightly").await.is_err() {
+        eprintln!("nightly isn't installed; skipping");
+        return;
+    }
+
+    let (status, body) = post(
+        "/validate",
+        json!({ "code": NIGHTLY_ONLY, "toolchain": "nightly" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], true, "{}", body);
+
+    let (status, body) = post(
+        "/validate",
+        json!({ "code": NIGHTLY_ONLY, "toolchain": "stable" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false, "{}", body);
+}
+
+#[actix_web::test]
+async fn unknown_toolchains_are_rejected() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "toolchain": "1.0.0" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unsupported toolchain '1.0.0'"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn full_queue_rejects_builds_with_retry_after() {
+    let state = state_with(|config| {
+        config.max_concurrent_builds = 1;
+        config.max_queue_depth = 0;
+        config.queue_retry_after_secs = 7;
+    });
+    let _permit = state.limiter.acquire(0).await.expect("a free slot");
+
+    let response = send(
+        &state,
+        TestRequest::post()
+            .uri("/transform")
+            .set_json(json!({ "event": {}, "beforeSendCode": IDENTITY })),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get("Retry-After").unwrap(), "7");
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["error"], QUEUE_FULL_MESSAGE);
+
+    let (_, status) = json_response(&state, TestRequest::get().uri("/status")).await;
+    assert_eq!(status["activeBuilds"], 1);
+    assert_eq!(status["rejectedRequests"], 1);
+}
+
+#[actix_web::test]
+async fn pretty_output_parses_back_to_the_event() {
+    let event = json!({ "level": "info", "tags": { "a": "b" }, "extra": [1, 2] });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY, "pretty": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let pretty = body["prettyJson"].as_str().expect("prettyJson is a string");
+    assert!(pretty.contains("\n  \"level\": \"info\""), "{}", pretty);
+    let parsed: Value = serde_json::from_str(pretty).unwrap();
+    assert_eq!(parsed, body["transformedEvent"]);
+    assert_eq!(parsed, event);
+}
+
+#[actix_web::test]
+async fn printed_sentinels_cannot_spoof_the_result() {
+    let code = r#"println!("{}", std::env::var("TRANSFORM_RESULT_SENTINEL").unwrap_or_default());
+println!("TRANSFORM_RESULT_SENTINEL");
+println!("{}", json!({ "forged": true }));
+event["tags"]["real"] = json!(true);
+Some(event)"#;
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "tags": { "real": true } })
+    );
+}
+
+/// Code panicking on `fatal`-level events
+const PANIC_ON_FATAL: &str = r#"if event["level"] == "fatal" {
+    panic!("boom");
+}
+Some(event)"#;
+
+#[actix_web::test]
+async fn panics_under_abort_report_their_message() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "fatal" }, "beforeSendCode": PANIC_ON_FATAL, "panicAbort": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"], "Runtime error: user code panicked: boom");
+}
+
+#[actix_web::test]
+async fn validate_and_transform_agree_on_a_seeded_event() {
+    let event = json!({ "level": "warning", "tags": { "region": "eu" } });
+    let (status, validation) =
+        post("/validate", json!({ "code": DROP_ERRORS, "event": event })).await;
+    assert_eq!(status, StatusCode::OK, "{}", validation);
+    assert_eq!(validation["valid"], true, "{}", validation);
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": DROP_ERRORS }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"]["tags"],
+        json!({ "region": "eu", "seen": "yes" })
+    );
+}
+
+#[actix_web::test]
+async fn rate_limits_apply_per_client_ip() {
+    let state = state_with(|config| {
+        config.rate_limit_per_minute = 1;
+        config.rate_limit_burst = 1;
+        config.trusted_proxy_header = Some("X-Forwarded-For".to_string());
+    });
+    // An unsupported toolchain fails before building, after the rate limit
+    let from = |ip: &str| {
+        TestRequest::post()
+            .uri("/transform")
+            .insert_header(("X-Forwarded-For", ip.to_string()))
+            .set_json(json!({ "event": {}, "beforeSendCode": IDENTITY, "toolchain": "1.0.0" }))
+    };
+
+    let response = send(&state, from("203.0.113.1")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = send(&state, from("203.0.113.1")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response
+        .headers()
+        .get("Retry-After")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!((1..=60).contains(&retry_after), "{}", retry_after);
+
+    let response = send(&state, from("198.51.100.2, 203.0.113.1")).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn transform_code_calls_supplied_modules() {
+    let rules = r#"pub fn scrub_email(event: &mut Value) {
+    if let Some(user) = event.get_mut("user") {
+        user["email"] = json!("[redacted]");
+    }
+}"#;
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "user": { "id": 1, "email": "a@example.com" } },
+            "beforeSendCode": "use rules::scrub_email;\nscrub_email(&mut event);\nSome(event)",
+            "modules": { "rules": rules }
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"]["user"],
+        json!({ "id": 1, "email": "[redacted]" })
+    );
+}
+
+/// A request that passes auth, then fails before building
+fn unsupported_toolchain_request() -> TestRequest {
+    TestRequest::post()
+        .uri("/transform")
+        .set_json(json!({ "event": {}, "beforeSendCode": IDENTITY, "toolchain": "1.0.0" }))
+}
+
+#[actix_web::test]
+async fn configured_tokens_guard_write_endpoints() {
+    let state = state_with(|config| config.auth_token = Some("s3cret".to_string()));
+
+    let (status, body) = json_response(&state, unsupported_toolchain_request()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Missing bearer token");
+
+    let request = unsupported_toolchain_request().insert_header(("Authorization", "Bearer wrong"));
+    let (status, body) = json_response(&state, request).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "Invalid bearer token");
+
+    let request = unsupported_toolchain_request().insert_header(("Authorization", "Bearer s3cret"));
+    let (status, _) = json_response(&state, request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let response = send(&state, TestRequest::get().uri("/health")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn auth_is_disabled_without_a_token() {
+    let (status, body) = json_response(&state(), unsupported_toolchain_request()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+}
+
+#[actix_web::test]
+async fn low_disk_space_rejects_builds_and_fails_health() {
+    let state = state_with(|config| config.min_free_disk_bytes = u64::MAX);
+
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"], "Insufficient disk space for compilation");
+
+    let (status, health) = json_response(&state, TestRequest::get().uri("/health")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(health["status"], "unhealthy");
+    assert!(health["diskFreeBytes"].as_u64().is_some(), "{}", health);
+}
+
+#[actix_web::test]
+async fn frame_helpers_walk_the_whole_exception_chain() {
+    let frame = |password: &str| json!({ "function": "login", "vars": { "password": password, "user": "ana" } });
+    let event = json!({
+        "exception": { "values": [
+            { "type": "IOError", "stacktrace": { "frames": [frame("hunter2")] } },
+            { "type": "LoginError", "stacktrace": { "frames": [frame("swordfish"), { "function": "main" }] } }
+        ] }
+    });
+    let code = r#"for frame in frames_mut(&mut event) {
+    if let Some(vars) = frame.get_mut("vars") {
+        vars["password"] = json!("[Filtered]");
+    }
+}
+let chain = exceptions_mut(&mut event).count();
+event["tags"]["chain"] = json!(chain);
+Some(event)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": code }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let transformed = &body["transformedEvent"];
+    assert_eq!(transformed["tags"]["chain"], 2);
+    for pointer in [
+        "/exception/values/0/stacktrace/frames/0/vars",
+        "/exception/values/1/stacktrace/frames/0/vars",
+    ] {
+        assert_eq!(
+            transformed.pointer(pointer).unwrap(),
+            &json!({ "password": "[Filtered]", "user": "ana" })
+        );
+    }
+    assert_eq!(
+        transformed.pointer("/exception/values/1/stacktrace/frames/1"),
+        Some(&json!({ "function": "main" }))
+    );
+}
+
+/// `(event, data)` pairs of a server-sent event stream
+fn sse_events(body: &[u8]) -> Vec<(String, Value)> {
+    String::from_utf8_lossy(body)
+        .split("\n\n")
+        .filter(|event| !event.trim().is_empty())
+        .map(|event| {
+            let field = |name: &str| {
+                event
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .unwrap_or_else(|| panic!("event without {}: {}", name, event))
+                    .to_string()
+            };
+            let data = serde_json::from_str(&field("data: ")).unwrap();
+            (field("event: "), data)
+        })
+        .collect()
+}
+
+#[actix_web::test]
+async fn stream_progress_counts_up_to_the_total() {
+    // A fresh state, so the build isn't already cached
+    let state = state_with(|_| {});
+    let response = send(
+        &state,
+        TestRequest::post()
+            .uri("/transform/stream")
+            .set_json(json!({ "event": {}, "beforeSendCode": IDENTITY })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = sse_events(&test::read_body(response).await);
+
+    let (last, progress) = events.split_last().expect("events were streamed");
+    assert_eq!(last.0, "result");
+    assert_eq!(last.1["success"], true, "{}", last.1);
+
+    assert!(!progress.is_empty());
+    let total = progress[0].1["total"].as_u64().unwrap();
+    assert!(total > 1, "{}", total);
+    let mut previous = 0;
+    for (event, data) in progress {
+        assert_eq!(event, "progress");
+        assert_eq!(data["phase"], "compiling");
+        assert_eq!(data["total"], total);
+        let done = data["done"].as_u64().unwrap();
+        assert!(
+            previous <= done && done <= total,
+            "{} after {}",
+            done,
+            previous
+        );
+        previous = done;
+    }
+    assert!(previous > 0, "no crate was reported as compiled");
+}
+
+#[actix_web::test]
+async fn msgpack_transforms_match_the_json_path() {
+    let request = json!({
+        "event": { "level": "warning", "extra": { "ratio": 0.25, "count": 3 } },
+        "beforeSendCode": DROP_ERRORS
+    });
+    let (status, mut from_json) = post("/transform", request.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", from_json);
+
+    let response = send(
+        &state(),
+        TestRequest::post()
+            .uri("/transform")
+            .insert_header(("Content-Type", "application/msgpack"))
+            .insert_header(("Accept", "application/msgpack"))
+            .set_payload(rmp_serde::to_vec_named(&request).unwrap()),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/msgpack"
+    );
+    let mut from_msgpack: Value = rmp_serde::from_slice(&test::read_body(response).await).unwrap();
+
+    for body in [&mut from_json, &mut from_msgpack] {
+        // Only the cache status may differ, since the first request built the code
+        let body = body.as_object_mut().unwrap();
+        body.remove("requestId");
+        body.remove("cache");
+    }
+    assert_eq!(from_msgpack, from_json);
+}
+
+#[actix_web::test]
+async fn drop_reasons_are_reported_only_when_given() {
+    let code = r#"if event["level"] == "debug" {
+    return drop_with_reason("level too low");
+}
+None"#;
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "debug" }, "beforeSendCode": code }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+    assert_eq!(body["dropReason"], "level too low");
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "info" }, "beforeSendCode": code }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+    assert!(body.get("dropReason").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn concurrent_cold_builds_all_succeed() {
+    let state = state_with(|config| config.max_concurrent_builds = 3);
+    let requests = (0..3).map(|build| {
+        let code = format!("// cold build {}\nSome(event)", build);
+        post_to(
+            &state,
+            "/transform",
+            json!({ "event": { "build": build }, "beforeSendCode": code }),
+        )
+    });
+
+    for (build, (status, body)) in futures_util::future::join_all(requests)
+        .await
+        .into_iter()
+        .enumerate()
+    {
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["transformedEvent"], json!({ "build": build }));
+        assert_eq!(body["cache"]["status"], "miss");
+    }
+}
+
+#[actix_web::test]
+async fn custom_signatures_bind_breadcrumbs() {
+    let code = r#"if breadcrumb["category"] == "console" {
+    return None;
+}
+breadcrumb["data"]["checked"] = json!(true);
+Some(breadcrumb)"#;
+    let signature = json!({ "binding": "breadcrumb", "returns": "Option<Value>" });
+    let breadcrumb = json!({ "category": "http", "data": { "url": "/api" } });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": breadcrumb, "beforeSendCode": code, "signature": signature }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "category": "http", "data": { "url": "/api", "checked": true } })
+    );
+}
+
+#[actix_web::test]
+async fn signatures_outside_the_allowlist_are_rejected() {
+    let signature = json!({ "binding": "breadcrumb", "returns": "String" });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "None", "signature": signature }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Unsupported signature return type 'String'"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn size_limits_warn_about_oversized_output() {
+    let event = json!({ "message": "m".repeat(9000) });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY, "enforceSizeLimits": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body["sizeWarnings"]);
+    assert_eq!(
+        body["sizeWarnings"],
+        json!(["message exceeds 8192 chars (9000)"])
+    );
+}
+
+#[actix_web::test]
+async fn failed_transforms_store_their_full_output() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "fatal" }, "beforeSendCode": PANIC_ON_FATAL, "includeTraceback": false }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert!(body.get("traceback").is_none(), "{}", body);
+    let id = body["errorId"].as_str().expect("failures have an errorId");
+
+    let (status, stored) =
+        json_response(&state(), TestRequest::get().uri(&format!("/errors/{}", id))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored["id"], id);
+    assert_eq!(stored["error"], body["error"]);
+    assert!(
+        stored["traceback"]
+            .as_str()
+            .unwrap()
+            .contains("panicked at"),
+        "{}",
+        stored
+    );
+
+    let (status, _) = json_response(&state(), TestRequest::get().uri("/errors/unknown")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// A state running transforms under wasmtime, or `None` where it's unavailable
+async fn wasm_state() -> Option<web::Data<AppState>> {
+    let available = tokio::process::Command::new("wasmtime")
+        .arg("--version")
+        .output()
+        .await
+        .is_ok_and(|output| output.status.success());
+    if !available {
+        eprintln!("wasmtime isn't installed; skipping");
+        return None;
+    }
+    Some(state_with(|config| {
+        config.exec_backend = sandbox::ExecBackend::Wasm
+    }))
+}
+
+#[actix_web::test]
+async fn wasm_and_native_transforms_agree() {
+    let Some(wasm) = wasm_state().await else {
+        return;
+    };
+    let request =
+        json!({ "event": { "level": "info", "tags": {} }, "beforeSendCode": DROP_ERRORS });
+
+    let (status, native) = post("/transform", request.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", native);
+    let (status, body) = post_to(&wasm, "/transform", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], native["transformedEvent"]);
+}
+
+#[actix_web::test]
+async fn wasm_transforms_cannot_read_files() {
+    let Some(wasm) = wasm_state().await else {
+        return;
+    };
+    let code = r#"event["read"] = json!(std::fs::read_to_string("/etc/hostname").is_ok());
+Some(event)"#;
+    let (status, body) = post_to(
+        &wasm,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": code }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"]["read"], false);
+}
+
+#[actix_web::test]
+async fn sizes_shrink_with_scrubbing_and_are_zero_when_dropped() {
+    let event = json!({ "message": "hi", "extra": { "payload": "x".repeat(100) } });
+    let code = r#"event.as_object_mut().unwrap().remove("extra");
+Some(event)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": code, "includeSizes": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["inputBytes"],
+        serde_json::to_vec(&event).unwrap().len()
+    );
+    assert_eq!(body["outputBytes"], r#"{"message":"hi"}"#.len());
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "error" }, "beforeSendCode": DROP_ERRORS, "includeSizes": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["inputBytes"], r#"{"level":"error"}"#.len());
+    assert_eq!(body["outputBytes"], 0);
+}
+
+#[actix_web::test]
+async fn batch_counters_sum_over_every_event() {
+    let code = r#"if event["logger"] == "db" && event["level"] == "error" {
+    count("dropped_db_errors");
+    return None;
+}
+Some(event)"#;
+    let events = json!([
+        { "logger": "db", "level": "error" },
+        { "logger": "db", "level": "info" },
+        { "logger": "http", "level": "error" },
+        { "logger": "db", "level": "error" }
+    ]);
+    let (status, body) = post(
+        "/transform/batch",
+        json!({ "events": events, "beforeSendCode": code }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["counters"], json!({ "dropped_db_errors": 2 }));
+    let dropped: Vec<bool> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["transformedEvent"].is_null())
+        .collect();
+    assert_eq!(dropped, [true, false, false, true]);
+}
+
+#[actix_web::test]
+async fn cache_hits_skip_a_saturated_build_queue() {
+    let state = state_with(|config| {
+        config.max_concurrent_builds = 1;
+        config.max_queue_depth = 0;
+    });
+    let request = json!({ "event": {}, "beforeSendCode": IDENTITY });
+    let (status, body) = post_to(&state, "/transform", request.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let _permit = state.limiter.acquire(0).await.expect("a free slot");
+    let started = Instant::now();
+    let (status, body) = post_to(&state, "/transform", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["cache"]["status"], "hit");
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        started.elapsed()
+    );
+}
+
+/// `errorKind` of a failed /transform of `code`, checking the status too
+async fn transform_error_kind(
+    state: &web::Data<AppState>,
+    code: &str,
+    event: Value,
+    status: StatusCode,
+) -> Value {
+    let (actual, body) = post_to(
+        state,
+        "/transform",
+        json!({ "event": event, "beforeSendCode": code }),
+    )
+    .await;
+    assert_eq!(actual, status, "{}", body);
+    assert_eq!(body["success"], false);
+    body["errorKind"].clone()
+}
+
+#[actix_web::test]
+async fn failures_report_their_kind() {
+    let state = state();
+    let error = StatusCode::INTERNAL_SERVER_ERROR;
+    let kind = transform_error_kind(
+        &state,
+        "undefined_helper(); Some(event)",
+        json!({}),
+        StatusCode::BAD_REQUEST,
+    );
+    assert_eq!(kind.await, "compile");
+    let kind = transform_error_kind(&state, PANIC_ON_FATAL, json!({ "level": "fatal" }), error);
+    assert_eq!(kind.await, "runtime");
+    // NaN isn't valid JSON, so the result can't be read back
+    let kind = transform_error_kind(&state, "f64::NAN", json!({}), error);
+    assert_eq!(kind.await, "internal");
+}
+
+#[actix_web::test]
+async fn runs_past_the_exec_timeout_are_killed() {
+    let state = state_with(|config| config.exec_timeout_secs = 1);
+    let started = Instant::now();
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "while event.is_object() {\n    std::hint::spin_loop();\n}\nSome(event)" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(body["errorKind"], "timeout");
+    assert_eq!(
+        body["error"],
+        "Runtime error: transform did not finish within 1s"
+    );
+    assert!(started.elapsed() < Duration::from_secs(120));
+}
+
+#[actix_web::test]
+async fn rejections_before_running_have_a_kind() {
+    let state = state_with(|config| {
+        config.max_concurrent_builds = 1;
+        config.max_queue_depth = 0;
+    });
+    let _permit = state.limiter.acquire(0).await.expect("a free slot");
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["errorKind"], "internal");
+
+    let limited = state_with(|config| {
+        config.rate_limit_per_minute = 1;
+        config.rate_limit_burst = 1;
+    });
+    for (expected, kind) in [
+        (StatusCode::BAD_REQUEST, "invalid_input"),
+        (StatusCode::TOO_MANY_REQUESTS, "rate_limited"),
+    ] {
+        let (status, body) = json_response(&limited, unsupported_toolchain_request()).await;
+        assert_eq!(status, expected);
+        assert_eq!(body["errorKind"], kind);
+    }
+}
+
+#[actix_web::test]
+async fn snippets_round_trip_by_id() {
+    let snippet = json!({
+        "title": "Drop errors",
+        "code": DROP_ERRORS,
+        "mode": "beforeSend",
+        "event": { "level": "error" }
+    });
+    let (status, saved) = post("/snippets", snippet.clone()).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", saved);
+    let id = saved["id"].as_str().unwrap();
+
+    let uri = format!("/snippets/{}", id);
+    let (status, mut loaded) = json_response(&state(), TestRequest::get().uri(&uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(loaded.as_object_mut().unwrap().remove("id").unwrap(), id);
+    assert_eq!(loaded, snippet);
+}
+
+#[actix_web::test]
+async fn unknown_or_invalid_snippets_are_rejected() {
+    let (status, body) = json_response(&state(), TestRequest::get().uri("/snippets/missing")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "Unknown snippet id");
+
+    let (status, body) = post("/snippets", json!({ "title": " ", "code": IDENTITY })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "Invalid snippet: title must not be empty");
+}
+
+#[actix_web::test]
+async fn idempotent_transforms_pass_the_second_run() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "info" }, "beforeSendCode": DROP_ERRORS, "checkIdempotent": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["idempotent"], true);
+    assert!(body.get("idempotencyDiff").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn non_idempotent_transforms_report_the_second_run_diff() {
+    let code = r#"let mut crumbs = event["breadcrumbs"].as_array().cloned().unwrap_or_default();
+crumbs.push(json!("scrubbed"));
+event["breadcrumbs"] = json!(crumbs);
+Some(event)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": code, "checkIdempotent": true }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "breadcrumbs": ["scrubbed"] })
+    );
+    assert_eq!(body["idempotent"], false);
+    assert_eq!(
+        body["idempotencyDiff"],
+        json!([{ "path": "/breadcrumbs/1", "after": "scrubbed" }])
+    );
+}
+
+#[actix_web::test]
+async fn stalled_request_bodies_time_out() {
+    let state = state_with(|config| config.request_body_timeout_secs = 1);
+    let request = TestRequest::post()
+        .uri("/transform")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Content-Length", "1000"))
+        .to_request();
+    // Half the body arrives, then the client goes quiet
+    let chunks = stream::iter([Ok::<_, PayloadError>(web::Bytes::from_static(
+        b"{\"event\": {",
+    ))])
+    .chain(stream::pending());
+    let (request, _) = request.replace_payload(actix_http::Payload::Stream {
+        payload: chunks.boxed_local(),
+    });
+
+    let started = Instant::now();
+    let response = send_request(&state, request).await;
+    assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+    assert!(
+        started.elapsed() < Duration::from_secs(5),
+        "{:?}",
+        started.elapsed()
+    );
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["error"], "Request body was not received within 1s");
+    assert_eq!(body["errorKind"], "timeout");
+}
+
+#[actix_web::test]
+async fn compare_diffs_the_first_two_variants() {
+    let (status, body) = post(
+        "/transform/compare",
+        json!({
+            "event": { "level": "error" },
+            "variants": [
+                { "name": "keep", "code": IDENTITY },
+                { "name": "drop", "code": DROP_ERRORS }
+            ]
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["name"], "keep");
+    assert_eq!(results[0]["dropped"], false);
+    assert_eq!(results[1]["name"], "drop");
+    assert_eq!(results[1]["dropped"], true);
+    assert_eq!(
+        body["diff"],
+        json!([{ "path": "", "before": { "level": "error" }, "after": null }])
+    );
+}
+
+#[actix_web::test]
+async fn code_can_return_early_and_use_question_marks() {
+    let code = r#"event.get("user")?;
+if event["level"] == "debug" {
+    return None;
+}
+event["tags"]["checked"] = json!(true);
+Some(event)"#;
+    let run = |event: Value| {
+        post(
+            "/transform",
+            json!({ "event": event, "beforeSendCode": code }),
+        )
+    };
+
+    let (status, body) = run(json!({ "level": "info" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+
+    let (status, body) = run(json!({ "user": {}, "level": "debug" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+
+    let (status, body) = run(json!({ "user": {}, "level": "info" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"]["tags"], json!({ "checked": true }));
+}
+
+#[actix_web::test]
+async fn pointer_assertions_run_on_cached_builds_too() {
+    let request = json!({
+        "event": { "tags": { "env": "prod" } },
+        "beforeSendCode": IDENTITY,
+        "pointerAssertions": [
+            { "pointer": "/tags/env", "equals": "prod" },
+            { "pointer": "/user" }
+        ]
+    });
+    // The second request hits the cache the first one filled
+    for _ in 0..2 {
+        let (status, body) = post("/transform", request.clone()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(
+            body["pointerAssertionResults"],
+            json!([
+                { "pointer": "/tags/env", "passed": true, "actual": "prod" },
+                { "pointer": "/user", "passed": false, "message": "expected a value" }
+            ])
+        );
+    }
+}
+
+#[actix_web::test]
+async fn transforms_run_with_an_alternate_allocator() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "info" }, "beforeSendCode": DROP_ERRORS, "allocator": "mimalloc" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"]["tags"], json!({ "seen": "yes" }));
+}
+
+#[actix_web::test]
+async fn prune_empty_applies_the_requested_empty_values() {
+    let event = json!({ "message": "hi", "user": { "email": "" }, "extra": null });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY, "pruneEmpty": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], json!({ "message": "hi" }));
+
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": event,
+            "beforeSendCode": IDENTITY,
+            "pruneEmpty": true,
+            "emptyValues": ["null"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "message": "hi", "user": { "email": "" } })
+    );
+}
+
+#[actix_web::test]
+async fn modifying_without_returning_the_event_hints_at_the_mistake() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": r#"event["tags"] = json!({ "x": 1 });"# }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+    assert_eq!(
+        body["mistakeHint"],
+        "You modified event but returned (); did you forget to return Some(event)?"
+    );
+
+    // An explicit drop is deliberate
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": r#"event["tags"] = json!({ "x": 1 }); None"# }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], Value::Null);
+    assert!(body.get("mistakeHint").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn redaction_rules_apply_whatever_the_code_does() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.json");
+    std::fs::write(
+        &rules,
+        json!([{ "name": "user-email", "pointer": "/user/email", "action": "mask" }]).to_string(),
+    )
+    .unwrap();
+    let state = state_with(|config| config.redaction_rules_path = Some(rules));
+
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({
+            "event": { "user": { "id": 1, "email": "a@example.com" } },
+            "beforeSendCode": IDENTITY
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"]["user"],
+        json!({ "id": 1, "email": "[Filtered]" })
+    );
+    assert_eq!(body["appliedRedactions"], json!(["user-email"]));
+}
+
+#[actix_web::test]
+async fn fuzzing_finds_unwraps_on_missing_fields() {
+    let code = r#"let id = event["user"]["id"].as_u64().unwrap();
+event["tags"] = json!({ "user": id });
+Some(event)"#;
+    let (status, body) = post(
+        "/transform/fuzz",
+        json!({
+            "event": { "user": { "id": 1 }, "message": "hi" },
+            "beforeSendCode": code,
+            "iterations": 30,
+            "seed": 1
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], true);
+    assert_eq!(body["seed"], 1);
+    let failures = body["failures"].as_array().unwrap();
+    assert!(!failures.is_empty(), "{}", body);
+    for failure in failures {
+        assert!(
+            failure["input"]["user"]["id"].as_u64().is_none(),
+            "{}",
+            failure
+        );
+        assert!(
+            failure["error"].as_str().unwrap().contains("panicked"),
+            "{}",
+            failure
+        );
+        // Minimizing leaves the one mutation that breaks the id
+        let mutations = failure["mutations"].as_array().unwrap();
+        assert_eq!(mutations.len(), 1, "{}", failure);
+        assert!(mutations[0]["pointer"]
+            .as_str()
+            .unwrap()
+            .starts_with("/user"));
+    }
+}
+
+#[actix_web::test]
+async fn the_api_allowlist_is_checked_before_compiling() {
+    let state = state_with(|config| {
+        config.allowed_apis = Some(vec!["Some".to_string(), ".as_str".to_string()]);
+    });
+
+    let (status, body) = post_to(
+        &state,
+        "/lint",
+        json!({ "code": "event[\"level\"].as_str();\nSome(event)" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], true, "{}", body);
+
+    let code = "let _ = event[\"level\"].as_str();\nstd::process::exit(1)";
+    let (status, body) = post_to(&state, "/lint", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false);
+    assert_eq!(
+        body["errors"],
+        json!([{
+            "line": 2,
+            "column": 1,
+            "message": "`std::process::exit` is not in the API allowlist"
+        }])
+    );
+
+    // Rejected without a build
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": code }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "Code uses APIs outside the allowlist: line 2, column 1: `std::process::exit` is not in the API allowlist"
+    );
+    assert_eq!(body["errorKind"], "invalid_input");
+}
+
+#[actix_web::test]
+async fn sort_keys_sorts_nested_objects() {
+    fn keys(value: &Value) -> Vec<&str> {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+    let event = json!({
+        "user": { "username": "a", "id": 1 },
+        "extra": { "z": [{ "b": 1, "a": 2 }], "a": null },
+        "message": "hi"
+    });
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        keys(&body["transformedEvent"]),
+        ["user", "extra", "message"]
+    );
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY, "sortKeys": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let sorted = &body["transformedEvent"];
+    assert_eq!(keys(sorted), ["extra", "message", "user"]);
+    assert_eq!(keys(&sorted["user"]), ["id", "username"]);
+    assert_eq!(keys(&sorted["extra"]), ["a", "z"]);
+    assert_eq!(keys(&sorted["extra"]["z"][0]), ["a", "b"]);
+}
+
+/// Run a websocket session sending `messages` in order, returning the replies
+async fn ws_session(state: &web::Data<AppState>, messages: &[Value]) -> Vec<Value> {
+    use actix_http::ws::{Frame, Message};
+
+    let messages = messages
+        .iter()
+        .map(|message| Message::Text(message.to_string().into()))
+        .collect();
+    ws_frames(state, messages)
+        .await
+        .into_iter()
+        .filter_map(|frame| match frame {
+            Frame::Text(text) => Some(serde_json::from_slice(&text).unwrap()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Send websocket messages followed by a close, returning every frame sent back
+async fn ws_frames(
+    state: &web::Data<AppState>,
+    messages: Vec<actix_http::ws::Message>,
+) -> Vec<actix_http::ws::Frame> {
+    use actix_codec::{Decoder, Encoder};
+    use actix_http::ws::{Codec, Message};
+
+    let mut codec = Codec::new().client_mode();
+    let mut frames = web::BytesMut::new();
+    for message in messages {
+        codec.encode(message, &mut frames).unwrap();
+    }
+    codec.encode(Message::Close(None), &mut frames).unwrap();
+
+    let request = TestRequest::get()
+        .uri("/ws")
+        .insert_header(("Upgrade", "websocket"))
+        .insert_header(("Connection", "Upgrade"))
+        .insert_header(("Sec-WebSocket-Version", "13"))
+        .insert_header(("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="))
+        .to_request();
+    let chunk = Ok::<_, PayloadError>(frames.freeze());
+    let (request, _) = request.replace_payload(actix_http::Payload::Stream {
+        payload: futures_util::stream::iter([chunk]).boxed_local(),
+    });
+    let response = send_request(state, request).await;
+    assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+
+    let mut body = web::BytesMut::from(&test::read_body(response).await[..]);
+    let mut replies = Vec::new();
+    while let Some(frame) = codec.decode(&mut body).unwrap() {
+        replies.push(frame);
+    }
+    replies
+}
+
+#[actix_web::test]
+async fn websocket_sessions_reuse_one_build() {
+    let state = state_with(|_| {});
+    let replies = ws_session(
+        &state,
+        &[
+            json!({ "type": "code", "beforeSendCode": DROP_ERRORS }),
+            json!({ "type": "event", "event": { "level": "info" } }),
+            json!({ "type": "event", "event": { "level": "warning", "message": "hi" } }),
+        ],
+    )
+    .await;
+
+    assert_eq!(replies.len(), 3, "{:?}", replies);
+    assert_eq!(replies[0]["type"], "ready");
+    assert_eq!(replies[0]["cached"], false);
+    for (reply, level) in replies[1..].iter().zip(["info", "warning"]) {
+        assert_eq!(reply["type"], "result");
+        assert_eq!(reply["success"], true, "{}", reply);
+        assert_eq!(reply["transformedEvent"]["level"], level);
+        assert_eq!(reply["transformedEvent"]["tags"], json!({ "seen": "yes" }));
+    }
+
+    let (_, status) = json_response(&state, TestRequest::get().uri("/status")).await;
+    assert_eq!(status["compilations"], 1);
+}
+
+#[actix_web::test]
+async fn websocket_events_need_code_that_built() {
+    let replies = ws_session(
+        &state(),
+        &[
+            json!({ "type": "event", "event": {} }),
+            json!({ "type": "code", "beforeSendCode": "undefined_helper(); Some(event)" }),
+            json!({ "type": "event", "event": {} }),
+            json!("not a message"),
+        ],
+    )
+    .await;
+
+    assert_eq!(replies.len(), 4, "{:?}", replies);
+    assert!(replies.iter().all(|reply| reply["type"] == "error"));
+    assert_eq!(replies[0]["error"], "Send code before events");
+    assert_eq!(replies[1]["errorKind"], "compile");
+    assert_eq!(replies[2]["error"], "Send code before events");
+    assert!(replies[3]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Invalid message"));
+}
+
+#[actix_web::test]
+async fn websocket_fragments_are_reassembled_up_to_the_message_limit() {
+    use actix_http::ws::{CloseCode, Frame, Item, Message};
+    use actix_web::web::Bytes;
+
+    let fragment = |item: Item| Message::Continuation(item);
+    let message = json!({ "type": "event", "event": {} }).to_string();
+    let (first, rest) = message.split_at(10);
+    let (middle, last) = rest.split_at(10);
+    let frames = ws_frames(
+        &state(),
+        vec![
+            fragment(Item::FirstText(Bytes::copy_from_slice(first.as_bytes()))),
+            fragment(Item::Continue(Bytes::copy_from_slice(middle.as_bytes()))),
+            fragment(Item::Last(Bytes::copy_from_slice(last.as_bytes()))),
+        ],
+    )
+    .await;
+    let Some(Frame::Text(reply)) = frames.first() else {
+        panic!("expected a reply, got {:?}", frames);
+    };
+    let reply: Value = serde_json::from_slice(reply).unwrap();
+    assert_eq!(reply["error"], "Send code before events");
+
+    // Each frame is within the limit, but the message they add up to isn't
+    let part = Bytes::from(vec![b' '; 600 * 1024]);
+    let frames = ws_frames(
+        &state(),
+        vec![
+            fragment(Item::FirstText(part.clone())),
+            fragment(Item::Continue(part.clone())),
+            fragment(Item::Last(part)),
+        ],
+    )
+    .await;
+    assert_eq!(frames.len(), 1, "{:?}", frames);
+    let Frame::Close(Some(reason)) = &frames[0] else {
+        panic!("expected a close, got {:?}", frames);
+    };
+    assert_eq!(reason.code, CloseCode::Size);
+}
+
+#[actix_web::test]
+async fn compile_failures_classify_their_diagnostics() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "undefined_helper(); Some(event)" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+    let diagnostics = body["diagnostics"].as_array().unwrap();
+    let errors: Vec<&Value> = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic["level"] == "error")
+        .collect();
+    assert_eq!(errors.len(), 1, "{:?}", diagnostics);
+    assert_eq!(errors[0]["code"], "E0425");
+    assert!(errors[0]["message"]
+        .as_str()
+        .unwrap()
+        .contains("undefined_helper"));
+
+    for diagnostic in diagnostics {
+        let message = diagnostic["message"].as_str().unwrap();
+        if message.starts_with("Compiling") || message.contains("could not compile") {
+            assert_eq!(diagnostic["level"], "status", "{}", diagnostic);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn strict_event_output_flags_a_numeric_level() {
+    let request = |code: &str| {
+        json!({
+            "event": { "level": "error", "message": "hi" },
+            "beforeSendCode": code,
+            "strictEventOutput": true
+        })
+    };
+
+    let (status, body) = post(
+        "/transform",
+        request(r#"event["level"] = json!(40); Some(event)"#),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["strictEventErrors"],
+        json!(["`level`: expected a level string, found 40"])
+    );
+
+    let (status, body) = post("/transform", request(IDENTITY)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["strictEventErrors"], json!([]));
+}
+
+#[actix_web::test]
+async fn allowed_rustflags_reach_the_build() {
+    let request = |rustflags: Value| {
+        json!({
+            "event": {},
+            "beforeSendCode": r#"event["tags"] = json!({ "sse3": cfg!(target_feature = "sse3") }); Some(event)"#,
+            "rustflags": rustflags
+        })
+    };
+
+    let (status, body) = post("/transform", request(json!(["-C linker=/bin/true"]))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Unsupported rustflag '-C linker=/bin/true'"));
+
+    // x86_64 targets leave SSE3 off unless asked for it
+    if cfg!(target_arch = "x86_64") {
+        let (status, body) = post("/transform", request(json!(["-C target-feature=+sse3"]))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["transformedEvent"]["tags"], json!({ "sse3": true }));
+    }
+}
+
+#[actix_web::test]
+async fn selftest_passes_on_a_working_toolchain() {
+    let (status, body) = json_response(&state(), TestRequest::get().uri("/selftest")).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["ok"], true);
+    assert_eq!(body["details"]["actual"], body["details"]["expected"]);
+    assert_eq!(body["details"]["actual"]["tags"]["selftest"], "ok");
+    assert!(body["details"].get("error").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn selftest_fails_when_its_code_is_rejected() {
+    let state = state_with(|config| config.allowed_apis = Some(vec!["Some".to_string()]));
+    let (status, body) = json_response(&state, TestRequest::get().uri("/selftest")).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE, "{}", body);
+    assert_eq!(body["ok"], false);
+    assert!(body["details"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("Code uses APIs outside the allowlist"));
+}
+
+#[actix_web::test]
+async fn jsonpatch_responses_describe_the_changes() {
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "level": "info", "tags": { "env": "prod" } },
+            "beforeSendCode": DROP_ERRORS,
+            "responseFormat": "jsonpatch"
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body.get("transformedEvent").is_none(), "{}", body);
+    assert_eq!(
+        body["patch"],
+        json!([{ "op": "add", "path": "/tags/seen", "value": "yes" }])
+    );
+}
+
+#[actix_web::test]
+async fn output_from_lingering_threads_does_not_break_the_result() {
+    // The thread may print before, while, or after the wrapper writes the result
+    let code = r#"std::thread::spawn(|| {
+    for i in 0..1000 {
+        println!("garbage {}", i);
+    }
+});
+event["tags"] = json!({ "ok": true });
+Some(event)"#;
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], json!({ "tags": { "ok": true } }));
+}
+
+#[actix_web::test]
+async fn flag_noop_marks_only_unchanged_events() {
+    let noop = |code: &str, level: &str, flag: bool| {
+        let request = json!({
+            "event": { "level": level },
+            "beforeSendCode": code,
+            "flagNoop": flag
+        });
+        async move {
+            let (status, body) = post("/transform", request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            body.get("noop").cloned()
+        }
+    };
+
+    assert_eq!(noop(IDENTITY, "info", true).await, Some(json!(true)));
+    assert_eq!(noop(DROP_ERRORS, "info", true).await, Some(json!(false)));
+    // Dropping changes what's sent
+    assert_eq!(noop(DROP_ERRORS, "error", true).await, Some(json!(false)));
+    assert_eq!(noop(IDENTITY, "info", false).await, None);
+}
+
+#[actix_web::test]
+async fn responses_report_the_binary_size() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["binaryBytes"].as_u64().unwrap() > 0, "{}", body);
+}
+
+#[actix_web::test]
+async fn panic_context_carries_the_partially_modified_event() {
+    let code = r#"event["tags"] = json!({ "step": 1 });
+let id = event["user"]["id"].as_u64().unwrap();
+event["tags"]["id"] = json!(id);
+Some(event)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "message": "hi" },
+            "beforeSendCode": code,
+            "capturePanicContext": true
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(body["errorKind"], "runtime");
+    let context = &body["panicContext"];
+    assert_eq!(
+        context["event"],
+        json!({ "message": "hi", "tags": { "step": 1 } })
+    );
+    assert_eq!(
+        context["message"],
+        "called `Option::unwrap()` on a `None` value"
+    );
+    assert!(context["location"].is_string(), "{}", context);
+}
+
+#[actix_web::test]
+async fn panic_context_keeps_the_event_as_it_was_moved() {
+    let moved = r#"event["tags"] = json!({ "step": 1 });
+let mut moved = event;
+moved["tags"]["step"] = json!(2);
+let id = moved["user"]["id"].as_u64().unwrap();
+Some(moved)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "message": "hi" },
+            "beforeSendCode": moved,
+            "capturePanicContext": true
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(
+        body["panicContext"]["event"],
+        json!({ "message": "hi", "tags": { "step": 1 } })
+    );
+
+    // The log handler runs on `log`, which the input is moved into
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "type": "log", "body": "hi" },
+            "beforeSendLog": "log[\"body\"] = json!(\"changed\");\nlog[\"level\"].as_str().unwrap();\nSome(log)",
+            "capturePanicContext": true
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(
+        body["panicContext"]["event"],
+        json!({ "type": "log", "body": "changed" })
+    );
+}
+
+#[actix_web::test]
+async fn panic_context_is_redacted() {
+    let dir = tempfile::tempdir().unwrap();
+    let rules = dir.path().join("rules.json");
+    std::fs::write(
+        &rules,
+        json!([{ "name": "user-email", "pointer": "/user/email", "action": "mask" }]).to_string(),
+    )
+    .unwrap();
+    let state = state_with(|config| config.redaction_rules_path = Some(rules));
+
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({
+            "event": { "user": { "email": "a@example.com" } },
+            "beforeSendCode": "event[\"user\"][\"id\"].as_u64().unwrap();\nSome(event)",
+            "capturePanicContext": true
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(
+        body["panicContext"]["event"],
+        json!({ "user": { "email": "[Filtered]" } })
+    );
+}
+
+#[actix_web::test]
+async fn disallow_drop_turns_drops_into_errors() {
+    let request = |event: Value, disallow: bool| json!({ "event": event, "beforeSendCode": DROP_ERRORS, "disallowDrop": disallow });
+
+    let (status, body) = post("/transform", request(json!({ "level": "error" }), false)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], true);
+    assert_eq!(body["transformedEvent"], Value::Null);
+
+    let (status, body) = post("/transform", request(json!({ "level": "error" }), true)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["error"], "Dropping events is not permitted");
+    assert_eq!(body["errorKind"], "runtime");
+
+    // Events that aren't dropped pass as usual
+    let (status, body) = post("/transform", request(json!({ "level": "info" }), true)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"]["tags"], json!({ "seen": "yes" }));
+}
+
+#[actix_web::test]
+async fn misspelled_methods_get_rustcs_suggestion() {
+    let code = "let level = event[\"level\"].as_strr();\nlet _ = level;\nSome(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false);
+    let error = &body["errors"][0];
+    assert_eq!(error["line"], 1, "{}", error);
+    let hint = error["hint"].as_str().unwrap();
+    assert!(hint.contains("as_str"), "{}", hint);
+}
+
+/// Code reading past the end of its allocation
+const OUT_OF_BOUNDS_READ: &str = "let bytes = vec![1u8];\n\
+                                  let past_end = unsafe { *bytes.as_ptr().add(1) };\n\
+                                  event[\"tags\"] = json!({ \"byte\": past_end });\n\
+                                  Some(event)";
+
+#[actix_web::test]
+async fn miri_runs_need_the_server_to_allow_them() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": OUT_OF_BOUNDS_READ, "miri": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Miri runs are disabled on this server");
+}
+
+#[actix_web::test]
+async fn miri_reports_undefined_behavior() {
+    if let Err(e) = sandbox::check_miri().await {
+        eprintln!("{}; skipping", e);
+        return;
+    }
+
+    let state = state_with(|config| config.allow_miri = true);
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": OUT_OF_BOUNDS_READ, "miri": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR, "{}", body);
+    assert_eq!(body["errorKind"], "runtime");
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("Miri detected undefined behavior"));
+}
+
+#[actix_web::test]
+async fn normalize_shows_what_the_sdk_would_send() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "message": "hi" }, "beforeSendCode": IDENTITY, "normalize": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let event = &body["transformedEvent"];
+    assert_eq!(event["event_id"].as_str().unwrap().len(), 32, "{}", event);
+    assert_eq!(event["level"], "error");
+    assert_eq!(event["platform"], "native");
+    assert!(event["timestamp"].is_number(), "{}", event);
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "message": "hi" }, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], json!({ "message": "hi" }));
+}
+
+/// The `X-Request-Id` header and body `requestId` of a response
+async fn request_ids(request: TestRequest) -> (String, Value) {
+    let response = send(&state(), request).await;
+    let header = response.headers().get("X-Request-Id").unwrap();
+    let header = header.to_str().unwrap().to_string();
+    let body: Value = serde_json::from_slice(&test::read_body(response).await).unwrap();
+    (header, body["requestId"].clone())
+}
+
+#[actix_web::test]
+async fn request_ids_are_echoed_or_generated() {
+    let (header, body) = request_ids(TestRequest::post().uri("/transform").set_json(json!({
+        "event": {},
+        "beforeSendCode": IDENTITY,
+        "toolchain": "1.0.0",
+        "requestId": "ui-42"
+    })))
+    .await;
+    assert_eq!((header.as_str(), body), ("ui-42", json!("ui-42")));
+
+    let (header, body) =
+        request_ids(unsupported_toolchain_request().insert_header(("X-Request-Id", "trace-7")))
+            .await;
+    assert_eq!((header.as_str(), body), ("trace-7", json!("trace-7")));
+
+    // Missing and unusable ids are replaced with generated ones
+    for request in [
+        unsupported_toolchain_request(),
+        unsupported_toolchain_request().insert_header(("X-Request-Id", "x".repeat(200))),
+    ] {
+        let (header, body) = request_ids(request).await;
+        assert!(uuid::Uuid::parse_str(&header).is_ok(), "{}", header);
+        assert_eq!(body, json!(header));
+    }
+
+    let (first, _) = request_ids(unsupported_toolchain_request()).await;
+    let (second, _) = request_ids(unsupported_toolchain_request()).await;
+    assert_ne!(first, second);
+}
+
+#[actix_web::test]
+async fn include_title_reports_the_title_of_the_transformed_event() {
+    let code = r#"event["exception"] = json!({ "values": [{ "type": "Error", "value": "scrubbed" }] });
+Some(event)"#;
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "message": "raw" }, "beforeSendCode": code, "includeTitle": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["title"], "Error: scrubbed");
+
+    // Dropped events have no title
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "error" }, "beforeSendCode": DROP_ERRORS, "includeTitle": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body.get("title").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn footprints_report_the_keys_read_and_written() {
+    let footprint = |code: &str| {
+        post(
+            "/transform",
+            json!({
+                "event": { "level": "error", "tags": { "env": "prod" }, "message": "hi" },
+                "beforeSendCode": code,
+                "reportFootprint": true
+            }),
+        )
+    };
+
+    let (status, body) = footprint(
+        r#"if event["tags"]["env"] == "prod" {
+    event["level"] = json!("warning");
+}
+Some(event)"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["accessedPaths"], json!(["tags"]));
+    assert_eq!(body["mutatedPaths"], json!(["level"]));
+
+    // Reads are recorded as the code runs: through aliases and computed keys,
+    // but not in branches that don't run
+    let (status, body) = footprint(
+        r#"let alias = &event;
+let key = format!("{}s", "tag");
+let prod = alias[&key]["env"] == "prod";
+if !prod {
+    let _ = event.pointer("/user/id");
+}
+Some(event)"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["accessedPaths"], json!(["tags"]));
+    assert_eq!(body["mutatedPaths"], json!([]));
+
+    // Reaching the whole event reads every key it has
+    let (status, body) = footprint(
+        r#"if event.as_object().unwrap().contains_key("message") {
+    return Some(json!({ "message": "replaced" }));
+}
+Some(event.into_inner())"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["accessedPaths"], json!(["level", "message", "tags"]));
+    assert_eq!(body["transformedEvent"], json!({ "message": "replaced" }));
+    assert_eq!(body["mutatedPaths"], json!([]));
+
+    // Writes are recorded even when they leave the event as it was
+    let (status, body) = footprint(
+        r#"event["level"] = json!("error");
+let message = event["message"].take();
+event["message"] = message;
+if let Some(tags) = event.get_mut("tags") {
+    tags["env"] = json!("prod");
+}
+Some(event)"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["mutatedPaths"], json!(["level", "message", "tags"]));
+    assert_eq!(body["accessedPaths"], json!([]));
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "level": "error", "tags": { "env": "prod" }, "message": "hi" })
+    );
+
+    // Writing through `&mut Value` covers the keys it adds, too
+    let (status, body) = footprint(
+        r#"event.as_object_mut().unwrap().insert("extra".to_string(), json!(1));
+Some(event)"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["mutatedPaths"],
+        json!(["extra", "level", "message", "tags"])
+    );
+
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": {},
+            "beforeSendCode": IDENTITY,
+            "mode": "beforeSendLog",
+            "reportFootprint": true
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "reportFootprint needs beforeSendCode in the default mode, without a signature"
+    );
+}
+
+#[actix_web::test]
+async fn expected_output_is_compared_to_the_result() {
+    let request = |event: Value, expected: Value| json!({ "event": event, "beforeSendCode": DROP_ERRORS, "expectedOutput": expected });
+
+    let (status, body) = post(
+        "/transform",
+        request(
+            json!({ "level": "info" }),
+            json!({ "level": "info", "tags": { "seen": "yes" } }),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["matchedExpected"], true);
+    assert!(body.get("expectedDiff").is_none(), "{}", body);
+
+    let (status, body) = post(
+        "/transform",
+        request(json!({ "level": "info" }), json!({ "level": "warning" })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["matchedExpected"], false);
+    assert_eq!(
+        body["expectedDiff"],
+        json!([
+            { "path": "/level", "before": "warning", "after": "info" },
+            { "path": "/tags", "after": { "seen": "yes" } }
+        ])
+    );
+
+    // A drop matches an expected null
+    let (status, body) = post(
+        "/transform",
+        request(json!({ "level": "error" }), Value::Null),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["matchedExpected"], true, "{}", body);
+}
+
+/// An event with `depth` levels of nested objects
+fn nested_event(depth: usize) -> Value {
+    (1..depth).fold(json!({}), |inner, _| json!({ "a": inner }))
+}
+
+#[actix_web::test]
+async fn events_nested_too_deeply_are_rejected() {
+    let state = state_with(|config| config.max_event_depth = 5);
+    let request = |depth| json!({ "event": nested_event(depth), "beforeSendCode": IDENTITY });
+
+    let (status, body) = post_to(&state, "/transform", request(6)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "Event is nested 6 levels deep; at most 5 levels are allowed"
+    );
+    assert_eq!(body["errorKind"], "invalid_input");
+
+    // Past serde_json's own limit the body can't be parsed, and is refused alike
+    let body = format!(
+        r#"{{"beforeSendCode":"Some(event)","event":{}{}}}"#,
+        "[".repeat(200),
+        "]".repeat(200)
+    );
+    let response = send(
+        &state,
+        TestRequest::post()
+            .uri("/transform")
+            .insert_header(("Content-Type", "application/json"))
+            .set_payload(body),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["success"], false);
+    assert_eq!(
+        body["error"],
+        "Request body is nested more than 128 levels deep"
+    );
+    assert_eq!(body["errorKind"], "invalid_input");
+}
+
+#[test]
+fn json_depth_counts_nested_objects_and_arrays() {
+    assert_eq!(json_depth(&json!("scalar")), 0);
+    assert_eq!(json_depth(&json!({})), 1);
+    assert_eq!(json_depth(&json!({ "a": [1, { "b": [] }], "c": 1 })), 4);
+    assert_eq!(json_depth(&nested_event(6)), 6);
+}
+
+/// The files in an uncompressed tar archive, by path
+fn untar(mut archive: &[u8]) -> BTreeMap<String, String> {
+    let mut files = BTreeMap::new();
+    while archive.len() >= 512 && archive[0] != 0 {
+        let field = |range: std::ops::Range<usize>| {
+            String::from_utf8_lossy(&archive[range])
+                .trim_matches(['\0', ' '])
+                .to_string()
+        };
+        let path = field(0..100);
+        let size = usize::from_str_radix(&field(124..136), 8).unwrap();
+        let contents = String::from_utf8_lossy(&archive[512..512 + size]).to_string();
+        files.insert(path, contents);
+        archive = &archive[512 + size.next_multiple_of(512)..];
+    }
+    files
+}
+
+#[actix_web::test]
+async fn exported_projects_contain_the_code_and_event() {
+    let response = send(
+        &state(),
+        TestRequest::post()
+            .uri("/transform/project")
+            .set_json(json!({
+                "event": { "message": "hi" },
+                "beforeSendCode": DROP_ERRORS
+            })),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/x-tar"
+    );
+
+    let files = untar(&test::read_body(response).await);
+    let paths: Vec<&str> = files.keys().map(String::as_str).collect();
+    assert_eq!(
+        paths,
+        [
+            "transform/Cargo.toml",
+            "transform/event.json",
+            "transform/src/main.rs"
+        ]
+    );
+    assert!(files["transform/src/main.rs"].contains(r#"event["tags"]["seen"] = json!("yes");"#));
+    assert!(files["transform/src/main.rs"].starts_with("// Run locally with:"));
+    assert_eq!(
+        serde_json::from_str::<Value>(&files["transform/event.json"]).unwrap(),
+        json!({ "message": "hi" })
+    );
+    for contents in files.values() {
+        assert!(!contents.contains("/tmp/"), "{}", contents);
+    }
+}
+
+#[actix_web::test]
+async fn include_timings_reports_each_dependency() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "includeTimings": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let timings = body["depTimings"].as_array().unwrap();
+    let crates: Vec<&str> = timings
+        .iter()
+        .map(|timing| timing["crate"].as_str().unwrap())
+        .collect();
+    assert!(crates.contains(&"serde"), "{:?}", crates);
+    assert!(crates.contains(&"serde_json"), "{:?}", crates);
+    assert!(!crates.contains(&"transform"), "{:?}", crates);
+    assert!(timings
+        .iter()
+        .all(|timing| timing["seconds"].as_f64().unwrap() >= 0.0));
+}
+
+/// State remembering the messages already seen in a batch
+const SEEN_MESSAGES: &str = "#[derive(Default, serde::Serialize, serde::Deserialize)]\n\
+                             pub struct State {\n    pub seen: Vec<String>,\n}";
+
+#[actix_web::test]
+async fn shared_state_lets_batches_drop_duplicates() {
+    let code = r#"let message = event["message"].as_str().unwrap_or_default().to_string();
+if state.seen.contains(&message) {
+    return None;
+}
+state.seen.push(message);
+Some(event)"#;
+    let (status, body) = post(
+        "/transform/batch",
+        json!({
+            "events": [
+                { "message": "a" },
+                { "message": "b" },
+                { "message": "a" },
+                { "message": "a" }
+            ],
+            "beforeSendCode": code,
+            "preamble": SEEN_MESSAGES,
+            "sharedState": true
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let kept: Vec<bool> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| !result["transformedEvent"].is_null())
+        .collect();
+    assert_eq!(kept, [true, true, false, false]);
+    assert_eq!(body["state"], json!({ "seen": ["a", "b"] }));
+}
+
+#[actix_web::test]
+async fn shared_state_needs_a_preamble() {
+    let (status, body) = post(
+        "/transform/batch",
+        json!({ "events": [{}], "beforeSendCode": IDENTITY, "sharedState": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "sharedState needs a preamble defining `State`"
+    );
+}
+
+#[actix_web::test]
+async fn borrowed_returns_get_a_friendly_compile_error() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "Some(&event)" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "Compilation error: the code returns a borrowed value, not one it owns. \
+         Return owned data: use event.clone() or Some(event) by value"
+    );
+    // The raw compiler output is still available
+    assert!(
+        body["traceback"].as_str().unwrap().contains("error["),
+        "{}",
+        body
+    );
+
+    // With a declared return type, the mismatch is at the returned value
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": {},
+            "beforeSendCode": "breadcrumb.get(\"data\")",
+            "signature": { "binding": "breadcrumb", "returns": "Option<Value>" }
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "Compilation error: the code returns a borrowed value, not one it owns. \
+         Return owned data: use breadcrumb.clone() or Some(breadcrumb) by value"
+    );
+}
+
+#[actix_web::test]
+async fn other_reference_mismatches_keep_the_compiler_summary() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "let level: u32 = \"error\";\nSome(event)" }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let error = body["error"].as_str().unwrap();
+    assert!(error.contains("mismatched types"), "{}", error);
+    assert!(!error.contains("borrowed value"), "{}", error);
+
+    // A borrowed `Value` assigned mid-body isn't what the code returns
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "let tags: Value = &event[\"tags\"];\nSome(tags)" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let error = body["error"].as_str().unwrap();
+    assert!(error.contains("mismatched types"), "{}", error);
+    assert!(!error.contains("borrowed value"), "{}", error);
+}
+
+#[actix_web::test]
+async fn code_can_branch_on_the_sdk_hint() {
+    let code = r#"if hint["originalException"]["type"] == "ConnectionError" {
+    return None;
+}
+event["tags"] = json!({ "attachments": hint["attachments"].as_array().map_or(0, Vec::len) });
+Some(event)"#;
+    let run = |hint: Option<Value>| {
+        let mut request = json!({ "event": {}, "beforeSendCode": code });
+        if let Some(hint) = hint {
+            request["hint"] = hint;
+        }
+        async move {
+            let (status, body) = post("/transform", request).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            body["transformedEvent"].clone()
+        }
+    };
+
+    let dropped = run(Some(
+        json!({ "originalException": { "type": "ConnectionError" } }),
+    ))
+    .await;
+    assert_eq!(dropped, Value::Null);
+
+    let kept = run(Some(json!({
+        "originalException": { "type": "ValueError" },
+        "attachments": [{ "filename": "log.txt" }]
+    })))
+    .await;
+    assert_eq!(kept, json!({ "tags": { "attachments": 1 } }));
+
+    // Without a hint, `hint` is null
+    assert_eq!(run(None).await, json!({ "tags": { "attachments": 0 } }));
+}
+
+#[actix_web::test]
+async fn hints_past_the_environment_cap_are_rejected() {
+    let hint = json!({ "attachments": "x".repeat(64 * 1024) });
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "hint": hint, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    let error = body["error"].as_str().unwrap();
+    assert!(error.contains("65536 bytes"), "{}", error);
+    assert!(error.contains("TRANSFORM_HINT"), "{}", error);
+}
+
+#[actix_web::test]
+async fn reaching_max_compilations_asks_for_a_recycle() {
+    let state = state_with(|config| config.max_compilations = 2);
+    let recycled = || tokio::time::timeout(Duration::from_millis(50), state.recycle.notified());
+
+    state.record_compilation();
+    assert!(recycled().await.is_err());
+    state.record_compilation();
+    assert!(recycled().await.is_ok());
+    assert_eq!(state.compilations.load(Ordering::Relaxed), 2);
+}
+
+#[actix_web::test]
+async fn status_counts_builds_but_not_cache_hits() {
+    let state = state_with(|_| {});
+    for _ in 0..2 {
+        let (status, body) = post_to(
+            &state,
+            "/transform",
+            json!({ "event": {}, "beforeSendCode": IDENTITY }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+    }
+
+    let (_, status) = json_response(&state, TestRequest::get().uri("/status")).await;
+    assert_eq!(status["compilations"], 1);
+}
+
+/// Code marking events an earlier stage tagged
+const READ_SEEN_TAG: &str = r#"if event["tags"]["seen"] == "yes" {
+    event["tags"]["checked"] = json!(true);
+}
+Some(event)"#;
+
+#[actix_web::test]
+async fn pipeline_stages_feed_each_other() {
+    let stages = json!([{ "code": DROP_ERRORS }, { "code": READ_SEEN_TAG }]);
+    let (status, body) = post(
+        "/transform/pipeline",
+        json!({ "event": { "level": "info" }, "stages": stages }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["success"], true);
+    let tagged = json!({ "level": "info", "tags": { "seen": "yes" } });
+    assert_eq!(body["stages"][0]["transformedEvent"], tagged);
+    assert_eq!(
+        body["finalEvent"],
+        json!({ "level": "info", "tags": { "seen": "yes", "checked": true } })
+    );
+    assert!(body.get("droppedAt").is_none(), "{}", body);
+
+    // Later stages don't run once one drops the event
+    let (status, body) = post(
+        "/transform/pipeline",
+        json!({ "event": { "level": "error" }, "stages": stages }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["finalEvent"], Value::Null);
+    assert_eq!(body["droppedAt"], 0);
+    assert_eq!(body["stages"].as_array().unwrap().len(), 1);
+}
+
+#[actix_web::test]
+async fn extra_clones_report_more_allocations() {
+    let count = |code: &'static str| async move {
+        let (status, body) = post(
+            "/transform",
+            json!({
+                "event": { "message": "hi", "tags": { "a": "b" } },
+                "beforeSendCode": code,
+                "countAllocations": true
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        (
+            body["allocations"].as_u64().unwrap(),
+            body["bytesAllocated"].as_u64().unwrap(),
+        )
+    };
+    let cloning = r#"let copies: Vec<Value> = (0..50).map(|_| event.clone()).collect();
+let _ = copies.len();
+Some(event)"#;
+
+    let (minimal, minimal_bytes) = count(IDENTITY).await;
+    let (cloned, cloned_bytes) = count(cloning).await;
+    assert!(cloned > minimal + 50, "{} vs {}", cloned, minimal);
+    assert!(
+        cloned_bytes > minimal_bytes,
+        "{} vs {}",
+        cloned_bytes,
+        minimal_bytes
+    );
+}
+
+/// Code taking at least 50ms
+const SLOW: &str = "std::thread::sleep(std::time::Duration::from_millis(50));\nSome(event)";
+
+#[actix_web::test]
+async fn slow_transforms_flag_the_exec_budget() {
+    let run = |code: &'static str, budget: u64| async move {
+        let (status, body) = post(
+            "/transform",
+            json!({ "event": {}, "beforeSendCode": code, "maxExecMs": budget }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["execMs"].as_f64().is_some(), "{}", body);
+        body["exceededBudget"].clone()
+    };
+
+    assert_eq!(run(SLOW, 10).await, true);
+    assert_eq!(run(IDENTITY, 10_000).await, false);
+}
+
+#[actix_web::test]
+async fn result_types_follow_what_the_code_returned() {
+    let result_type = |code: &'static str, event: Value| async move {
+        let (status, body) = post(
+            "/transform",
+            json!({ "event": event, "beforeSendCode": code }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        (body["resultType"].clone(), body["transformedEvent"].clone())
+    };
+
+    let (kind, _) = result_type(DROP_ERRORS, json!({ "level": "info" })).await;
+    assert_eq!(kind, "event");
+    let (kind, output) = result_type(DROP_ERRORS, json!({ "level": "error" })).await;
+    assert_eq!((kind, output), (json!("dropped"), Value::Null));
+
+    // A number is a sample rate unless returned as a JSON value
+    let (kind, output) = result_type("0.25", json!({})).await;
+    assert_eq!(kind, "sampleRate");
+    assert_eq!(output.as_f64(), Some(0.25));
+    let (kind, output) = result_type("json!(0.25)", json!({})).await;
+    assert_eq!(kind, "event");
+    assert_eq!(output.as_f64(), Some(0.25));
+}
+
+/// A `multipart/form-data` request to `path` with the given text fields
+fn multipart_request(path: &str, fields: &[(&str, &str)]) -> TestRequest {
+    TestRequest::post()
+        .uri(path)
+        .insert_header(("Content-Type", "multipart/form-data; boundary=boundary"))
+        .set_payload(multipart_body(fields))
+}
+
+/// A `multipart/form-data` body with the given text fields, split on `boundary`
+fn multipart_body(fields: &[(&str, &str)]) -> String {
+    let mut body = String::new();
+    for (name, value) in fields {
+        body.push_str(&format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            name, value
+        ));
+    }
+    body.push_str("--boundary--\r\n");
+    body
+}
+
+#[actix_web::test]
+async fn uploads_transform_each_line_and_report_malformed_ones() {
+    let file = "{\"level\":\"info\"}\n\n[1, 2]\n{\"level\":\"error\"}\n";
+    let request = multipart_request(
+        "/transform/upload",
+        &[("file", file), ("beforeSendCode", DROP_ERRORS)],
+    );
+    let response = send(&state(), request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let events = sse_events(&test::read_body(response).await);
+
+    let lines: Vec<_> = events
+        .iter()
+        .filter(|(name, _)| name == "result")
+        .map(|(_, result)| {
+            (
+                result["line"].clone(),
+                result["success"].clone(),
+                result["transformedEvent"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (
+                json!(1),
+                json!(true),
+                json!({ "level": "info", "tags": { "seen": "yes" } })
+            ),
+            (json!(3), json!(false), Value::Null),
+            (json!(4), json!(true), Value::Null),
+        ]
+    );
+    let malformed = &events[1].1;
+    assert_eq!(malformed["errorKind"], "invalid_input");
+    assert!(
+        malformed["error"].as_str().unwrap().contains("Line 3"),
+        "{}",
+        malformed
+    );
+    assert_eq!(
+        events.last().unwrap(),
+        &("done".to_string(), json!({ "lines": 3, "failed": 1 }))
+    );
+}
+
+#[actix_web::test]
+async fn uploads_need_a_file_and_code() {
+    let request = multipart_request("/transform/upload", &[("beforeSendCode", IDENTITY)]);
+    let (status, body) = json_response(&state(), request).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["errorKind"], "invalid_input");
+}
+
+#[actix_web::test]
+async fn measure_spawn_splits_the_process_time() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "measureSpawn": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let millis = |field: &str| {
+        body[field]
+            .as_f64()
+            .unwrap_or_else(|| panic!("missing {}: {}", field, body))
+    };
+    let (spawn, user, process) = (millis("spawnMs"), millis("userMs"), millis("processMs"));
+    assert!(spawn > 0.0 && user >= 0.0, "{}", body);
+    // Reading the event and writing the result make up the rest
+    assert!(spawn + user <= process, "{}", body);
+
+    let (_, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert!(body.get("spawnMs").is_none() && body.get("processMs").is_none());
+}
+
+#[actix_web::test]
+async fn no_std_rejects_os_apis_that_build_normally() {
+    let code = "let _started = std::time::Instant::now();\nSome(event)";
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": code, "noStd": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("`std::time::Instant` is not available with noStd"),
+        "{}",
+        body
+    );
+
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (_, capabilities) = json_response(&state(), TestRequest::get().uri("/capabilities")).await;
+    let denied = capabilities["noStdDeniedPaths"].as_array().unwrap();
+    assert!(
+        denied.contains(&json!("std::time::Instant")),
+        "{}",
+        capabilities
+    );
+}
+
+#[actix_web::test]
+async fn max_breadcrumbs_trims_the_transformed_event() {
+    let breadcrumbs: Vec<Value> = (0..10).map(|i| json!({ "message": i })).collect();
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "breadcrumbs": { "values": breadcrumbs } },
+            "beforeSendCode": IDENTITY,
+            "maxBreadcrumbs": 3
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"]["breadcrumbs"]["values"],
+        json!([{ "message": 7 }, { "message": 8 }, { "message": 9 }])
+    );
+
+    let (_, body) = post(
+        "/transform",
+        json!({ "event": { "message": "hi" }, "beforeSendCode": IDENTITY, "maxBreadcrumbs": 3 }),
+    )
+    .await;
+    assert_eq!(body["transformedEvent"], json!({ "message": "hi" }));
+}
+
+/// Serve `body` for every request on a local port, returning its base URL
+fn stub_server(content_type: &'static str, body: String) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = actix_web::HttpServer::new(move || {
+        let body = body.clone();
+        App::new().default_service(web::to(move || {
+            let body = body.clone();
+            async move { HttpResponse::Ok().content_type(content_type).body(body) }
+        }))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    url
+}
+
+/// Serve a redirect to `location` from a local port, returning its base URL
+fn redirect_server(location: String) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = actix_web::HttpServer::new(move || {
+        let location = location.clone();
+        App::new().default_service(web::to(move || {
+            let location = location.clone();
+            async move {
+                HttpResponse::Found()
+                    .insert_header(("Location", location))
+                    .finish()
+            }
+        }))
+    })
+    .workers(1)
+    .listen(listener)
+    .unwrap()
+    .run();
+    actix_web::rt::spawn(server);
+    url
+}
+
+#[actix_web::test]
+async fn parity_diffs_the_sibling_sdks_event() {
+    let sibling = json!({
+        "success": true,
+        "transformedEvent": { "level": "info", "tags": { "seen": "no" } }
+    });
+    let sibling = stub_server("application/json", sibling.to_string());
+    let state = state_with(|config| config.parity_sdk_url = Some(sibling));
+    let (status, body) = post_to(
+        &state,
+        "/transform/parity",
+        json!({
+            "event": { "level": "info" },
+            "beforeSendCode": DROP_ERRORS,
+            "siblingCode": "return event;"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["rust"]["transformedEvent"],
+        json!({ "level": "info", "tags": { "seen": "yes" } })
+    );
+    assert_eq!(body["sibling"]["transformedEvent"]["tags"]["seen"], "no");
+    assert_eq!(body["matches"], false);
+    assert_eq!(
+        body["diff"],
+        json!([{ "path": "/tags/seen", "before": "yes", "after": "no" }])
+    );
+}
+
+#[actix_web::test]
+async fn parity_needs_a_sibling_url() {
+    let (status, body) = post(
+        "/transform/parity",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "siblingCode": "return event;" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"].as_str().unwrap().contains("PARITY_SDK_URL"));
+}
+
+#[actix_web::test]
+async fn validate_stream_sends_diagnostics_before_done() {
+    let validate = |code: &'static str| async move {
+        let response = send(
+            &state(),
+            TestRequest::post()
+                .uri("/validate/stream")
+                .set_json(json!({ "code": code, "strictUnused": true })),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        sse_events(&test::read_body(response).await)
+    };
+
+    let events = validate("let level: u32 = \"error\";\nSome(event)").await;
+    let names: Vec<_> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["diagnostic", "done"], "{:?}", events);
+    assert_eq!(events[0].1["level"], "error");
+    assert_eq!(events[0].1["line"], 1);
+    assert_eq!(events[1].1, json!({ "valid": false }));
+
+    // Warnings stream too, without failing the check
+    let events = validate("let unused = 1;\nSome(event)").await;
+    let names: Vec<_> = events.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["diagnostic", "done"], "{:?}", events);
+    assert_eq!(events[0].1["level"], "warning");
+    assert_eq!(events[1].1, json!({ "valid": true }));
+}
+
+#[actix_web::test]
+async fn strict_unused_reports_unused_bindings() {
+    let code = "let unused = event[\"level\"].clone();\nSome(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], true);
+    assert!(body.get("warnings").is_none(), "{}", body);
+    assert_eq!(
+        body["suppressedWarnings"],
+        json!(["#![allow(unused_variables)] hides \"unused variable: `unused`\" at line 1"])
+    );
+
+    let (_, body) = post("/validate", json!({ "code": code, "strictUnused": true })).await;
+    assert_eq!(body["valid"], true);
+    let warning = &body["warnings"][0];
+    assert!(
+        warning["message"]
+            .as_str()
+            .unwrap()
+            .contains("unused variable"),
+        "{}",
+        body
+    );
+    assert_eq!(warning["line"], 1);
+
+    // The wrapper's own code doesn't warn
+    let (_, body) = post(
+        "/validate",
+        json!({ "code": IDENTITY, "strictUnused": true }),
+    )
+    .await;
+    assert!(body.get("warnings").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn coerce_ids_stringifies_a_numeric_event_id() {
+    let request = |coerce_ids: bool| {
+        json!({
+            "event": { "event_id": 42, "user": { "id": 7 }, "extra": { "count": 3 } },
+            "beforeSendCode": IDENTITY,
+            "coerceIds": coerce_ids
+        })
+    };
+    let (status, body) = post("/transform", request(true)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "event_id": "42", "user": { "id": "7" }, "extra": { "count": 3 } })
+    );
+
+    let (_, body) = post("/transform", request(false)).await;
+    assert_eq!(body["transformedEvent"]["event_id"], 42);
+}
+
+#[test]
+fn latency_stats_use_nearest_rank_percentiles() {
+    let samples = (1..=100).rev().map(Duration::from_millis).collect();
+    let stats = LatencyStats::from_samples(samples);
+    assert_eq!(
+        (stats.p50_ms, stats.p95_ms, stats.p99_ms),
+        (50.0, 95.0, 99.0)
+    );
+    assert_eq!(
+        (stats.min_ms, stats.max_ms, stats.mean_ms),
+        (1.0, 100.0, 50.5)
+    );
+
+    let stats = LatencyStats::from_samples(vec![Duration::from_millis(3)]);
+    assert_eq!((stats.p50_ms, stats.p99_ms), (3.0, 3.0));
+}
+
+#[actix_web::test]
+async fn bench_reports_ordered_percentiles() {
+    let (status, body) = post(
+        "/transform/bench",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "iterations": 20 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["iterations"], 20);
+    for timing in ["total", "spawn", "user"] {
+        let stats = &body[timing];
+        let [min, p50, p95, p99, max] = ["minMs", "p50Ms", "p95Ms", "p99Ms", "maxMs"]
+            .map(|field| stats[field].as_f64().unwrap());
+        assert!(
+            min <= p50 && p50 <= p95 && p95 <= p99 && p99 <= max,
+            "{}",
+            stats
+        );
+        assert!(stats["meanMs"].as_f64().unwrap() <= max, "{}", stats);
+    }
+
+    for iterations in [0, MAX_BENCH_ITERATIONS + 1] {
+        let (status, _) = post(
+            "/transform/bench",
+            json!({ "event": {}, "beforeSendCode": IDENTITY, "iterations": iterations }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
+
+#[actix_web::test]
+async fn large_and_precise_numbers_round_trip_exactly() {
+    let event = r#"{"max":18446744073709551615,"min":-9223372036854775808,"wide":123456789012345678901234567890,"precise":0.1000000000000000055511151231257827}"#;
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": serde_json::from_str::<Value>(event).unwrap(),
+            "beforeSendCode": IDENTITY
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"].to_string(), event);
+}
+
+#[actix_web::test]
+async fn fractional_exec_budgets_are_accepted() {
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "maxExecMs": 0.5 }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["exceededBudget"].is_boolean(), "{}", body);
+
+    let replies = ws_session(
+        &state(),
+        &[
+            json!({ "type": "code", "beforeSendCode": IDENTITY, "maxExecMs": 0.5 }),
+            json!({ "type": "event", "event": {} }),
+        ],
+    )
+    .await;
+    assert_eq!(replies[0]["type"], "ready", "{:?}", replies);
+    assert!(replies[1]["exceededBudget"].is_boolean(), "{:?}", replies);
+}
+
+#[actix_web::test]
+async fn delta_responses_rebuild_the_transformed_event() {
+    let event = json!({ "level": "info", "user": { "ip": "::1", "id": "1" }, "message": "hi" });
+    let code = "event[\"user\"].as_object_mut().unwrap().remove(\"ip\");\n\
+                event[\"tags\"] = json!({ \"scrubbed\": \"yes\" });\n\
+                Some(event)";
+    let request = |delta: bool| {
+        TestRequest::post()
+            .uri("/transform")
+            .insert_header(("X-Request-Id", "delta-1"))
+            .set_json(json!({ "event": event, "beforeSendCode": code, "responseDelta": delta }))
+    };
+
+    let (status, body) = json_response(&state(), request(true)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let keys: Vec<&str> = body
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(String::as_str)
+        .collect();
+    assert_eq!(keys, ["success", "requestId", "delta"]);
+    assert_eq!(
+        (&body["success"], &body["requestId"]),
+        (&json!(true), &json!("delta-1"))
+    );
+
+    let mut rebuilt = event.clone();
+    for path in body["delta"]["remove"].as_array().unwrap() {
+        let (parent, key) = path.as_str().unwrap().rsplit_once('/').unwrap();
+        rebuilt
+            .pointer_mut(parent)
+            .unwrap()
+            .as_object_mut()
+            .unwrap()
+            .shift_remove(key);
+    }
+    for (path, value) in body["delta"]["set"].as_object().unwrap() {
+        let (parent, key) = path.rsplit_once('/').unwrap();
+        rebuilt.pointer_mut(parent).unwrap()[key] = value.clone();
+    }
+    let (_, full) = json_response(&state(), request(false)).await;
+    assert_eq!(rebuilt, full["transformedEvent"]);
+}
+
+#[test]
+fn snippets_mark_the_error_line_within_its_context() {
+    let code: String = (1..=10).map(|n| format!("line{}\n", n)).collect();
+    let snippet = |line: usize| {
+        ValidationError {
+            line: Some(line),
+            ..ValidationError::message_only("error".to_string())
+        }
+        .with_snippet(&code)
+        .snippet
+    };
+    assert_eq!(snippet(1), ["> 1 | line1", "  2 | line2", "  3 | line3"]);
+    assert_eq!(
+        snippet(9),
+        [
+            "   7 | line7",
+            "   8 | line8",
+            ">  9 | line9",
+            "  10 | line10"
+        ]
+    );
+    assert!(ValidationError::message_only("error".to_string())
+        .with_snippet(&code)
+        .snippet
+        .is_empty());
+}
+
+#[actix_web::test]
+async fn include_snippets_shows_the_code_around_an_error() {
+    let code = "let level = event[\"level\"].clone();\n\
+                let tags = &event[\"tags\"];\n\
+                let count: u32 = \"three\";\n\
+                event[\"level\"] = level;\n\
+                Some(event)";
+    let (status, body) = post(
+        "/validate",
+        json!({ "code": code, "includeSnippets": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["errors"][0]["snippet"],
+        json!([
+            "  1 | let level = event[\"level\"].clone();",
+            "  2 | let tags = &event[\"tags\"];",
+            "> 3 | let count: u32 = \"three\";",
+            "  4 | event[\"level\"] = level;",
+            "  5 | Some(event)"
+        ])
+    );
+
+    let (_, body) = post("/validate", json!({ "code": code })).await;
+    assert!(body["errors"][0].get("snippet").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn from_url_summarizes_fetched_events() {
+    let export = "{\"level\":\"info\"}\n{\"level\":\"error\"}\nnot json\n{\"level\":\"warning\"}\n";
+    let url = stub_server("application/x-ndjson", export.to_string());
+    let state = state_with(|config| config.fetch_allowed_hosts = vec!["127.0.0.1".to_string()]);
+
+    let (status, body) = post_to(
+        &state,
+        "/transform/from-url",
+        json!({ "url": format!("{}/export.ndjson", url), "beforeSendCode": DROP_ERRORS }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        (
+            &body["events"],
+            &body["kept"],
+            &body["dropped"],
+            &body["failed"]
+        ),
+        (&json!(4), &json!(2), &json!(1), &json!(1))
+    );
+    assert_eq!(body["failures"][0]["line"], 3);
+}
+
+#[actix_web::test]
+async fn from_url_only_fetches_from_allowed_hosts() {
+    let fetch = |state: web::Data<AppState>, url: &'static str| async move {
+        post_to(
+            &state,
+            "/transform/from-url",
+            json!({ "url": url, "beforeSendCode": IDENTITY }),
+        )
+        .await
+    };
+
+    let (status, body) = fetch(state(), "http://127.0.0.1/events").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error"]
+        .as_str()
+        .unwrap()
+        .contains("FETCH_ALLOWED_HOSTS is unset"));
+
+    let state =
+        state_with(|config| config.fetch_allowed_hosts = vec!["exports.example.com".to_string()]);
+    for url in [
+        "http://169.254.169.254/latest/meta-data",
+        "http://exports.example.com.evil.test/",
+    ] {
+        let (status, body) = fetch(state.clone(), url).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            body["error"]
+                .as_str()
+                .unwrap()
+                .contains("is not in FETCH_ALLOWED_HOSTS"),
+            "{}",
+            body
+        );
+    }
+}
+
+#[actix_web::test]
+async fn from_url_reports_an_unreachable_host_as_a_bad_gateway() {
+    // Nothing listens on the port once the listener is dropped
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/export.ndjson", listener.local_addr().unwrap());
+    drop(listener);
+    let state = state_with(|config| config.fetch_allowed_hosts = vec!["127.0.0.1".to_string()]);
+
+    let (status, body) = post_to(
+        &state,
+        "/transform/from-url",
+        json!({ "url": url, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+    assert_eq!(body["success"], false);
+    assert_eq!(body["errorKind"], "upstream");
+}
+
+#[actix_web::test]
+async fn from_url_follows_redirects_only_to_allowed_hosts() {
+    let export = stub_server("application/x-ndjson", "{\"level\":\"info\"}\n".to_string());
+    let state = state_with(|config| config.fetch_allowed_hosts = vec!["127.0.0.1".to_string()]);
+    let fetch = |url: String| {
+        post_to(
+            &state,
+            "/transform/from-url",
+            json!({ "url": url, "beforeSendCode": IDENTITY }),
+        )
+    };
+
+    let moved = redirect_server(format!("{}/export.ndjson", export));
+    let (status, body) = fetch(format!("{}/old.ndjson", moved)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["kept"], 1);
+
+    // The same server, under a name that isn't allowed
+    let port = export.rsplit(':').next().unwrap();
+    let escaped = redirect_server(format!("http://localhost:{}/export.ndjson", port));
+    let (status, body) = fetch(format!("{}/old.ndjson", escaped)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY, "{}", body);
+    assert_eq!(body["errorKind"], "upstream");
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("redirected to host 'localhost', which is not in FETCH_ALLOWED_HOSTS"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn wrap_envelope_returns_a_ready_to_send_envelope() {
+    let wrap = |event: Value| async move {
+        let (status, body) = post(
+            "/transform",
+            json!({ "event": event, "beforeSendCode": DROP_ERRORS, "wrapEnvelope": true }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body
+    };
+
+    let body = wrap(json!({ "level": "info", "message": "ünïcode" })).await;
+    let envelope = body["envelope"].as_str().unwrap();
+    let lines: Vec<&str> = envelope.lines().collect();
+    let item_header: Value = serde_json::from_str(lines[1]).unwrap();
+    assert_eq!(item_header["length"], lines[2].len());
+    assert_eq!(
+        serde_json::from_str::<Value>(lines[2]).unwrap(),
+        body["transformedEvent"]
+    );
+
+    let body = wrap(json!({ "level": "error" })).await;
+    assert!(body.get("envelope").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn synthetic_events_transform_for_every_template() {
+    for template in ["exception", "message", "transaction"] {
+        let (status, body) = post(
+            "/transform/synthetic",
+            json!({ "template": template, "beforeSendCode": IDENTITY }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["success"], true);
+        assert!(body["input"]["event_id"].is_string(), "{}", body);
+        assert_eq!(body["transformedEvent"], body["input"], "{}", template);
+    }
+
+    let (status, _) = post(
+        "/transform/synthetic",
+        json!({ "template": "exception", "beforeSendCode": IDENTITY, "mode": "beforeSendLog" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn dropped_events_echo_their_event_id() {
+    let transform = |event: Value| {
+        post(
+            "/transform",
+            json!({ "event": event, "beforeSendCode": DROP_ERRORS }),
+        )
+    };
+
+    let (status, body) =
+        transform(json!({ "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0", "level": "error" }))
+            .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["droppedEventId"], "fc6d8c0c43fc4630ad850ee518f1b9d0");
+
+    let (_, body) = transform(json!({ "level": "error" })).await;
+    assert!(body.get("droppedEventId").is_none(), "{}", body);
+
+    // Kept events don't need it
+    let (_, body) =
+        transform(json!({ "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0", "level": "info" })).await;
+    assert!(body.get("droppedEventId").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn cache_status_reports_hits_and_misses() {
+    let cache_status = |state: web::Data<AppState>, code: &'static str| async move {
+        let (status, body) = post_to(
+            &state,
+            "/transform",
+            json!({ "event": {}, "beforeSendCode": code }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        body["cache"]["status"].clone()
+    };
+
+    let state = state_with(|_| {});
+    assert_eq!(cache_status(state.clone(), IDENTITY).await, "miss");
+    assert_eq!(cache_status(state.clone(), IDENTITY).await, "hit");
+    assert_eq!(cache_status(state, DROP_ERRORS).await, "miss");
+
+    let state = state_with(|config| config.build_cache_entries = 0);
+    assert_eq!(cache_status(state.clone(), IDENTITY).await, "disabled");
+    assert_eq!(cache_status(state, IDENTITY).await, "disabled");
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[actix_web::test]
+async fn gzipped_transform_requests_match_plain_ones() {
+    let body = json!({ "event": { "level": "info" }, "beforeSendCode": DROP_ERRORS });
+    let (_, plain) = post("/transform", body.clone()).await;
+    let request = TestRequest::post()
+        .uri("/transform")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Content-Encoding", "gzip"))
+        .set_payload(gzip(body.to_string().as_bytes()));
+    let (status, gzipped) = json_response(&state(), request).await;
+    assert_eq!(status, StatusCode::OK, "{}", gzipped);
+    assert_eq!(gzipped["transformedEvent"], plain["transformedEvent"]);
+
+    let corrupt = TestRequest::post()
+        .uri("/transform")
+        .insert_header(("Content-Type", "application/json"))
+        .insert_header(("Content-Encoding", "gzip"))
+        .set_payload(body.to_string());
+    let response = send(&state(), corrupt).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let message = test::read_body(response).await;
+    assert!(String::from_utf8_lossy(&message).contains("not valid gzip data"));
+}
+
+#[actix_web::test]
+async fn gzipped_uploads_are_inflated() {
+    let upload = |body: Vec<u8>| {
+        TestRequest::post()
+            .uri("/transform/upload")
+            .insert_header(("Content-Type", "multipart/form-data; boundary=boundary"))
+            .insert_header(("Content-Encoding", "gzip"))
+            .set_payload(body)
+    };
+    let form = multipart_body(&[
+        ("file", "{\"level\":\"info\"}\n{\"level\":\"error\"}\n"),
+        ("beforeSendCode", DROP_ERRORS),
+    ]);
+
+    let plain = TestRequest::post()
+        .uri("/transform/upload")
+        .insert_header(("Content-Type", "multipart/form-data; boundary=boundary"))
+        .set_payload(form.clone());
+    let response = send(&state(), plain).await;
+    let plain = sse_events(&test::read_body(response).await);
+    let response = send(&state(), upload(gzip(form.as_bytes()))).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(sse_events(&test::read_body(response).await), plain);
+
+    // A small body inflating past the limit is cut off
+    let bomb = gzip(&vec![b' '; MAX_UPLOAD_BYTES + 1]);
+    assert!(bomb.len() < 64 * 1024);
+    let (status, body) = json_response(&state(), upload(bomb)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("inflates to more than"),
+        "{}",
+        body
+    );
+
+    let (status, body) = json_response(&state(), upload(form.into_bytes())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"]
+            .as_str()
+            .unwrap()
+            .contains("not valid gzip data"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn syntax_errors_are_reported_without_a_build_slot() {
+    // With every slot taken, anything reaching cargo is turned away
+    let state = state_with(|config| {
+        config.max_concurrent_builds = 1;
+        config.max_queue_depth = 0;
+    });
+    let _permit = state.limiter.acquire(0).await.expect("a free slot");
+    let unbalanced = "if event[\"level\"] == \"error\" {\n    return None;\nSome(event)";
+
+    let (status, body) = post_to(&state, "/validate", json!({ "code": unbalanced })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false);
+    let error = &body["errors"][0];
+    assert_eq!((&error["line"], &error["column"]), (&json!(1), &json!(30)));
+    assert!(
+        error["message"]
+            .as_str()
+            .unwrap()
+            .contains("unclosed delimiter"),
+        "{}",
+        body
+    );
+
+    let (status, body) = post_to(
+        &state,
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": unbalanced }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["errorKind"], "compile");
+
+    let (status, _) = post_to(&state, "/validate", json!({ "code": IDENTITY })).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[actix_web::test]
+async fn validate_rejects_returns_no_mode_accepts() {
+    let code = "event[\"message\"].as_str().unwrap_or_default().to_string()";
+    let (status, body) = post(
+        "/validate",
+        json!({ "code": code, "event": { "message": "hi" } }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false);
+    let message = body["errors"][0]["message"].as_str().unwrap();
+    assert!(
+        message.contains("E0277") && message.contains("TransformResult"),
+        "{}",
+        body
+    );
+    assert!(message.contains("From<std::string::String>"), "{}", body);
+
+    // /transform agrees
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["errorKind"], "compile");
+}
+
+#[actix_web::test]
+async fn per_type_handlers_run_the_code_for_the_events_type() {
+    let handlers = json!({
+        "beforeSend": "event[\"tags\"][\"handled_by\"] = json!(\"beforeSend\");\nSome(event)",
+        "beforeSendTransaction": "event[\"tags\"][\"handled_by\"] = json!(\"beforeSendTransaction\");\nSome(event)"
+    });
+    let transform = |event: Value| {
+        let mut body = handlers.clone();
+        body["event"] = event;
+        post("/transform", body)
+    };
+
+    let (status, body) = transform(json!({ "level": "error" })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["handler"], "beforeSend");
+    assert_eq!(body["transformedEvent"]["tags"]["handled_by"], "beforeSend");
+
+    let (_, body) = transform(json!({ "type": "transaction" })).await;
+    assert_eq!(body["handler"], "beforeSendTransaction");
+    assert_eq!(
+        body["transformedEvent"]["tags"]["handled_by"],
+        "beforeSendTransaction"
+    );
+
+    // No beforeSendLog: logs are kept as they are
+    let log = json!({ "type": "log", "level": "info", "body": "hi" });
+    let (_, body) = transform(log.clone()).await;
+    assert_eq!(body["handler"], "beforeSendLog");
+    assert_eq!(body["transformedEvent"], log);
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": IDENTITY, "beforeSend": IDENTITY }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"].as_str().unwrap().contains("not both"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn every_corpus_sample_transforms() {
+    let (status, body) = json_response(&state(), TestRequest::get().uri("/corpus")).await;
+    assert_eq!(status, StatusCode::OK);
+    let samples = body["samples"].as_array().unwrap();
+    assert_eq!(samples.len(), corpus::SAMPLES.len());
+
+    for sample in samples {
+        let (status, body) = post(
+            "/transform",
+            json!({ "corpusId": sample["id"], "beforeSendCode": IDENTITY }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}: {}", sample["id"], body);
+        assert_eq!(
+            body["transformedEvent"], sample["event"],
+            "{}",
+            sample["id"]
+        );
+    }
+}
+
+#[actix_web::test]
+async fn corpus_ids_must_exist_and_replace_the_event() {
+    for (request, error) in [
+        (
+            json!({ "corpusId": "missing", "beforeSendCode": IDENTITY }),
+            "Unknown corpusId",
+        ),
+        (
+            json!({ "corpusId": "rust-panic", "event": {}, "beforeSendCode": IDENTITY }),
+            "not both",
+        ),
+        (json!({ "beforeSendCode": IDENTITY }), "Missing event"),
+    ] {
+        let (status, body) = post("/transform", request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"].as_str().unwrap().contains(error), "{}", body);
+    }
+}
+
+#[actix_web::test]
+async fn build_messages_and_program_output_stay_apart() {
+    // With build info, cargo's JSON messages share stdout with nothing the binary prints
+    let code = "println!(\"{{\\\"reason\\\":\\\"compiler-message\\\"}}\");\nSome(event)";
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": { "level": "info" }, "beforeSendCode": code, "includeBuildInfo": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["transformedEvent"], json!({ "level": "info" }));
+    assert!(!crate_names(&body["buildInfo"]).is_empty(), "{}", body);
+
+    let (status, body) = post(
+        "/transform",
+        json!({ "event": {}, "beforeSendCode": "let x: u32 = event;\nSome(x)", "includeBuildInfo": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(
+        body["error"].as_str().unwrap().contains("mismatched types"),
+        "{}",
+        body
+    );
+}
+
+#[actix_web::test]
+async fn include_fingerprint_reports_the_hash_and_its_rule() {
+    let event = json!({ "exception": { "values": [{ "type": "KeyError", "value": "'user'" }] } });
+    let fingerprint = |code: &'static str| {
+        post(
+            "/transform",
+            json!({ "event": event, "beforeSendCode": code, "includeFingerprint": true }),
+        )
+    };
+
+    let (status, body) = fingerprint(IDENTITY).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["fingerprintRule"], "exception");
+    let default = body["fingerprint"].as_str().unwrap().to_string();
+    assert_eq!(default.len(), 32);
+
+    let code = "event[\"fingerprint\"] = json!([\"{{ type }}\", \"{{ value }}\"]);\nSome(event)";
+    let (_, body) = fingerprint(code).await;
+    assert_eq!(body["fingerprintRule"], "fingerprint");
+    // A single exception's type and value are all the default groups by too
+    assert_eq!(body["fingerprint"], default);
+
+    let (_, body) = post(
+        "/transform",
+        json!({ "event": event, "beforeSendCode": IDENTITY }),
+    )
+    .await;
+    assert!(body.get("fingerprint").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn impact_flags_a_candidate_that_drops_the_event() {
+    let impact = |event: Value, candidate: &'static str| {
+        post(
+            "/transform/impact",
+            json!({ "event": event, "baselineCode": IDENTITY, "candidateCode": candidate }),
+        )
+    };
+
+    let (status, body) = impact(json!({ "level": "error" }), DROP_ERRORS).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        (&body["baseline"]["dropped"], &body["candidate"]["dropped"]),
+        (&json!(false), &json!(true))
+    );
+    assert_eq!(body["dispositionChanged"], true);
+
+    let (_, body) = impact(json!({ "level": "info" }), DROP_ERRORS).await;
+    assert_eq!(body["dispositionChanged"], false);
+    assert_eq!(
+        body["diff"],
+        json!([{ "path": "/tags", "after": { "seen": "yes" } }])
+    );
+
+    // A candidate that fails leaves nothing to compare
+    let (status, body) = impact(json!({}), "event.unknown_method()").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["candidate"]["success"], false);
+    assert!(body.get("dispositionChanged").is_none() && body.get("diff").is_none());
+}
+
+#[actix_web::test]
+async fn transactions_over_the_span_limit_are_rejected() {
+    let state = state_with(|config| config.max_spans = 3);
+    let transaction = |spans: usize| {
+        json!({
+            "event": { "type": "transaction", "spans": vec![json!({ "op": "db" }); spans] },
+            "beforeSendCode": IDENTITY
+        })
+    };
+
+    let (status, body) = post_to(&state, "/transform", transaction(4)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["errorKind"], "invalid_input");
+    assert_eq!(
+        body["error"],
+        "Event has 4 spans; at most 3 spans are allowed"
+    );
+
+    let (status, body) = post_to(&state, "/transform", transaction(3)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (_, capabilities) = json_response(&state, TestRequest::get().uri("/capabilities")).await;
+    assert_eq!(capabilities["maxSpans"], 3);
+}
+
+#[test]
+fn only_the_wrappers_allows_count_as_suppressing() {
+    let lint = |code: &str| wrapper_allowed_lint(&json!({ "code": { "code": code } }));
+    assert_eq!(lint("unused_mut"), Some("unused_mut"));
+    assert_eq!(lint("dead_code"), None);
+    assert_eq!(wrapper_allowed_lint(&json!({ "code": null })), None);
+}
+
+#[actix_web::test]
+async fn suppressed_warnings_name_each_allow_that_hid_one() {
+    let code = "use std::collections::BTreeMap;\n\
+                let mut unused = json!({});\n\
+                Some(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], true);
+    let mut suppressed: Vec<&str> = body["suppressedWarnings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|warning| warning.as_str().unwrap())
+        .collect();
+    suppressed.sort();
+    assert_eq!(
+        suppressed,
+        [
+            "#![allow(unused_imports)] hides \"unused import: `std::collections::BTreeMap`\" at line 1",
+            "#![allow(unused_mut)] hides \"variable does not need to be mutable\" at line 2",
+            "#![allow(unused_variables)] hides \"unused variable: `unused`\" at line 2",
+        ]
+    );
+
+    let (_, body) = post("/validate", json!({ "code": IDENTITY })).await;
+    assert!(body.get("suppressedWarnings").is_none(), "{}", body);
+}
+
+#[actix_web::test]
+async fn streaming_batches_send_the_buffered_results_as_ndjson() {
+    let code = r#"if event["level"] == "error" {
+    count("dropped");
+    return None;
+}
+event["tags"]["seen"] = json!("yes");
+Some(event)"#;
+    let request = |streaming: bool| {
+        json!({
+            "events": [{ "level": "info" }, { "level": "error" }, { "level": "warning" }],
+            "beforeSendCode": code,
+            "streaming": streaming
+        })
+    };
+    let (status, buffered) = post("/transform/batch", request(false)).await;
+    assert_eq!(status, StatusCode::OK, "{}", buffered);
+
+    let response = send(
+        &state(),
+        TestRequest::post()
+            .uri("/transform/batch")
+            .set_json(request(true)),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "application/x-ndjson"
+    );
+    let body = test::read_body(response).await;
+    let mut lines: Vec<Value> = String::from_utf8_lossy(&body)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("each line is JSON"))
+        .collect();
+    let done = lines.pop().expect("a final line");
+
+    assert_eq!(Value::from(lines), buffered["results"]);
+    assert_eq!(done["done"], true);
+    assert_eq!(done["counters"], buffered["counters"]);
+    assert_eq!(done["counters"], json!({ "dropped": 1 }));
+}
+
+#[actix_web::test]
+async fn json_macro_works_alike_in_validate_and_transform() {
+    let code = "event[\"extra\"] = json!({ \"checked\": [1, 2] });\nSome(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], true, "{}", body);
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body["transformedEvent"],
+        json!({ "extra": { "checked": [1, 2] } })
+    );
+
+    // A `mod` the code declares doesn't see the wrapper's import
+    let code =
+        "mod extra {\n    pub fn value() -> serde_json::Value {\n        json!(1)\n    }\n}\n\
+                event[\"extra\"] = extra::value();\nSome(event)";
+    let (status, body) = post("/validate", json!({ "code": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["valid"], false, "{}", body);
+    let (status, body) = post("/transform", json!({ "event": {}, "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(
+        body["error"],
+        "Compilation error: the json! macro is imported for the code itself, but not inside \
+         a `mod` it declares; add `use serde_json::json;` there, and call it as json!({...})"
+    );
+}
+
+#[actix_web::test]
+async fn quota_projects_the_drop_rate_over_the_corpus() {
+    let code = r#"if event["level"] == "error" {
+    return None;
+}
+Some(event)"#;
+    // Four of the ten samples are errors
+    let (status, body) = post("/transform/quota", json!({ "beforeSendCode": code })).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(
+        body,
+        json!({
+            "success": true,
+            "total": 10,
+            "kept": 6,
+            "dropped": 4,
+            "failed": 0,
+            "dropRate": 40.0
+        })
+    );
+
+    let (status, body) = post(
+        "/transform/quota",
+        json!({
+            "beforeSendCode": code,
+            "corpusIds": ["python-keyerror", "rust-panic", "go-logentry-message", "android-anr"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!((&body["kept"], &body["dropped"]), (&json!(2), &json!(2)));
+    assert_eq!(body["dropRate"], 50.0);
+
+    let (status, body) = post(
+        "/transform/quota",
+        json!({ "beforeSendCode": code, "events": [{}], "corpusIds": ["rust-panic"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+    assert_eq!(body["error"], "Send either events or corpusIds, not both");
+}
+
+#[test]
+fn canonical_hashes_ignore_key_order_and_skipped_keys() {
+    let hash = canonical_hash(&json!({ "a": 1, "b": { "c": 2, "d": 3 } }), &[]);
+    assert_eq!(hash.len(), 64);
+    assert_eq!(
+        canonical_hash(&json!({ "b": { "d": 3, "c": 2 }, "a": 1 }), &[]),
+        hash
+    );
+    assert_eq!(
+        canonical_hash(
+            &json!({ "a": 1, "b": { "c": 2, "d": 3 }, "event_id": "x" }),
+            &["event_id"]
+        ),
+        hash
+    );
+    assert_ne!(
+        canonical_hash(&json!({ "a": 2, "b": { "c": 2, "d": 3 } }), &[]),
+        hash
+    );
+}
+
+#[actix_web::test]
+async fn output_hashes_are_stable_across_identical_runs() {
+    let request = json!({
+        "event": { "message": "hi", "level": "info" },
+        "beforeSendCode": DROP_ERRORS,
+        "includeOutputHash": true,
+        "normalize": true
+    });
+    let (status, first) = post("/transform", request.clone()).await;
+    assert_eq!(status, StatusCode::OK, "{}", first);
+    let (status, second) = post("/transform", request).await;
+    assert_eq!(status, StatusCode::OK, "{}", second);
+
+    // The generated event_id differs, the hash doesn't
+    assert_ne!(
+        first["transformedEvent"]["event_id"],
+        second["transformedEvent"]["event_id"]
+    );
+    let hash = first["outputHash"].as_str().unwrap();
+    assert_eq!(second["outputHash"], hash);
+
+    // Ids the event already had count towards the hash
+    let (status, body) = post(
+        "/transform",
+        json!({
+            "event": { "message": "hi", "level": "info", "event_id": "fc6d8c0c43fc4630ad850ee518f1b9d0" },
+            "beforeSendCode": DROP_ERRORS,
+            "includeOutputHash": true,
+            "normalize": true
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_ne!(body["outputHash"], hash);
+}
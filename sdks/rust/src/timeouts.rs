@@ -0,0 +1,89 @@
+//! Deadlines for reading client requests
+//!
+//! Request heads are bounded by actix's client request timeout
+//! (`REQUEST_HEADER_TIMEOUT_SECS`). Bodies get their own deadline
+//! (`REQUEST_BODY_TIMEOUT_SECS`), counted from when the head arrived, so a
+//! client stalling mid-upload gets a 408 instead of holding a worker. Both
+//! are separate from the cargo timeouts used while building.
+
+use crate::sandbox::ErrorKind;
+use crate::AppState;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::PayloadError;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use futures_util::{FutureExt, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+
+/// Request body that fails once its deadline passes
+struct DeadlinePayload {
+    inner: Payload,
+    deadline: Pin<Box<Sleep>>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Stream for DeadlinePayload {
+    type Item = Result<web::Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Poll::Ready(chunk) = self.inner.poll_next_unpin(cx) {
+            return Poll::Ready(chunk);
+        }
+
+        match self.deadline.poll_unpin(cx) {
+            Poll::Ready(()) => {
+                self.expired.store(true, Ordering::SeqCst);
+                let timed_out = std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "request body was not received in time",
+                );
+                Poll::Ready(Some(Err(PayloadError::Io(timed_out))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Middleware answering 408 when the request body isn't received in time
+pub async fn body_deadline(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    // Upgraded connections like websockets stream for as long as they're open
+    let timeout = req
+        .app_data::<web::Data<AppState>>()
+        .map(|state| Duration::from_secs(state.config.request_body_timeout_secs))
+        .filter(|timeout| !timeout.is_zero() && !req.head().upgrade());
+    let Some(timeout) = timeout else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let expired = Arc::new(AtomicBool::new(false));
+    let payload: Pin<Box<dyn Stream<Item = _>>> = Box::pin(DeadlinePayload {
+        inner: req.take_payload(),
+        deadline: Box::pin(tokio::time::sleep(timeout)),
+        expired: expired.clone(),
+    });
+    req.set_payload(Payload::from(payload));
+
+    let res = next.call(req).await?;
+    if !expired.load(Ordering::SeqCst) {
+        return Ok(res.map_into_left_body());
+    }
+
+    let response = HttpResponse::RequestTimeout().json(serde_json::json!({
+        "success": false,
+        "error": format!("Request body was not received within {}s", timeout.as_secs()),
+        "errorKind": ErrorKind::Timeout,
+    }));
+    Ok(res.into_response(response).map_into_right_body())
+}
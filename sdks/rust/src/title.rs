@@ -0,0 +1,123 @@
+//! The title Sentry derives for an event, as shown in its issue list
+//!
+//! Follows Sentry's event types: transactions are titled by their name,
+//! errors by their top exception's type and value, and anything else by its
+//! message. Values are cut to their first line and [`MAX_TITLE_CHARS`].
+
+use serde_json::Value;
+
+/// Longest message Sentry puts in a title before truncating it
+const MAX_TITLE_CHARS: usize = 100;
+
+/// Title Sentry falls back to for events with nothing to show
+const UNLABELED: &str = "<unlabeled event>";
+
+/// Derive the title of an event object
+pub fn derive(event: &Value) -> String {
+    if event["type"] == "transaction" {
+        return event["transaction"]
+            .as_str()
+            .filter(|name| !name.is_empty())
+            .unwrap_or(UNLABELED)
+            .to_string();
+    }
+
+    // The last exception is the one that was raised, earlier ones are its causes
+    let exceptions = event
+        .get("exception")
+        .map(|exception| exception.get("values").unwrap_or(exception))
+        .and_then(Value::as_array);
+    if let Some(exception) = exceptions.and_then(|values| values.last()) {
+        let ty = exception["type"].as_str().filter(|ty| !ty.is_empty());
+        let value = exception["value"]
+            .as_str()
+            .map(first_line)
+            .filter(|v| !v.is_empty());
+        return match (ty, value) {
+            (Some(ty), Some(value)) => format!("{}: {}", ty, value),
+            (Some(ty), None) => ty.to_string(),
+            (None, Some(value)) => value,
+            (None, None) => "Error".to_string(),
+        };
+    }
+
+    let message = [
+        &event["logentry"]["formatted"],
+        &event["logentry"]["message"],
+        &event["message"]["formatted"],
+        &event["message"]["message"],
+        &event["message"],
+    ]
+    .into_iter()
+    .find_map(|message| {
+        message
+            .as_str()
+            .filter(|message| !message.trim().is_empty())
+    });
+
+    match message {
+        Some(message) => first_line(message),
+        None => UNLABELED.to_string(),
+    }
+}
+
+/// First non-empty line of `text`, truncated with an ellipsis
+fn first_line(text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    if line.chars().count() <= MAX_TITLE_CHARS {
+        return line.to_string();
+    }
+    let truncated: String = line.chars().take(MAX_TITLE_CHARS - 3).collect();
+    format!("{}...", truncated.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn errors_are_titled_by_the_raised_exception() {
+        let event = json!({
+            "message": "ignored",
+            "exception": { "values": [
+                { "type": "IoError", "value": "cause" },
+                { "type": "ValueError", "value": "bad input\nmore detail" }
+            ] }
+        });
+        assert_eq!(derive(&event), "ValueError: bad input");
+        assert_eq!(
+            derive(&json!({ "exception": [{ "type": "Panic" }] })),
+            "Panic"
+        );
+        assert_eq!(derive(&json!({ "exception": { "values": [{}] } })), "Error");
+    }
+
+    #[test]
+    fn other_events_are_titled_by_their_message() {
+        assert_eq!(
+            derive(
+                &json!({ "logentry": { "formatted": "user 1 failed", "message": "user %s failed" } })
+            ),
+            "user 1 failed"
+        );
+        assert_eq!(derive(&json!({ "message": "\n  hello \nworld" })), "hello");
+        assert_eq!(derive(&json!({ "message": "  " })), UNLABELED);
+
+        let long = "x".repeat(150);
+        let title = derive(&json!({ "message": long }));
+        assert_eq!(title.chars().count(), MAX_TITLE_CHARS);
+        assert!(title.ends_with("..."));
+    }
+
+    #[test]
+    fn transactions_are_titled_by_their_name() {
+        let event = json!({ "type": "transaction", "transaction": "GET /users", "message": "hi" });
+        assert_eq!(derive(&event), "GET /users");
+        assert_eq!(derive(&json!({ "type": "transaction" })), UNLABELED);
+    }
+}